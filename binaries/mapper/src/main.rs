@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Instant;
+
+use voxelland_world::vec::IVec2;
+use voxelland_world::worldmap::{self, Palette, WorldMap, MAX_ZOOM};
+
+const USAGE: &str = "Usage: voxelland-mapper <world folder> [options]
+
+Renders a top-down map of the world as PNG tiles, <out>/<zoom>/<x>_<z>.png.
+
+  --out <folder>     Where the tiles go (default: <world folder>/map)
+  --zoom <z,z,...>   Zoom levels, 0 is a block a pixel and each one up halves it (default: 0, at most 6)
+  --from <x,z>       One corner of the region to draw, in blocks
+  --to <x,z>         The other corner (default: around everything that's been built, or the middle of the world)
+  --atlas <png>      The block texture atlas the colors come from (default: the game's assets/world.png)";
+
+/// How far past the edits the map goes when no region is given.
+const MARGIN: i32 = 128;
+
+struct Options {
+    world: PathBuf,
+    out: Option<PathBuf>,
+    zooms: Vec<u32>,
+    from: Option<IVec2>,
+    to: Option<IVec2>,
+    atlas: PathBuf,
+}
+
+fn parse_spot(text: &str) -> Result<IVec2, String> {
+    let parts: Vec<&str> = text.split(',').map(str::trim).collect();
+    match parts[..] {
+        [x, z] => match (x.parse(), z.parse()) {
+            (Ok(x), Ok(z)) => Ok(IVec2::new(x, z)),
+            _ => Err(format!("\"{}\" isn't two whole numbers", text)),
+        },
+        _ => Err(format!("\"{}\" should be x,z", text)),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        world: PathBuf::new(),
+        out: None,
+        zooms: vec![0],
+        from: None,
+        to: None,
+        atlas: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../../assets/world.png")),
+    };
+    let mut world = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--out" => options.out = Some(value()?.into()),
+            "--atlas" => options.atlas = value()?.into(),
+            "--from" => options.from = Some(parse_spot(&value()?)?),
+            "--to" => options.to = Some(parse_spot(&value()?)?),
+            "--zoom" => {
+                options.zooms = value()?
+                    .split(',')
+                    .map(|z| match z.trim().parse() {
+                        Ok(z) if z <= MAX_ZOOM => Ok(z),
+                        _ => Err(format!("zoom levels go from 0 to {}, not \"{}\"", MAX_ZOOM, z)),
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "--help" | "-h" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("don't know {}", arg)),
+            _ if world.is_none() => world = Some(PathBuf::from(arg)),
+            _ => return Err(format!("one world at a time, what's \"{}\"?", arg)),
+        }
+    }
+    options.world = world.ok_or(String::from("which world?"))?;
+    Ok(options)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            if !e.is_empty() {
+                println!("{}\n", e);
+            }
+            println!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let map = match WorldMap::open(&options.world) {
+        Ok(map) => map,
+        Err(e) => {
+            println!("Can't read the world: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let palette = match Palette::open(&options.atlas) {
+        Ok(palette) => palette,
+        Err(e) => {
+            println!("Can't read the texture atlas {}: {}", options.atlas.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (min, max) = match (options.from, options.to) {
+        (Some(a), Some(b)) => (IVec2::new(a.x.min(b.x), a.y.min(b.y)), IVec2::new(a.x.max(b.x), a.y.max(b.y))),
+        (None, None) => match map.edited_bounds() {
+            Some((min, max)) => (IVec2::new(min.x - MARGIN, min.y - MARGIN), IVec2::new(max.x + MARGIN, max.y + MARGIN)),
+            None => (IVec2::new(-MARGIN, -MARGIN), IVec2::new(MARGIN - 1, MARGIN - 1)),
+        },
+        _ => {
+            println!("--from and --to go together\n\n{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let out = options.out.unwrap_or(options.world.join("map"));
+    println!("Mapping {} from {} {} to {} {} into {}", options.world.display(), min.x, min.y, max.x, max.y, out.display());
+    for zoom in options.zooms {
+        let dir = out.join(zoom.to_string());
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            println!("Can't make {}: {}", dir.display(), e);
+            return ExitCode::FAILURE;
+        }
+        let tiles = worldmap::tiles_covering(min, max, zoom);
+        let started = Instant::now();
+        for (i, tile) in tiles.iter().enumerate() {
+            let path = dir.join(format!("{}_{}.png", tile.x, tile.y));
+            if let Err(e) = map.render_tile(&palette, zoom, *tile).save(&path) {
+                println!("Can't write {}: {}", path.display(), e);
+                return ExitCode::FAILURE;
+            }
+            println!("Zoom {}: tile {} of {}", zoom, i + 1, tiles.len());
+        }
+        println!("Zoom {} done in {:.1}s", zoom, started.elapsed().as_secs_f32());
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,507 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::blockinfo::Blocks;
+use crate::bulkedit;
+use crate::edithistory::{EditAction, HistoryEntry};
+use crate::schematic::Schematic;
+use crate::specialblocks::door::DOORTOP_BITS;
+use crate::vec::IVec3;
+
+/// Fills and brushes bigger than this many blocks aren't done. Copies go up to `schematic::MAX_VOLUME`.
+pub const MAX_TOOL_VOLUME: i64 = 64 * 64 * 64;
+
+/// How many messages of a queued edit go to the server a second, kept under its block edit budget.
+pub const BATCHES_PER_SECOND: f32 = 4.0;
+
+/// The most blocks that go to the server in one message.
+pub const BATCH_SIZE: usize = bulkedit::MAX_BULK_BLOCKS;
+
+/// The preview only draws this many blocks, a fill can be far more than that.
+pub const PREVIEW_LIMIT: usize = 16384;
+
+/// What a right click does in build mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BuildTool {
+    /// One of the builds that come with the game, picked with the scroll wheel.
+    #[default]
+    Stamp,
+    /// Right click the two corners of a box.
+    Select,
+    /// The copied box, on top of the block looked at.
+    Paste,
+    /// The whole selection, with what's in hand. Empty handed clears it.
+    Fill,
+    /// Everything in the selection that's the same as the block looked at, with what's in hand.
+    Replace,
+    HollowBox,
+    /// The biggest ball that fits in the selection.
+    HollowSphere,
+    /// The walls of the biggest upright cylinder that fits in the selection.
+    HollowCylinder,
+}
+
+impl BuildTool {
+    pub const ALL: [BuildTool; 8] = [
+        BuildTool::Stamp,
+        BuildTool::Select,
+        BuildTool::Paste,
+        BuildTool::Fill,
+        BuildTool::Replace,
+        BuildTool::HollowBox,
+        BuildTool::HollowSphere,
+        BuildTool::HollowCylinder,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BuildTool::Stamp => "Stamp",
+            BuildTool::Select => "Select",
+            BuildTool::Paste => "Paste",
+            BuildTool::Fill => "Fill",
+            BuildTool::Replace => "Replace",
+            BuildTool::HollowBox => "Hollow box",
+            BuildTool::HollowSphere => "Hollow sphere",
+            BuildTool::HollowCylinder => "Hollow cylinder",
+        }
+    }
+
+    pub fn next(self) -> BuildTool {
+        let index = BuildTool::ALL.iter().position(|t| *t == self).unwrap_or(0);
+        BuildTool::ALL[(index + 1) % BuildTool::ALL.len()]
+    }
+
+    /// Whether it works on the selection rather than where the player is looking.
+    pub fn uses_selection(self) -> bool {
+        matches!(self, BuildTool::Fill | BuildTool::Replace | BuildTool::HollowBox | BuildTool::HollowSphere | BuildTool::HollowCylinder)
+    }
+}
+
+/// Two corners of a box, set one right click at a time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Selection {
+    pub first: Option<IVec3>,
+    pub second: Option<IVec3>,
+}
+
+impl Selection {
+    /// The first corner, then the second, then a new first one starting over.
+    pub fn mark(&mut self, spot: IVec3) {
+        if self.first.is_some() && self.second.is_none() {
+            self.second = Some(spot);
+        } else {
+            *self = Selection { first: Some(spot), second: None };
+        }
+    }
+
+    /// (low, high) corners, both inside the box. None until both are set.
+    pub fn bounds(&self) -> Option<(IVec3, IVec3)> {
+        let (a, b) = (self.first?, self.second?);
+        Some((IVec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)), IVec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))))
+    }
+
+    pub fn size(&self) -> Option<IVec3> {
+        self.bounds().map(|(low, high)| high - low + IVec3::new(1, 1, 1))
+    }
+
+    pub fn volume(&self) -> i64 {
+        self.size().map_or(0, |s| s.x as i64 * s.y as i64 * s.z as i64)
+    }
+}
+
+/// Edits a tool would make, relative to `origin`, and what placing them costs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BuildPreview {
+    pub origin: IVec3,
+    /// Only the blocks that change, with their flag bits.
+    pub edits: Vec<(IVec3, u32)>,
+    pub cost: Vec<(u32, u32)>,
+    /// Changes whenever `edits` do, so the preview knows when to rebuild its geometry.
+    pub generation: u64,
+}
+
+impl BuildPreview {
+    /// Where the edits go in the world.
+    pub fn placed(&self) -> Vec<(IVec3, u32)> {
+        self.edits.iter().map(|(spot, block)| (self.origin + *spot, *block)).collect()
+    }
+}
+
+/// One message's worth of queued edits, with the items they took and gave so it can all be undone if the server
+/// turns them down.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Batch {
+    pub edits: Vec<(IVec3, u32)>,
+    pub cost: Vec<(u32, u32)>,
+    /// Only undo and redo give anything back, a stamp doesn't for what it builds over.
+    pub refund: Vec<(u32, u32)>,
+    pub history: Option<HistoryEntry>,
+}
+
+/// Build mode's state: the tool in use, the selection, the clipboard and the edits still to send.
+#[derive(Default)]
+pub struct BuildTools {
+    pub tool: BuildTool,
+    pub selection: Selection,
+    pub clipboard: Option<Schematic>,
+    /// Quarter turns of the clipboard when pasting.
+    pub turns: u32,
+    pub mirrored: bool,
+    pub preview: Option<BuildPreview>,
+    /// Why there's no preview, e.g. nothing's been copied yet.
+    pub problem: Option<String>,
+    /// Said in the build mode overlay, what the last click did or why it didn't.
+    pub status: String,
+    generation: u64,
+    /// (target, nudge, held, stamp) the preview was made for, and whether anything else it depends on changed since.
+    inputs: Option<(IVec3, IVec3, u32, usize)>,
+    stale: bool,
+    /// (spot, block before, block after)
+    queue: VecDeque<(IVec3, u32, u32)>,
+    /// The undo history action the queued edits are part of.
+    history: Option<HistoryEntry>,
+    allowance: f32,
+}
+
+impl BuildTools {
+    pub fn next_tool(&mut self) {
+        self.tool = self.tool.next();
+        self.status.clear();
+        self.stale = true;
+    }
+
+    /// Sets a corner of the selection.
+    pub fn mark(&mut self, spot: IVec3) {
+        self.selection.mark(spot);
+        self.stale = true;
+    }
+
+    /// A quarter turn more for the paste.
+    pub fn rotate(&mut self) {
+        self.turns = (self.turns + 1) % 4;
+        self.stale = true;
+    }
+
+    pub fn mirror(&mut self) {
+        self.mirrored = !self.mirrored;
+        self.stale = true;
+    }
+
+    /// The world changed under the preview.
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    /// Copies the selection to the clipboard, flags and all.
+    pub fn copy(&mut self, block_at: impl Fn(IVec3) -> u32) -> Result<usize, String> {
+        let (low, high) = self.selection.bounds().ok_or("Select two corners first")?;
+        let schematic = Schematic::capture(low, high, block_at, |_| None).map_err(|e| e.to_string())?;
+        let count = schematic.blocks.len();
+        self.clipboard = Some(schematic);
+        self.turns = 0;
+        self.mirrored = false;
+        self.stale = true;
+        Ok(count)
+    }
+
+    /// The clipboard the way it'll be pasted, turned and flipped.
+    pub fn paste_schematic(&self) -> Option<Schematic> {
+        let clipboard = self.clipboard.as_ref()?;
+        let flipped = if self.mirrored { clipboard.mirrored() } else { clipboard.clone() };
+        Some(flipped.rotated(self.turns))
+    }
+
+    /// What the current tool would do, as an origin and the edits relative to it. `target` is the block
+    /// looked at, `nudge` how far the arrow keys moved a stamp or paste from it, `held` the block in hand
+    /// and `stamp` the picked stamp's blocks. Only spots that would change are in it.
+    pub fn edits(
+        &self,
+        target: IVec3,
+        nudge: IVec3,
+        held: u32,
+        stamp: &[(IVec3, u32)],
+        block_at: impl Fn(IVec3) -> u32,
+    ) -> Result<(IVec3, Vec<(IVec3, u32)>), String> {
+        let tool = self.tool;
+        let (origin, relative) = match tool {
+            BuildTool::Select => return Ok((target, Vec::new())),
+            BuildTool::Stamp => (target + nudge, stamp.to_vec()),
+            //Sat on top of the block looked at
+            BuildTool::Paste => {
+                let schematic = self.paste_schematic().ok_or("Copy a selection first")?;
+                (target + nudge + IVec3::new(0, 1, 0), schematic.blocks)
+            }
+            _ => {
+                let (low, high) = self.selection.bounds().ok_or("Select two corners first")?;
+                if self.selection.volume() > MAX_TOOL_VOLUME {
+                    return Err(format!("{} blocks is too many, {} at most", self.selection.volume(), MAX_TOOL_VOLUME));
+                }
+                if held != 0 && Blocks::is_non_placeable(held) {
+                    return Err(format!("{} can't be placed", Blocks::get_name(held)));
+                }
+                let spots = match tool {
+                    BuildTool::Fill => cuboid(low, high),
+                    BuildTool::Replace => {
+                        let from = block_at(target) & Blocks::block_id_bits();
+                        cuboid(low, high).into_iter().filter(|spot| block_at(*spot) & Blocks::block_id_bits() == from).collect()
+                    }
+                    BuildTool::HollowBox => hollow_box(low, high),
+                    BuildTool::HollowSphere => hollow_sphere(low, high),
+                    _ => hollow_cylinder(low, high),
+                };
+                (low, spots.into_iter().map(|spot| (spot - low, held)).collect())
+            }
+        };
+        let edits = relative.into_iter().filter(|(spot, block)| block_at(origin + *spot) != *block).collect();
+        Ok((origin, edits))
+    }
+
+    /// Works the preview out again if anything it depends on changed. `stamp_index` says which stamp
+    /// `stamp` gives the blocks of, only called when it's needed.
+    pub fn refresh(
+        &mut self,
+        target: IVec3,
+        nudge: IVec3,
+        held: u32,
+        stamp_index: usize,
+        stamp: impl FnOnce() -> Vec<(IVec3, u32)>,
+        block_at: impl Fn(IVec3) -> u32,
+    ) {
+        let inputs = Some((target, nudge, held, stamp_index));
+        if !self.stale && self.inputs == inputs {
+            return;
+        }
+        self.stale = false;
+        self.inputs = inputs;
+        let stamp = if self.tool == BuildTool::Stamp { stamp() } else { Vec::new() };
+        match self.edits(target, nudge, held, &stamp, block_at) {
+            Ok((origin, edits)) => {
+                self.problem = None;
+                self.set_preview(origin, edits);
+            }
+            Err(problem) => {
+                self.problem = Some(problem);
+                self.preview = None;
+            }
+        }
+    }
+
+    /// Keeps `edits` as the preview, a new generation if they changed.
+    pub fn set_preview(&mut self, origin: IVec3, edits: Vec<(IVec3, u32)>) {
+        if let Some(preview) = &mut self.preview {
+            if preview.origin == origin && preview.edits == edits {
+                return;
+            }
+        }
+        self.generation += 1;
+        let cost = material_cost(&edits);
+        self.preview = Some(BuildPreview { origin, edits, cost, generation: self.generation });
+    }
+
+    /// Edits to send once there's room. Only once the last lot is all sent, since they're one `history` action.
+    pub fn queue(&mut self, edits: Vec<(IVec3, u32, u32)>, history: Option<HistoryEntry>) {
+        self.queue.extend(edits);
+        self.history = history;
+    }
+
+    /// Blocks still to send.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// What to send this frame, at most `BATCHES_PER_SECOND` messages on average, each up to `BATCH_SIZE`
+    /// blocks that fit one `BulkBlockSet`.
+    pub fn next_batches(&mut self, delta_time: f32) -> Vec<Batch> {
+        if self.queue.is_empty() {
+            self.allowance = 0.0;
+            return Vec::new();
+        }
+        self.allowance = (self.allowance + delta_time * BATCHES_PER_SECOND).min(BATCHES_PER_SECOND);
+        let mut batches = Vec::new();
+        while self.allowance >= 1.0 {
+            let Some(first) = self.queue.pop_front() else {
+                break;
+            };
+            let mut blocks = vec![first];
+            while blocks.len() < BATCH_SIZE && self.queue.front().is_some_and(|(spot, _, _)| bulkedit::fits(first.0, *spot)) {
+                blocks.push(self.queue.pop_front().unwrap());
+            }
+            self.allowance -= 1.0;
+            let action = EditAction { id: 0, blocks };
+            let edits = action.edits();
+            let (cost, refund) = match self.history {
+                Some(HistoryEntry::Undid(_) | HistoryEntry::Redid(_)) => action.exchange(),
+                _ => (material_cost(&edits), Vec::new()),
+            };
+            batches.push(Batch { edits, cost, refund, history: self.history });
+        }
+        batches
+    }
+}
+
+impl BuildTools {
+    /// What the build mode overlay says, a line each. True for lines to show in red.
+    pub fn overlay_lines(&self, inventory: &[(u32, u32)]) -> Vec<(String, bool)> {
+        let mut lines = vec![(format!("Tool: {}", self.tool.name()), false)];
+        match self.selection.size() {
+            Some(size) => lines.push((format!("Selection: {} x {} x {}", size.x, size.y, size.z), false)),
+            None if self.selection.first.is_some() => lines.push((String::from("Selection: one corner"), false)),
+            None => {}
+        }
+        if self.tool == BuildTool::Paste {
+            if let Some(clipboard) = &self.clipboard {
+                let mirrored = if self.mirrored { ", mirrored" } else { "" };
+                lines.push((format!("Clipboard: {} blocks, turned {}{}", clipboard.blocks.len(), self.turns * 90, mirrored), false));
+            }
+        }
+        if let Some(preview) = &self.preview {
+            if !preview.cost.is_empty() {
+                lines.push((String::from("Uses:"), false));
+            }
+            for (id, need) in &preview.cost {
+                let have = count_of(inventory, *id);
+                lines.push((format!("  {} {} (have {})", need, Blocks::get_name(*id), have), have < *need));
+            }
+        }
+        if let Some(problem) = &self.problem {
+            lines.push((problem.clone(), false));
+        }
+        if self.pending() > 0 {
+            lines.push((format!("Sending, {} blocks to go", self.pending()), false));
+        }
+        if !self.status.is_empty() {
+            lines.push((self.status.clone(), false));
+        }
+        lines
+    }
+}
+
+/// Every spot from `low` to `high`, both included.
+pub fn cuboid(low: IVec3, high: IVec3) -> Vec<IVec3> {
+    let mut spots = Vec::new();
+    for y in low.y..=high.y {
+        for z in low.z..=high.z {
+            for x in low.x..=high.x {
+                spots.push(IVec3::new(x, y, z));
+            }
+        }
+    }
+    spots
+}
+
+/// The six faces of the box.
+pub fn hollow_box(low: IVec3, high: IVec3) -> Vec<IVec3> {
+    cuboid(low, high)
+        .into_iter()
+        .filter(|s| s.x == low.x || s.x == high.x || s.y == low.y || s.y == high.y || s.z == low.z || s.z == high.z)
+        .collect()
+}
+
+/// The shell of the ellipsoid that fits in the box, a sphere when it's a cube.
+pub fn hollow_sphere(low: IVec3, high: IVec3) -> Vec<IVec3> {
+    let inside = |s: IVec3| in_ellipse(low, high, s, true);
+    shell(low, high, inside, &[IVec3::new(1, 0, 0), IVec3::new(0, 1, 0), IVec3::new(0, 0, 1)])
+}
+
+/// The walls of the upright elliptic cylinder that fits in the box, open at the top and bottom.
+pub fn hollow_cylinder(low: IVec3, high: IVec3) -> Vec<IVec3> {
+    let inside = |s: IVec3| in_ellipse(low, high, s, false);
+    shell(low, high, inside, &[IVec3::new(1, 0, 0), IVec3::new(0, 0, 1)])
+}
+
+/// Whether the middle of `spot` is inside the ellipse (or with `with_y`, ellipsoid) that fits the box.
+fn in_ellipse(low: IVec3, high: IVec3, spot: IVec3, with_y: bool) -> bool {
+    let axis = |l: i32, h: i32, v: i32| {
+        let radius = (h - l + 1) as f32 / 2.0;
+        let d = (v as f32 + 0.5 - (l as f32 + radius)) / radius;
+        d * d
+    };
+    let mut sum = axis(low.x, high.x, spot.x) + axis(low.z, high.z, spot.z);
+    if with_y {
+        sum += axis(low.y, high.y, spot.y);
+    }
+    sum <= 1.0
+}
+
+/// The spots inside the shape with a neighbor along `axes` that isn't.
+fn shell(low: IVec3, high: IVec3, inside: impl Fn(IVec3) -> bool, axes: &[IVec3]) -> Vec<IVec3> {
+    let within = |s: IVec3| (low.x..=high.x).contains(&s.x) && (low.y..=high.y).contains(&s.y) && (low.z..=high.z).contains(&s.z);
+    cuboid(low, high)
+        .into_iter()
+        .filter(|s| inside(*s))
+        .filter(|s| {
+            axes.iter().any(|a| {
+                let (up, down) = (*s + *a, *s - *a);
+                !within(up) || !inside(up) || !within(down) || !inside(down)
+            })
+        })
+        .collect()
+}
+
+/// The items placing `edits` uses up as (id, count), by id. A door is one item for both halves.
+pub fn material_cost(edits: &[(IVec3, u32)]) -> Vec<(u32, u32)> {
+    let mut cost = BTreeMap::new();
+    for (_, block) in edits {
+        let id = block & Blocks::block_id_bits();
+        if id != 0 && block & DOORTOP_BITS == 0 {
+            *cost.entry(id).or_insert(0) += 1;
+        }
+    }
+    cost.into_iter().collect()
+}
+
+/// How many of `id` are in the inventory, across every stack.
+pub fn count_of(inventory: &[(u32, u32)], id: u32) -> u32 {
+    inventory.iter().filter(|s| s.0 == id).map(|s| s.1).sum()
+}
+
+/// Each item `cost` needs more of than there is, as (id, needed, have).
+pub fn shortfall(cost: &[(u32, u32)], inventory: &[(u32, u32)]) -> Vec<(u32, u32, u32)> {
+    cost.iter().map(|(id, need)| (*id, *need, count_of(inventory, *id))).filter(|(_, need, have)| have < need).collect()
+}
+
+/// Takes `cost` out of the inventory, from the last stacks first so the ones in hand go last.
+/// Returns the slots that changed, or None and leaves it alone if there isn't enough.
+pub fn pay(inventory: &mut [(u32, u32)], cost: &[(u32, u32)]) -> Option<Vec<usize>> {
+    if !shortfall(cost, inventory).is_empty() {
+        return None;
+    }
+    let mut changed = Vec::new();
+    for (id, need) in cost {
+        let mut left = *need;
+        for (i, slot) in inventory.iter_mut().enumerate().rev() {
+            if left == 0 {
+                break;
+            }
+            if slot.0 == *id && slot.1 > 0 {
+                let take = left.min(slot.1);
+                slot.1 -= take;
+                left -= take;
+                if slot.1 == 0 {
+                    *slot = (0, 0);
+                }
+                if !changed.contains(&i) {
+                    changed.push(i);
+                }
+            }
+        }
+    }
+    changed.sort();
+    Some(changed)
+}
+
+/// Puts `items` in the inventory, onto a stack of the same thing if there is one, otherwise the first empty
+/// slot. Returns the slots that changed, or None and leaves it alone if there isn't room.
+pub fn give(inventory: &mut [(u32, u32)], items: &[(u32, u32)]) -> Option<Vec<usize>> {
+    let mut after = inventory.to_vec();
+    let mut changed = Vec::new();
+    for (id, count) in items {
+        let slot = after.iter().position(|s| s.0 == *id && s.1 > 0).or_else(|| after.iter().position(|s| s.1 == 0))?;
+        after[slot] = (*id, after[slot].1 + count);
+        if !changed.contains(&slot) {
+            changed.push(slot);
+        }
+    }
+    inventory.copy_from_slice(&after);
+    changed.sort();
+    Some(changed)
+}
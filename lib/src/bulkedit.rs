@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+
+use glam::Vec3;
+
+use crate::chunk::ChunkSystem;
+use crate::cube::Cube;
+use crate::netstream::PayloadReader;
+use crate::server_types::{Message, MessageType};
+use crate::vec::{IVec2, IVec3};
+
+/// Most blocks one `BulkBlockSet` carries, bigger edits go in several.
+pub const MAX_BULK_BLOCKS: usize = 4096;
+
+/// Each block packed: its offset from the anchor as three i16s, then the block.
+const ENTRY_SIZE: usize = 3 * 2 + 4;
+
+/// Longest payload anyone should allocate for, the most blocks compressed as badly as lz4 can.
+pub fn max_payload_len() -> usize {
+    4 + lz4_flex::block::get_maximum_output_size(MAX_BULK_BLOCKS * ENTRY_SIZE)
+}
+
+/// The blocks as offsets from `anchor`, lz4 compressed. None if there are too many or one is too far from it.
+pub fn pack(anchor: IVec3, blocks: &[(IVec3, u32)]) -> Option<Vec<u8>> {
+    if blocks.is_empty() || blocks.len() > MAX_BULK_BLOCKS {
+        return None;
+    }
+    let mut raw = Vec::with_capacity(blocks.len() * ENTRY_SIZE);
+    for (spot, block) in blocks {
+        let offset = spot.checked_sub(anchor)?;
+        for axis in [offset.x, offset.y, offset.z] {
+            raw.extend_from_slice(&i16::try_from(axis).ok()?.to_le_bytes());
+        }
+        raw.extend_from_slice(&block.to_le_bytes());
+    }
+    Some(lz4_flex::compress_prepend_size(&raw))
+}
+
+/// Reads back what `pack` made. None for anything it couldn't have made.
+pub fn unpack(anchor: IVec3, payload: &[u8]) -> Option<Vec<(IVec3, u32)>> {
+    //The size up front says how much to allocate, don't believe one bigger than a full message
+    let size = u32::from_le_bytes(payload.get(..4)?.try_into().ok()?) as usize;
+    if size == 0 || size > MAX_BULK_BLOCKS * ENTRY_SIZE || !size.is_multiple_of(ENTRY_SIZE) {
+        return None;
+    }
+    let raw = lz4_flex::decompress_size_prepended(payload).ok()?;
+    if raw.len() != size {
+        return None;
+    }
+    //An anchor near the edge of the world can put the blocks past it
+    raw.chunks_exact(ENTRY_SIZE)
+        .map(|entry| {
+            let axis = |i: usize| i16::from_le_bytes([entry[i], entry[i + 1]]) as i32;
+            let block = u32::from_le_bytes([entry[6], entry[7], entry[8], entry[9]]);
+            Some((anchor.checked_add(IVec3::new(axis(0), axis(2), axis(4)))?, block))
+        })
+        .collect()
+}
+
+/// Whether `spot` is close enough to `anchor` to be packed relative to it.
+pub fn fits(anchor: IVec3, spot: IVec3) -> bool {
+    spot.checked_sub(anchor).is_some_and(|offset| [offset.x, offset.y, offset.z].iter().all(|a| i16::try_from(*a).is_ok()))
+}
+
+/// `blocks` cut up in order into runs that each go in one `BulkBlockSet`, anchored on their first block.
+pub fn batches(blocks: &[(IVec3, u32)]) -> Vec<&[(IVec3, u32)]> {
+    let mut batches = Vec::new();
+    let mut rest = blocks;
+    while let Some((anchor, _)) = rest.first() {
+        let len = rest.iter().take(MAX_BULK_BLOCKS).take_while(|(spot, _)| fits(*anchor, *spot)).count();
+        let (batch, after) = rest.split_at(len);
+        batches.push(batch);
+        rest = after;
+    }
+    batches
+}
+
+/// A `BulkBlockSet` header followed by its packed blocks. None if they don't fit one message, see `fits`.
+pub fn encode(header: &Message, anchor: IVec3, blocks: &[(IVec3, u32)]) -> Option<Vec<u8>> {
+    let payload = pack(anchor, blocks)?;
+    let mut header = header.clone();
+    header.message_type = MessageType::BulkBlockSet;
+    header.otherpos = anchor;
+    header.info = payload.len() as u32;
+    let mut bytes = bincode::serialize(&header).unwrap();
+    bytes.extend_from_slice(&payload);
+    Some(bytes)
+}
+
+/// A header to go in front of blocks sent with sequence id `seq`.
+pub fn header(seq: u32) -> Message {
+    let mut header = Message::new(MessageType::BulkBlockSet, Vec3::ZERO, 0.0, 0);
+    header.seq = seq;
+    header
+}
+
+/// For the payload after a `BulkBlockSet` header that said it was `len` bytes. None if it's longer than any we'd send.
+pub fn payload_reader(len: u32) -> Option<PayloadReader> {
+    (len as usize <= max_payload_len()).then(|| PayloadReader::new(len as usize))
+}
+
+/// Every chunk whose mesh changes when `blocks` do, their own and any they border.
+pub fn implicated_chunks(blocks: &[(IVec3, u32)]) -> HashSet<IVec2> {
+    let mut chunks = HashSet::new();
+    for (spot, _) in blocks {
+        chunks.insert(ChunkSystem::spot_to_chunk_pos(spot));
+        for neighbor in Cube::get_neighbors() {
+            chunks.insert(ChunkSystem::spot_to_chunk_pos(&(*spot + *neighbor)));
+        }
+    }
+    chunks
+}
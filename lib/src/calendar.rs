@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Weather types as `WEATHERTYPE` has them.
+pub const CLEAR: f32 = 0.0;
+pub const SNOW: f32 = 1.0;
+pub const RAIN: f32 = 2.0;
+
+/// How quickly a client closes the gap to the server's clock, fraction per second.
+pub const CATCHUP_RATE: f32 = 2.0;
+
+/// Further off than this fraction of a day and a client just jumps, e.g. when it first joins.
+pub const SNAP_FRACTION: f32 = 0.25;
+
+/// Relative chances of each weather, they don't need to add up to anything.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct WeatherOdds {
+    pub clear: f32,
+    pub snow: f32,
+    pub rain: f32,
+}
+
+impl Default for WeatherOdds {
+    fn default() -> Self {
+        WeatherOdds { clear: 1.0, snow: 1.0, rain: 1.0 }
+    }
+}
+
+impl WeatherOdds {
+    /// The weather a `roll` in 0..=1 lands on.
+    pub fn pick(&self, roll: f32) -> f32 {
+        let total = self.clear.max(0.0) + self.snow.max(0.0) + self.rain.max(0.0);
+        if total <= 0.0 {
+            return CLEAR;
+        }
+        let at = roll.clamp(0.0, 1.0 - f32::EPSILON) * total;
+        if at < self.clear.max(0.0) {
+            CLEAR
+        } else if at < self.clear.max(0.0) + self.snow.max(0.0) {
+            SNOW
+        } else {
+            RAIN
+        }
+    }
+}
+
+/// The server's clock and weather rules, kept in `calendar.json` in the world folder so admins can edit them.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Calendar {
+    /// Seconds in a full day.
+    pub day_length: f32,
+    /// Time of day the server starts at, in seconds into the day.
+    pub start_time: f32,
+    /// Seconds between weather changes.
+    pub weather_interval: f32,
+    pub weather: WeatherOdds,
+    /// Overrides `weather` on a planet type, keyed by its number.
+    pub planet_weather: HashMap<u8, WeatherOdds>,
+    /// Stop the clock at `start_time`.
+    pub lock_time: bool,
+    /// Keep this weather forever instead of rolling.
+    pub lock_weather: Option<f32>,
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        Calendar {
+            day_length: 900.0,
+            start_time: 250.0,
+            weather_interval: 120.0,
+            weather: WeatherOdds::default(),
+            planet_weather: HashMap::new(),
+            lock_time: false,
+            lock_weather: None,
+        }
+    }
+}
+
+impl Calendar {
+    /// Reads `calendar.json` out of `world_dir`, writing the defaults there first if there isn't one.
+    pub fn load_or_create(world_dir: &Path) -> Calendar {
+        let path = world_dir.join("calendar.json");
+        match fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(calendar) => calendar,
+                Err(e) => {
+                    println!("Couldn't read {}, using the default calendar: {}", path.display(), e);
+                    Calendar::default()
+                }
+            },
+            Err(_) => {
+                let calendar = Calendar::default();
+                calendar.save(world_dir);
+                calendar
+            }
+        }
+    }
+
+    pub fn save(&self, world_dir: &Path) {
+        match File::create(world_dir.join("calendar.json")) {
+            Ok(mut file) => {
+                let _ = file.write_all(serde_json::to_string_pretty(self).unwrap().as_bytes());
+            }
+            Err(e) => println!("Couldn't save the calendar: {}", e),
+        }
+    }
+
+    pub fn odds(&self, planet_type: u8) -> WeatherOdds {
+        self.planet_weather.get(&planet_type).copied().unwrap_or(self.weather)
+    }
+}
+
+/// Where the sky is at right now.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sky {
+    /// Seconds into the day.
+    pub time: f32,
+    pub weather: f32,
+    /// Seconds since the weather last changed.
+    pub weather_timer: f32,
+}
+
+impl Sky {
+    pub fn new(calendar: &Calendar) -> Sky {
+        Sky {
+            time: calendar.start_time % calendar.day_length.max(1.0),
+            weather: calendar.lock_weather.unwrap_or(CLEAR),
+            weather_timer: 0.0,
+        }
+    }
+
+    /// Moves the clock on and rolls new weather when it's due. `roll` is only used if it is, any number in 0..1.
+    pub fn advance(&mut self, calendar: &Calendar, planet_type: u8, delta_time: f32, roll: impl FnOnce() -> f32) {
+        if !calendar.lock_time {
+            self.time = (self.time + delta_time) % calendar.day_length.max(1.0);
+        }
+
+        if let Some(weather) = calendar.lock_weather {
+            self.weather = weather;
+            return;
+        }
+        self.weather_timer += delta_time;
+        if self.weather_timer >= calendar.weather_interval {
+            self.weather = calendar.odds(planet_type).pick(roll());
+            self.weather_timer = 0.0;
+        }
+    }
+}
+
+/// How far `local` has to go to match `server`, the short way around the day. Positive is forward.
+pub fn drift(local: f32, server: f32, day_length: f32) -> f32 {
+    let mut d = (server - local) % day_length;
+    if d > day_length / 2.0 {
+        d -= day_length;
+    } else if d < -day_length / 2.0 {
+        d += day_length;
+    }
+    d
+}
+
+/// The part of `drift` a client makes up this frame, all of it if it's too far off to bother easing.
+pub fn catch_up(drift: f32, day_length: f32, delta_time: f32) -> f32 {
+    if drift.abs() > day_length * SNAP_FRACTION {
+        drift
+    } else {
+        drift * (delta_time * CATCHUP_RATE).min(1.0)
+    }
+}
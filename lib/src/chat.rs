@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use glam::Vec3;
+use uuid::Uuid;
+
+use crate::netstream::PayloadReader;
+use crate::server_types::{Message, MessageType, MAX_CHAT_LEN};
+
+/// How many lines the client keeps around.
+pub const CHAT_HISTORY: usize = 50;
+
+/// How long a line stays on screen when the chat box isn't open.
+pub const CHAT_FADE: Duration = Duration::from_secs(10);
+
+/// A `Chat` header followed by its text, cut down to `MAX_CHAT_LEN` bytes. No sender means it's from the server.
+pub fn encode(from: Option<Uuid>, text: &str) -> Vec<u8> {
+    let mut end = text.len().min(MAX_CHAT_LEN as usize);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let text = &text[..end];
+
+    let mut header = Message::new(MessageType::Chat, Vec3::ZERO, 0.0, text.len() as u32);
+    header.goose = from.map(|id| id.as_u64_pair()).unwrap_or((0, 0));
+
+    let mut bytes = bincode::serialize(&header).unwrap();
+    bytes.extend_from_slice(text.as_bytes());
+    bytes
+}
+
+/// For the text after a `Chat` header that said it was `len` bytes. None if that's longer than any line can be.
+pub fn text_reader(len: u32) -> Option<PayloadReader> {
+    (len <= MAX_CHAT_LEN).then(|| PayloadReader::new(len as usize))
+}
+
+pub struct ChatLine {
+    /// None for the server.
+    pub from: Option<Uuid>,
+    pub text: String,
+    pub at: Instant,
+}
+
+impl ChatLine {
+    pub fn label(&self) -> String {
+        match self.from {
+            //Players don't have names yet, the start of their id will do
+            Some(id) => format!("<{}> {}", &id.to_string()[..8], self.text),
+            None => format!("[Server] {}", self.text),
+        }
+    }
+}
+
+/// What the client has heard in chat, oldest first.
+#[derive(Default)]
+pub struct ChatLog {
+    pub lines: VecDeque<ChatLine>,
+}
+
+impl ChatLog {
+    pub fn push(&mut self, from: Option<Uuid>, text: String) {
+        if self.lines.len() >= CHAT_HISTORY {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(ChatLine { from, text, at: Instant::now() });
+    }
+
+    /// Lines still showing with the chat box closed.
+    pub fn recent(&self) -> impl Iterator<Item = &ChatLine> {
+        self.lines.iter().filter(|l| l.at.elapsed() < CHAT_FADE)
+    }
+}
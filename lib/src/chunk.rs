@@ -5,9 +5,10 @@ use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::path::Path;
+use std::path::PathBuf;
 
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use dashmap::DashMap;
 
@@ -23,7 +24,6 @@ use once_cell::sync::Lazy;
 use rand::rngs::StdRng;
 use rand::Rng;
 use rand::SeedableRng;
-use rusqlite::params;
 use rusqlite::Connection;
 
 use std::sync::{Arc};
@@ -35,6 +35,10 @@ use noise::{NoiseFn, Perlin};
 
 use crate::camera::Camera;
 use crate::chunkregistry::ChunkMemory;
+use crate::chunkstore::ChunkStore;
+use crate::terrain;
+use crate::worldgen;
+use crate::worldstorage::{StorageResult, WorldStorageError};
 use crate::chunkregistry::ChunkRegistry;
 use crate::cube::Cube;
 use crate::cube::CubeSide;
@@ -68,7 +72,7 @@ use crate::voxmodel::JVoxModel;
 
 use std::io::Write;
 
-pub type LightColor = glam::U16Vec3;
+pub use crate::blockinfo::LightColor;
 
 
 
@@ -227,8 +231,7 @@ pub struct ChunkFacade {
     pub pos: vec::IVec2,
 }
 
-pub static ChW: i32 = 15;
-pub static ChH: i32 = 255;
+pub use crate::terrain::{ChH, ChW};
 
 pub struct ReadyMesh {
     pub geo_index: usize,
@@ -305,95 +308,222 @@ pub struct ChunkSystem {
     pub lightmap: Arc<Mutex<HashMap<vec::IVec3, LightSegment>>>,
 
     pub generated_chunks: Arc<DashMap<vec::IVec2, bool>>,
+
+    pub db_path: PathBuf,
+
+    /// Where `userdatamap` loads chunks from, set up by `load_world_from_file`. Until then every edit just lives in memory.
+    pub edit_store: Option<ChunkStore>,
+    /// Chunks whose edits are in `userdatamap`, true if they've changed since.
+    pub edits_loaded: Arc<DashMap<vec::IVec2, bool>>,
+    /// Which spots in `userdatamap` each chunk has, so unloading one doesn't go through everybody's.
+    pub edit_spots: Arc<DashMap<vec::IVec2, HashSet<vec::IVec3>>>,
+    /// Chunks whose saved edits wouldn't read and when that was. They stay not loaded so they're never saved over.
+    pub edits_unreadable: Arc<DashMap<vec::IVec2, Instant>>,
+    /// Held while a chunk's edits go to or from the store, instead of a map entry, so nobody else waits on the disk.
+    edit_io: Mutex<()>,
 }
 
+/// How long before we try reading a chunk's edits again after they wouldn't.
+pub const EDIT_RETRY: Duration = Duration::from_secs(10);
+
 impl ChunkSystem {
-    pub fn write_new_udm_entry(&self, spot: vec::IVec3, block: u32) {
+    pub fn write_new_udm_entry(&self, spot: vec::IVec3, block: u32) -> StorageResult<()> {
         let seed = unsafe {CURRSEED.load(std::sync::atomic::Ordering::Relaxed)};
-        let table_name = format!("userdatamap_{}", seed);
+        ChunkStore::new(&self.db_path, seed).set_block(spot, block)?;
+        Ok(())
+    }
+
+    /// Reads a chunk's saved edits into `userdatamap` the first time anything asks about it. False if they
+    /// wouldn't read, then the chunk isn't loaded and we try again after `EDIT_RETRY`.
+    pub fn load_chunk_edits(&self, cpos: vec::IVec2) -> bool {
+        let Some(store) = &self.edit_store else {
+            return true;
+        };
+        if self.edits_loaded.contains_key(&cpos) {
+            return true;
+        }
+        if self.edits_unreadable.get(&cpos).is_some_and(|at| at.elapsed() < EDIT_RETRY) {
+            return false;
+        }
+
+        let _io = self.edit_io.lock();
+        //Someone else loaded it while we waited
+        if self.edits_loaded.contains_key(&cpos) {
+            return true;
+        }
+        match store.load_chunk(cpos) {
+            Ok(edits) => {
+                let mut spots = HashSet::new();
+                for (spot, block) in edits {
+                    //Anything set while it wouldn't load is newer
+                    self.userdatamap.entry(spot).or_insert(block);
+                    spots.insert(spot);
+                }
+                let mut indexed = self.edit_spots.entry(cpos).or_default();
+                let dirty = !indexed.is_empty();
+                indexed.extend(spots);
+                drop(indexed);
+                self.edits_unreadable.remove(&cpos);
+                self.edits_loaded.insert(cpos, dirty);
+                true
+            }
+            Err(e) => {
+                info!("Couldn't load the edits in chunk {} {}, leaving them be: {}", cpos.x, cpos.y, e);
+                self.edits_unreadable.insert(cpos, Instant::now());
+                false
+            }
+        }
+    }
+
+    /// Drops a chunk's edits from memory, saving them first if they changed. If that fails they stay loaded
+    /// and changed, so the next unload or save tries again.
+    pub fn unload_chunk_edits(&self, cpos: vec::IVec2) -> StorageResult<()> {
+        let Some(store) = &self.edit_store else {
+            return Ok(());
+        };
+        let _io = self.edit_io.lock();
+        //Out of edits_loaded first, so anyone editing it from here on waits for it to load back in
+        let Some((_, dirty)) = self.edits_loaded.remove(&cpos) else {
+            return Ok(());
+        };
+        let spots = self.edit_spots.remove(&cpos).map(|(_, spots)| spots).unwrap_or_default();
+        if dirty {
+            let edits: Vec<(vec::IVec3, u32)> = spots.iter().filter_map(|spot| self.userdatamap.get(spot).map(|b| (*spot, *b))).collect();
+            if let Err(e) = store.save_chunk(cpos, &edits) {
+                self.edit_spots.insert(cpos, spots);
+                self.edits_loaded.insert(cpos, true);
+                return Err(e);
+            }
+        }
+        for spot in &spots {
+            self.userdatamap.remove(spot);
+        }
+        Ok(())
+    }
 
-        let conn = Connection::open("db").unwrap();
+    /// Unloads every loaded chunk `far` says yes to.
+    pub fn unload_chunk_edits_where(&self, far: impl Fn(vec::IVec2) -> bool) {
+        let far: Vec<vec::IVec2> = self.edits_loaded.iter().map(|e| *e.key()).filter(|c| far(*c)).collect();
+        for cpos in far {
+            if let Err(e) = self.unload_chunk_edits(cpos) {
+                info!("Couldn't save the edits in chunk {} {}, keeping them loaded: {}", cpos.x, cpos.y, e);
+            }
+        }
+    }
 
-        // Insert userdatamap entries
-        let mut stmt = conn
-            .prepare(&format!(
-                "INSERT OR REPLACE INTO {} (x, y, z, value) VALUES (?, ?, ?, ?)",
-                table_name
-            ))
-            .unwrap();
+    /// Puts a player's edit in `userdatamap` and marks its chunk changed, loading it first if it isn't.
+    fn set_user_block(&self, spot: vec::IVec3, block: u32) {
+        let cpos = Self::spot_to_chunk_pos(&spot);
+        while self.edit_store.is_some() {
+            if !self.load_chunk_edits(cpos) {
+                //We can't tell what's saved there, so this one stays in memory until the chunk reads again
+                self.edit_spots.entry(cpos).or_default().insert(spot);
+                self.userdatamap.insert(spot, block);
+                if let Some(mut dirty) = self.edits_loaded.get_mut(&cpos) {
+                    *dirty = true;
+                }
+                return;
+            }
+            //It can be unloaded again before we get here, then it's loaded again
+            if let Some(mut dirty) = self.edits_loaded.get_mut(&cpos) {
+                *dirty = true;
+                self.edit_spots.entry(cpos).or_default().insert(spot);
+                self.userdatamap.insert(spot, block);
+                return;
+            }
+        }
+        self.userdatamap.insert(spot, block);
+    }
 
-        stmt.execute(params![spot.x, spot.y, spot.z, block])
-            .unwrap();
+    pub fn save_current_world_to_file(&self, path: String) -> StorageResult<()> {
+        self.save_current_world_to(&self.db_path, path)
     }
 
-    pub fn save_current_world_to_file(&self, path: String) {
+    /// Writes the world out in the layout a server expects in `world_dir` (`db` and `world/<seed>`).
+    pub fn save_current_world_to_dir(&self, world_dir: &Path) -> StorageResult<()> {
         let seed = unsafe {CURRSEED.load(std::sync::atomic::Ordering::Relaxed)};
-        let table_name = format!("userdatamap_{}", seed);
-
-        let conn = Connection::open("db").unwrap();
-
-        conn.execute(
-            &format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                    x INTEGER,
-                    y INTEGER,
-                    z INTEGER,
-                    value INTEGER,
-                    PRIMARY KEY (x, y, z)
-                )",
-                table_name
-            ),
-            (),
-        )
-        .unwrap();
-
-        // Insert userdatamap entries
-        let mut stmt = conn
-            .prepare(&format!(
-                "INSERT OR REPLACE INTO {} (x, y, z, value) VALUES (?, ?, ?, ?)",
-                table_name
-            ))
-            .unwrap();
+        let seeddir = world_dir.join(format!("world/{}", seed));
+        self.save_current_world_to(&world_dir.join("db"), seeddir.to_string_lossy().to_string())
+    }
+
+    fn save_current_world_to(&self, db_path: &Path, path: String) -> StorageResult<()> {
+        let seed = unsafe {CURRSEED.load(std::sync::atomic::Ordering::Relaxed)};
+        let target = ChunkStore::new(db_path, seed);
+
+        //Somewhere new gets the chunks that aren't loaded too
+        if let Some(store) = &self.edit_store {
+            if store.db_path != db_path {
+                store.copy_to(db_path)?;
+            }
+        }
+
+        let mut bychunk: HashMap<vec::IVec2, Vec<(vec::IVec3, u32)>> = HashMap::new();
+        for entry in self.edits_loaded.iter() {
+            bychunk.insert(*entry.key(), Vec::new());
+        }
         for entry in self.userdatamap.iter() {
-            stmt.execute(params![
-                entry.key().x,
-                entry.key().y,
-                entry.key().z,
-                *entry.value()
-            ])
-            .unwrap();
+            bychunk.entry(Self::spot_to_chunk_pos(entry.key())).or_default().push((*entry.key(), *entry.value()));
+        }
+
+        for (cpos, mut edits) in bychunk {
+            //A loaded chunk is all in memory, otherwise whatever's in memory goes on top of what was saved
+            if !self.edits_loaded.contains_key(&cpos) {
+                let saved = match target.load_chunk(cpos) {
+                    Ok(saved) => saved,
+                    //Only the ones that wouldn't load have edits in memory without being loaded, leave what's saved alone
+                    Err(e) => {
+                        info!("Not saving the edits in chunk {} {} over what's there: {}", cpos.x, cpos.y, e);
+                        continue;
+                    }
+                };
+                for (spot, block) in saved {
+                    if !edits.iter().any(|(s, _)| *s == spot) {
+                        edits.push((spot, block));
+                    }
+                }
+            }
+            target.save_chunk(cpos, &edits)?;
+        }
+        for mut entry in self.edits_loaded.iter_mut() {
+            *entry.value_mut() = false;
         }
 
-        fs::create_dir_all(&path).unwrap();
+        fs::create_dir_all(&path).map_err(|e| WorldStorageError::io(&path, e))?;
 
         // let mut file = File::create(path.clone() + "/udm").unwrap();
         // for entry in self.userdatamap.iter() {
         //     writeln!(file, "{} {}", entry.key(), entry.value()).unwrap();
         // }
 
-        let mut file = File::create(path.clone() + "/seed").unwrap();
-        writeln!(file, "{}", unsafe {CURRSEED.load(std::sync::atomic::Ordering::Relaxed)}).unwrap();
+        let seedpath = path.clone() + "/seed";
+        File::create(&seedpath)
+            .and_then(|mut file| writeln!(file, "{}", unsafe {CURRSEED.load(std::sync::atomic::Ordering::Relaxed)}))
+            .map_err(|e| WorldStorageError::io(&seedpath, e))?;
 
-        let mut file = File::create(path.clone() + "/pt").unwrap();
-        writeln!(file, "{}", self.planet_type).unwrap();
+        let ptpath = path.clone() + "/pt";
+        File::create(&ptpath)
+            .and_then(|mut file| writeln!(file, "{}", self.planet_type))
+            .map_err(|e| WorldStorageError::io(&ptpath, e))?;
+        Ok(())
     }
 
 
 
 
-    pub fn load_world_from_file(&mut self, path: String) {
+    pub fn load_world_from_file(&mut self, path: String) -> StorageResult<()> {
         self.userdatamap.clear();
         self.nonuserdatamap.clear();
 
-        match File::open(format!("{}/udm", path.clone())) {
+        //udm isn't written anymore, pt is what tells us this world was saved before
+        match File::open(format!("{}/pt", path.clone())) {
             Ok(_) => {}
             Err(_) => {
-                fs::create_dir_all(&path.clone()).unwrap();
-                self.save_current_world_to_file(path.clone());
+                fs::create_dir_all(&path.clone()).map_err(|e| WorldStorageError::io(&path, e))?;
+                self.save_current_world_to_file(path.clone())?;
             }
         }
 
-        let conn = Connection::open("db").unwrap();
+        let conn = Connection::open(&self.db_path).map_err(|e| WorldStorageError::sql(&self.db_path, e))?;
 
         conn.execute_batch(
             "
@@ -402,7 +532,7 @@ impl ChunkSystem {
             PRAGMA cache_size = 10000;
         ",
         )
-        .unwrap();
+        .map_err(|e| WorldStorageError::sql(&self.db_path, e))?;
 
         // let file = File::open(format!("{}/udm", path)).unwrap();
         // let reader = BufReader::new(file);
@@ -419,14 +549,14 @@ impl ChunkSystem {
 
 
         if Path::new(&pa).exists() {
-            let file = File::open(pa).unwrap();
+            let file = File::open(&pa).map_err(|e| WorldStorageError::io(&pa, e))?;
             let reader = BufReader::new(file);
 
             for line in reader.lines() {
-                let line = line.unwrap();
+                let line = line.map_err(|e| WorldStorageError::io(&pa, e))?;
                 let mut parts = line.splitn(2, ' ');
                 if let Some(seed) = parts.next() {
-                    let s = seed.parse::<u32>().unwrap();
+                    let s = seed.parse::<u32>().map_err(|_| WorldStorageError::corrupt(&pa, format!("{:?} isn't a seed", seed)))?;
                     info!("Seed Is {}", s);
                     *(self.perlin.write()) = Perlin::new(s);
 
@@ -439,53 +569,31 @@ impl ChunkSystem {
         }
 
         let seed = unsafe {CURRSEED.load(std::sync::atomic::Ordering::Relaxed)};
-        let table_name = format!("userdatamap_{}", seed);
-        info!("LOADING FROM TABLENAME {}", table_name);
-
-        conn.execute(
-            &format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                    x INTEGER,
-                    y INTEGER,
-                    z INTEGER,
-                    value INTEGER,
-                    PRIMARY KEY (x, y, z)
-                )",
-                table_name
-            ),
-            (),
-        )
-        .unwrap();
-
-        // Query the userdatamap table
-        let mut stmt = conn
-            .prepare(&format!("SELECT x, y, z, value FROM {}", table_name))
-            .unwrap();
-
-        let userdatamap_iter = stmt
-            .query_map([], |row| {
-                Ok((
-                    vec::IVec3::new(row.get(0)?, row.get(1)?, row.get(2)?),
-                    row.get(3)?,
-                ))
-            })
-            .unwrap();
-
-        for entry in userdatamap_iter {
-            let (key, value): (vec::IVec3, u32) = entry.unwrap();
-            self.userdatamap.insert(key, value);
+
+        //Edits load a chunk at a time from here on, worlds saved a row per block get moved over first
+        let store = ChunkStore::new(&self.db_path, seed);
+        match store.migrate() {
+            Ok(0) => {}
+            Ok(moved) => info!("Moved {} edits over to chunk storage", moved),
+            Err(e) => info!("Couldn't move the old edits over to chunk storage: {}", e),
         }
+        self.edits_loaded.clear();
+        self.edit_spots.clear();
+        self.edits_unreadable.clear();
+        self.edit_store = Some(store);
 
-        let file = File::open(format!("{}/pt", path)).unwrap();
+        let ptpath = format!("{}/pt", path);
+        let file = File::open(&ptpath).map_err(|e| WorldStorageError::io(&ptpath, e))?;
         let reader = BufReader::new(file);
 
         for line in reader.lines() {
-            let line = line.unwrap();
+            let line = line.map_err(|e| WorldStorageError::io(&ptpath, e))?;
             let mut parts = line.splitn(2, ' ');
             if let Some(pt) = parts.next() {
-                self.planet_type = pt.parse::<u8>().unwrap();
+                self.planet_type = pt.parse::<u8>().map_err(|_| WorldStorageError::corrupt(&ptpath, format!("{:?} isn't a planet type", pt)))?;
             }
         }
+        Ok(())
     }
 
     pub fn collision_predicate(&self, vec: vec::IVec3) -> bool {
@@ -625,6 +733,9 @@ impl ChunkSystem {
         self.userdatamap.clear();
         self.nonuserdatamap.clear();
         self.justcollisionmap.clear();
+        self.edits_loaded.clear();
+        self.edit_spots.clear();
+        self.edits_unreadable.clear();
         info!("After clearing the next 3 things");
     }
 
@@ -637,6 +748,14 @@ impl ChunkSystem {
         self.planet_type = noisetype as u8;
         unsafe {CURRSEED.store(seed, std::sync::atomic::Ordering::Relaxed)};
 
+        //A new planet's edits are in its own table
+        if let Some(store) = &mut self.edit_store {
+            store.seed = seed;
+        }
+        self.edits_loaded.clear();
+        self.edit_spots.clear();
+        self.edits_unreadable.clear();
+
         info!("After setting currentseed");
 
         if !self.headless {
@@ -694,6 +813,12 @@ impl ChunkSystem {
             hashadinitiallightpass: Arc::new(Mutex::new(HashMap::new())),
             lightmap: Arc::new(Mutex::new(HashMap::new())),
             generated_chunks: Arc::new(DashMap::new()),
+            db_path: PathBuf::from("db"),
+            edit_store: None,
+            edits_loaded: Arc::new(DashMap::new()),
+            edit_spots: Arc::new(DashMap::new()),
+            edits_unreadable: Arc::new(DashMap::new()),
+            edit_io: Mutex::new(()),
         };
 
         // let directory_path = "assets/voxelmodels/";
@@ -734,10 +859,7 @@ impl ChunkSystem {
         cs
     }
     pub fn spot_to_chunk_pos(spot: &vec::IVec3) -> vec::IVec2 {
-        return vec::IVec2 {
-            x: (spot.x as f32 / ChW as f32).floor() as i32,
-            y: (spot.z as f32 / ChW as f32).floor() as i32,
-        };
+        terrain::spot_to_chunk_pos(spot)
     }
     pub fn initial_rebuild_on_main_thread(
         csys: &Arc<RwLock<ChunkSystem>>,
@@ -847,6 +969,12 @@ impl ChunkSystem {
             }
         }
     }
+    /// Rebuilds every chunk we have loaded, for when the user data under them was swapped out (like a multiplayer resync).
+    pub fn queue_rerender_all(&self) {
+        for cf in self.takencare.iter() {
+            self.queue_geoindex_rerender(cf.geo_index, false, false);
+        }
+    }
     pub fn queue_rerender(&self, spot: vec::IVec3, user_power: bool, light: bool) {
         let chunk_key = &Self::spot_to_chunk_pos(&spot);
         match self.takencare.get(chunk_key) {
@@ -1008,7 +1136,7 @@ impl ChunkSystem {
         match user_power {
             true => {
                 //info!("Has user power, set block to {block}");
-                self.userdatamap.insert(spot, block);
+                self.set_user_block(spot, block);
             }
             false => {
                 //info!("Non user power");
@@ -1051,7 +1179,7 @@ unsafe {
         match user_power {
             true => {
                 //info!("Has user power, set block to {block}");
-                self.userdatamap.insert(spot, block);
+                self.set_user_block(spot, block);
             }
             false => {
                 //info!("Non user power");
@@ -1070,7 +1198,12 @@ unsafe {
 
             if tc.contains_key(&chunkgeoarc.pos.lock()) {
                 tc.remove(&chunkgeoarc.pos.lock());
+                let oldpos = *chunkgeoarc.pos.lock();
+                if let Err(e) = self.unload_chunk_edits(oldpos) {
+                    info!("Couldn't save the edits in chunk {} {}, keeping them loaded: {}", oldpos.x, oldpos.y, e);
+                }
             }
+            self.load_chunk_edits(cpos);
 
             unsafe {
                 CHUNKDRAWINGHERE.remove(&chunkgeoarc.pos.lock());
@@ -2284,6 +2417,9 @@ unsafe {
     }
 
     pub fn generate_chunk(&self, cpos: &vec::IVec2) {
+        if !worldgen::features_on() {
+            return;
+        }
         // Seed for the RNG.
         let seed: [u8; 32] = [
             (cpos.x % 255) as u8,
@@ -2391,67 +2527,27 @@ unsafe {
             }
         }
     }
-    fn mix(a: f64, b: f64, t: f64) -> f64 {
-        a * (1.0 - t) + b * t
-    }
 
     pub fn biome_noise(&self, spot: vec::IVec2) -> f64 {
         return Self::_biome_noise(&self.perlin.read(), spot);
     }
 
     pub fn _biome_noise(perlin: &Perlin, spot: vec::IVec2) -> f64 {
-        const XZDIVISOR1: f64 = 100.35 * 4.0;
-
-        let y = 20;
-
-        let noise1 = f64::max(
-            0.0,
-            perlin.get([
-                spot.x as f64 / XZDIVISOR1,
-                y as f64,
-                spot.y as f64 / XZDIVISOR1,
-            ]),
-        );
-
-        noise1
+        terrain::biome_noise(perlin, spot)
     }
     pub fn ore_noise(&self, spot: vec::IVec3) -> f64 {
         return Self::_ore_noise(&self.perlin.read(), spot);
     }
 
     pub fn _ore_noise(perlin: &Perlin, spot: vec::IVec3) -> f64 {
-        const XYZDIVISOR: f64 = 15.53;
-
-        let noise1 = f64::max(
-            0.0,
-            perlin.get([
-                spot.x as f64 / XYZDIVISOR,
-                spot.y as f64 / XYZDIVISOR,
-                spot.z as f64 / XYZDIVISOR,
-            ]),
-        );
-
-        noise1 * ((60.0 - spot.y as f64).max(0.0) / 7.0)
+        terrain::ore_noise(perlin, spot)
     }
     pub fn feature_noise(&self, spot: vec::IVec2) -> f64 {
         return Self::_feature_noise(&self.perlin.read(), spot);
     }
 
     pub fn _feature_noise(perlin: &Perlin, spot: vec::IVec2) -> f64 {
-        const XZDIVISOR1: f64 = 45.35 * 4.0;
-
-        let y = 20;
-
-        let noise1 = f64::max(
-            0.0,
-            perlin.get([
-                (spot.x as f64 + 200.0) / XZDIVISOR1,
-                y as f64,
-                spot.y as f64 / XZDIVISOR1,
-            ]),
-        );
-
-        noise1
+        terrain::feature_noise(perlin, spot)
     }
 
     pub fn cave_noise(&self, spot: vec::IVec3) -> f64 {
@@ -2459,18 +2555,7 @@ unsafe {
     }
 
     pub fn _cave_noise(perlin: &Perlin, spot: vec::IVec3) -> f64 {
-        const XZDIVISOR1: f64 = 25.35;
-
-        let noise1 = f64::max(
-            0.0,
-            perlin.get([
-                (spot.x as f64) / XZDIVISOR1,
-                (spot.y as f64) / XZDIVISOR1,
-                spot.z as f64 / XZDIVISOR1,
-            ]),
-        );
-
-        noise1
+        terrain::cave_noise(perlin, spot)
     }
 
     pub fn noise_func(&self, spot: vec::IVec3) -> f64 {
@@ -2478,98 +2563,7 @@ unsafe {
     }
 
     pub fn _noise_func(perlin: &Perlin, spot: vec::IVec3) -> f64 {
-
-        let per = perlin;
-
-        let spot = spot;
-        let spot = (Vec3::new(spot.x as f32, spot.y as f32, spot.z as f32) / 3.0) + Vec3::new(0.0, 10.0, 0.0);
-        let xzdivisor1 = 600.35 * 4.0;
-        let xzdivisor2 = 1000.35 * 4.0;
-
-        let mut y = spot.y - 20.0;
-
-        let noise1 = f64::max(
-            0.0,
-            20.0 + per.get([
-                spot.x as f64 / xzdivisor2,
-                y as f64 / xzdivisor2,
-                spot.z as f64 / xzdivisor2,
-            ]) * 5.0
-                - f64::max(
-                    y as f64 / 1.7
-                        + per
-                            .get([spot.x as f64 / 65.0, spot.z as f64 / 65.0])
-                            * 10.0,
-                    0.0,
-                ),
-        ) * 2.0;
-
-        y += 100.0;
-
-        let noise2 = f64::max(
-            0.0,
-            50.0 + per.get([
-                spot.x as f64 / 100.35,
-                y as f64 / 50.35,
-                spot.z as f64 / 100.35,
-            ]) * 10.0
-                + per.get([
-                    spot.x as f64 / 300.35,
-                    y as f64 / 100.35,
-                    spot.z as f64 / 300.35,
-                ]) * 10.0
-                - f64::max(y as f64 / 3.0, 0.0),
-        );
-
-        let mut p = per
-            .get([spot.x as f64 / 500.0, spot.z as f64 / 500.0])
-            * 2.0;
-
-        p = f64::max(p, 0.0);
-        p = f64::min(p, 1.0);
-
-        // Mixing noise1 and noise2 based on p, assuming `mix` is a function that blends the two values
-        // Rust doesn't have a direct `mix` function, but you can create one or use a linear interpolation
-        let noisemix = ChunkSystem::mix(noise1, noise2, p);
-
-        let texture = per.get([
-            spot.x as f64 / 12.35,
-            y as f64 / 12.35,
-            spot.z as f64 / 12.35,
-        ]) * 1.0;
-
-        let noise3 = f64::max(
-            0.0,
-            50.0 + per.get([
-                spot.x as f64 / 25.35,
-                y as f64 / 25.35,
-                spot.z as f64 / 25.35,
-            ]) * 10.0
-                + per.get([
-                    spot.x as f64 / 60.35,
-                    y as f64 / 50.35,
-                    spot.z as f64 / 60.35,
-                ]) * 10.0
-                - f64::max(y as f64 / 3.0, 0.0),
-        );
-
-        let mut p2 = 0.5 + per.get([
-            (spot.x as f64 + 4500.0) / 150.0,
-            (spot.y as f64 + 5000.0) / 150.0,
-            (spot.z as f64 - 5000.0) / 150.0,
-        ]) * 1.0;
-
-        let p3 = (per.get([
-            (spot.x as f64 - 1500.0) / 3500.0,
-            (spot.z as f64 + 1000.0) / 3500.0,
-        ]) * 10.0).min(9.0);
-
-    
-
-        p2 = f64::max(p2, 0.0);
-        p2 = f64::min(p2, 1.0);
-
-        ChunkSystem::mix(noisemix + texture, noise3, p2.clamp(0.0, 1.0)).min(20.0) + p3
+        terrain::noise_func(perlin, spot)
     }
 
     pub fn noise_func2(&self, spot: vec::IVec3) -> f64 {
@@ -2619,7 +2613,7 @@ unsafe {
 
         // Mixing noise1 and noise2 based on p, assuming `mix` is a function that blends the two values
         // Rust doesn't have a direct `mix` function, but you can create one or use a linear interpolation
-        ChunkSystem::mix(noise1, noise2, p * 0.5)
+        terrain::mix(noise1, noise2, p * 0.5)
     }
 
     pub fn blockatmemo(&self, spot: vec::IVec3, memo: &mut HashMap<vec::IVec3, u32>) -> u32 {
@@ -2647,6 +2641,7 @@ unsafe {
         // }
     }
     pub fn blockat(&self, spot: vec::IVec3) -> u32 {
+        self.load_chunk_edits(Self::spot_to_chunk_pos(&spot));
         Self::_blockat(&self.nonuserdatamap.clone(), &self.userdatamap.clone(), &self.perlin.read(), spot)
     }
     pub fn _blockat(nonuserdatamap: &Arc<DashMap<IVec3, u32>>, userdatamap: &Arc<DashMap<IVec3, u32>>, perlin: &Perlin, spot: vec::IVec3) -> u32 {
@@ -2678,97 +2673,6 @@ unsafe {
     }
 
     pub fn _natural_blockat(perlin: &Perlin, spot: vec::IVec3) -> u32 {
-
-
-        let per = perlin;
-        if spot.y == 0 {
-            return 15;
-        }
-
-        
-        let ret = match 0 {
-            // 1 => {
-            //     if self.noise_func2(spot) > 10.0 {
-            //         if self.noise_func2(spot + vec::IVec3 { x: 0, y: 1, z: 0 }) < 10.0 {
-            //             14
-            //         } else {
-            //             1
-            //         }
-
-            //     } else {
-            //         0
-            //     }
-            // }
-            _ => {
-                static WL: f32 = 30.0;
-
-                let biomenum = Self::_biome_noise(per, IVec2 {
-                    x: spot.x,
-                    y: spot.z,
-                });
-                let biomenum2 = Self::_biome_noise(per, IVec2 {
-                    x: spot.x * 20 + 5000,
-                    y: spot.z * 20 + 5000,
-                });
-
-                let mut underdirt = 5;
-                let mut surface = 3;
-                let mut undersurface = 4;
-                let mut liquid = 2;
-                let mut beach = 1;
-
-                if biomenum > 0.0 {
-                    underdirt = 1;
-                    surface = 1;
-                    undersurface = 1;
-                    liquid = 2;
-                    beach = 1;
-                } else {
-                    if biomenum2 > 0.0 {
-                        surface = 34;
-                    }
-                }
-
-                if Self::_noise_func(per, spot) > 10.0 {
-                    if Self::_noise_func(per, spot + vec::IVec3 { x: 0, y: 10, z: 0 }) > 10.0 {
-                        if Self::_ore_noise(per, spot) > 1.0 {
-                            35
-                        } else {
-                            underdirt
-                        }
-                    } else {
-
-                        let beachnoise = per.get([spot.y as f64/7.5, spot.z as f64/7.5, spot.x as f64/7.5]);
-                        if spot.y > (WL + beachnoise as f32) as i32
-                        || Self::_noise_func(per, spot + vec::IVec3 { x: 0, y: 5, z: 0 }) > 10.0
-                        {
-                            if Self::_noise_func(per, spot + vec::IVec3 { x: 0, y: 1, z: 0 }) < 10.0 {
-                                surface
-                            } else {
-                                undersurface
-                            }
-                            
-                        } else {
-                            beach
-                        }
-                    }
-
-
-                    
-                } else {
-                    if spot.y < WL as i32 {
-                        liquid
-                    } else {
-                        0
-                    }
-                }
-            }
-        };
-        if ret != 2 {
-            if Self::_cave_noise(per, spot) > 0.5 {
-                return 0;
-            }
-        }
-        ret
+        terrain::natural_blockat(perlin, spot)
     }
 }
@@ -0,0 +1,52 @@
+use crate::server_types::MAX_PAYLOAD_SIZE;
+
+/// Payloads smaller than this go out raw, lz4 on a few hundred bytes isn't worth it.
+pub const COMPRESSION_THRESHOLD: usize = 4096;
+
+/// How a bulk payload (`Udm`, `ChestReg`) is encoded on the wire.
+///
+/// The client asks for a mode in the `info` of its `TellYouMyID` greeting, and the server puts
+/// the codec it actually used in the `info2` of each payload header. Old clients send 0 and old
+/// servers send 0, so either side can be older and everything stays raw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None = 0,
+    Lz4 = 1,
+}
+
+impl Compression {
+    pub fn from_u32(v: u32) -> Option<Compression> {
+        match v {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            _ => None,
+        }
+    }
+}
+
+/// Compresses `data` with `mode` if it's big enough to be worth it. Returns the bytes to send and the codec used.
+pub fn encode_payload(data: &[u8], mode: Compression) -> (Vec<u8>, Compression) {
+    match mode {
+        Compression::Lz4 if data.len() >= COMPRESSION_THRESHOLD => {
+            (lz4_flex::compress_prepend_size(data), Compression::Lz4)
+        }
+        _ => (data.to_vec(), Compression::None),
+    }
+}
+
+/// Undoes `encode_payload`. `None` for an unknown codec, a corrupt payload or one that claims to inflate past `MAX_PAYLOAD_SIZE`.
+pub fn decode_payload(data: &[u8], codec: u32) -> Option<Vec<u8>> {
+    match Compression::from_u32(codec)? {
+        Compression::None => Some(data.to_vec()),
+        Compression::Lz4 => {
+            if data.len() < 4 {
+                return None;
+            }
+            let size = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            if size > MAX_PAYLOAD_SIZE {
+                return None;
+            }
+            lz4_flex::decompress_size_prepended(data).ok()
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bincode::Options;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// Servers listen here for LAN discovery broadcasts, on top of their own port.
+pub const DISCOVERY_PORT: u16 = 4849;
+
+pub use crate::worlddir::GAME_VERSION;
+
+/// Starts a status query datagram, followed by an 8 byte nonce that comes back in the answer.
+const QUERY_MAGIC: [u8; 4] = *b"VLq?";
+/// Starts a status answer, followed by the nonce and the bincoded `ServerStatus`.
+const STATUS_MAGIC: [u8; 4] = *b"VLs!";
+
+/// How long a refresh waits for answers.
+pub const QUERY_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// The server browser asks everything again this often while it's open.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What a server tells anyone who asks, no connection needed.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ServerStatus {
+    pub name: String,
+    pub motd: String,
+    pub players: u32,
+    pub version: String,
+    /// TCP port to join on, which may not be where the answer came from for LAN broadcasts.
+    pub port: u16,
+}
+
+pub fn encode_query(nonce: u64) -> Vec<u8> {
+    let mut bytes = QUERY_MAGIC.to_vec();
+    bytes.extend_from_slice(&nonce.to_le_bytes());
+    bytes
+}
+
+/// The nonce of a status query, None if this isn't one.
+pub fn decode_query(bytes: &[u8]) -> Option<u64> {
+    if bytes.len() != 12 || bytes[..4] != QUERY_MAGIC {
+        return None;
+    }
+    Some(u64::from_le_bytes(bytes[4..12].try_into().ok()?))
+}
+
+pub fn encode_status(nonce: u64, status: &ServerStatus) -> Vec<u8> {
+    let mut bytes = STATUS_MAGIC.to_vec();
+    bytes.extend_from_slice(&nonce.to_le_bytes());
+    bytes.extend_from_slice(&bincode::serialize(status).unwrap());
+    bytes
+}
+
+pub fn decode_status(bytes: &[u8]) -> Option<(u64, ServerStatus)> {
+    if bytes.len() < 12 || bytes[..4] != STATUS_MAGIC {
+        return None;
+    }
+    let nonce = u64::from_le_bytes(bytes[4..12].try_into().ok()?);
+    //Capped so a hostile answer can't claim a giant string
+    let status: ServerStatus = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(1024)
+        .deserialize(&bytes[12..])
+        .ok()?;
+    Some((nonce, status))
+}
+
+fn new_nonce() -> u64 {
+    uuid::Uuid::new_v4().as_u64_pair().0
+}
+
+/// Asks one server for its status. The ping is how long the answer took.
+pub fn query_status(address: &str, timeout: Duration) -> io::Result<(ServerStatus, Duration)> {
+    let addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address didn't resolve"))?;
+
+    let socket = UdpSocket::bind(if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+    socket.connect(addr)?;
+
+    let nonce = new_nonce();
+    let start = Instant::now();
+    socket.send(&encode_query(nonce))?;
+
+    let mut buffer = [0u8; 2048];
+    while start.elapsed() < timeout {
+        socket.set_read_timeout(Some((timeout - start.elapsed()).max(Duration::from_millis(1))))?;
+        let n = match socket.recv(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e),
+        };
+        if let Some((n, status)) = decode_status(&buffer[..n]) {
+            if n == nonce {
+                return Ok((status, start.elapsed()));
+            }
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, "no answer"))
+}
+
+/// A server that answered a LAN broadcast.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LanServer {
+    /// Where to join, the address it answered from with its TCP port.
+    pub address: SocketAddr,
+    pub status: ServerStatus,
+    pub ping: Duration,
+}
+
+/// Broadcasts a status query on the LAN and collects every answer that comes back within `timeout`.
+pub fn discover_lan(timeout: Duration) -> io::Result<Vec<LanServer>> {
+    discover_on(SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT)), timeout)
+}
+
+/// `discover_lan` against any address, which the tests point at localhost.
+pub fn discover_on(target: SocketAddr, timeout: Duration) -> io::Result<Vec<LanServer>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_broadcast(true)?;
+
+    let nonce = new_nonce();
+    let start = Instant::now();
+    socket.send_to(&encode_query(nonce), target)?;
+
+    let mut found: Vec<LanServer> = Vec::new();
+    let mut buffer = [0u8; 2048];
+    while start.elapsed() < timeout {
+        socket.set_read_timeout(Some((timeout - start.elapsed()).max(Duration::from_millis(1))))?;
+        let (n, from) = match socket.recv_from(&mut buffer) {
+            Ok(r) => r,
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e),
+        };
+        if let Some((n, status)) = decode_status(&buffer[..n]) {
+            let address = SocketAddr::new(from.ip(), status.port);
+            if n == nonce && !found.iter().any(|s| s.address == address) {
+                found.push(LanServer { address, status, ping: start.elapsed() });
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// A server the player saved to the browser.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Favorite {
+    pub name: String,
+    pub address: String,
+}
+
+pub fn load_favorites(path: impl AsRef<Path>) -> Vec<Favorite> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub fn save_favorites(path: impl AsRef<Path>, favorites: &[Favorite]) {
+    match File::create(path) {
+        Ok(mut file) => {
+            let _ = file.write_all(serde_json::to_string_pretty(favorites).unwrap().as_bytes());
+        }
+        Err(e) => info!("Couldn't save favorites: {e}"),
+    }
+}
+
+/// One line in the server browser.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BrowserEntry {
+    pub address: String,
+    pub favorite: Option<Favorite>,
+    /// None if it hasn't answered (yet).
+    pub status: Option<ServerStatus>,
+    pub ping: Option<Duration>,
+}
+
+impl BrowserEntry {
+    pub fn label(&self) -> String {
+        match (&self.favorite, &self.status) {
+            (Some(f), _) if !f.name.is_empty() => f.name.clone(),
+            (_, Some(s)) => s.name.clone(),
+            _ => self.address.clone(),
+        }
+    }
+}
+
+/// The main menu's server list: saved favorites plus whatever answers on the LAN, each with a live ping.
+/// Refreshing happens on a background thread so the menu never waits on the network.
+pub struct ServerBrowser {
+    pub favorites_path: String,
+    pub favorites: Vec<Favorite>,
+    answers: Arc<Mutex<HashMap<String, (ServerStatus, Duration)>>>,
+    lan: Arc<Mutex<Vec<LanServer>>>,
+    refreshing: Arc<AtomicBool>,
+    last_refresh: Option<Instant>,
+}
+
+impl ServerBrowser {
+    pub fn new(favorites_path: impl Into<String>) -> ServerBrowser {
+        let favorites_path = favorites_path.into();
+        ServerBrowser {
+            favorites: load_favorites(&favorites_path),
+            favorites_path,
+            answers: Arc::new(Mutex::new(HashMap::new())),
+            lan: Arc::new(Mutex::new(Vec::new())),
+            refreshing: Arc::new(AtomicBool::new(false)),
+            last_refresh: None,
+        }
+    }
+
+    pub fn is_refreshing(&self) -> bool {
+        self.refreshing.load(Ordering::Relaxed)
+    }
+
+    /// Asks the LAN and every favorite again, unless a refresh is already running.
+    pub fn refresh(&mut self) {
+        if self.refreshing.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        self.last_refresh = Some(Instant::now());
+
+        let addresses: Vec<String> = self.favorites.iter().map(|f| f.address.clone()).collect();
+        let answers = self.answers.clone();
+        let lan = self.lan.clone();
+        let refreshing = self.refreshing.clone();
+
+        thread::spawn(move || {
+            let favs: Vec<_> = addresses
+                .into_iter()
+                .map(|address| thread::spawn(move || (query_status(&address, QUERY_TIMEOUT).ok(), address)))
+                .collect();
+
+            let found = discover_lan(QUERY_TIMEOUT).unwrap_or_default();
+            *lan.lock() = found;
+
+            for handle in favs {
+                if let Ok((answer, address)) = handle.join() {
+                    match answer {
+                        Some(a) => {
+                            answers.lock().insert(address, a);
+                        }
+                        None => {
+                            answers.lock().remove(&address);
+                        }
+                    }
+                }
+            }
+            refreshing.store(false, Ordering::Relaxed);
+        });
+    }
+
+    /// Call every frame the browser is showing, keeps the pings live.
+    pub fn tick(&mut self) {
+        if self.last_refresh.is_none_or(|t| t.elapsed() >= REFRESH_INTERVAL) {
+            self.refresh();
+        }
+    }
+
+    pub fn is_favorite(&self, address: &str) -> bool {
+        self.favorites.iter().any(|f| f.address == address)
+    }
+
+    pub fn add_favorite(&mut self, name: impl Into<String>, address: impl Into<String>) {
+        let address = address.into();
+        if address.is_empty() || self.is_favorite(&address) {
+            return;
+        }
+        self.favorites.push(Favorite { name: name.into(), address });
+        save_favorites(&self.favorites_path, &self.favorites);
+        self.last_refresh = None;
+    }
+
+    pub fn remove_favorite(&mut self, address: &str) {
+        self.favorites.retain(|f| f.address != address);
+        save_favorites(&self.favorites_path, &self.favorites);
+    }
+
+    /// Favorites first in the order they were saved, then LAN servers that aren't favorites.
+    pub fn entries(&self) -> Vec<BrowserEntry> {
+        let answers = self.answers.lock();
+        let lan = self.lan.lock();
+
+        let mut entries: Vec<BrowserEntry> = self
+            .favorites
+            .iter()
+            .map(|f| {
+                let answer = answers.get(&f.address).cloned().or_else(|| {
+                    lan.iter().find(|s| s.address.to_string() == f.address).map(|s| (s.status.clone(), s.ping))
+                });
+                BrowserEntry {
+                    address: f.address.clone(),
+                    favorite: Some(f.clone()),
+                    status: answer.as_ref().map(|a| a.0.clone()),
+                    ping: answer.map(|a| a.1),
+                }
+            })
+            .collect();
+
+        for server in lan.iter() {
+            let address = server.address.to_string();
+            if !self.is_favorite(&address) {
+                entries.push(BrowserEntry {
+                    address,
+                    favorite: None,
+                    status: Some(server.status.clone()),
+                    ping: Some(server.ping),
+                });
+            }
+        }
+        entries
+    }
+}
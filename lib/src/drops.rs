@@ -164,6 +164,16 @@ impl Drops {
         let drop = Drop::new(block_id, pos, &self.csys, amt);
         self.drops.push(drop);
     }
+    /// Removes a drop of `block_id` lying near `pos` that hasn't been picked up yet, `false` if there isn't one.
+    pub fn take_back(&mut self, pos: Vec3, block_id: u32) -> bool {
+        match self.drops.iter().position(|d| d.block_id == block_id && !d.to_be_deleted && d.position.distance(pos) < 3.0) {
+            Some(index) => {
+                self.drops.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
     pub fn update_and_draw_drops(&mut self, delta_time: &f32, mvp: &Mat4) {
         self.update_drops(delta_time);
         #[cfg(feature = "glfw")]
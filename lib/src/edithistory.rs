@@ -0,0 +1,193 @@
+use crate::blockinfo::Blocks;
+use crate::buildtools;
+use crate::vec::IVec3;
+
+/// How many actions back undo goes, the oldest are forgotten past this.
+pub const MAX_UNDO: usize = 100;
+
+/// Items as (id, count).
+pub type Items = Vec<(u32, u32)>;
+
+/// Something the player did to the world in one go: a block placed or broken, or a whole stamp.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EditAction {
+    /// Given when it's recorded, so the edits sent for it can find it again. See `HistoryEntry`.
+    pub id: u32,
+    /// (spot, block before, block after), in the order they were set.
+    pub blocks: Vec<(IVec3, u32, u32)>,
+}
+
+/// Which action in the history an edit sent to the server belongs to, to take it back out if the server turns it down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HistoryEntry {
+    /// It's part of building the action with this id.
+    Recorded(u32),
+    /// It's undoing the action with this id, which is on the redo stack since.
+    Undid(u32),
+    /// It's redoing it, back on the undo stack since.
+    Redid(u32),
+}
+
+impl EditAction {
+    /// What puts it back the way it was, last block first.
+    pub fn reversed(&self) -> EditAction {
+        EditAction { id: self.id, blocks: self.blocks.iter().rev().map(|(spot, before, after)| (*spot, *after, *before)).collect() }
+    }
+
+    /// Only the spots that are still what this starts from, anything changed since by someone else is left alone.
+    pub fn still_applicable(&self, block_at: impl Fn(IVec3) -> u32) -> EditAction {
+        EditAction { id: self.id, blocks: self.blocks.iter().filter(|(spot, before, _)| block_at(*spot) == *before).cloned().collect() }
+    }
+
+    /// The blocks to set.
+    pub fn edits(&self) -> Vec<(IVec3, u32)> {
+        self.blocks.iter().map(|(spot, _, after)| (*spot, *after)).collect()
+    }
+
+    /// What doing it takes out of the inventory and what it gives back, like `buildtools::material_cost`
+    /// counts them. Blocks that only change their flags, like a door opening, are free.
+    pub fn exchange(&self) -> (Items, Items) {
+        let swapped: Vec<&(IVec3, u32, u32)> = self
+            .blocks
+            .iter()
+            .filter(|(_, before, after)| before & Blocks::block_id_bits() != after & Blocks::block_id_bits())
+            .collect();
+        let placed: Vec<(IVec3, u32)> = swapped.iter().map(|(spot, _, after)| (*spot, *after)).collect();
+        let removed: Vec<(IVec3, u32)> = swapped.iter().map(|(spot, before, _)| (*spot, *before)).collect();
+        (buildtools::material_cost(&placed), buildtools::material_cost(&removed))
+    }
+
+    /// Whether any block in it became a different block.
+    fn changes_anything(&self) -> bool {
+        self.blocks.iter().any(|(_, before, after)| before & Blocks::block_id_bits() != after & Blocks::block_id_bits())
+    }
+}
+
+/// This session's undo and redo stacks for block edits.
+///
+/// A click is recorded between `begin` and `end`, with every edit it predicts `note`d in between, so a door
+/// and its other half come undone together. Stamps and fills are `record`ed whole.
+#[derive(Debug, Default)]
+pub struct EditHistory {
+    undo: Vec<EditAction>,
+    redo: Vec<EditAction>,
+    open: Option<EditAction>,
+    next_id: u32,
+}
+
+impl EditHistory {
+    pub fn new() -> EditHistory {
+        EditHistory::default()
+    }
+
+    pub fn begin(&mut self) {
+        self.next_id = self.next_id.wrapping_add(1);
+        self.open = Some(EditAction { id: self.next_id, blocks: Vec::new() });
+    }
+
+    /// The action edits are being `note`d into right now.
+    pub fn open_entry(&self) -> Option<HistoryEntry> {
+        self.open.as_ref().map(|action| HistoryEntry::Recorded(action.id))
+    }
+
+    /// Edits made while an action is open become part of it, otherwise they aren't ours to undo.
+    pub fn note(&mut self, blocks: &[(IVec3, u32, u32)]) {
+        let Some(action) = &mut self.open else {
+            return;
+        };
+        for (spot, before, after) in blocks {
+            match action.blocks.iter_mut().find(|b| b.0 == *spot) {
+                Some(b) => b.2 = *after,
+                None => action.blocks.push((*spot, *before, *after)),
+            }
+        }
+    }
+
+    /// Closes the open action. One that only flipped flags, like opening a door, isn't kept.
+    pub fn end(&mut self) {
+        if let Some(action) = self.open.take() {
+            if action.changes_anything() {
+                self.push(action);
+            }
+        }
+    }
+
+    /// Records a whole action at once, what its edits should be sent with.
+    pub fn record(&mut self, blocks: Vec<(IVec3, u32, u32)>) -> HistoryEntry {
+        self.begin();
+        let entry = HistoryEntry::Recorded(self.next_id);
+        self.note(&blocks);
+        self.end();
+        entry
+    }
+
+    /// The server turned down `spots`, sent for `entry`. They never changed, so they're not part of an action
+    /// being recorded anymore, and an undo or redo that didn't go through goes back on the stack it came from.
+    pub fn rejected(&mut self, entry: HistoryEntry, spots: &[IVec3]) {
+        match entry {
+            HistoryEntry::Recorded(id) => {
+                let Some(index) = self.undo.iter().position(|a| a.id == id) else {
+                    return;
+                };
+                self.undo[index].blocks.retain(|(spot, _, _)| !spots.contains(spot));
+                if self.undo[index].blocks.is_empty() {
+                    self.undo.remove(index);
+                }
+            }
+            //Once is enough when it's rejected a batch at a time
+            HistoryEntry::Undid(id) => {
+                if let Some(index) = self.redo.iter().position(|a| a.id == id) {
+                    let action = self.redo.remove(index);
+                    self.undo.push(action);
+                }
+            }
+            HistoryEntry::Redid(id) => {
+                if let Some(index) = self.undo.iter().position(|a| a.id == id) {
+                    let action = self.undo.remove(index);
+                    self.redo.push(action);
+                }
+            }
+        }
+    }
+
+    /// A new action, which makes anything undone stay undone.
+    fn push(&mut self, action: EditAction) {
+        self.redo.clear();
+        self.undo.push(action);
+        if self.undo.len() > MAX_UNDO {
+            self.undo.remove(0);
+        }
+    }
+
+    pub fn take_undo(&mut self) -> Option<EditAction> {
+        self.undo.pop()
+    }
+
+    pub fn take_redo(&mut self) -> Option<EditAction> {
+        self.redo.pop()
+    }
+
+    /// `action` was undone, it can be redone.
+    pub fn undone(&mut self, action: EditAction) {
+        self.redo.push(action);
+    }
+
+    /// `action` was redone, or couldn't be undone after all, so it's back on top of the undo stack.
+    pub fn redone(&mut self, action: EditAction) {
+        self.undo.push(action);
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.open = None;
+    }
+}
@@ -1,7 +1,9 @@
 use std::cmp::max;
 use std::collections::HashSet;
 use std::f32::consts::{self};
-use std::io::Write;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
 use atomic_float::AtomicF32;
 use noise::Perlin;
@@ -18,7 +20,7 @@ use std::time::{Duration, Instant};
 use lockfree::queue::Queue;
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
 use uuid::Uuid;
 
 use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI8, AtomicU32, Ordering};
@@ -42,10 +44,12 @@ use crate::audio::{spawn_audio_thread, AudioPlayer};
 pub const PLAYERSCALE: f32 = 1.0;
 
 use crate::blockinfo::Blocks;
+use crate::buildtools::{self, BuildTool, BuildTools, PREVIEW_LIMIT};
+use crate::bulkedit;
 use crate::blockoverlay::BlockOverlay;
+use crate::calendar;
 use crate::chunk::{ChunkFacade, ChunkSystem, AUTOMATA_QUEUED_CHANGES};
 
-pub static mut LIST_OF_PREVIEWED_SPOTS: Vec<(IVec3, u32)> = Vec::new();
 
 pub static mut VOXEL_SELECT_DISTANCE: f32 = 10.0;
 
@@ -72,8 +76,15 @@ use crate::inventory::*;
 
 use crate::modelentity::ModelEntity;
 use crate::network::NetworkConnector;
+use crate::saves;
+use crate::schematic::SCHEMATICS_DIR;
+use crate::worlddir::CLIENT_DIR;
+use crate::worldstorage::{StorageResult, WorldStorageError};
+use crate::server::{Server, ServerConfig};
 use crate::planetinfo::Planets;
 use crate::playerposition::PlayerPosition;
+use crate::edithistory::{EditHistory, HistoryEntry};
+use crate::prediction::{PendingEdit, PendingEdits};
 use crate::raycast::*;
 use crate::recipes::{Recipe, RecipeEntry, RECIPES};
 use crate::selectcube::SelectCube;
@@ -113,6 +124,15 @@ pub static WINDEDLENGTH: f32 = 2.0;
 
 pub static mut SINGLEPLAYER: bool = false;
 
+/// Where the one singleplayer world was kept before there were save slots, see `saves`.
+pub const SINGLEPLAYER_WORLD_DIR: &str = "singleplayer";
+/// The world picked in the world list.
+pub static SINGLEPLAYER_WORLD: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+/// Seconds between taking the world list's thumbnail of the singleplayer world.
+pub const THUMBNAIL_INTERVAL: f32 = 30.0;
+/// The port we try first when opening singleplayer to LAN.
+pub const LAN_PORT: u16 = 4848;
+
 pub static mut DECIDEDSPORMP: bool = false;
 
 pub static mut MOVING: bool = false;
@@ -411,6 +431,10 @@ pub struct Game {
     pub nodes: Vec<Vec<Node>>,
     pub current_time: f32,
     pub netconn: NetworkConnector,
+    /// The server we run ourselves in singleplayer, reached over an in-memory stream.
+    pub local_server: Option<Server>,
+    /// Seconds since the world's thumbnail was last taken.
+    pub thumbnail_timer: f32,
     pub server_command_queue: Arc<lockfree::queue::Queue<Message>>,
     pub hp_server_command_queue: Arc<lockfree::queue::Queue<Message>>,
     pub headless: bool,
@@ -419,6 +443,10 @@ pub struct Game {
     pub ambient_bright_mult: f32,
     pub daylength: f32,
     pub timeofday: Arc<Mutex<f32>>,
+    /// How far behind the server's clock we are, made up a bit each frame so the sky doesn't jump.
+    pub time_drift: f32,
+    /// The server stopped its clock.
+    pub time_locked: bool,
     pub sunrise_factor: f32,
     pub sunset_factor: f32,
     pub visions_timer: f32,
@@ -440,9 +468,14 @@ pub struct Game {
 
     pub health: Arc<AtomicI8>,
     pub crafting_open: bool,
+    /// Typing a chat line, the keyboard goes to the chat box.
+    pub chat_open: bool,
     pub stamina: Arc<AtomicI32>,
     pub weathertype: f32,
     pub chest_registry: Arc<DashMap<vec::IVec3, ChestInventory>>,
+    pub pending_edits: PendingEdits,
+    pub edit_history: EditHistory,
+    pub build_tools: BuildTools,
 }
 
 pub const ROWLENGTH: i32 = 8;
@@ -495,15 +528,8 @@ impl Game {
         unsafe {
             SHOULDRUN = true;
         }
-        let mut connectonstart = connectonstart;
         //wait_for_decide_singleplayer();
 
-        unsafe {
-            if SINGLEPLAYER {
-                connectonstart = false;
-            }
-        }
-
         let oldshader = Shader::new(path!("assets/oldvert.glsl"), path!("assets/oldfrag.glsl"));
         let shader0 = Shader::new(path!("assets/vert.glsl"), path!("assets/frag.glsl"));
         let skyshader = Shader::new(path!("assets/skyvert.glsl"), path!("assets/skyfrag.glsl"));
@@ -1059,6 +1085,9 @@ impl Game {
                 &chest_registry,
                 &needtosend,
             ),
+            local_server: None,
+            //The first one a few seconds after landing
+            thumbnail_timer: THUMBNAIL_INTERVAL - 5.0,
             server_command_queue: server_command_queue.clone(),
             hp_server_command_queue: server_command_hp_queue.clone(),
             headless,
@@ -1067,6 +1096,8 @@ impl Game {
             ambient_bright_mult: 1.0,
             daylength: 900.0,
             timeofday: Arc::new(Mutex::new(250.0)),
+            time_drift: 0.0,
+            time_locked: false,
             sunrise_factor: 0.0,
             sunset_factor: 0.0,
             visions_timer: 0.0,
@@ -1088,9 +1119,13 @@ impl Game {
             needtosend,
             health,
             crafting_open: false,
+            chat_open: false,
             stamina,
             weathertype: 0.0,
             chest_registry,
+            pending_edits: PendingEdits::new(),
+            edit_history: EditHistory::new(),
+            build_tools: BuildTools::default(),
         };
         #[cfg(feature = "glfw")]
         if !headless {
@@ -1309,64 +1344,10 @@ impl Game {
         }
     }
 
-    pub fn save_one_chest_to_file(&self, key: IVec3) {
-        let seed = {
-            let c = self.chunksys.read();
-            let s = unsafe { CURRSEED.load(std::sync::atomic::Ordering::Relaxed) };
-            s.clone()
-        };
-
-        let table_name = format!("chest_registry_{}", seed);
-
-        match Connection::open("chestdb") {
-            Ok(conn) => {
-                // Ensure the table exists
-                conn.execute(
-                    &format!(
-                        "CREATE TABLE IF NOT EXISTS {} (
-                            x INTEGER,
-                            y INTEGER,
-                            z INTEGER,
-                            dirty BOOLEAN,
-                            inventory BLOB,
-                            PRIMARY KEY (x, y, z)
-                        )",
-                        table_name
-                    ),
-                    (),
-                )
-                .unwrap();
-
-                // Get the chest inventory for the given key
-                if let Some(chest_inventory) = self.chest_registry.get(&key) {
-                    let inv_bin = bincode::serialize(&chest_inventory.inv).unwrap();
-
-                    // Update the specific entry in the database
-                    let mut stmt = conn.prepare(&format!(
-                        "INSERT OR REPLACE INTO {} (x, y, z, dirty, inventory) VALUES (?, ?, ?, ?, ?)",
-                        table_name
-                    )).unwrap();
-
-                    stmt.execute(params![key.x, key.y, key.z, chest_inventory.dirty, inv_bin])
-                        .unwrap();
-                } else {
-                    info!("No chest inventory found for key {:?}", key);
-                }
-            }
-            Err(_e) => {}
-        };
-    }
-
-    pub fn save_current_chests_to_file(&self) {
-        let seed = {
-            let c = self.chunksys.read();
-            let s = unsafe { CURRSEED.load(std::sync::atomic::Ordering::Relaxed) };
-            s.clone()
-        };
-
+    /// The chest database at `path`, with the table for `seed`'s chests made if it isn't there yet.
+    fn open_chest_table(path: &Path, seed: u32) -> StorageResult<(Connection, String)> {
         let table_name = format!("chest_registry_{}", seed);
-
-        let conn = Connection::open("chestdb").unwrap();
+        let conn = Connection::open(path).map_err(|e| WorldStorageError::sql(path, e))?;
 
         conn.execute(
             &format!(
@@ -1382,7 +1363,39 @@ impl Game {
             ),
             (),
         )
-        .unwrap();
+        .map_err(|e| WorldStorageError::sql(path, e))?;
+        Ok((conn, table_name))
+    }
+
+    pub fn save_one_chest_to_file(&self, key: IVec3) -> StorageResult<()> {
+        let seed = unsafe { CURRSEED.load(std::sync::atomic::Ordering::Relaxed) };
+        let path = Path::new("chestdb");
+        let (conn, table_name) = Self::open_chest_table(path, seed)?;
+
+        // Get the chest inventory for the given key
+        if let Some(chest_inventory) = self.chest_registry.get(&key) {
+            let inv_bin = bincode::serialize(&chest_inventory.inv).unwrap();
+
+            // Update the specific entry in the database
+            conn.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {} (x, y, z, dirty, inventory) VALUES (?, ?, ?, ?, ?)",
+                    table_name
+                ),
+                params![key.x, key.y, key.z, chest_inventory.dirty, inv_bin],
+            )
+            .map_err(|e| WorldStorageError::sql(path, e))?;
+        } else {
+            info!("No chest inventory found for key {:?}", key);
+        }
+        Ok(())
+    }
+
+    pub fn save_current_chests_to_file(&self) -> StorageResult<()> {
+        let seed = unsafe { CURRSEED.load(std::sync::atomic::Ordering::Relaxed) };
+        let path = Path::new("chestdb");
+        let sql_error = |e| WorldStorageError::sql(path, e);
+        let (conn, table_name) = Self::open_chest_table(path, seed)?;
 
         // Insert chest_registry entries
         let mut stmt = conn
@@ -1390,100 +1403,47 @@ impl Game {
                 "INSERT OR REPLACE INTO {} (x, y, z, dirty, inventory) VALUES (?, ?, ?, ?, ?)",
                 table_name
             ))
-            .unwrap();
+            .map_err(sql_error)?;
 
         for entry in self.chest_registry.iter() {
             let key = entry.key();
             let chest_inventory = entry.value();
             let inv_bin = bincode::serialize(&chest_inventory.inv).unwrap();
             stmt.execute(params![key.x, key.y, key.z, chest_inventory.dirty, inv_bin])
-                .unwrap();
+                .map_err(sql_error)?;
         }
+        Ok(())
     }
 
-    pub fn load_chests_from_file(&self) {
-        let seed = {
-            let c = self.chunksys.read();
-            let s = unsafe { CURRSEED.load(std::sync::atomic::Ordering::Relaxed) };
-            s.clone()
-        };
-
-        let table_name = format!("chest_registry_{}", seed);
-
-        let conn = Connection::open("chestdb").unwrap();
-
-        conn.execute(
-            &format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                x INTEGER,
-                y INTEGER,
-                z INTEGER,
-                dirty BOOLEAN,
-                inventory BLOB,
-                PRIMARY KEY (x, y, z)
-            )",
-                table_name
-            ),
-            (),
-        )
-        .unwrap();
-
-        let mut stmt = conn
-            .prepare(&format!(
-                "SELECT x, y, z, dirty, inventory FROM {}",
-                table_name
-            ))
-            .unwrap();
-
-        let chest_iter = stmt
-            .query_map([], |row| {
-                let x: i32 = row.get(0)?;
-                let y: i32 = row.get(1)?;
-                let z: i32 = row.get(2)?;
-                let dirty: bool = row.get(3)?;
-                let inventory: Vec<u8> = row.get(4)?;
-                let inv: [(u32, u32); ROWLENGTH as usize * 4] =
-                    bincode::deserialize(&inventory).unwrap();
-                Ok((IVec3 { x, y, z }, ChestInventory { dirty, inv }))
-            })
-            .unwrap();
-
-        for chest in chest_iter {
-            let (coords, chest_inventory) = chest.unwrap();
-            self.chest_registry.insert(coords, chest_inventory);
-        }
+    pub fn load_chests_from_file(&self) -> StorageResult<()> {
+        let seed = unsafe { CURRSEED.load(std::sync::atomic::Ordering::Relaxed) };
+        Self::static_load_chests_from_path("chestdb", seed, &self.chest_registry)
     }
 
     pub fn static_load_chests_from_file(
         seed: u32,
         chest_registry: &Arc<DashMap<IVec3, ChestInventory>>,
-    ) {
-        let table_name = format!("chest_registry_{}", seed);
-
-        let conn = Connection::open("chestdb").unwrap();
+    ) -> StorageResult<()> {
+        Self::static_load_chests_from_path("chestdb", seed, chest_registry)
+    }
 
-        conn.execute(
-            &format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                x INTEGER,
-                y INTEGER,
-                z INTEGER,
-                dirty BOOLEAN,
-                inventory BLOB,
-                PRIMARY KEY (x, y, z)
-            )",
-                table_name
-            ),
-            (),
-        )
-        .unwrap();
+    /// Reads every chest saved on planet `seed` into `chest_registry`. A chest that won't read back
+    /// means the database is damaged, and stops the load rather than leaving that chest empty.
+    pub fn static_load_chests_from_path<P: AsRef<Path>>(
+        path: P,
+        seed: u32,
+        chest_registry: &Arc<DashMap<IVec3, ChestInventory>>,
+    ) -> StorageResult<()> {
+        let path = path.as_ref();
+        let sql_error = |e| WorldStorageError::sql(path, e);
+        let (conn, table_name) = Self::open_chest_table(path, seed)?;
 
         let mut stmt = conn
             .prepare(&format!(
                 "SELECT x, y, z, dirty, inventory FROM {}",
                 table_name
             ))
-            .unwrap();
+            .map_err(sql_error)?;
 
         let chest_iter = stmt
             .query_map([], |row| {
@@ -1492,20 +1452,26 @@ impl Game {
                 let z: i32 = row.get(2)?;
                 let dirty: bool = row.get(3)?;
                 let inventory: Vec<u8> = row.get(4)?;
-                let inv: [(u32, u32); ROWLENGTH as usize * 4] =
-                    bincode::deserialize(&inventory).unwrap();
-                Ok((IVec3 { x, y, z }, ChestInventory { dirty, inv }))
+                Ok((IVec3 { x, y, z }, dirty, inventory))
             })
-            .unwrap();
+            .map_err(sql_error)?;
 
         for chest in chest_iter {
-            let (coords, chest_inventory) = chest.unwrap();
-            chest_registry.insert(coords, chest_inventory);
+            let (coords, dirty, inventory) = chest.map_err(sql_error)?;
+            let inv: [(u32, u32); ROWLENGTH as usize * 4] = bincode::deserialize(&inventory)
+                .map_err(|e| WorldStorageError::corrupt(path, format!("the chest at {} won't read back: {}", coords, e)))?;
+            chest_registry.insert(coords, ChestInventory { dirty, inv });
         }
+        Ok(())
     }
 
     pub fn wait_for_new_address(&mut self) {
-        if self.vars.in_multiplayer {
+        if unsafe { SINGLEPLAYER } {
+            match self.start_local_server() {
+                Ok(()) => info!("Started the singleplayer server!"),
+                Err(e) => info!("Couldn't start the singleplayer server: {e}"),
+            }
+        } else if self.vars.in_multiplayer {
             //print!("Enter server address (e.g., 127.0.0.1:4848): ");
             //io::stdout().flush().unwrap(); // Ensure the prompt is printed before reading input
 
@@ -1522,6 +1488,73 @@ impl Game {
             info!("Connected to the server!");
         }
     }
+
+    /// Singleplayer is a server in this process that only we are connected to, so the world, chests, drops
+    /// and mobs all work exactly like they do in multiplayer.
+    pub fn start_local_server(&mut self) -> io::Result<()> {
+        let world_dir = SINGLEPLAYER_WORLD.lock().clone().unwrap_or_else(|| PathBuf::from(SINGLEPLAYER_WORLD_DIR));
+        let mut config = ServerConfig::new("127.0.0.1:0", world_dir.clone());
+        config.udp = false;
+        config.lan_discovery = false;
+        config.name = String::from("LAN World");
+        config.schematics_dir = Some(PathBuf::from(SCHEMATICS_DIR));
+        let server = Server::start(config)?;
+
+        //Our copy of what it sends stays with the world instead of in the working directory
+        self.netconn.download_dir = world_dir.join(CLIENT_DIR);
+        self.netconn.connect_stream(server.connect_local());
+        self.local_server = Some(server);
+        Ok(())
+    }
+
+    /// Keeps what we're looking at every so often as the singleplayer world's thumbnail in the world list.
+    /// Taken between drawing the world and the HUD, so it's just the view.
+    #[cfg(feature = "glfw")]
+    pub fn update_thumbnail(&mut self) {
+        let Some(server) = &self.local_server else {
+            return;
+        };
+        self.thumbnail_timer += self.delta_time;
+        if self.thumbnail_timer < THUMBNAIL_INTERVAL || self.vars.menu_open {
+            return;
+        }
+        self.thumbnail_timer = 0.0;
+
+        let (width, height) = self.window.read().get_framebuffer_size();
+        if width <= 0 || height <= 0 {
+            return;
+        }
+        let mut pixels = vec![0u8; width as usize * height as usize * 4];
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(0, 0, width, height, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut gl::types::GLvoid);
+        }
+
+        //Scaling and encoding can happen off the render thread
+        let dir = server.state.world_dir.as_ref().clone();
+        thread::spawn(move || {
+            if let Err(e) = saves::save_thumbnail(&dir, width as u32, height as u32, pixels) {
+                info!("Couldn't save the world's thumbnail: {}", e);
+            }
+        });
+    }
+
+    /// Lets other players on the LAN join the singleplayer world we're in. Its server is already running
+    /// in this process, it just starts listening on the network as well.
+    pub fn open_to_lan(&mut self) -> io::Result<SocketAddr> {
+        let server = match self.local_server.as_mut() {
+            Some(server) => server,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "not in a singleplayer world")),
+        };
+
+        let addr = server
+            .open_to_lan(&format!("0.0.0.0:{}", LAN_PORT))
+            .or_else(|_| server.open_to_lan("0.0.0.0:0"))?;
+
+        info!("Opened to LAN on port {}", addr.port());
+        Ok(addr)
+    }
+
     pub fn button_command(&mut self, str: String) {
         match str.as_str() {
             "quittomainmenu" => {
@@ -1530,6 +1563,9 @@ impl Game {
                     self.netconn
                         .send(&Message::new(MessageType::Disconnect, Vec3::ZERO, 0.0, 0))
                 }
+                if let Some(server) = self.local_server.take() {
+                    server.shutdown();
+                }
                 #[cfg(feature = "glfw")]
                 self.window.write().set_should_close(true);
             }
@@ -1546,8 +1582,32 @@ impl Game {
                     ("Close Menu".to_string(), "closemenu".to_string()),
                     ("Recipe Book".to_string(), "recipemenu".to_string()),
                     ("Settings".to_string(), "settingsmenu".to_string()),
-                    ("Quit Game".to_string(), "quittomainmenu".to_string()),
                 ];
+                if let Some(server) = &self.local_server {
+                    match server.lan_addr {
+                        Some(addr) => self.currentbuttons.push((format!("Open to LAN on port {}", addr.port()), "".to_string())),
+                        None => self.currentbuttons.push(("Open to LAN".to_string(), "opentolan".to_string())),
+                    }
+                }
+                self.currentbuttons.push(("Quit Game".to_string(), "quittomainmenu".to_string()));
+                self.vars.menu_open = true;
+            }
+            "opentolan" => {
+                match self.open_to_lan() {
+                    Ok(addr) => {
+                        self.currentbuttons = vec![
+                            (format!("Open to LAN on port {}", addr.port()), "".to_string()),
+                            ("Close Menu".to_string(), "closemenu".to_string()),
+                        ];
+                    }
+                    Err(e) => {
+                        info!("Couldn't open to LAN: {e}");
+                        self.currentbuttons = vec![
+                            (format!("Couldn't open to LAN: {e}"), "".to_string()),
+                            ("Back to Previous Menu".to_string(), "escapemenu".to_string()),
+                        ];
+                    }
+                }
                 self.vars.menu_open = true;
             }
             "settingsmenu" => {
@@ -2776,15 +2836,7 @@ impl Game {
                         .read()
                         .set_block_and_queue_rerender_no_sound(spot, 41, false, true, true);
                 } else {
-                    let mut message = Message::new(
-                        MessageType::BlockSet,
-                        Vec3::new(spot.x as f32, spot.y as f32, spot.z as f32),
-                        0.0,
-                        41,
-                    );
-                    message.infof = 0.0;
-
-                    self.netconn.sendqueue.push(message);
+                    self.predict_block_edit(&[(spot, 41)], false, &[], None);
                 }
 
                 #[cfg(feature = "audio")]
@@ -2803,15 +2855,7 @@ impl Game {
                         .read()
                         .set_block_and_queue_rerender_no_sound(spot, 40, false, true, true);
                 } else {
-                    let mut message = Message::new(
-                        MessageType::BlockSet,
-                        Vec3::new(spot.x as f32, spot.y as f32, spot.z as f32),
-                        0.0,
-                        40,
-                    );
-                    message.infof = 0.0;
-
-                    self.netconn.sendqueue.push(message);
+                    self.predict_block_edit(&[(spot, 40)], false, &[], None);
                 }
                 #[cfg(feature = "audio")]
                 unsafe {
@@ -2894,95 +2938,66 @@ impl Game {
         return b / peak_height;
     }
 
-    pub fn load_my_inv_from_file(&self) {
-        let table_name = "invs";
+    /// Reads our own row out of `table` in the chest database, None if we haven't got one yet.
+    fn load_my_row(&self, table_name: &str, column: &str) -> StorageResult<Option<Vec<u8>>> {
+        let path = &self.netconn.download_dir.join("chestdb");
+        let sql_error = |e| WorldStorageError::sql(path, e);
 
-        let conn = Connection::open("chestdb").unwrap();
+        let conn = Connection::open(path).map_err(sql_error)?;
 
         conn.execute(
             &format!(
                 "CREATE TABLE IF NOT EXISTS {} (
                 id TEXT PRIMARY KEY,
-                inventory BLOB
+                {} BLOB
             )",
-                table_name
+                table_name, column
             ),
             (),
         )
-        .unwrap();
-
-        let mut stmt = conn
-            .prepare(&format!(
-                "SELECT inventory FROM {} WHERE id = ?1",
-                table_name
-            ))
-            .unwrap();
-
-        let mut rows = stmt
-            .query([self.my_uuid.read().unwrap().to_string()])
-            .unwrap();
+        .map_err(sql_error)?;
 
-        if let Some(row) = rows.next().unwrap() {
-            let inventory: Vec<u8> = row.get(0).unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM {} WHERE id = ?1", column, table_name),
+            [self.my_uuid.read().unwrap().to_string()],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(sql_error)
+    }
 
-            match bincode::deserialize::<[(u32, u32); ROWLENGTH as usize]>(&inventory) {
-                Ok(inv) => {
-                    let mut invlock = self.inventory.write();
-                    invlock.inv = inv.clone();
-                }
-                Err(_e) => {
-                    info!("Couldn't de-serialize inventory blob");
-                }
-            }
-        } else {
+    pub fn load_my_inv_from_file(&self) -> StorageResult<()> {
+        if let Some(inventory) = self.load_my_row("invs", "inventory")? {
+            let inv = bincode::deserialize::<[(u32, u32); ROWLENGTH as usize]>(&inventory)
+                .map_err(|e| WorldStorageError::corrupt(self.netconn.download_dir.join("chestdb"), format!("our inventory won't read back: {}", e)))?;
+            let mut invlock = self.inventory.write();
+            invlock.inv = inv;
         }
+        Ok(())
     }
 
-    pub fn load_my_pos_from_file(&self) {
-        let table_name = "poses";
-
-        let conn = Connection::open("chestdb").unwrap();
-
-        conn.execute(
-            &format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                id TEXT PRIMARY KEY,
-                playerposition BLOB
-            )",
-                table_name
-            ),
-            (),
-        )
-        .unwrap();
-
-        let mut stmt = conn
-            .prepare(&format!(
-                "SELECT playerposition FROM {} WHERE id = ?1",
-                table_name
-            ))
-            .unwrap();
-
-        let mut rows = stmt
-            .query([self.my_uuid.read().unwrap().to_string()])
-            .unwrap();
-
-        if let Some(row) = rows.next().unwrap() {
-            let pp: Vec<u8> = row.get(0).unwrap();
+    pub fn load_my_pos_from_file(&self) -> StorageResult<()> {
+        if let Some(pp) = self.load_my_row("poses", "playerposition")? {
+            let playpos = bincode::deserialize::<PlayerPosition>(&pp)
+                .map_err(|e| WorldStorageError::corrupt(self.netconn.download_dir.join("chestdb"), format!("our position won't read back: {}", e)))?;
+            let mut camlock = self.camera.lock();
+            camlock.position = Vec3::new(playpos.pos.x, playpos.pos.y, playpos.pos.z);
+            camlock.pitch = playpos.pitch;
+            camlock.yaw = playpos.yaw;
+        }
+        Ok(())
+    }
 
-            match bincode::deserialize::<PlayerPosition>(&pp) {
-                Ok(playpos) => {
-                    let mut camlock = self.camera.lock();
-                    camlock.position = Vec3::new(playpos.pos.x, playpos.pos.y, playpos.pos.z);
-                    camlock.pitch = playpos.pitch;
-                    camlock.yaw = playpos.yaw;
-                    drop(camlock);
-                }
-                Err(_e) => {
-                    info!("Couldn't de-serialize playerpos blob");
-                }
-            }
-        } else {
+    /// Puts up a menu saying what went wrong with the world's files, instead of going down with them.
+    pub fn show_storage_error(&mut self, e: &WorldStorageError) {
+        info!("World storage error: {}", e);
+        self.currentbuttons = vec![(format!("Couldn't load the world: {}", e), "".to_string())];
+        if e.is_corrupt() {
+            self.currentbuttons.push(("Its backups can be restored from the world list".to_string(), "".to_string()));
         }
+        self.currentbuttons.push(("Close Menu".to_string(), "closemenu".to_string()));
+        self.currentbuttons.push(("Quit Game".to_string(), "quittomainmenu".to_string()));
+        self.vars.menu_open = true;
     }
 
     pub fn update(&mut self) {
@@ -3022,6 +3037,8 @@ impl Game {
             }
         }
 
+        self.send_build_edits();
+
         #[cfg(feature = "audio")]
         self.update_music_volume();
 
@@ -3143,7 +3160,14 @@ impl Game {
 
         let mut todlock = self.timeofday.lock();
 
-        if !self.vars.menu_open || self.vars.in_multiplayer {
+        if self.vars.in_multiplayer {
+            if !self.time_locked {
+                *todlock += self.delta_time;
+            }
+            let step = calendar::catch_up(self.time_drift, self.daylength, self.delta_time);
+            *todlock = (*todlock + step).rem_euclid(self.daylength);
+            self.time_drift -= step;
+        } else if !self.vars.menu_open {
             *todlock = (*todlock + self.delta_time) % self.daylength;
         }
 
@@ -3250,71 +3274,44 @@ impl Game {
                     Some(comm) => {
                         match comm.message_type {
                             MessageType::BlockSet => {
-                                if comm.infof == 1.0 {
-                                    if comm.info == 0 {
-                                        self.chunksys.read().set_block_and_queue_rerender(
-                                            IVec3::new(comm.x as i32, comm.y as i32, comm.z as i32),
-                                            comm.info,
-                                            true,
-                                            true,
-                                            false,
-                                        );
-                                    } else {
-                                        self.chunksys.read().set_block_and_queue_rerender(
-                                            IVec3::new(comm.x as i32, comm.y as i32, comm.z as i32),
-                                            comm.info,
-                                            false,
-                                            true,
-                                            false,
-                                        );
-                                    }
-                                } else {
-                                    if comm.info == 0 {
-                                        self.chunksys.read().set_block_and_queue_rerender_no_sound(
-                                            IVec3::new(comm.x as i32, comm.y as i32, comm.z as i32),
-                                            comm.info,
-                                            true,
-                                            true,
-                                            false,
-                                        );
-                                    } else {
-                                        self.chunksys.read().set_block_and_queue_rerender_no_sound(
-                                            IVec3::new(comm.x as i32, comm.y as i32, comm.z as i32),
-                                            comm.info,
-                                            false,
-                                            true,
-                                            false,
-                                        );
-                                    }
-                                }
+                                let spot = IVec3::new(comm.x as i32, comm.y as i32, comm.z as i32);
+                                self.reconcile_block_edit(&comm, &[(spot, comm.info)]);
 
                                 unsafe {
                                     UPDATE_THE_BLOCK_OVERLAY = true;
                                 }
                             }
                             MessageType::MultiBlockSet => {
-                                let cread = self.chunksys.read();
+                                let spot = IVec3::new(comm.x as i32, comm.y as i32, comm.z as i32);
+                                self.reconcile_block_edit(&comm, &[(spot, comm.info), (comm.otherpos, comm.info2)]);
 
-                                cread.set_block_no_sound(
-                                    IVec3::new(comm.x as i32, comm.y as i32, comm.z as i32),
-                                    comm.info,
-                                    true,
-                                );
+                                unsafe {
+                                    UPDATE_THE_BLOCK_OVERLAY = true;
+                                }
+                            }
+                            MessageType::BulkBlockSet => {
+                                if let Some(blocks) = self.netconn.bulk_edits.pop() {
+                                    self.reconcile_block_edit(&comm, &blocks);
+                                }
 
-                                cread.set_block_and_queue_rerender(
-                                    comm.otherpos,
-                                    comm.info2,
-                                    true,
-                                    true,
-                                    false,
-                                );
                                 unsafe {
                                     UPDATE_THE_BLOCK_OVERLAY = true;
                                 }
                             }
+                            MessageType::BlockSetRejected => {
+                                self.roll_back_block_edit(comm.info);
+                            }
                             MessageType::ChestReg => {
-                                self.load_my_inv_from_file();
-                                self.load_my_pos_from_file();
+                                let mut loaded = self.load_my_inv_from_file();
+                                if comm.bo {
+                                    //Resync after a reconnect, stay where we are and redraw against the fresh world
+                                    self.chunksys.read().queue_rerender_all();
+                                } else {
+                                    loaded = loaded.and_then(|_| self.load_my_pos_from_file());
+                                }
+                                if let Err(e) = loaded {
+                                    self.show_storage_error(&e);
+                                }
                             }
                             MessageType::ChestInvUpdate => {
                                 let currchest = comm.otherpos;
@@ -3395,6 +3392,13 @@ impl Game {
                                     self.update_inventory();
                                 }
                             }
+                            MessageType::ChestOpen => {
+                                //The server's answer to us asking, a plugin may have said no
+                                #[cfg(feature = "glfw")]
+                                if comm.bo && comm.otherpos == self.hud.current_chest {
+                                    self.open_chest();
+                                }
+                            }
 
                             _ => {}
                         }
@@ -3416,8 +3420,12 @@ impl Game {
                             }
                             MessageType::TimeUpdate => {
                                 //println!("Songindex: {}", unsafe { SONGINDEX });
-                                let mut todlock = self.timeofday.lock();
-                                *todlock = comm.infof;
+                                if comm.x > 0.0 {
+                                    self.daylength = comm.x;
+                                }
+                                self.time_locked = comm.y == 1.0;
+                                let now = *self.timeofday.lock();
+                                self.time_drift = calendar::drift(now, comm.infof, self.daylength);
                                 unsafe {
                                     WEATHERTYPE = comm.rot;
                                 }
@@ -3475,15 +3483,11 @@ impl Game {
                                 match nsme.get_mut(&id) {
                                     Some(mut me) => {
                                         let modent = me.value_mut();
-                                        (*modent).lastpos = (*modent).position.clone();
-                                        (*modent).position = newpos;
                                         (*modent).scale = scale;
-                                        (*modent).lastrot = (*modent).rot.clone();
-                                        (*modent).rot = Vec3::new(0.0, rot, 0.0);
                                         (*modent).sounding = sounding;
                                         (*modent).hostile = hostile;
                                         unsafe {
-                                            (*modent).time_stamp = glfwGetTime();
+                                            (*modent).receive_snapshot(glfwGetTime(), newpos, Vec3::new(0.0, rot, 0.0));
                                         }
                                     }
                                     None => {
@@ -3516,14 +3520,10 @@ impl Game {
                                 match pme.get_mut(&uuid) {
                                     Some(mut me) => {
                                         let modent = me.value_mut();
-                                        (*modent).lastpos = (*modent).position.clone();
-                                        (*modent).position = newpos;
                                         (*modent).scale = scale;
-                                        (*modent).lastrot = (*modent).rot.clone();
-                                        (*modent).rot = Vec3::new(0.0, rot, 0.0);
                                         //(*modent).sounding = sounding;
                                         unsafe {
-                                            (*modent).time_stamp = glfwGetTime();
+                                            (*modent).receive_snapshot(glfwGetTime(), newpos, Vec3::new(0.0, rot, 0.0));
                                         }
                                     }
                                     None => {
@@ -3542,6 +3542,8 @@ impl Game {
                             MessageType::Seed => {
                                 //Means we're going to a new world
                                 self.non_static_model_entities.clear();
+                                self.pending_edits.clear();
+                                self.edit_history.clear();
                             }
                             _ => {}
                         }
@@ -3586,24 +3588,36 @@ impl Game {
             }
             self.draw();
 
+            #[cfg(feature = "glfw")]
+            self.update_thumbnail();
+
             //if !self.vars.ship_taken_off {
             let camclone = self.draw_select_cube();
             //}
 
             
-            unsafe {
-                if BUILD_PREVIEW_MODE && MOUSE_ON_CUBE {
-                    LIST_OF_PREVIEWED_SPOTS = Self::draw_user_build_preview(
-                        unsafe { &SELECTCUBESPOT },
-                        &BUILD_VOXEL_MODELS[SELECTED_BUILD],
-                        &self.oldshader,
-                        &camclone,
-                        self.ambient_bright_mult,
-                        self.vars.walkbobtimer,
-                        &self.tex,
-                    );
+            if unsafe { BUILD_PREVIEW_MODE } {
+                if unsafe { MOUSE_ON_CUBE } {
+                    self.update_build_preview();
+                    if let Some(preview) = &self.build_tools.preview {
+                        Self::draw_user_build_preview(
+                            &preview.origin,
+                            &preview.edits,
+                            preview.generation,
+                            &self.oldshader,
+                            &camclone,
+                            self.ambient_bright_mult,
+                            self.vars.walkbobtimer,
+                            &self.tex,
+                        );
+                    }
+                }
+
+                //The selection's corners
+                for corner in [self.build_tools.selection.first, self.build_tools.selection.second].into_iter().flatten() {
+                    let at = Vec3::new(corner.x as f32, corner.y as f32, corner.z as f32);
+                    self.select_cube.draw_at(at, &camclone.mvp, self.vars.walkbobtimer);
                 }
-                
             }
             
             
@@ -4406,7 +4420,9 @@ impl Game {
                         BREAK_TIME = BREAK_TIME + self.delta_time * modifier;
                         if bprog >= 1.0 {
                             if !self.vars.ship_taken_off {
+                                self.edit_history.begin();
                                 self.cast_break_ray();
+                                self.edit_history.end();
                                 //UPDATE_THE_OVERLAY = true;
                             }
                             BREAK_TIME = 0.0;
@@ -5746,6 +5762,355 @@ impl Game {
             }
         }
     }
+    /// The block in the selected hotbar slot, 0 if it's empty.
+    #[cfg(feature = "glfw")]
+    pub fn held_block(&self) -> u32 {
+        let slot = self.inventory.read().inv[self.hud.bumped_slot];
+        if slot.1 > 0 { slot.0 } else { 0 }
+    }
+
+    /// Works out what the build tool would do where we're looking, for the preview and its cost.
+    #[cfg(feature = "glfw")]
+    pub fn update_build_preview(&mut self) {
+        let target = unsafe { SELECTCUBESPOT };
+        let nudge = unsafe { IVec3::new(BUILD_MODEL_OFFSET.x, 0, BUILD_MODEL_OFFSET.y) };
+        let held = self.held_block();
+        let stamp_index = unsafe { SELECTED_BUILD };
+        let csys = self.chunksys.read();
+        self.build_tools.refresh(
+            target,
+            nudge,
+            held,
+            stamp_index,
+            || unsafe { BUILD_VOXEL_MODELS.get(stamp_index).map(|m| m.blocks()).unwrap_or_default() },
+            |spot| csys.blockat(spot),
+        );
+    }
+
+    /// A right click in build mode: marks a corner with the select tool, otherwise pays for what the
+    /// preview shows and queues it to be sent.
+    #[cfg(feature = "glfw")]
+    pub fn use_build_tool(&mut self) {
+        if self.build_tools.tool == BuildTool::Select {
+            self.build_tools.mark(unsafe { SELECTCUBESPOT });
+            self.build_tools.status = match self.build_tools.selection.size() {
+                Some(size) => format!("Selected {} x {} x {}", size.x, size.y, size.z),
+                None => String::from("Now the other corner"),
+            };
+            return;
+        }
+
+        if self.build_tools.pending() > 0 {
+            self.build_tools.status = format!("Still sending the last one, {} blocks to go", self.build_tools.pending());
+            return;
+        }
+
+        self.update_build_preview();
+        let Some(preview) = self.build_tools.preview.clone() else {
+            self.build_tools.status = self.build_tools.problem.clone().unwrap_or_default();
+            return;
+        };
+        if preview.edits.is_empty() {
+            self.build_tools.status = String::from("Nothing there to change");
+            return;
+        }
+
+        if let Err(e) = self.trade_inventory(&preview.cost, &[]) {
+            self.build_tools.status = format!("Not enough, {}", e);
+            return;
+        }
+        let blocks: Vec<(IVec3, u32, u32)> = {
+            let csys = self.chunksys.read();
+            preview.placed().into_iter().map(|(spot, block)| (spot, csys.blockat(spot), block)).collect()
+        };
+        let history = self.edit_history.record(blocks.clone());
+
+        self.build_tools.status = format!("{} blocks on their way", preview.edits.len());
+        self.build_tools.queue(blocks, Some(history));
+    }
+
+    /// Takes `cost` out of the inventory and puts `refund` in, all or neither, and tells the server about
+    /// every slot that changed. Says what's missing if it can't.
+    fn trade_inventory(&mut self, cost: &[(u32, u32)], refund: &[(u32, u32)]) -> Result<(), String> {
+        let changed = {
+            let mut inventory = self.inventory.write();
+            let mut inv = inventory.inv;
+            let Some(mut slots) = buildtools::pay(&mut inv, cost) else {
+                let short = buildtools::shortfall(cost, &inv);
+                let list: Vec<String> = short.iter().map(|(id, need, have)| format!("{} {} (have {})", need, Blocks::get_name(*id), have)).collect();
+                return Err(format!("needs {}", list.join(", ")));
+            };
+            let Some(given) = buildtools::give(&mut inv, refund) else {
+                return Err(String::from("no room in the inventory for what comes back"));
+            };
+            slots.extend(given);
+            slots.sort();
+            slots.dedup();
+            inventory.inv = inv;
+            inventory.dirty = true;
+            slots.into_iter().map(|slot| (slot, inv[slot])).collect::<Vec<_>>()
+        };
+        if changed.is_empty() {
+            return Ok(());
+        }
+        if self.vars.in_multiplayer {
+            for (slot, (id, count)) in changed {
+                self.netconn.send(&Message::invupdate(slot, id, count));
+            }
+        }
+        Game::update_avail_recipes(&self.inventory);
+        #[cfg(feature = "glfw")]
+        {
+            self.hud.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Takes back the last thing we built or broke, by setting the blocks it changed back through the
+    /// same path as any other edit, so the server hears about it and it costs or gives back blocks.
+    #[cfg(feature = "glfw")]
+    pub fn undo_edit(&mut self) {
+        self.step_edit_history(true);
+    }
+
+    #[cfg(feature = "glfw")]
+    pub fn redo_edit(&mut self) {
+        self.step_edit_history(false);
+    }
+
+    #[cfg(feature = "glfw")]
+    fn step_edit_history(&mut self, undo: bool) {
+        let verb = if undo { "undo" } else { "redo" };
+        if self.build_tools.pending() > 0 {
+            self.build_tools.status = format!("Still sending the last one, {} blocks to go", self.build_tools.pending());
+            return;
+        }
+        let taken = if undo { self.edit_history.take_undo() } else { self.edit_history.take_redo() };
+        let Some(action) = taken else {
+            self.build_tools.status = format!("Nothing to {}", verb);
+            return;
+        };
+
+        let step = if undo { action.reversed() } else { action.clone() };
+        let step = {
+            let csys = self.chunksys.read();
+            step.still_applicable(|spot| csys.blockat(spot))
+        };
+        //Someone's built over all of it since, there's nothing left of it to undo
+        if step.blocks.is_empty() {
+            self.build_tools.status = format!("Nothing to {} there anymore, it's changed since", verb);
+            return;
+        }
+
+        let (cost, refund) = step.exchange();
+        if let Err(e) = self.trade_inventory(&cost, &refund) {
+            self.build_tools.status = format!("Can't {} that, {}", verb, e);
+            if undo {
+                self.edit_history.redone(action);
+            } else {
+                self.edit_history.undone(action);
+            }
+            return;
+        }
+
+        //Both the trade and the move between stacks are undone for any of it the server turns down
+        self.build_tools.status = format!("{} {} blocks", if undo { "Undid" } else { "Redid" }, step.blocks.len());
+        let history = if undo { HistoryEntry::Undid(action.id) } else { HistoryEntry::Redid(action.id) };
+        self.build_tools.queue(step.blocks, Some(history));
+        if undo {
+            self.edit_history.undone(action);
+        } else {
+            self.edit_history.redone(action);
+        }
+    }
+
+    /// Sends what the build tools have queued, a few blocks a frame so the server's edit budget isn't blown.
+    pub fn send_build_edits(&mut self) {
+        if self.build_tools.pending() == 0 {
+            return;
+        }
+        for batch in self.build_tools.next_batches(self.delta_time) {
+            self.predict_edit(&batch.edits, false, &batch.cost, &batch.refund, None, batch.history);
+        }
+        if self.build_tools.pending() == 0 {
+            self.build_tools.invalidate();
+        }
+    }
+
+    /// Sets blocks the way a server echo of them would, one block like a `BlockSet`, two like a `MultiBlockSet`
+    /// and more like a `BulkBlockSet`, which rebuilds every chunk it touches once.
+    pub fn draw_block_edit(csys: &ChunkSystem, blocks: &[(IVec3, u32)], sound: bool) {
+        match blocks {
+            [(spot, block)] => {
+                if sound {
+                    csys.set_block_and_queue_rerender(*spot, *block, *block == 0, true, false);
+                } else {
+                    csys.set_block_and_queue_rerender_no_sound(*spot, *block, *block == 0, true, false);
+                }
+            }
+            _ if blocks.len() > 2 => {
+                let mut light = false;
+                for (spot, block) in blocks {
+                    light |= Blocks::is_light(*block) || Blocks::is_light(csys.blockat(*spot));
+                    csys.set_block_no_sound(*spot, *block, true);
+                }
+                for chunk in bulkedit::implicated_chunks(blocks) {
+                    csys.queue_rerender_with_key(chunk, true, light);
+                }
+            }
+            _ => {
+                for (i, (spot, block)) in blocks.iter().enumerate() {
+                    if i + 1 < blocks.len() {
+                        csys.set_block_no_sound(*spot, *block, true);
+                    } else {
+                        csys.set_block_and_queue_rerender(*spot, *block, true, true, false);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws a multiplayer block edit now instead of waiting for the server, and sends it with a sequence id
+    /// so the server's answer can confirm it or roll it back. `cost` is the items placing it used up, `yields`
+    /// the item breaking it dropped.
+    pub fn predict_block_edit(&mut self, blocks: &[(IVec3, u32)], sound: bool, cost: &[(u32, u32)], yields: Option<u32>) {
+        let history = self.edit_history.open_entry();
+        self.predict_edit(blocks, sound, cost, &[], yields, history);
+    }
+
+    /// `predict_block_edit` for an edit that's part of `history` and gave `refund` as well as costing `cost`,
+    /// all of it undone if it's rolled back.
+    fn predict_edit(
+        &mut self,
+        blocks: &[(IVec3, u32)],
+        sound: bool,
+        cost: &[(u32, u32)],
+        refund: &[(u32, u32)],
+        yields: Option<u32>,
+        history: Option<HistoryEntry>,
+    ) {
+        let bulk = blocks.len() > 2;
+        if bulk && (blocks.len() > bulkedit::MAX_BULK_BLOCKS || !blocks.iter().all(|(spot, _)| bulkedit::fits(blocks[0].0, *spot))) {
+            info!("{} blocks don't fit one bulk edit, not sending them", blocks.len());
+            return;
+        }
+        let seq = self.pending_edits.next_seq();
+
+        let recorded = {
+            let csys = self.chunksys.read();
+            let recorded: Vec<(IVec3, u32, u32)> = blocks.iter().map(|(spot, block)| (*spot, csys.blockat(*spot), *block)).collect();
+            Game::draw_block_edit(&csys, blocks, sound);
+            recorded
+        };
+
+        let (spot, block) = blocks[0];
+        let mut message = if bulk {
+            bulkedit::header(seq)
+        } else {
+            Message::new(
+                if blocks.len() > 1 { MessageType::MultiBlockSet } else { MessageType::BlockSet },
+                Vec3::new(spot.x as f32, spot.y as f32, spot.z as f32),
+                0.0,
+                block,
+            )
+        };
+        if let (false, Some((otherpos, otherblock))) = (bulk, blocks.get(1)) {
+            message.otherpos = *otherpos;
+            message.info2 = *otherblock;
+        }
+        message.infof = if sound { 1.0 } else { 0.0 };
+        message.seq = seq;
+
+        self.edit_history.note(&recorded);
+        self.pending_edits.push(PendingEdit { seq, blocks: recorded, cost: cost.to_vec(), refund: refund.to_vec(), yields, history });
+        if bulk {
+            self.netconn.send_bulk(&message, blocks);
+        } else {
+            self.netconn.send(&message);
+        }
+
+        unsafe {
+            UPDATE_THE_BLOCK_OVERLAY = true;
+        }
+    }
+
+    fn is_my_echo(&self, comm: &Message) -> bool {
+        comm.seq != 0 && self.my_uuid.read().is_some_and(|u| u.as_u64_pair() == comm.goose)
+    }
+
+    /// A `BlockSet`, `MultiBlockSet` or `BulkBlockSet` from the server, either our own predicted edit coming back or someone else's.
+    fn reconcile_block_edit(&mut self, comm: &Message, blocks: &[(IVec3, u32)]) {
+        if self.is_my_echo(comm) {
+            if let Some((_edit, corrections)) = self.pending_edits.confirm(comm.seq, blocks) {
+                if !corrections.is_empty() {
+                    Game::draw_block_edit(&self.chunksys.read(), &corrections, false);
+                }
+                return;
+            }
+        }
+
+        let draw: Vec<(IVec3, u32)> = blocks
+            .iter()
+            .filter(|(spot, block)| !self.pending_edits.absorb_foreign(*spot, *block))
+            .cloned()
+            .collect();
+        if draw.len() == blocks.len() {
+            Game::draw_block_edit(&self.chunksys.read(), blocks, comm.infof == 1.0);
+        } else if !draw.is_empty() {
+            Game::draw_block_edit(&self.chunksys.read(), &draw, false);
+        }
+    }
+
+    /// The server threw out one of our predicted edits: put the old blocks back and undo what it did to the inventory.
+    fn roll_back_block_edit(&mut self, seq: u32) {
+        let (edit, restore) = match self.pending_edits.reject(seq) {
+            Some(r) => r,
+            None => return,
+        };
+        info!("Server rejected block edit {}, rolling it back", seq);
+
+        if !restore.is_empty() {
+            Game::draw_block_edit(&self.chunksys.read(), &restore, false);
+        }
+
+        if !edit.cost.is_empty() || !edit.refund.is_empty() {
+            //What's been used up of the refund since can't be taken back
+            let refund: Vec<(u32, u32)> = {
+                let inv = self.inventory.read().inv;
+                edit.refund.iter().map(|(id, count)| (*id, (*count).min(buildtools::count_of(&inv, *id)))).filter(|(_, count)| *count > 0).collect()
+            };
+            if let Err(e) = self.trade_inventory(&refund, &edit.cost) {
+                info!("Couldn't give back what edit {} cost, {}", seq, e);
+            }
+        }
+
+        if let Some(history) = edit.history {
+            let spots: Vec<IVec3> = edit.blocks.iter().map(|(spot, _, _)| *spot).collect();
+            self.edit_history.rejected(history, &spots);
+        }
+
+        if let Some(id) = edit.yields {
+            let spot = edit.blocks[0].0;
+            #[cfg(feature = "glfw")]
+            let tookdrop = self.drops.take_back(Vec3::new(spot.x as f32 + 0.5, spot.y as f32 + 0.5, spot.z as f32 + 0.5), id);
+            #[cfg(not(feature = "glfw"))]
+            let tookdrop = false;
+
+            if !tookdrop {
+                let slot = self.inventory.read().inv.iter().position(|item| item.0 == id && item.1 > 0);
+                if let Some(slot) = slot {
+                    let count = self.inventory.read().inv[slot].1;
+                    let newid = if count == 1 { 0 } else { id };
+                    let _ = Game::set_in_inventory(&self.inventory, slot, newid, count - 1, true, &self.needtosend);
+                }
+            }
+        }
+
+        unsafe {
+            UPDATE_THE_BLOCK_OVERLAY = true;
+        }
+    }
+
     pub fn cast_break_ray(&mut self) {
         let cl = {
             let cl = self.camera.lock();
@@ -5783,16 +6148,7 @@ impl Game {
                     }
 
                     if self.vars.in_multiplayer {
-                        let mut message = Message::new(
-                            MessageType::MultiBlockSet,
-                            Vec3::new(block_hit.x as f32, block_hit.y as f32, block_hit.z as f32),
-                            0.0,
-                            0,
-                        );
-                        message.info2 = 0;
-                        message.otherpos = other_half;
-
-                        self.netconn.send(&message);
+                        self.predict_block_edit(&[(block_hit, 0), (other_half, 0)], true, &[], None);
                     } else {
                         self.chunksys.read().set_block(block_hit, 0, true);
                         self.chunksys
@@ -5805,15 +6161,10 @@ impl Game {
                         self.drops.add_drop(tip, blockat, 1);
                     }
 
-                    //TODO: PROBLEM HERE THAT WILL ALLOW USERS TO KEEP DUPING A BLOCK AS LONG AS THE SERVER DOESNT RESPOND
                     if self.vars.in_multiplayer {
-                        let message = Message::new(
-                            MessageType::BlockSet,
-                            Vec3::new(block_hit.x as f32, block_hit.y as f32, block_hit.z as f32),
-                            0.0,
-                            0,
-                        );
-                        self.netconn.send(&message);
+                        //The drop is taken back if the server says no
+                        let yields = if blockat != 0 { Some(blockat) } else { None };
+                        self.predict_block_edit(&[(block_hit, 0)], true, &[], yields);
                     } else {
                         self.chunksys
                             .read()
@@ -5836,7 +6187,7 @@ impl Game {
             invrowchange -= 1;
         }
 
-        if unsafe {BUILD_PREVIEW_MODE} {
+        if unsafe {BUILD_PREVIEW_MODE} && self.build_tools.tool == BuildTool::Stamp {
             let mut proposednewbuild = unsafe { SELECTED_BUILD } as i8 + invrowchange;
             if proposednewbuild < 0 {
                 proposednewbuild = unsafe { BUILD_VOXEL_MODELS.len() } as i8 - 1;
@@ -5858,15 +6209,18 @@ impl Game {
     }
 
     #[cfg(feature = "glfw")]
+    /// Draws `blocks`, relative to `origin`, see-through where they'd go. Their geometry is only rebuilt
+    /// when `generation` changes.
     pub fn draw_user_build_preview(
-        spot: &IVec3,
-        vox: &JVoxModel,
+        origin: &IVec3,
+        blocks: &[(IVec3, u32)],
+        generation: u64,
         oldshader: &Shader,
         cam_clone: &Camera,
         amb_bm: f32,
         walkbobt: f32,
         texture: &Texture,
-    ) -> Vec<(IVec3, u32)> {
+    ) {
         use crate::{
             chunk::ChW,
             specialblocks::{
@@ -5875,8 +6229,6 @@ impl Game {
             textureface::ONE_OVER_16,
         };
 
-        static mut vec: Vec<(IVec3, u32)> = Vec::new();
-
         unsafe {
             use glam::{I16Vec3, U16Vec3};
 
@@ -5899,24 +6251,14 @@ impl Game {
 
             gl::UseProgram(oldshader.shader_id);
 
-            static mut LAST_MOD_IND: i32 = -99;
+            static mut LAST_GENERATION: Option<u64> = None;
 
-            if LAST_MOD_IND != vox.idnumber {
-
-                vec.clear();
-                //println!("Lastmodind {LAST_MOD_IND} is not equal to {}", vox.idnumber);
+            if LAST_GENERATION != Some(generation) {
 
                 UBP_UVDATA.clear();
                 UBP_VDATA.clear();
 
-                for i in &vox.model.models {
-                    let size = i.size;
-                    for v in &i.voxels {
-                        let rearr_point = IVec3::new(
-                            v.point.x as i32 - (size.x / 2) as i32,
-                            v.point.z as i32,
-                            v.point.y as i32 - (size.y / 2) as i32,
-                        );
+                for &(rearr_point, combined) in blocks.iter().take(PREVIEW_LIMIT) {
                         //println!("{:?}", rearr_point);
                         
 
@@ -5924,10 +6266,6 @@ impl Game {
                         let doorbottomuvs = DoorInfo::get_door_uvs(TextureFace::new(11, 0));
                         let doortopuvs = DoorInfo::get_door_uvs(TextureFace::new(11, 1));
 
-                     
-                        let combined = v.color_index.0 as u32; 
-
-                        vec.push((rearr_point , combined));
                         let block = combined & Blocks::block_id_bits();
                         let flags = combined & Blocks::block_flag_bits();
 
@@ -6041,10 +6379,9 @@ impl Game {
                                 ]);
                             }
                         }
-                    }
                 }
 
-                LAST_MOD_IND = vox.idnumber;
+                LAST_GENERATION = Some(generation);
 
                 WorldGeometry::bind_old_geometry_diff_vao(
                     UBP_VBO,
@@ -6186,9 +6523,9 @@ impl Game {
                     oldshader.shader_id,
                     b"transformpos\0".as_ptr() as *const i8,
                 ),
-                spot.x as f32 + BUILD_MODEL_OFFSET.x as f32,
-                spot.y as f32,
-                spot.z as f32 + BUILD_MODEL_OFFSET.y as f32,
+                origin.x as f32,
+                origin.y as f32,
+                origin.z as f32,
             );
 
             //println!("UVDATALEN: {}", UBP_VDATA.len() as f32 / 5.0);
@@ -6196,7 +6533,6 @@ impl Game {
             gl::DrawArrays(gl::TRIANGLES, 0, (*UBP_VDATA).len() as i32 / 5);
 
         }
-        unsafe { vec.clone() }
     }
 
     #[cfg(feature = "glfw")]
@@ -6239,19 +6575,7 @@ impl Game {
                         DoorInfo::toggle_door_open_bit(&mut otherhalfbits);
 
                         if self.vars.in_multiplayer {
-                            let mut message = Message::new(
-                                MessageType::MultiBlockSet,
-                                Vec3::new(
-                                    block_hit.x as f32,
-                                    block_hit.y as f32,
-                                    block_hit.z as f32,
-                                ),
-                                0.0,
-                                blockbitshere,
-                            );
-                            message.info2 = otherhalfbits;
-                            message.otherpos = otherhalf;
-                            self.netconn.send(&message);
+                            self.predict_block_edit(&[(block_hit, blockbitshere), (otherhalf, otherhalfbits)], true, &[], None);
                         } else {
                             self.chunksys
                                 .write()
@@ -6270,7 +6594,14 @@ impl Game {
                         //let _csys = self.chunksys.write();
 
                         self.hud.current_chest = block_hit;
-                        updateinv = true;
+                        if self.vars.in_multiplayer {
+                            //Opens when the server says we may
+                            let mut ask = Message::new(MessageType::ChestOpen, Vec3::ZERO, 0.0, 0);
+                            ask.otherpos = block_hit;
+                            self.netconn.send(&ask);
+                        } else {
+                            updateinv = true;
+                        }
                     } else if blockidhere == 31 {
                         unsafe {
                             ATSMALLTABLE = false;
@@ -6403,6 +6734,7 @@ impl Game {
 
                                         let rightup = right + IVec3::new(0, 1, 0);
                                         let mut neightopbits = csysread.blockat(rightup);
+                                        drop(csysread);
 
                                         DoorInfo::set_opposite_door_bits(&mut top_id, 1);
                                         DoorInfo::set_opposite_door_bits(&mut bottom_id, 1);
@@ -6413,21 +6745,7 @@ impl Game {
                                         let _chunktoreb = ChunkSystem::spot_to_chunk_pos(&right);
 
                                         if self.vars.in_multiplayer {
-                                            let mut message = Message::new(
-                                                MessageType::MultiBlockSet,
-                                                Vec3::new(
-                                                    right.x as f32,
-                                                    right.y as f32,
-                                                    right.z as f32,
-                                                ),
-                                                0.0,
-                                                blockbitsright,
-                                            );
-
-                                            message.info2 = neightopbits;
-                                            message.otherpos = rightup;
-
-                                            self.netconn.send(&message);
+                                            self.predict_block_edit(&[(right, blockbitsright), (rightup, neightopbits)], true, &[], None);
                                         } else {
                                             self.chunksys.read().set_block_and_queue_rerender(
                                                 right,
@@ -6457,6 +6775,7 @@ impl Game {
                                         let csysread = self.chunksys.read();
 
                                         let mut neightopbits = csysread.blockat(leftup);
+                                        drop(csysread);
 
                                         DoorInfo::set_opposite_door_bits(&mut top_id, 1);
                                         DoorInfo::set_opposite_door_bits(&mut bottom_id, 1);
@@ -6467,21 +6786,7 @@ impl Game {
                                         let _chunktoreb = ChunkSystem::spot_to_chunk_pos(&left);
 
                                         if self.vars.in_multiplayer {
-                                            let mut message = Message::new(
-                                                MessageType::MultiBlockSet,
-                                                Vec3::new(
-                                                    left.x as f32,
-                                                    left.y as f32,
-                                                    left.z as f32,
-                                                ),
-                                                0.0,
-                                                blockbitsleft,
-                                            );
-
-                                            message.info2 = neightopbits;
-                                            message.otherpos = leftup;
-
-                                            self.netconn.send(&message);
+                                            self.predict_block_edit(&[(left, blockbitsleft), (leftup, neightopbits)], true, &[], None);
                                         } else {
                                             self.chunksys.read().set_block_and_queue_rerender(
                                                 left,
@@ -6502,21 +6807,7 @@ impl Game {
                                 }
 
                                 if self.vars.in_multiplayer {
-                                    let mut message = Message::new(
-                                        MessageType::MultiBlockSet,
-                                        Vec3::new(
-                                            place_point.x as f32,
-                                            place_point.y as f32,
-                                            place_point.z as f32,
-                                        ),
-                                        0.0,
-                                        bottom_id,
-                                    );
-
-                                    message.info2 = top_id;
-                                    message.otherpos = place_above;
-
-                                    self.netconn.send(&message);
+                                    self.predict_block_edit(&[(place_point, bottom_id), (place_above, top_id)], true, &[(id, 1)], None);
                                 } else {
                                     self.chunksys.read().set_block_and_queue_rerender(
                                         place_point,
@@ -6553,18 +6844,7 @@ impl Game {
                             Blocks::set_direction_bits(&mut conveyor_id, direction);
 
                             if self.vars.in_multiplayer {
-                                let message = Message::new(
-                                    MessageType::BlockSet,
-                                    Vec3::new(
-                                        place_point.x as f32,
-                                        place_point.y as f32,
-                                        place_point.z as f32,
-                                    ),
-                                    0.0,
-                                    conveyor_id,
-                                );
-
-                                self.netconn.send(&message);
+                                self.predict_block_edit(&[(place_point, conveyor_id)], true, &[(id, 1)], None);
                             } else {
                                 self.chunksys.read().set_block_and_queue_rerender(
                                     place_point,
@@ -6593,18 +6873,7 @@ impl Game {
                             Blocks::set_direction_bits(&mut ladder_id, direction);
 
                             if self.vars.in_multiplayer {
-                                let message = Message::new(
-                                    MessageType::BlockSet,
-                                    Vec3::new(
-                                        place_point.x as f32,
-                                        place_point.y as f32,
-                                        place_point.z as f32,
-                                    ),
-                                    0.0,
-                                    ladder_id,
-                                );
-
-                                self.netconn.send(&message);
+                                self.predict_block_edit(&[(place_point, ladder_id)], true, &[(id, 1)], None);
                             } else {
                                 self.chunksys.read().set_block_and_queue_rerender(
                                     place_point,
@@ -6633,18 +6902,7 @@ impl Game {
                             Blocks::set_direction_bits(&mut chest_id, direction);
 
                             if self.vars.in_multiplayer {
-                                let message = Message::new(
-                                    MessageType::BlockSet,
-                                    Vec3::new(
-                                        place_point.x as f32,
-                                        place_point.y as f32,
-                                        place_point.z as f32,
-                                    ),
-                                    0.0,
-                                    chest_id,
-                                );
-
-                                self.netconn.send(&message);
+                                self.predict_block_edit(&[(place_point, chest_id)], true, &[(id, 1)], None);
                             } else {
                                 self.chunksys.read().set_block_and_queue_rerender(
                                     place_point,
@@ -6657,17 +6915,7 @@ impl Game {
                         } else {
                             if !Blocks::is_non_placeable(slot.0) {
                                 if self.vars.in_multiplayer {
-                                    let message = Message::new(
-                                        MessageType::BlockSet,
-                                        Vec3::new(
-                                            place_point.x as f32,
-                                            place_point.y as f32,
-                                            place_point.z as f32,
-                                        ),
-                                        0.0,
-                                        id,
-                                    );
-                                    self.netconn.send(&message);
+                                    self.predict_block_edit(&[(place_point, id)], true, &[(id, 1)], None);
                                 } else {
                                     self.chunksys.read().set_block_and_queue_rerender(
                                         place_point,
@@ -6697,7 +6945,13 @@ impl Game {
 
                                     self.netconn.send(&msg);
                                 } else {
-                                    let slot = &self.inventory.read().inv[slot_selected];
+                                    //Taken off locally too, so a refund after a rollback counts from here
+                                    let slot = {
+                                        let mut inv = self.inventory.write();
+                                        inv.inv[slot_selected].1 -= 1;
+                                        inv.dirty = true;
+                                        inv.inv[slot_selected]
+                                    };
 
                                     let mut msg = Message::new(
                                         MessageType::ChestInvUpdate,
@@ -6705,7 +6959,7 @@ impl Game {
                                         slot.0 as f32,
                                         slot_selected as u32,
                                     );
-                                    msg.infof = slot.1 as f32 - 1.0;
+                                    msg.infof = slot.1 as f32;
                                     msg.info2 = 1;
 
                                     self.netconn.send(&msg);
@@ -6789,19 +7043,36 @@ impl Game {
         }
 
         if updateinv {
-            self.update_inventory();
-            self.hud.chest_open = true;
-
-            self.window
-                .write()
-                .set_cursor_mode(glfw::CursorMode::Normal);
-            self.set_mouse_focused(false);
+            self.open_chest();
         }
 
         if openedcraft {
             self.set_mouse_focused(false);
         }
     }
+
+    #[cfg(feature = "glfw")]
+    pub fn close_chat(&mut self) {
+        self.chat_open = false;
+        self.window
+            .write()
+            .set_cursor_mode(glfw::CursorMode::Disabled);
+        self.set_mouse_focused(true);
+        unsafe {
+            uncapkb.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[cfg(feature = "glfw")]
+    pub fn open_chest(&mut self) {
+        self.update_inventory();
+        self.hud.chest_open = true;
+
+        self.window
+            .write()
+            .set_cursor_mode(glfw::CursorMode::Normal);
+        self.set_mouse_focused(false);
+    }
     #[cfg(feature = "glfw")]
     pub fn mouse_button(&mut self, mb: MouseButton, a: Action) {
         if self.hud.chest_open {
@@ -7004,29 +7275,13 @@ impl Game {
                         if self.vars.right_mouse_clicked {
                             //println!("RMC");
                             if unsafe { BUILD_PREVIEW_MODE } {
-                                
-                                unsafe {
-                                    
-                                    if MOUSE_ON_CUBE {
-                                        let mut implic = HashSet::new();
-                                        //println!("LOPS len: {}", LIST_OF_PREVIEWED_SPOTS.len());
-                                        for (spot, block) in LIST_OF_PREVIEWED_SPOTS.clone() {
-                                            let spot = spot + SELECTCUBESPOT + IVec3::new(BUILD_MODEL_OFFSET.x, 0, BUILD_MODEL_OFFSET.y);
-                                            let chunkspot = ChunkSystem::spot_to_chunk_pos(&spot);
-                                            implic.insert(chunkspot);
-                                            //println!("Setting a block {} at {}", block, spot);
-                                            self.chunksys.read().set_block_no_sound(spot, block, true);
-                                        }
-                    
-                                        for imp in implic {
-                                            self.chunksys.read().queue_rerender_with_key(imp, true, false);
-                                        }
-                                    }   
-                                    
-    
+                                if unsafe { MOUSE_ON_CUBE } {
+                                    self.use_build_tool();
                                 }
                             } else {
+                                self.edit_history.begin();
                                 self.cast_place_ray();
+                                self.edit_history.end();
                             }
                             
                         }
@@ -7120,6 +7375,43 @@ impl Game {
                         unsafe{BUILD_PREVIEW_MODE = !BUILD_PREVIEW_MODE};
                     }
                 }
+                "Next Build Tool" => {
+                    if action == Action::Press && unsafe { BUILD_PREVIEW_MODE } {
+                        self.build_tools.next_tool();
+                    }
+                }
+                "Copy Selection" => {
+                    if action == Action::Press && unsafe { BUILD_PREVIEW_MODE } {
+                        let copied = {
+                            let csys = self.chunksys.read();
+                            self.build_tools.copy(|spot| csys.blockat(spot))
+                        };
+                        self.build_tools.status = match copied {
+                            Ok(count) => format!("Copied {} blocks", count),
+                            Err(e) => e,
+                        };
+                    }
+                }
+                "Rotate Build" => {
+                    if action == Action::Press && unsafe { BUILD_PREVIEW_MODE } {
+                        self.build_tools.rotate();
+                    }
+                }
+                "Mirror Build" => {
+                    if action == Action::Press && unsafe { BUILD_PREVIEW_MODE } {
+                        self.build_tools.mirror();
+                    }
+                }
+                "Undo" => {
+                    if action == Action::Press {
+                        self.undo_edit();
+                    }
+                }
+                "Redo" => {
+                    if action == Action::Press {
+                        self.redo_edit();
+                    }
+                }
                 "Exit/Menu" => {
                     if action == Action::Press {
                         if !self.vars.menu_open && !self.hud.chest_open && !self.crafting_open {
@@ -7172,6 +7464,15 @@ impl Game {
                         self.controls.left = false;
                     }
                 }
+                "Chat" => {
+                    if action == Action::Press && self.vars.in_multiplayer {
+                        self.chat_open = true;
+                        self.window
+                            .write()
+                            .set_cursor_mode(glfw::CursorMode::Normal);
+                        self.set_mouse_focused(false);
+                    }
+                }
                 "Craft" => {
                     if action == Action::Press {
                         unsafe {
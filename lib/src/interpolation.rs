@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+/// How remote players and mobs are smoothed between network updates. Lives in the misc settings file.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct InterpolationSettings {
+    /// Seconds we render behind the newest snapshot, so there's usually a later one to lerp toward.
+    /// Players send every 250ms, so this should be a bit more than that.
+    pub delay: f64,
+    /// Snapshots kept per entity.
+    pub buffer_size: usize,
+    /// Seconds we keep moving an entity along its last velocity once we run out of snapshots.
+    pub max_extrapolation: f64,
+}
+
+impl Default for InterpolationSettings {
+    fn default() -> Self {
+        InterpolationSettings {
+            delay: 0.3,
+            buffer_size: 8,
+            max_extrapolation: 0.25,
+        }
+    }
+}
+
+/// A jump further than this between two snapshots is a teleport, the buffer starts over instead of lerping across it.
+pub const TELEPORT_DISTANCE: f32 = 20.0;
+
+/// Where an entity was, as of the time we got the update.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Snapshot {
+    pub time: f64,
+    pub position: Vec3,
+    pub rot: Vec3,
+}
+
+/// Angle lerp that goes the short way around, so a yaw going from 3.1 to -3.1 doesn't spin all the way back.
+pub fn lerp_angle(a: f32, b: f32, t: f32) -> f32 {
+    let mut diff = (b - a) % (2.0 * PI);
+    if diff > PI {
+        diff -= 2.0 * PI;
+    } else if diff < -PI {
+        diff += 2.0 * PI;
+    }
+    a + diff * t
+}
+
+fn lerp_rot(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    Vec3::new(lerp_angle(a.x, b.x, t), lerp_angle(a.y, b.y, t), lerp_angle(a.z, b.z, t))
+}
+
+/// The last few snapshots of one remote entity, oldest first.
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl SnapshotBuffer {
+    pub fn new() -> SnapshotBuffer {
+        SnapshotBuffer::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    pub fn latest(&self) -> Option<&Snapshot> {
+        self.snapshots.back()
+    }
+
+    /// Adds a snapshot, dropping the oldest past `capacity`. Snapshots older than the newest we have are out of order and ignored.
+    pub fn push(&mut self, snapshot: Snapshot, capacity: usize) {
+        if let Some(last) = self.snapshots.back() {
+            if snapshot.time < last.time {
+                return;
+            }
+        }
+        self.snapshots.push_back(snapshot);
+        while self.snapshots.len() > capacity.max(1) {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Where the entity should be drawn at `render_time`, which is normally now minus the delay.
+    ///
+    /// Between two snapshots this lerps. Before the first it holds the first. Past the last it carries on
+    /// along the velocity of the last two for at most `max_extrapolation` seconds, then holds there until
+    /// another update arrives.
+    pub fn sample(&self, render_time: f64, max_extrapolation: f64) -> Option<Snapshot> {
+        let first = *self.snapshots.front()?;
+        let last = *self.snapshots.back()?;
+
+        if render_time <= first.time {
+            return Some(Snapshot { time: render_time, ..first });
+        }
+
+        if render_time >= last.time {
+            if self.snapshots.len() < 2 {
+                return Some(Snapshot { time: render_time, ..last });
+            }
+            let prev = self.snapshots[self.snapshots.len() - 2];
+            let span = last.time - prev.time;
+            if span <= 0.0 {
+                return Some(Snapshot { time: render_time, ..last });
+            }
+            let over = (render_time - last.time).min(max_extrapolation.max(0.0));
+            let t = (over / span) as f32;
+            let velocity = last.position - prev.position;
+            return Some(Snapshot {
+                time: render_time,
+                position: last.position + velocity * t,
+                rot: last.rot,
+            });
+        }
+
+        for i in 1..self.snapshots.len() {
+            let a = self.snapshots[i - 1];
+            let b = self.snapshots[i];
+            if render_time <= b.time {
+                let span = b.time - a.time;
+                let t = if span <= 0.0 { 1.0 } else { ((render_time - a.time) / span) as f32 };
+                return Some(Snapshot {
+                    time: render_time,
+                    position: a.position.lerp(b.position, t),
+                    rot: lerp_rot(a.rot, b.rot, t),
+                });
+            }
+        }
+
+        Some(Snapshot { time: render_time, ..last })
+    }
+}
@@ -2,17 +2,18 @@
 
 #[macro_use]
 pub mod macros;
-pub mod blockinfo;
+pub use voxelland_world::{blockinfo, chunkstore, cube, terrain, vec, worlddir, worldgen, worldmap, worldstorage};
+pub mod buildtools;
 pub mod camera;
 pub mod chunk;
+pub mod saves;
+pub mod schematic;
 pub mod collisioncage;
-pub mod cube;
 pub mod fader;
 pub mod game;
 pub mod packedvertex;
 pub mod shader;
 pub mod texture;
-pub mod vec;
 pub mod windowandkey;
 pub mod worldgeometry;
 pub mod raycast;
@@ -22,6 +23,9 @@ pub mod currentplanet;
 pub mod planetinfo;
 pub mod model;
 pub mod modelentity;
+pub mod interpolation;
+pub mod prediction;
+pub mod edithistory;
 pub mod selectcube;
 pub mod blockoverlay;
 pub mod glyphface;
@@ -34,7 +38,13 @@ pub mod audio;
 pub mod monsters;
 pub mod serializemap;
 pub mod server_types;
+pub mod compression;
 pub mod network;
+pub mod netstream;
+pub mod chat;
+pub mod bulkedit;
+pub mod calendar;
+pub mod discovery;
 pub mod inventory;
 pub mod visions;
 pub mod specialblocks;
@@ -43,3 +53,4 @@ pub mod statics;
 pub mod playerposition;
 pub mod tools;
 pub mod keybinds;
+pub mod server;
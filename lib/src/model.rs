@@ -529,6 +529,9 @@ impl Game {
             let nsme = self.non_static_model_entities.iter().map(|e| e).collect::<Vec<_>>();
             let pme = self.player_model_entities.iter().map(|e| e).collect::<Vec<_>>();
 
+            let interp = crate::statics::MISCSETTINGS.interpolation;
+            let rendertime = glfwGetTime() - interp.delay;
+
             for modelt in self.static_model_entities.iter().map(ModelEntityType::Static)
             .chain(nsme.iter().map(|arg0| ModelEntityType::NonStatic(arg0.value())))
             .chain(pme.iter().map(|arg0| ModelEntityType::NonStatic(arg0.value())))
@@ -543,6 +546,16 @@ impl Game {
                     },
                 };
                     
+                //Remote players and server mobs are drawn a little in the past, between the snapshots we got for them
+                let sampled = match modelt {
+                    ModelEntityType::Static(_) => None,
+                    ModelEntityType::NonStatic(entity) => entity.snapshots.sample(rendertime, interp.max_extrapolation),
+                };
+                let (drawpos, drawlastpos, drawinterp, drawrot, drawlastrot) = match sampled {
+                    Some(s) => (s.position, s.position, 1.0, s.rot, s.rot),
+                    None => (modelent.position, modelent.lastpos, glfwGetTime() as f32 - modelent.time_stamp as f32, modelent.rot, modelent.lastrot),
+                };
+
                 let index = modelent.model_index;
                 if index < self.gltf_vaos.len() && index < self.gltf_textures.len() {
                     //println!("Tis true");
@@ -595,15 +608,15 @@ impl Game {
                                         entity.position.z
                                     );
                                 },
-                                ModelEntityType::NonStatic(entity) => {
+                                ModelEntityType::NonStatic(_entity) => {
                                     gl::Uniform3f(
                                         gl::GetUniformLocation(
                                             self.modelshader.shader_id,
                                             b"pos\0".as_ptr() as *const i8,
                                         ),
-                                        entity.position.x,
-                                        entity.position.y + self.planet_y_offset,
-                                        entity.position.z
+                                        drawpos.x,
+                                        drawpos.y + self.planet_y_offset,
+                                        drawpos.z
                                     );
                                 },
                             }
@@ -613,7 +626,7 @@ impl Game {
                                     self.modelshader.shader_id,
                                     b"interp_time\0".as_ptr() as *const i8,
                                 ),
-                                glfwGetTime() as f32 - modelent.time_stamp as f32
+                                drawinterp
                             );
 
                             gl::Uniform3f(
@@ -621,9 +634,9 @@ impl Game {
                                     self.modelshader.shader_id,
                                     b"lastpos\0".as_ptr() as *const i8,
                                 ),
-                                modelent.lastpos.x,
-                                modelent.lastpos.y + self.planet_y_offset,
-                                modelent.lastpos.z
+                                drawlastpos.x,
+                                drawlastpos.y + self.planet_y_offset,
+                                drawlastpos.z
                             );
                             
 
@@ -632,14 +645,14 @@ impl Game {
                                     self.modelshader.shader_id,
                                     b"xrot\0".as_ptr() as *const i8,
                                 ),
-                                modelent.rot.x,
+                                drawrot.x,
                             );
                             gl::Uniform1f(
                                 gl::GetUniformLocation(
                                     self.modelshader.shader_id,
                                     b"yrot\0".as_ptr() as *const i8,
                                 ),
-                                modelent.rot.y,
+                                drawrot.y,
                             );
 
                             gl::Uniform1f(
@@ -647,7 +660,7 @@ impl Game {
                                     self.modelshader.shader_id,
                                     b"zrot\0".as_ptr() as *const i8,
                                 ),
-                                modelent.rot.z,
+                                drawrot.z,
                             );
 
                             match modelt {
@@ -711,9 +724,9 @@ impl Game {
                                     self.modelshader.shader_id,
                                     b"lastrot\0".as_ptr() as *const i8,
                                 ),
-                                modelent.lastrot.x,
-                                modelent.lastrot.y,
-                                modelent.lastrot.z
+                                drawlastrot.x,
+                                drawlastrot.y,
+                                drawlastrot.z
                             );
 
 
@@ -56,6 +56,8 @@ pub fn euler_to_direction(euler_angles: Vec3) -> Vec3 {
 }
 
 
+use crate::interpolation::{Snapshot, SnapshotBuffer, TELEPORT_DISTANCE};
+use crate::statics::MISCSETTINGS;
 use crate::{blockinfo::Blocks, camera::Camera, chunk::ChunkSystem, collisioncage::{BoundBox, CollCage}, game::{Animation, ControlsState, Node, AMBIENTBRIGHTNESS}, planetinfo::Planets, raycast::{raycast_voxel}, vec::{self, IVec3}};
 
 static mut CURRENT_ID: u32 = 0;
@@ -100,13 +102,29 @@ pub struct ModelEntity {
     pub soundvolume: f32,
     pub attackinterval: f32,
     pub soundinterval: f32,
-    pub lastchunkpos: vec::IVec2
+    pub lastchunkpos: vec::IVec2,
+    /// Network updates for remote players and server mobs, empty for anything simulated locally.
+    pub snapshots: SnapshotBuffer
 }
 
 pub static SERVER_GENERATED_CHUNKS: Lazy<DashMap<vec::IVec2, bool>> = Lazy::new(|| DashMap::new());
 
 impl ModelEntity {
 
+    /// Takes a position from the server. The entity is drawn from `snapshots`, `position` is just the newest one.
+    pub fn receive_snapshot(&mut self, time: f64, position: Vec3, rot: Vec3) {
+        if self.snapshots.latest().is_some_and(|s| s.position.distance(position) > TELEPORT_DISTANCE) {
+            //Respawned or teleported, don't slide them across the map
+            self.snapshots.clear();
+        }
+        self.lastpos = self.position;
+        self.position = position;
+        self.lastrot = self.rot;
+        self.rot = rot;
+        self.time_stamp = time;
+        let capacity = unsafe { MISCSETTINGS.interpolation.buffer_size };
+        self.snapshots.push(Snapshot { time, position, rot }, capacity);
+    }
 
     pub fn new_with_jump_height(model_index: usize, pos: Vec3, scale: f32, rot: Vec3, csys: &Arc<RwLock<ChunkSystem>>, cam: &Arc<Mutex<Camera>>, jump_height: f32, hostile: bool) -> ModelEntity {
         let mut modent = ModelEntity::new(model_index, pos, scale, rot, csys, cam, hostile);
@@ -205,7 +223,8 @@ impl ModelEntity {
                 soundvolume: 0.0,
                 attackinterval: Planets::get_mob_attack_interval(model_index),
                 soundinterval: Planets::get_mob_sound_interval(model_index),
-                lastchunkpos: vec::IVec2::new(-99,99)
+                lastchunkpos: vec::IVec2::new(-99,99),
+                snapshots: SnapshotBuffer::new()
             }
         }
         
@@ -264,7 +283,8 @@ impl ModelEntity {
                 soundvolume: 0.0,
                 attackinterval: Planets::get_mob_attack_interval(model_index),
                 soundinterval: Planets::get_mob_sound_interval(model_index),
-                lastchunkpos: vec::IVec2::new(-99,99)
+                lastchunkpos: vec::IVec2::new(-99,99),
+                snapshots: SnapshotBuffer::new()
             }
      
         
@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+/// What the client and server talk over. Usually TCP, but singleplayer talks to its own server in the same
+/// process through a `MemoryStream` instead, so both go through exactly the same message handling.
+pub enum NetStream {
+    Tcp(TcpStream),
+    Memory(MemoryStream),
+}
+
+impl NetStream {
+    /// Two connected in-memory ends, anything written to one can be read from the other.
+    pub fn memory_pair() -> (NetStream, NetStream) {
+        let (a, b) = MemoryStream::pair();
+        (NetStream::Memory(a), NetStream::Memory(b))
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            NetStream::Tcp(s) => s.set_nonblocking(nonblocking),
+            NetStream::Memory(s) => {
+                s.nonblocking.store(nonblocking, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            NetStream::Tcp(s) => s.set_read_timeout(timeout),
+            NetStream::Memory(s) => {
+                *s.timeout.lock() = timeout;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            NetStream::Tcp(s) => s.peek(buf),
+            NetStream::Memory(s) => s.read_inner(buf, false),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            NetStream::Tcp(s) => s.shutdown(how),
+            NetStream::Memory(s) => {
+                s.close();
+                Ok(())
+            }
+        }
+    }
+
+    /// In-memory streams have no address, which also keeps them off the UDP side channel.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            NetStream::Tcp(s) => s.peer_addr(),
+            NetStream::Memory(_) => Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "in-memory stream")),
+        }
+    }
+}
+
+impl From<TcpStream> for NetStream {
+    fn from(stream: TcpStream) -> NetStream {
+        NetStream::Tcp(stream)
+    }
+}
+
+impl Read for NetStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            NetStream::Tcp(s) => s.read(buf),
+            NetStream::Memory(s) => s.read_inner(buf, true),
+        }
+    }
+}
+
+impl Write for NetStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            NetStream::Tcp(s) => s.write(buf),
+            NetStream::Memory(s) => s.write_inner(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            NetStream::Tcp(s) => s.flush(),
+            NetStream::Memory(_) => Ok(()),
+        }
+    }
+}
+
+/// How long the bytes after a header get to all show up before we give up on whoever's sending them.
+pub const PAYLOAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The bytes that follow a `Chat` or `BulkBlockSet` header, taken off a non-blocking stream as they arrive.
+/// Keep it between reads so the stream is only ever held for one read, and nobody writing to it waits on a slow sender.
+pub struct PayloadReader {
+    bytes: Vec<u8>,
+    filled: usize,
+    deadline: Instant,
+}
+
+impl PayloadReader {
+    pub fn new(len: usize) -> PayloadReader {
+        PayloadReader { bytes: vec![0; len], filled: 0, deadline: Instant::now() + PAYLOAD_TIMEOUT }
+    }
+
+    /// Reads whatever has arrived. The payload once it's all in, None while some is still on its way.
+    /// An error if the stream closed or broke, or it's taken longer than `PAYLOAD_TIMEOUT`.
+    pub fn poll(&mut self, stream: &mut NetStream) -> io::Result<Option<Vec<u8>>> {
+        while self.filled < self.bytes.len() {
+            match stream.read(&mut self.bytes[self.filled..]) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.filled += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if Instant::now() > self.deadline {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    return Ok(None);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(std::mem::take(&mut self.bytes)))
+    }
+}
+
+/// Bytes going one way between two `MemoryStream`s.
+#[derive(Default)]
+struct Pipe {
+    bytes: Mutex<VecDeque<u8>>,
+    arrived: Condvar,
+    closed: AtomicBool,
+}
+
+/// One end of an in-memory connection. Behaves like a `TcpStream` as far as the network code cares:
+/// nonblocking reads give `WouldBlock`, blocking ones honor the read timeout, reads after the other end
+/// closes give 0 and writes give `BrokenPipe`.
+pub struct MemoryStream {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    nonblocking: AtomicBool,
+    timeout: Mutex<Option<Duration>>,
+}
+
+impl MemoryStream {
+    pub fn pair() -> (MemoryStream, MemoryStream) {
+        let atob = Arc::new(Pipe::default());
+        let btoa = Arc::new(Pipe::default());
+        let a = MemoryStream {
+            incoming: btoa.clone(),
+            outgoing: atob.clone(),
+            nonblocking: AtomicBool::new(false),
+            timeout: Mutex::new(None),
+        };
+        let b = MemoryStream {
+            incoming: atob,
+            outgoing: btoa,
+            nonblocking: AtomicBool::new(false),
+            timeout: Mutex::new(None),
+        };
+        (a, b)
+    }
+
+    fn read_inner(&self, buf: &mut [u8], consume: bool) -> io::Result<usize> {
+        let timeout = *self.timeout.lock();
+        let mut bytes = self.incoming.bytes.lock();
+        loop {
+            if !bytes.is_empty() {
+                let n = buf.len().min(bytes.len());
+                for (i, b) in bytes.iter().take(n).enumerate() {
+                    buf[i] = *b;
+                }
+                if consume {
+                    bytes.drain(..n);
+                }
+                return Ok(n);
+            }
+            if self.incoming.closed.load(Ordering::Relaxed) {
+                return Ok(0);
+            }
+            if self.nonblocking.load(Ordering::Relaxed) {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            match timeout {
+                Some(t) => {
+                    if self.incoming.arrived.wait_for(&mut bytes, t).timed_out() && bytes.is_empty() {
+                        return Err(io::ErrorKind::WouldBlock.into());
+                    }
+                }
+                None => self.incoming.arrived.wait(&mut bytes),
+            }
+        }
+    }
+
+    fn write_inner(&self, buf: &[u8]) -> io::Result<usize> {
+        if self.outgoing.closed.load(Ordering::Relaxed) {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        self.outgoing.bytes.lock().extend(buf);
+        self.outgoing.arrived.notify_all();
+        Ok(buf.len())
+    }
+
+    /// Both directions, like shutting down a socket completely.
+    fn close(&self) {
+        for pipe in [&self.incoming, &self.outgoing] {
+            let _bytes = pipe.bytes.lock();
+            pipe.closed.store(true, Ordering::Relaxed);
+            pipe.arrived.notify_all();
+        }
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -1,12 +1,14 @@
-use std::fs::{self, File};
-use std::net::{TcpStream, ToSocketAddrs};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
+
+use crate::netstream::{NetStream, PayloadReader};
 use std::io::{self, Read, Write};
 use tracing::info;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8};
 use std::sync::{Arc};
 use parking_lot::{Mutex, RwLock};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use bincode;
 use dashmap::DashMap;
 use glam::Vec3;
@@ -16,18 +18,62 @@ use lockfree::queue::Queue;
 use uuid::Uuid;
 
 use crate::camera::Camera;
+use crate::bulkedit;
+use crate::chat::{self, ChatLog};
 use crate::chunk::ChunkSystem;
+use crate::compression::{self, Compression};
 use crate::game::{Game, CURRSEED, PLAYERPOS, PLAYERSCALE};
 use crate::inventory::ChestInventory;
 use crate::modelentity::{direction_to_euler, ModelEntity};
-use crate::server_types::{self, Message, MessageType, MOB_BATCH_SIZE};
+use crate::server_types::{self, Message, MessageType, UdpPacket, MOB_BATCH_SIZE};
 use crate::statics::MY_MULTIPLAYER_UUID;
+use crate::worldgen::GeneratorSettings;
+use crate::worldstorage;
 use crate::vec;
 
 
 
+/// How often our transform goes out over UDP, TCP stays at every 250ms.
+pub const UDP_SEND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// No datagram from the server for this long and we go back to sending transforms over TCP.
+pub const UDP_ALIVE_TIMEOUT: Duration = Duration::from_millis(2500);
+
+/// Our `PlayerUpdate`s over TCP (at least once a second) are the heartbeat, the server answers each with a `TimeUpdate`.
+/// Nothing at all from the server for this long and we call the connection dead.
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wait before the first reconnect attempt, doubled after every failed one up to `RECONNECT_BACKOFF_MAX`.
+pub const RECONNECT_BACKOFF_START: Duration = Duration::from_millis(500);
+pub const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// How long to wait before reconnect attempt number `attempt` (starting at 0).
+pub fn reconnect_backoff(attempt: u32) -> Duration {
+    RECONNECT_BACKOFF_START.saturating_mul(1u32 << attempt.min(16)).min(RECONNECT_BACKOFF_MAX)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionState {
+    Disconnected = 0,
+    Connecting = 1,
+    Connected = 2,
+    /// Lost the server, trying to get back in with the same UUID.
+    Reconnecting = 3,
+}
+
+impl ConnectionState {
+    pub fn from_u8(v: u8) -> ConnectionState {
+        match v {
+            1 => ConnectionState::Connecting,
+            2 => ConnectionState::Connected,
+            3 => ConnectionState::Reconnecting,
+            _ => ConnectionState::Disconnected,
+        }
+    }
+}
+
 pub struct NetworkConnector {
-    pub stream: Option<Arc<Mutex<TcpStream>>>,
+    pub stream: Option<Arc<Mutex<NetStream>>>,
     pub recvthread: Option<JoinHandle<()>>,
     pub sendthread: Option<JoinHandle<()>>,
     pub shouldrun: Arc<AtomicBool>,
@@ -44,6 +90,17 @@ pub struct NetworkConnector {
     pub pme: Arc<DashMap<Uuid, ModelEntity>>,
     pub sendqueue: Arc<Queue<Message>>,
     pub chest_registry: Arc<DashMap<vec::IVec3, ChestInventory>>,
+    /// The UDP side channel is up, transforms go over it instead of TCP.
+    pub udp_alive: Arc<AtomicBool>,
+    /// A `ConnectionState`.
+    pub state: Arc<AtomicU8>,
+    /// Failed reconnect attempts since we lost the server, for the "reconnecting" notice.
+    pub reconnect_attempts: Arc<AtomicU32>,
+    pub chat: Arc<Mutex<ChatLog>>,
+    /// The blocks of each `BulkBlockSet`, in the same order their headers go into `highprioritycommqueue`.
+    pub bulk_edits: Arc<Queue<Vec<(vec::IVec3, u32)>>>,
+    /// Where our copy of what the server sends goes (`db`, `chestdb`, `mp/`). The working directory, unless it's our own server.
+    pub download_dir: PathBuf,
 }
 
 impl NetworkConnector {
@@ -67,21 +124,130 @@ impl NetworkConnector {
             shouldsend: Arc::new(AtomicBool::new(false)),
             pme: pme.clone(),
             sendqueue: sendqueue.clone(),
-            chest_registry: chest_reg.clone()
+            chest_registry: chest_reg.clone(),
+            udp_alive: Arc::new(AtomicBool::new(false)),
+            state: Arc::new(AtomicU8::new(ConnectionState::Disconnected as u8)),
+            reconnect_attempts: Arc::new(AtomicU32::new(0)),
+            chat: Arc::new(Mutex::new(ChatLog::default())),
+            bulk_edits: Arc::new(Queue::new()),
+            download_dir: PathBuf::new(),
         }
     }
 
+    pub fn connection_state(&self) -> ConnectionState {
+        ConnectionState::from_u8(self.state.load(std::sync::atomic::Ordering::Relaxed))
+    }
+
     pub fn send(&self, message: &Message) {
         //info!("Sending a {}", message.message_type);
 
         if let Some(stream) = &self.stream {
             let serialized_message = bincode::serialize(message).unwrap();
             let mut stream_lock = stream.lock();
-            stream_lock.write_all(&serialized_message).unwrap();
+            //If this fails the connection is gone, the recv thread notices and reconnects
+            let _ = stream_lock.write_all(&serialized_message);
         }
     }
 
-    pub fn sendto(message: &Message, stream: &Arc<Mutex<TcpStream>>) {
+    /// Says something in chat. The server sends it back to everyone, us included, unless a plugin stops it.
+    pub fn send_chat(&self, text: &str) {
+        if let Some(stream) = &self.stream {
+            let _ = stream.lock().write_all(&chat::encode(None, text));
+        }
+    }
+
+    /// Sends `blocks` in one `BulkBlockSet` under `header`, anchored on the first. False if they don't fit one.
+    pub fn send_bulk(&self, header: &Message, blocks: &[(vec::IVec3, u32)]) -> bool {
+        let Some(bytes) = blocks.first().and_then(|(anchor, _)| bulkedit::encode(header, *anchor, blocks)) else {
+            return false;
+        };
+        if let Some(stream) = &self.stream {
+            let _ = stream.lock().write_all(&bytes);
+        }
+        true
+    }
+
+    /// Reads exactly one message frame, so frames that arrive together don't get thrown away.
+    fn read_frame(stream: &mut NetStream, buffer: &mut [u8]) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let result = stream.read_exact(buffer);
+        stream.set_nonblocking(true)?;
+        result
+    }
+
+    /// Tries to get back to the server with backoff until it works or `shouldrun` goes false. On success the new
+    /// stream is swapped into `stream`, so everything holding it carries on, and our greeting with the same UUID has gone out.
+    /// `session` is the token the server gave us, which it wants to see before handing the old session over.
+    fn reconnect(stream: &Arc<Mutex<NetStream>>, addrs: &[SocketAddr], shouldrun: &Arc<AtomicBool>, state: &Arc<AtomicU8>, attempts: &Arc<AtomicU32>, session: Option<(u64, u64)>) -> bool {
+        let _ = stream.lock().shutdown(std::net::Shutdown::Both);
+        if addrs.is_empty() {
+            return false;
+        }
+        state.store(ConnectionState::Reconnecting as u8, std::sync::atomic::Ordering::Relaxed);
+        attempts.store(0, std::sync::atomic::Ordering::Relaxed);
+
+        let mut attempt = 0;
+        while shouldrun.load(std::sync::atomic::Ordering::Relaxed) {
+            let wait = reconnect_backoff(attempt);
+            info!("Lost the server, reconnecting in {:?} (attempt {})", wait, attempt + 1);
+            thread::sleep(wait);
+
+            match TcpStream::connect(addrs) {
+                Ok(newstream) => match NetworkConnector::greet(stream, newstream, session) {
+                    Ok(()) => {
+                        info!("Reconnected to the server");
+                        state.store(ConnectionState::Connected as u8, std::sync::atomic::Ordering::Relaxed);
+                        return true;
+                    }
+                    Err(e) => info!("Reconnected but couldn't greet the server: {e}"),
+                },
+                Err(e) => info!("Reconnect failed: {e}"),
+            }
+            //Every way of failing lands here, so the next try always backs off further
+            attempt += 1;
+            attempts.store(attempt, std::sync::atomic::Ordering::Relaxed);
+        }
+        false
+    }
+
+    /// Hands the finished payload of a `Chat` or `BulkBlockSet` to whoever's waiting for it. False if it makes no sense.
+    fn take_payload(header: &Message, payload: &[u8], chatlog: &Mutex<ChatLog>, bulk_edits: &Queue<Vec<(vec::IVec3, u32)>>, hpcommqueue: &Queue<Message>) -> bool {
+        if header.message_type == MessageType::Chat {
+            let from = if header.goose == (0, 0) { None } else { Some(Uuid::from_u64_pair(header.goose.0, header.goose.1)) };
+            let text = String::from_utf8_lossy(payload).into_owned();
+            info!("Chat: {text}");
+            chatlog.lock().push(from, text);
+            return true;
+        }
+        match bulkedit::unpack(header.otherpos, payload) {
+            Some(blocks) => {
+                bulk_edits.push(blocks);
+                hpcommqueue.push(header.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Swaps `newstream` into `stream` and sends our greeting on it, plus `session` if the server gave us one.
+    fn greet(stream: &Arc<Mutex<NetStream>>, newstream: TcpStream, session: Option<(u64, u64)>) -> io::Result<()> {
+        newstream.set_nonblocking(true)?;
+        let mut idgreeting = Message::new(MessageType::TellYouMyID, Vec3::ZERO, 0.0, Compression::Lz4 as u32);
+        idgreeting.goose = unsafe { (*MY_MULTIPLAYER_UUID).as_u64_pair() };
+
+        let mut stream_lock = stream.lock();
+        *stream_lock = NetStream::Tcp(newstream);
+        stream_lock.write_all(&bincode::serialize(&idgreeting).unwrap())?;
+        if let Some(token) = session {
+            let mut proof = Message::new(MessageType::SessionToken, Vec3::ZERO, 0.0, 0);
+            proof.goose = token;
+            stream_lock.write_all(&bincode::serialize(&proof).unwrap())?;
+        }
+        Ok(())
+    }
+
+    pub fn sendto(message: &Message, stream: &Arc<Mutex<NetStream>>) {
        // info!("Sending a {}", message.message_type);
         let serialized_message = bincode::serialize(message).unwrap();
         let mut stream_lock = stream.lock();
@@ -103,507 +269,730 @@ impl NetworkConnector {
         }
     }
 
-    pub fn sendtolocked(message: &Message, stream: &mut TcpStream) {
+    /// Moves a remote player's model, or queues it to be spawned if we haven't seen them yet.
+    pub fn apply_player_update(pme: &Arc<DashMap<Uuid, ModelEntity>>, commqueue: &Arc<Queue<Message>>, comm: &Message) {
+        let newpos = Vec3::new(comm.x, comm.y, comm.z);
+        //let id = comm.info;
+        let _modind = comm.info2;
+        let rot = comm.rot;
+        let scale = PLAYERSCALE;
+
+        let uuid = Uuid::from_u64_pair(comm.goose.0, comm.goose.1);
+
+        //info!("Player update: {uuid}");
+        //info!("NSME Length: {}", nsme.len());
+        match pme.get_mut(&uuid) {
+            Some(mut me) => {
+                let modent = me.value_mut();
+                (*modent).scale = scale;
+                unsafe {
+                    (*modent).receive_snapshot(glfwGetTime(), newpos, Vec3::new(0.0, rot, 0.0));
+                }
+            }
+            None => {
+                commqueue.push(comm.clone());
+            }
+        };
+    }
+
+    pub fn apply_mob_batch(commqueue: &Arc<Queue<Message>>, comm: &Message) {
+        //info!("Got MUB, count {}", comm.count);
+        if comm.count > server_types::MOB_BATCH_SIZE as u8 {
+            info!("Ignoring invalid mobbatch with count > {} of {}", server_types::MOB_BATCH_SIZE, comm.count);
+        } else {
+            for i in 0..comm.count.min(MOB_BATCH_SIZE as u8) {
+                let msg = Message::from_mob_message(&comm.msgs[i as usize]);
+                commqueue.push(msg);
+            }
+        }
+    }
+
+    /// Our own transform, as sent in every `PlayerUpdate`.
+    pub fn my_player_update() -> Message {
+        let c = unsafe {
+            PLAYERPOS.snapshot()
+        };
+
+        let dir = direction_to_euler(c.dir.into());
+        let mut message = Message::new(MessageType::PlayerUpdate, Into::<glam::Vec3>::into(c.pos) - Vec3::new(0.0, 1.25, 0.0), dir.y, 0);
+
+        message.infof = c.pitch;
+        message.info2 = c.yaw as u32;
+        message
+    }
+
+    /// Starts the UDP side channel: our transform goes out every `UDP_SEND_INTERVAL`, and other players and mobs come in.
+    /// `udp_alive` stays true while the server's datagrams keep reaching us, the send thread falls back to TCP when it isn't.
+    pub fn start_udp(server: SocketAddr, token: (u64, u64), shouldrun: &Arc<AtomicBool>, udp_alive: &Arc<AtomicBool>,
+                        pme: &Arc<DashMap<Uuid, ModelEntity>>, commqueue: &Arc<Queue<Message>>) {
+        let bindaddr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = match UdpSocket::bind(bindaddr) {
+            Ok(s) => s,
+            Err(e) => {
+                info!("Couldn't open the udp channel, staying on tcp: {e}");
+                return;
+            }
+        };
+        if socket.connect(server).is_err() || socket.set_read_timeout(Some(Duration::from_millis(10))).is_err() {
+            info!("Couldn't reach the udp channel, staying on tcp");
+            return;
+        }
+
+        let sr = shouldrun.clone();
+        let udp_alive = udp_alive.clone();
+        let pme = pme.clone();
+        let commqueue = commqueue.clone();
+
+        thread::spawn(move || {
+            let mut seq_out: u32 = 0;
+            let mut seq_in: u32 = 0;
+            let mut last_send = Instant::now() - UDP_SEND_INTERVAL;
+            let mut last_recv: Option<Instant> = None;
+            let mut buffer = [0u8; 2048];
+
+            while sr.load(std::sync::atomic::Ordering::Relaxed) {
+                if last_send.elapsed() >= UDP_SEND_INTERVAL {
+                    last_send = Instant::now();
+                    seq_out = seq_out.wrapping_add(1);
+                    let packet = UdpPacket { token, seq: seq_out, message: NetworkConnector::my_player_update() };
+                    let _ = socket.send(&packet.encode());
+                }
+
+                while let Ok(n) = socket.recv(&mut buffer) {
+                    let packet = match UdpPacket::decode(&buffer[..n]) {
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    if packet.seq <= seq_in && last_recv.is_some() {
+                        continue;
+                    }
+                    seq_in = packet.seq;
+                    last_recv = Some(Instant::now());
+
+                    match packet.message.message_type {
+                        MessageType::PlayerUpdate => NetworkConnector::apply_player_update(&pme, &commqueue, &packet.message),
+                        MessageType::MobUpdateBatch => NetworkConnector::apply_mob_batch(&commqueue, &packet.message),
+                        _ => {}
+                    }
+                }
+
+                let alive = last_recv.is_some_and(|t| t.elapsed() < UDP_ALIVE_TIMEOUT);
+                udp_alive.store(alive, std::sync::atomic::Ordering::Relaxed);
+            }
+
+            udp_alive.store(false, std::sync::atomic::Ordering::Relaxed);
+        });
+    }
+
+    pub fn sendtolocked(message: &Message, stream: &mut NetStream) {
        // info!("Sending a {}", message.message_type);
         let serialized_message = bincode::serialize(message).unwrap();
-        stream.write_all(&serialized_message).unwrap();
+        let _ = stream.write_all(&serialized_message);
     }
 
 
 
     pub fn connect<A: ToSocketAddrs + Clone>(&mut self, address: A) {
         self.shouldrun.store(true, std::sync::atomic::Ordering::Relaxed);
-        const PACKET_SIZE: usize = 90000;
-        let mut conned = false;
+        self.state.store(ConnectionState::Connecting as u8, std::sync::atomic::Ordering::Relaxed);
 
-
-        while !conned {
-            match TcpStream::connect(address.clone()) {
+        loop {
+            //Kept so we can get back to the same server if we lose it
+            let addrs: Vec<SocketAddr> = match address.to_socket_addrs() {
+                Ok(a) => a.collect(),
+                Err(e) => {
+                    info!("Couldn't resolve the server address: {e}");
+                    thread::sleep(Duration::from_secs(1));
+                    continue;
+                }
+            };
+            match TcpStream::connect(&addrs[..]) {
                 Ok(tcp_stream) => {
-                    conned = true;
+                    self.start(NetStream::Tcp(tcp_stream), addrs);
+                    return;
+                }
+                Err(e) => {
+                    info!("Error from connect(): {e}");
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+    }
 
-                    tcp_stream.set_nonblocking(true).unwrap();
-                    self.stream = Some(Arc::new(Mutex::new(tcp_stream)));
+    /// Talks to a server over a stream we already have, like the in-memory one from `Server::connect_local`.
+    /// There's no address to go back to, so a dropped stream isn't reconnected.
+    pub fn connect_stream(&mut self, stream: NetStream) {
+        self.shouldrun.store(true, std::sync::atomic::Ordering::Relaxed);
+        self.start(stream, Vec::new());
+    }
 
-                    let sr = self.shouldrun.clone();
-                    let sr2 = sr.clone();
+    fn start(&mut self, tcp_stream: NetStream, addrs: Vec<SocketAddr>) {
+        const PACKET_SIZE: usize = 90000;
+        self.state.store(ConnectionState::Connected as u8, std::sync::atomic::Ordering::Relaxed);
 
-                    let stream = self.stream.as_ref().unwrap().clone();
-                    let stream2 = stream.clone();
+        tcp_stream.set_nonblocking(true).unwrap();
+        self.stream = Some(Arc::new(Mutex::new(tcp_stream)));
 
-                    let mut idgreeting = Message::new(MessageType::TellYouMyID, Vec3::ZERO, 0.0, 0);
-                    idgreeting.goose = unsafe { (*MY_MULTIPLAYER_UUID).as_u64_pair() };
+        let sr = self.shouldrun.clone();
+        let sr2 = sr.clone();
 
-                    self.send(&idgreeting);
+        let stream = self.stream.as_ref().unwrap().clone();
+        let stream2 = stream.clone();
 
-                    let csys = self.csys.clone();
-                    let recv_world_bool = self.received_world.clone();
-                    let commqueue = self.commqueue.clone();
-                    let gknowncams = self.gknowncams.clone();
-                    let _my_uuid = self.my_uuid.clone();
-                    let _nsmes = self.nsme.clone();
-                    let pme = self.pme.clone();
+        //Ask for compressed world and chest payloads, servers that don't know about it send them raw
+        let mut idgreeting = Message::new(MessageType::TellYouMyID, Vec3::ZERO, 0.0, Compression::Lz4 as u32);
+        idgreeting.goose = unsafe { (*MY_MULTIPLAYER_UUID).as_u64_pair() };
 
+        self.send(&idgreeting);
 
-                    let shouldsend = self.shouldsend.clone();
-                    let shouldsend2 = self.shouldsend.clone();
+        let csys = self.csys.clone();
+        let recv_world_bool = self.received_world.clone();
+        let commqueue = self.commqueue.clone();
+        let gknowncams = self.gknowncams.clone();
+        let _my_uuid = self.my_uuid.clone();
+        let _nsmes = self.nsme.clone();
+        let pme = self.pme.clone();
 
 
-                    let camclone = self.mycam.clone();
+        let shouldsend = self.shouldsend.clone();
+        let shouldsend2 = self.shouldsend.clone();
 
-                    let hpcommqueue = self.highprioritycommqueue.clone();
 
-                    let sendqueue = self.sendqueue.clone();
+        let camclone = self.mycam.clone();
 
-                    let chestreg = self.chest_registry.clone();
+        let hpcommqueue = self.highprioritycommqueue.clone();
 
-                    self.sendthread = Some(thread::spawn(move || {
-                        let sr = sr2.clone();
-                        let stream = stream2.clone();
-                        //let cam = camclone.clone();
-                        let shouldsend = shouldsend.clone();
-                        while sr.load(std::sync::atomic::Ordering::Relaxed) {
-                            if shouldsend.load(std::sync::atomic::Ordering::Relaxed) {
-                                match sendqueue.pop() {
-                                    Some(t) => {
-                                        NetworkConnector::sendto(&t, &stream);
-                                    }
-                                    None => {
+        let sendqueue = self.sendqueue.clone();
 
-                                    }
-                                }
+        let chestreg = self.chest_registry.clone();
 
-                                let c = unsafe {
-                                    PLAYERPOS.snapshot()
-                                };
-                                
-                                
-                                let dir = direction_to_euler(c.dir.into());
-                                let mut message = Message::new(MessageType::PlayerUpdate, Into::<glam::Vec3>::into(c.pos) - Vec3::new(0.0, 1.25, 0.0), dir.y, 0);
-                                
-                                message.infof = c.pitch;
-                                message.info2 = c.yaw as u32;
+        let udp_alive = self.udp_alive.clone();
+        let udp_alive_send = self.udp_alive.clone();
 
-                                NetworkConnector::sendto(&message, &stream);
-                      
-                                
-                                
-                            }
-                            thread::sleep(Duration::from_millis(250));
+        let connstate = self.state.clone();
+        let connstate_send = self.state.clone();
+        let reconnect_attempts = self.reconnect_attempts.clone();
+        let chatlog = self.chat.clone();
+        let bulk_edits = self.bulk_edits.clone();
+        let download_dir = self.download_dir.clone();
+
+        //Cleared on reconnect so the send thread asks for a new token
+        let asked_udp = Arc::new(AtomicBool::new(false));
+        let asked_udp_recv = asked_udp.clone();
+
+        self.sendthread = Some(thread::spawn(move || {
+            let sr = sr2.clone();
+            let stream = stream2.clone();
+            //let cam = camclone.clone();
+            let shouldsend = shouldsend.clone();
+            let mut ticks: u32 = 0;
+            while sr.load(std::sync::atomic::Ordering::Relaxed) {
+                //Hold on to queued messages while reconnecting instead of writing them into a dead stream
+                let connected = ConnectionState::from_u8(connstate_send.load(std::sync::atomic::Ordering::Relaxed)) == ConnectionState::Connected;
+                if connected && shouldsend.load(std::sync::atomic::Ordering::Relaxed) {
+                    match sendqueue.pop() {
+                        Some(t) => {
+                            NetworkConnector::sendto(&t, &stream);
                         }
-                    }));
+                        None => {
 
+                        }
+                    }
+
+                    if !asked_udp.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                        NetworkConnector::sendto(&Message::new(MessageType::UdpToken, Vec3::ZERO, 0.0, 0), &stream);
+                    }
+
+                    //With UDP up this is only the clock and position saving, so once a second is plenty
+                    let on_udp = udp_alive_send.load(std::sync::atomic::Ordering::Relaxed);
+                    ticks = ticks.wrapping_add(1);
+
+                    if !on_udp || ticks % 4 == 0 {
+                        let mut message = NetworkConnector::my_player_update();
+                        message.bo = on_udp;
+
+                        NetworkConnector::sendto(&message, &stream);
+                    }
+                }
+                thread::sleep(Duration::from_millis(250));
+            }
+        }));
+
+        
+        self.recvthread = Some(thread::spawn(move || {
+            let mut buffer = vec![0; PACKET_SIZE];
+            let csys = csys.clone();
+
+            //let sumsg = Message::new(MessageType::ShutUpMobMsgs, Vec3::ZERO, 0.0, 0);
+            let shouldsend = shouldsend2.clone();
+
+            //NetworkConnector::sendto(&sumsg, &stream);
+            
+            shouldsend.store(false, std::sync::atomic::Ordering::Relaxed);
+            
+            let requdm = Message::new(MessageType::RequestUdm, Vec3::ZERO, 0.0, 0);
+            let reqseed = Message::new(MessageType::RequestSeed, Vec3::ZERO, 0.0, 0);
+            let reqpt = Message::new(MessageType::RequestPt, Vec3::ZERO, 0.0, 0);
+            let reqchest = Message::new(MessageType::ReqChestReg, Vec3::ZERO, 0.0, 0);
+            
+            NetworkConnector::sendto(&requdm, &stream);
+
+            let framesize = Message::get_serialized_size();
+            let mut last_heard = Instant::now();
+            //Set after a reconnect until the resync finishes with the chest registry
+            let mut resyncing = false;
+            //Each UDP thread runs until its connection is replaced
+            let mut udp_run = Arc::new(AtomicBool::new(true));
+            //When to ask for the world again after a download that didn't make it, waited out here so the stream isn't held meanwhile
+            let mut udm_retry: Option<Instant> = None;
+            //From the server when we first joined, shown again when we reconnect
+            let mut session: Option<(u64, u64)> = None;
+            //A Chat or BulkBlockSet whose bytes are still coming in, so the send thread isn't kept off the stream meanwhile
+            let mut pending: Option<(Message, PayloadReader)> = None;
+
+            while sr.load(std::sync::atomic::Ordering::Relaxed) {
+                let mut lost = last_heard.elapsed() > HEARTBEAT_TIMEOUT;
+                if lost {
+                    info!("Haven't heard from the server in {:?}", HEARTBEAT_TIMEOUT);
+                }
+
+                if udm_retry.is_some_and(|at| Instant::now() >= at) {
+                    udm_retry = None;
+                    NetworkConnector::sendto(&requdm, &stream);
+                }
+
+                let mut temp_buffer = vec![0; PACKET_SIZE];
+
+                let data_available = {
+                    match stream.try_lock() {
+                        Some(stream_lock) => {
+                            //Errors count too, reading will find out the connection is gone
+                            !matches!(stream_lock.peek(&mut temp_buffer), Err(ref e) if e.kind() == io::ErrorKind::WouldBlock)
+                        }
+                        None => {
+                            false
+                        }
+                    }
                     
-                    self.recvthread = Some(thread::spawn(move || {
-                        let mut buffer = vec![0; PACKET_SIZE];
-                        let csys = csys.clone();
-
-                        //let sumsg = Message::new(MessageType::ShutUpMobMsgs, Vec3::ZERO, 0.0, 0);
-                        let shouldsend = shouldsend2.clone();
-
-                        //NetworkConnector::sendto(&sumsg, &stream);
-                        
-                        shouldsend.store(false, std::sync::atomic::Ordering::Relaxed);
-                        
-                        let requdm = Message::new(MessageType::RequestUdm, Vec3::ZERO, 0.0, 0);
-                        let reqseed = Message::new(MessageType::RequestSeed, Vec3::ZERO, 0.0, 0);
-                        let reqpt = Message::new(MessageType::RequestPt, Vec3::ZERO, 0.0, 0);
-                        let reqchest = Message::new(MessageType::ReqChestReg, Vec3::ZERO, 0.0, 0);
-                        
-                        NetworkConnector::sendto(&requdm, &stream);
-
-                        while sr.load(std::sync::atomic::Ordering::Relaxed) {
-                            let mut temp_buffer = vec![0; PACKET_SIZE];
-
-                            let data_available = {
-                                match stream.try_lock() {
-                                    Some(stream_lock) => {
-                                        stream_lock.peek(&mut temp_buffer).is_ok()
-                                    }
-                                    None => {
-                                        false
+                };
+
+                if data_available && !lost && pending.is_none() {
+                    let mut stream_lock = stream.lock();
+
+
+
+
+                    match NetworkConnector::read_frame(&mut stream_lock, &mut buffer[..framesize]) {
+                        Ok(()) => {
+                            last_heard = Instant::now();
+                            let comm: Message = match Message::decode(&buffer[..framesize]) {
+                                Some(msg) => {
+
+                                    match msg.message_type {
+                                        MessageType::ChestInvUpdate => {
+                                            info!("CIU incoming goose {}", Uuid::from_u64_pair(msg.goose.0, msg.goose.1));
+                                        }
+                                        _ => {
+
+                                        }
                                     }
+                                    msg
+                                }
+                                None => {
+                                    Message::new(MessageType::None, Vec3::ZERO, 0.0, 0)
                                 }
-                                
                             };
 
-                            if data_available {
-                                let mut stream_lock = stream.lock();
+                            match comm.message_type {
+                                MessageType::Disconnect => {
+                                    pme.remove(&Uuid::from_u64_pair(comm.goose.0, comm.goose.1));
+                                }
+                                MessageType::ChestReg => {
+                                    
+                                    info!("Receiving ChestReg:");
 
+                                    if comm.info > server_types::MAX_PAYLOAD_SIZE {
+                                        info!("Ignoring chestreg claiming {} bytes", comm.info);
+                                    } else if comm.info > 0 {
 
 
 
-                                match stream_lock.read(&mut buffer) {
-                                    Ok(size) if size > 0 => {
-                                        let comm: Message = match bincode::deserialize::<Message>(&buffer[..size]) {
-                                            Ok(msg) => {
 
-                                                match msg.message_type {
-                                                    MessageType::ChestInvUpdate => {
-                                                        info!("CIU incoming goose {}", Uuid::from_u64_pair(msg.goose.0, msg.goose.1));
-                                                    }
-                                                    _ => {
 
-                                                    }
+
+                                        let mut payload_buffer = vec![0u8; comm.info as usize];
+                                        let mut total_read = 0;
+
+                                        let mut numtimes = 0;
+            
+                                        while total_read < comm.info as usize {
+                                            match stream_lock.read(&mut payload_buffer[total_read..]) {
+                                                Ok(n) if n > 0 => total_read += n,
+                                                Ok(_) => {
+                                                    // Connection closed
+                                                    info!("Connection closed by server during chestreg");
+                                                    break;
+                                                }
+                                                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                                                    // Sleep for a short period and retry
+                                                    thread::sleep(Duration::from_millis(10));
+                                                }
+                                                Err(e) => {
+                                                    info!("Error receiving chestreg: {}", e);
+                                                    break;
                                                 }
-                                                msg
                                             }
-                                            Err(_e) => {
-                                                Message::new(MessageType::None, Vec3::ZERO, 0.0, 0)
+                                            numtimes += 1;
+                                            if numtimes > 100 {
+                                                numtimes = 0;
+                                                NetworkConnector::sendtolocked(&reqchest, &mut stream_lock);
                                             }
+                                        }
+            
+                                        let payload = if total_read == comm.info as usize {
+                                            compression::decode_payload(&payload_buffer, comm.info2)
+                                        } else {
+                                            None
                                         };
 
-                                        match comm.message_type {
-                                            MessageType::Disconnect => {
-                                                pme.remove(&Uuid::from_u64_pair(comm.goose.0, comm.goose.1));
-                                            }
-                                            MessageType::ChestReg => {
-                                                
-                                                info!("Receiving ChestReg:");
-
-                                                if comm.info > 0 {
-
-
-
-
-
-
-                                                    let mut payload_buffer = vec![0u8; comm.info as usize];
-                                                    let mut total_read = 0;
-
-                                                    let mut numtimes = 0;
-                        
-                                                    while total_read < comm.info as usize {
-                                                        match stream_lock.read(&mut payload_buffer[total_read..]) {
-                                                            Ok(n) if n > 0 => total_read += n,
-                                                            Ok(_) => {
-                                                                // Connection closed
-                                                                info!("Connection closed by server during chestreg");
-                                                                break;
-                                                            }
-                                                            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                                                                // Sleep for a short period and retry
-                                                                thread::sleep(Duration::from_millis(10));
-                                                            }
-                                                            Err(e) => {
-                                                                info!("Error receiving chestreg: {}", e);
-                                                                break;
-                                                            }
-                                                        }
-                                                        numtimes += 1;
-                                                        if numtimes > 100 {
-                                                            numtimes = 0;
-                                                            NetworkConnector::sendtolocked(&reqchest, &mut stream_lock);
-                                                        }
-                                                    }
-                        
-                                                    if total_read == comm.info as usize {
+                                        if let Some(payload) = payload {
 
-                                                        info!("Got the expected bytes for chestreg");
-                                                        let mut file = File::create("chestdb").unwrap();
-                                                        file.write_all(&payload_buffer).unwrap();
+                                            info!("Got the expected bytes for chestreg");
+                                            if let Err(e) = worldstorage::write_file(download_dir.join("chestdb"), &payload) {
+                                                info!("Couldn't save the chests the server sent: {}", e);
+                                            }
 
-                                                        let seed = unsafe {CURRSEED.load(std::sync::atomic::Ordering::Relaxed)};
+                                            let seed = unsafe {CURRSEED.load(std::sync::atomic::Ordering::Relaxed)};
 
 
-                                                        Game::static_load_chests_from_file(seed, &chestreg);
-                                                        //csys.write().load_my_inv_from_file();
-                                                        hpcommqueue.push(comm);
-                                                        recv_world_bool.store(true, std::sync::atomic::Ordering::Relaxed);
-                                                        shouldsend.store(true, std::sync::atomic::Ordering::Relaxed);
-                                                        
-                                                    } else {
+                                            if let Err(e) = Game::static_load_chests_from_path(download_dir.join("chestdb"), seed, &chestreg) {
+                                                info!("Couldn't load the chests the server sent: {}", e);
+                                            }
+                                            //csys.write().load_my_inv_from_file();
+                                            //bo tells the game this is a resync, so it keeps the player where they are
+                                            let mut comm = comm;
+                                            comm.bo = resyncing;
+                                            resyncing = false;
+                                            hpcommqueue.push(comm);
+                                            recv_world_bool.store(true, std::sync::atomic::Ordering::Relaxed);
+                                            shouldsend.store(true, std::sync::atomic::Ordering::Relaxed);
+                                            
+                                        } else {
 
 
-                                                        info!("Error receiving chestreg, trying again...");
-                                                        NetworkConnector::sendtolocked(&reqchest, &mut stream_lock);
-                                                    }
+                                            info!("Error receiving chestreg, trying again...");
+                                            NetworkConnector::sendtolocked(&reqchest, &mut stream_lock);
+                                        }
 
 
 
 
 
 
-                                                
-                                                    
-                                                } else {
-                                                    recv_world_bool.store(true, std::sync::atomic::Ordering::Relaxed);
-                                                    shouldsend.store(true, std::sync::atomic::Ordering::Relaxed);
-                                                }
+                                    
+                                        
+                                    } else {
+                                        recv_world_bool.store(true, std::sync::atomic::Ordering::Relaxed);
+                                        shouldsend.store(true, std::sync::atomic::Ordering::Relaxed);
+                                    }
 
-                                                
-                                                
+                                    
+                                    
 
-                                            }
-                                            MessageType::ReqChestReg => {
+                                }
+                                MessageType::ReqChestReg => {
 
-                                            }
-                                            MessageType::TellYouMyID => {
+                                }
+                                MessageType::TellYouMyID => {
 
-                                            }
-                                            MessageType::None => {
-                                                
-                                            }
-                                            MessageType::RequestUdm => {
+                                }
+                                MessageType::None => {
+                                    
+                                }
+                                MessageType::RequestUdm => {
 
-                                            },
-                                            MessageType::RequestSeed => {
-                                                
-                                            },
-                                            
-                                            MessageType::PlayerUpdate => {
-
-                                                
-
-                                                let newpos = Vec3::new(comm.x, comm.y, comm.z);
-                                                //let id = comm.info;
-                                                let _modind = comm.info2;
-                                                let rot = comm.rot;
-                                                let scale = PLAYERSCALE;
-
-                                                let pme: Arc<DashMap<Uuid, ModelEntity>> = pme.clone();
-
-
-                                                let uuid = Uuid::from_u64_pair(comm.goose.0, comm.goose.1);
-
-                                                //info!("Player update: {uuid}");
-                                                //info!("NSME Length: {}", nsme.len());
-                                                match pme.get_mut(&uuid) {
-                                                    Some(mut me) => {
-                                                        let modent = me.value_mut();
-                                                        (*modent).lastpos = (*modent).position.clone();
-                                                        (*modent).position = newpos;
-                                                        (*modent).scale = scale;
-                                                        (*modent).lastrot = (*modent).rot.clone();
-                                                        (*modent).rot = Vec3::new(0.0, rot, 0.0);
-                                                        unsafe {
-                                                            (*modent).time_stamp = glfwGetTime();
-                                                        }
-                                                        
-                                                        
-                                                    }
-                                                    None => {
-                                                        commqueue.push(comm.clone());
-                                                    }
-                                                };
-
-                                                
-                                            },
-                                            MessageType::BlockSet => {
-                                                // if recv_m.info == 0 {
-                                                //     csys.read().set_block_and_queue_rerender(IVec3::new(recv_m.x as i32, recv_m.y as i32, recv_m.z as i32), 
-                                                //     recv_m.info, true, true);
-                                                // } else {
-                                                //     csys.read().set_block_and_queue_rerender(IVec3::new(recv_m.x as i32, recv_m.y as i32, recv_m.z as i32), 
-                                                //     recv_m.info, false, true);
-                                                // }
-                                                
-                                                
-                                                hpcommqueue.push(comm.clone());
-                                            },
-                                            MessageType::MultiBlockSet => {
-                                                // if recv_m.info == 0 {
-                                                //     csys.read().set_block_and_queue_rerender(IVec3::new(recv_m.x as i32, recv_m.y as i32, recv_m.z as i32), 
-                                                //     recv_m.info, true, true);
-                                                // } else {
-                                                //     csys.read().set_block_and_queue_rerender(IVec3::new(recv_m.x as i32, recv_m.y as i32, recv_m.z as i32), 
-                                                //     recv_m.info, false, true);
-                                                // }
-                                                hpcommqueue.push(comm.clone());
-                                            },
-                                            MessageType::Udm => {
-                                                info!("Receiving Udm:");
-                                                shouldsend.store(false, std::sync::atomic::Ordering::Relaxed);
-                                                
-                                                stream_lock.set_nonblocking(false).unwrap();
-                                                
-
-
-
-                                                let mut buff = vec![0 as u8; comm.info as usize];
-
-                                                stream_lock.set_read_timeout(Some(Duration::from_secs(5)));
-
-                                                match stream_lock.read_exact(&mut buff) {
-
-
-                                                    Ok(_) => {
-                                                        info!("Got the expected bytes for udm");
-                                                        let mut file = File::create("db").unwrap();
-                                                        file.write_all(&buff).unwrap();
-
-                                                        NetworkConnector::sendtolocked(&reqseed, &mut stream_lock);
-                                                    }
-                                                    Err(e) => {
-                                                        info!("Error receiving, trying again... {e}");
-                                                        thread::sleep(Duration::from_millis(1000));
-                                                        NetworkConnector::sendtolocked(&requdm, &mut stream_lock);
+                                },
+                                MessageType::RequestSeed => {
+                                    
+                                },
+                                
+                                MessageType::PlayerUpdate => {
+                                    NetworkConnector::apply_player_update(&pme, &commqueue, &comm);
+                                },
+                                MessageType::BlockSet => {
+                                    // if recv_m.info == 0 {
+                                    //     csys.read().set_block_and_queue_rerender(IVec3::new(recv_m.x as i32, recv_m.y as i32, recv_m.z as i32), 
+                                    //     recv_m.info, true, true);
+                                    // } else {
+                                    //     csys.read().set_block_and_queue_rerender(IVec3::new(recv_m.x as i32, recv_m.y as i32, recv_m.z as i32), 
+                                    //     recv_m.info, false, true);
+                                    // }
+                                    
+                                    
+                                    hpcommqueue.push(comm.clone());
+                                },
+                                MessageType::MultiBlockSet => {
+                                    // if recv_m.info == 0 {
+                                    //     csys.read().set_block_and_queue_rerender(IVec3::new(recv_m.x as i32, recv_m.y as i32, recv_m.z as i32), 
+                                    //     recv_m.info, true, true);
+                                    // } else {
+                                    //     csys.read().set_block_and_queue_rerender(IVec3::new(recv_m.x as i32, recv_m.y as i32, recv_m.z as i32), 
+                                    //     recv_m.info, false, true);
+                                    // }
+                                    hpcommqueue.push(comm.clone());
+                                },
+                                MessageType::Udm if comm.info > server_types::MAX_PAYLOAD_SIZE => {
+                                    info!("Ignoring udm claiming {} bytes", comm.info);
+                                },
+                                MessageType::Udm => {
+                                    info!("Receiving Udm:");
+                                    shouldsend.store(false, std::sync::atomic::Ordering::Relaxed);
+                                    
+                                    stream_lock.set_nonblocking(false).unwrap();
+                                    
+
+
+
+                                    let mut buff = vec![0 as u8; comm.info as usize];
+
+                                    stream_lock.set_read_timeout(Some(Duration::from_secs(5)));
+
+                                    match stream_lock.read_exact(&mut buff) {
+
+
+                                        Ok(_) => {
+                                            match compression::decode_payload(&buff, comm.info2) {
+                                                Some(payload) => {
+                                                    info!("Got the expected bytes for udm");
+                                                    if let Err(e) = worldstorage::write_file(download_dir.join("db"), &payload) {
+                                                        info!("Couldn't save the world the server sent: {}", e);
                                                     }
 
+                                                    NetworkConnector::sendtolocked(&reqseed, &mut stream_lock);
                                                 }
+                                                None => {
+                                                    info!("Couldn't decode udm, trying again...");
+                                                    //Wait out the server's world sync cooldown or the retry gets dropped
+                                                    udm_retry = Some(Instant::now() + Duration::from_millis(5000));
+                                                }
+                                            }
+                                        }
+                                        Err(e) => {
+                                            info!("Error receiving, trying again... {e}");
+                                            udm_retry = Some(Instant::now() + Duration::from_millis(1000));
+                                        }
 
-                                                stream_lock.set_nonblocking(true).unwrap();
-                                            },
-                                            MessageType::Seed => {
-                                                //info!("Receiving Seed:");
-                                                // let mut buff = vec![0 as u8; comm.info as usize];
-
-                                                // stream_lock.set_nonblocking(false).unwrap();
-
-
-                                                // stream_lock.read_exact(&mut buff).unwrap();
+                                    }
 
+                                    stream_lock.set_nonblocking(true).unwrap();
+                                },
+                                MessageType::Seed => {
+                                    //info!("Receiving Seed:");
+                                    // let mut buff = vec![0 as u8; comm.info as usize];
 
-                                                let recv_s = format!("{}", comm.info);
+                                    // stream_lock.set_nonblocking(false).unwrap();
 
-                                                info!("Received seed: {}", recv_s);
 
-                                                // Create directory if not exists
-                                                    fs::create_dir_all("mp").unwrap();
+                                    // stream_lock.read_exact(&mut buff).unwrap();
 
-                                                    // Create or open file for writing
-                                                    let mut file = File::create("mp/seed2").unwrap();
 
-                                                    // Write the received seed to the file
-                                                    file.write_all(recv_s.as_bytes()).unwrap();
-                                                    // Flush the buffer to ensure all data is written
-                                                    file.flush().unwrap();
+                                    let recv_s = format!("{}", comm.info);
 
-                                                    // Verify if the content is correctly written
-                                                    let content = std::fs::read_to_string("mp/seed2").unwrap();
-                                                    info!("File content: {}", content);
+                                    info!("Received seed: {}", recv_s);
 
+                                    //Before any terrain gets made with it
+                                    GeneratorSettings::from_bits(comm.info2).apply();
 
-                                                        commqueue.push(comm.clone());
-                                                        
-                                                        thread::sleep(Duration::from_millis(200));
-                                                        NetworkConnector::sendtolocked(&reqpt, &mut stream_lock);
+                                        if let Err(e) = worldstorage::write_file(download_dir.join("mp/seed2"), recv_s.as_bytes()) {
+                                            info!("Couldn't save the seed the server sent: {}", e);
+                                        }
 
 
-                                                stream_lock.set_nonblocking(true).unwrap();
-                                                //info!("{}", recv_s);
+                                            commqueue.push(comm.clone());
+                                            
+                                            thread::sleep(Duration::from_millis(200));
+                                            NetworkConnector::sendtolocked(&reqpt, &mut stream_lock);
 
-                                                
-                                            },
-                                            MessageType::RequestTakeoff => {
-                                                commqueue.push(comm.clone());
-                                            },
-                                            MessageType::RequestPt => {
-                                                
-                                            },
-                                            MessageType::Pt => {
-                                                //info!("Receiving Pt:");
-                                                // let mut buff = vec![0 as u8; comm.info as usize];
 
-                                                // stream_lock.set_nonblocking(false).unwrap();
+                                    stream_lock.set_nonblocking(true).unwrap();
+                                    //info!("{}", recv_s);
 
-                                                // stream_lock.read_exact(&mut buff).unwrap();
+                                    
+                                },
+                                MessageType::RequestTakeoff => {
+                                    commqueue.push(comm.clone());
+                                },
+                                MessageType::RequestPt => {
+                                    
+                                },
+                                MessageType::Pt => {
+                                    //info!("Receiving Pt:");
+                                    // let mut buff = vec![0 as u8; comm.info as usize];
 
+                                    // stream_lock.set_nonblocking(false).unwrap();
 
-                                                fs::create_dir_all("mp").unwrap();
-                                                let mut file = File::create("mp/pt").unwrap(); 
+                                    // stream_lock.read_exact(&mut buff).unwrap();
 
-                                                
-                                                let pt = comm.info;
-                                                let recv_s = format!("{pt}");
-                                                file.write_all(recv_s.as_bytes()).unwrap();
 
+                                    let pt = comm.info;
+                                    let recv_s = format!("{pt}");
+                                    if let Err(e) = worldstorage::write_file(download_dir.join("mp/pt"), recv_s.as_bytes()) {
+                                        info!("Couldn't save the planet type the server sent: {}", e);
+                                    }
 
 
 
-                                                csys.write().load_world_from_file(String::from("mp"));
 
-                                                thread::sleep(Duration::from_millis(200));
-                                                NetworkConnector::sendtolocked(&reqchest, &mut stream_lock);
-                                                
-                            
-                                                //info!("{}", recv_s);
-
-                                                
-                                                
-                                            },
-                                            MessageType::YourId => {
-                                                // //info!("Receiving Your ID:");
-                                                // stream_lock.set_nonblocking(false).unwrap();
-                                                // let mut buff = vec![0 as u8; comm.info as usize];
-                                                // stream_lock.read_exact(&mut buff).unwrap();
-
-                                                let recv_s = comm.goose;
-                                                let uuid = Uuid::from_u64_pair(recv_s.0, recv_s.1);
-                                                //info!("{}", uuid);
-
-                                                info!("My uuid, I am being told, is {uuid}");
-
-                                                gknowncams.insert(
-                                                    uuid.clone(), Vec3::ZERO
-                                                );
-                                                //*(my_uuid.write()) = Some(uuid);
-
-
-                                                
-                                                // stream_lock.set_nonblocking(true).unwrap();
-                                            },
-                                            MessageType::MobUpdate => {
-                                                
-                                                commqueue.push(comm.clone());
-                                                
-                                            },
-                                            MessageType::NewMob => {
-                                                let _newid = comm.info;
-
-                                                let _newtype = comm.info2;
-
-                                                let _newpos = Vec3::new(comm.x, comm.y, comm.z);
-                                            },
-                                            MessageType::WhatsThatMob => todo!(),
-                                            MessageType::ShutUpMobMsgs =>  {
-                                                
-                                            },
-                                            MessageType::MobUpdateBatch => {
-                                                //info!("Got MUB, count {}", comm.count);
-                                                if comm.count > server_types::MOB_BATCH_SIZE as u8 {
-                                                    info!("Ignoring invalid mobbatch with count > {} of {}", server_types::MOB_BATCH_SIZE, comm.count);
-                                                } else {
-                                                    for i in 0..comm.count.min(MOB_BATCH_SIZE as u8) {
-                                                        
-                                                        let msg = Message::from_mob_message(&comm.msgs[i as usize]);
-                                                        commqueue.push(msg);
-                                                    }
-                                                }
-                                                        
-            
-                                            }
-                                            MessageType::TimeUpdate => {
-                                                commqueue.push(comm.clone());
-                                            }
-                                            MessageType::ChestInvUpdate => {
-                                                //info!("Receiving CIU from goose {}", Uuid::from_u64_pair(comm.goose.0, comm.goose.1));
-                                                hpcommqueue.push(comm.clone());
-                                            },
+                                    {
+                                        let mut csys_lock = csys.write();
+                                        csys_lock.db_path = download_dir.join("db");
+                                        if let Err(e) = csys_lock.load_world_from_file(download_dir.join("mp").to_string_lossy().to_string()) {
+                                            info!("Couldn't load the world the server sent: {}", e);
                                         }
+                                    }
 
-                                        //info!("Received message from server: {:?}", recv_m);
+                                    thread::sleep(Duration::from_millis(200));
+                                    NetworkConnector::sendtolocked(&reqchest, &mut stream_lock);
+                                    
+                
+                                    //info!("{}", recv_s);
+
+                                    
+                                    
+                                },
+                                MessageType::YourId => {
+                                    // //info!("Receiving Your ID:");
+                                    // stream_lock.set_nonblocking(false).unwrap();
+                                    // let mut buff = vec![0 as u8; comm.info as usize];
+                                    // stream_lock.read_exact(&mut buff).unwrap();
+
+                                    let recv_s = comm.goose;
+                                    let uuid = Uuid::from_u64_pair(recv_s.0, recv_s.1);
+                                    //info!("{}", uuid);
+
+                                    info!("My uuid, I am being told, is {uuid}");
+
+                                    gknowncams.insert(
+                                        uuid.clone(), Vec3::ZERO
+                                    );
+                                    //*(my_uuid.write()) = Some(uuid);
+
+
+                                    
+                                    // stream_lock.set_nonblocking(true).unwrap();
+                                },
+                                MessageType::MobUpdate => {
+                                    
+                                    commqueue.push(comm.clone());
+                                    
+                                },
+                                MessageType::NewMob => {
+                                    let _newid = comm.info;
+
+                                    let _newtype = comm.info2;
+
+                                    let _newpos = Vec3::new(comm.x, comm.y, comm.z);
+                                },
+                                MessageType::WhatsThatMob => {
+
+                                },
+                                MessageType::ShutUpMobMsgs =>  {
+                                    
+                                },
+                                MessageType::MobUpdateBatch => {
+                                    NetworkConnector::apply_mob_batch(&commqueue, &comm);
+                                }
+                                MessageType::UdpToken => {
+                                    //Port 0 means the server has no UDP, we just stay on TCP
+                                    if comm.info != 0 {
+                                        if let Ok(peer) = stream_lock.peer_addr() {
+                                            NetworkConnector::start_udp(SocketAddr::new(peer.ip(), comm.info as u16), comm.goose, &udp_run, &udp_alive, &pme, &commqueue);
+                                        }
                                     }
-                                    Ok(_) => {
-                                        info!("Connection closed by server");
-                                        break;
+                                }
+                                MessageType::TimeUpdate => {
+                                    commqueue.push(comm.clone());
+                                }
+                                MessageType::SessionToken => {
+                                    session = Some(comm.goose);
+                                }
+                                MessageType::ChestInvUpdate => {
+                                    //info!("Receiving CIU from goose {}", Uuid::from_u64_pair(comm.goose.0, comm.goose.1));
+                                    hpcommqueue.push(comm.clone());
+                                },
+                                MessageType::BlockSetRejected => {
+                                    hpcommqueue.push(comm.clone());
+                                },
+                                MessageType::Chat => {
+                                    match chat::text_reader(comm.info) {
+                                        Some(reader) => pending = Some((comm.clone(), reader)),
+                                        None => {
+                                            info!("Couldn't read a chat line");
+                                            lost = true;
+                                        }
                                     }
-                                    Err(e) => {
-                                        info!("Failed to receive message: {}", e);
-                                        break;
+                                },
+                                MessageType::ChestOpen => {
+                                    hpcommqueue.push(comm.clone());
+                                },
+                                MessageType::BulkBlockSet => {
+                                    match bulkedit::payload_reader(comm.info) {
+                                        Some(reader) => pending = Some((comm.clone(), reader)),
+                                        None => {
+                                            info!("Couldn't read a bulk block set");
+                                            lost = true;
+                                        }
                                     }
-                                }
+                                },
                             }
+
+                            //info!("Received message from server: {:?}", recv_m);
+                        }
+                        Err(e) => {
+                            info!("Failed to receive message: {}", e);
+                            lost = true;
                         }
-                    }));
+                    }
                 }
-                Err(e) => {
-                    info!("Error from connect(): {e}");
-                    thread::sleep(Duration::from_secs(1));
+
+                if let Some((header, mut reader)) = pending.take().filter(|_| !lost) {
+                    let polled = reader.poll(&mut stream.lock());
+                    match polled {
+                        Ok(Some(payload)) => {
+                            last_heard = Instant::now();
+                            if !NetworkConnector::take_payload(&header, &payload, &chatlog, &bulk_edits, &hpcommqueue) {
+                                info!("Couldn't read a {}", header.message_type);
+                                lost = true;
+                            }
+                        }
+                        Ok(None) => pending = Some((header, reader)),
+                        Err(e) => {
+                            info!("Didn't get the rest of a {}: {}", header.message_type, e);
+                            lost = true;
+                        }
+                    }
+                }
+
+                if lost && sr.load(std::sync::atomic::Ordering::Relaxed) {
+                    udp_run.store(false, std::sync::atomic::Ordering::Relaxed);
+                    udp_alive.store(false, std::sync::atomic::Ordering::Relaxed);
+
+                    if !NetworkConnector::reconnect(&stream, &addrs, &sr, &connstate, &reconnect_attempts, session) {
+                        break;
+                    }
+
+                    //Same UUID, so the server gives us our inventory back. Then the usual chain: udm, seed, pt, chests.
+                    udp_run = Arc::new(AtomicBool::new(true));
+                    asked_udp_recv.store(false, std::sync::atomic::Ordering::Relaxed);
+                    resyncing = true;
+                    last_heard = Instant::now();
+                    udm_retry = None;
+                    NetworkConnector::sendto(&requdm, &stream);
                 }
             }
-        }
-        //let tcp_stream = TcpStream::connect(address).unwrap();
-        
+
+            udp_run.store(false, std::sync::atomic::Ordering::Relaxed);
+            connstate.store(ConnectionState::Disconnected as u8, std::sync::atomic::Ordering::Relaxed);
+        }));
     }
 }
@@ -6,6 +6,22 @@ pub struct Planets {}
 
 
 impl Planets {
+    /// How many planet types there are, numbered from 0.
+    pub const COUNT: u32 = 2;
+
+    pub fn get_name(dim_id: u32) -> &'static str {
+        return match dim_id {
+            0 => {
+                "Temperate"
+            }
+            1 => {
+                "Hostile"
+            }
+            _ => {
+                "Unknown"
+            }
+        }
+    }
     pub fn get_voxel_model_index_range(dim_id: u32) -> (usize, usize) {
         //inclusive range of what voxel model indexes go in each dimension
         return match dim_id {
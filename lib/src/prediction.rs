@@ -0,0 +1,119 @@
+use crate::edithistory::HistoryEntry;
+use crate::vec::IVec3;
+
+/// A block edit we drew before the server agreed to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingEdit {
+    pub seq: u32,
+    /// (spot, block before, block we set). One entry for a `BlockSet`, two for a `MultiBlockSet`.
+    pub blocks: Vec<(IVec3, u32, u32)>,
+    /// Items taken out of the inventory to place this as (id, count), given back if it's rolled back.
+    pub cost: Vec<(u32, u32)>,
+    /// Items it gave, like the blocks an undo took away, taken back if it's rolled back.
+    pub refund: Vec<(u32, u32)>,
+    /// Item this dropped when broken, taken back if it's rolled back.
+    pub yields: Option<u32>,
+    /// The undo history action it's part of.
+    pub history: Option<HistoryEntry>,
+}
+
+/// Client side prediction for multiplayer block edits.
+///
+/// Every edit we apply locally gets a sequence id and sits here until the server echoes it back
+/// (confirmed) or answers with a `BlockSetRejected` (rolled back). The server handles a client's
+/// messages in order and TCP keeps them in order, so anything the server sends us about a spot was
+/// decided before all of our still pending edits there.
+#[derive(Debug, Default)]
+pub struct PendingEdits {
+    next: u32,
+    pending: Vec<PendingEdit>,
+}
+
+impl PendingEdits {
+    pub fn new() -> PendingEdits {
+        PendingEdits::default()
+    }
+
+    /// A fresh sequence id, never 0 since that means "not predicted" on the wire.
+    pub fn next_seq(&mut self) -> u32 {
+        self.next = self.next.wrapping_add(1);
+        if self.next == 0 {
+            self.next = 1;
+        }
+        self.next
+    }
+
+    pub fn push(&mut self, edit: PendingEdit) {
+        self.pending.push(edit);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    pub fn is_pending(&self, spot: IVec3) -> bool {
+        self.pending.iter().any(|e| e.blocks.iter().any(|b| b.0 == spot))
+    }
+
+    /// Someone else's edit landed at `spot` while we have edits pending there. It happened before ours on
+    /// the server, so instead of drawing it (and flickering back when our echo arrives) it becomes what a
+    /// rollback of our first edit there restores. `false` if nothing here is pending and it should be drawn.
+    pub fn absorb_foreign(&mut self, spot: IVec3, block: u32) -> bool {
+        for edit in self.pending.iter_mut() {
+            for b in edit.blocks.iter_mut() {
+                if b.0 == spot {
+                    b.1 = block;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// The server echoed our edit `seq` with what it actually set. Returns the edit and any spots where the
+    /// server set something other than what we drew and no later edit of ours covers it, those need redrawing.
+    pub fn confirm(&mut self, seq: u32, actual: &[(IVec3, u32)]) -> Option<(PendingEdit, Vec<(IVec3, u32)>)> {
+        let index = self.pending.iter().position(|e| e.seq == seq)?;
+        let edit = self.pending.remove(index);
+
+        let mut corrections = Vec::new();
+        for (spot, block) in actual {
+            let predicted = edit.blocks.iter().find(|b| b.0 == *spot).map(|b| b.2);
+            if predicted == Some(*block) {
+                continue;
+            }
+            if !self.absorb_foreign(*spot, *block) {
+                corrections.push((*spot, *block));
+            }
+        }
+        Some((edit, corrections))
+    }
+
+    /// The server threw out edit `seq`. Returns the edit and the blocks to put back. A spot that a later
+    /// pending edit also touched stays as drawn, that edit now restores to what this one would have.
+    pub fn reject(&mut self, seq: u32) -> Option<(PendingEdit, Vec<(IVec3, u32)>)> {
+        let index = self.pending.iter().position(|e| e.seq == seq)?;
+        let edit = self.pending.remove(index);
+
+        let mut restore = Vec::new();
+        for (spot, old, _) in edit.blocks.iter() {
+            let later = self.pending[index..]
+                .iter_mut()
+                .flat_map(|e| e.blocks.iter_mut())
+                .find(|b| b.0 == *spot);
+            match later {
+                Some(b) => b.1 = *old,
+                None => restore.push((*spot, *old)),
+            }
+        }
+        Some((edit, restore))
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::worldgen::{self, WorldOptions};
+use crate::worlddir::{self, Manifest, BACKUP_DIR, CLIENT_DIR};
+
+/// Where singleplayer worlds live, a folder each.
+pub const SAVES_DIR: &str = "saves";
+
+/// The last view of a world, shown next to it in the world list.
+pub const THUMBNAIL_FILE: &str = "thumbnail.png";
+
+pub const THUMBNAIL_WIDTH: u32 = 256;
+
+/// One singleplayer world.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveSlot {
+    pub dir: PathBuf,
+    pub manifest: Manifest,
+}
+
+impl SaveSlot {
+    pub fn name(&self) -> &str {
+        if self.manifest.name.is_empty() {
+            self.dir.file_name().and_then(|n| n.to_str()).unwrap_or("World")
+        } else {
+            &self.manifest.name
+        }
+    }
+
+    pub fn thumbnail(&self) -> Option<PathBuf> {
+        let path = self.dir.join(THUMBNAIL_FILE);
+        path.exists().then_some(path)
+    }
+}
+
+/// The world list: creates, renames, copies and deletes the world folders under `root`.
+pub struct Saves {
+    pub root: PathBuf,
+}
+
+impl Saves {
+    pub fn new(root: impl Into<PathBuf>) -> Saves {
+        Saves { root: root.into() }
+    }
+
+    /// Every world, the last one played first. Folders without a manifest aren't worlds and are left out.
+    pub fn list(&self) -> Vec<SaveSlot> {
+        let mut slots = Vec::new();
+        if let Ok(entries) = fs::read_dir(&self.root) {
+            for entry in entries.flatten() {
+                let dir = entry.path();
+                if !dir.is_dir() {
+                    continue;
+                }
+                match Manifest::read(&dir) {
+                    Ok(Some(manifest)) => slots.push(SaveSlot { dir, manifest }),
+                    Ok(None) => {}
+                    Err(e) => println!("Couldn't read the world in {}: {}", dir.display(), e),
+                }
+            }
+        }
+        slots.sort_by(|a, b| b.manifest.last_played.cmp(&a.manifest.last_played).then_with(|| a.dir.cmp(&b.dir)));
+        slots
+    }
+
+    /// A new world made the way the world creation screen says. Everything it was made with goes in
+    /// the manifest, so the same options always make the same world.
+    pub fn create(&self, name: &str, options: &WorldOptions) -> io::Result<SaveSlot> {
+        let dir = self.free_dir(name);
+        fs::create_dir_all(&dir)?;
+
+        let seed = worldgen::seed_from_text(&options.seed_text).unwrap_or_else(|| StdRng::from_entropy().gen());
+        let mut manifest = Manifest::new(seed, options.planet_type);
+        manifest.name = name.trim().to_string();
+        manifest.seed_text = options.seed_text.trim().to_string();
+        manifest.generator = options.generator;
+        manifest.write(&dir)?;
+
+        let manifest = worlddir::open(&dir, seed)?;
+        Ok(SaveSlot { dir, manifest })
+    }
+
+    /// Only the name changes, the folder stays where it is.
+    pub fn rename(&self, slot: &SaveSlot, name: &str) -> io::Result<SaveSlot> {
+        let mut manifest = Manifest::read(&slot.dir)?.unwrap_or_else(|| slot.manifest.clone());
+        manifest.name = name.trim().to_string();
+        manifest.write(&slot.dir)?;
+        Ok(SaveSlot { dir: slot.dir.clone(), manifest })
+    }
+
+    /// A copy of the world as it is now, without its backups or the game's copy of it.
+    pub fn duplicate(&self, slot: &SaveSlot, name: &str) -> io::Result<SaveSlot> {
+        let dir = self.free_dir(name);
+        fs::create_dir_all(&dir)?;
+        for entry in fs::read_dir(&slot.dir)? {
+            let entry = entry?;
+            if entry.file_name() == BACKUP_DIR || entry.file_name() == CLIENT_DIR {
+                continue;
+            }
+            worlddir::copy_all(&entry.path(), &dir.join(entry.file_name()))?;
+        }
+
+        let mut manifest = Manifest::read(&dir)?.unwrap_or_else(|| slot.manifest.clone());
+        manifest.name = name.trim().to_string();
+        manifest.created = worlddir::now();
+        manifest.write(&dir)?;
+        Ok(SaveSlot { dir, manifest })
+    }
+
+    pub fn delete(&self, slot: &SaveSlot) -> io::Result<()> {
+        //Never anything outside the saves folder, whatever the slot says
+        if slot.dir.parent() != Some(self.root.as_path()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't in {}", slot.dir.display(), self.root.display())));
+        }
+        fs::remove_dir_all(&slot.dir)
+    }
+
+    /// Moves the one world there was before there were save slots into the list.
+    pub fn import_legacy(&self, legacy: &Path, seed: u32) -> io::Result<Option<SaveSlot>> {
+        if !legacy.is_dir() {
+            return Ok(None);
+        }
+        let dir = self.free_dir("World");
+        fs::create_dir_all(&self.root)?;
+        fs::rename(legacy, &dir)?;
+
+        let mut manifest = worlddir::open(&dir, seed)?;
+        if manifest.name.is_empty() {
+            manifest.name = String::from("World");
+            manifest.write(&dir)?;
+        }
+        println!("Moved the singleplayer world in {} to {}", legacy.display(), dir.display());
+        Ok(Some(SaveSlot { dir, manifest }))
+    }
+
+    /// A folder named after `name` that isn't taken yet.
+    fn free_dir(&self, name: &str) -> PathBuf {
+        let base = folder_name(name);
+        let mut dir = self.root.join(&base);
+        let mut n = 2;
+        while dir.exists() {
+            dir = self.root.join(format!("{}-{}", base, n));
+            n += 1;
+        }
+        dir
+    }
+}
+
+/// `name` as something safe to make a folder out of on any OS.
+pub fn folder_name(name: &str) -> String {
+    let mut folder = String::new();
+    for c in name.trim().chars() {
+        if c.is_ascii_alphanumeric() {
+            folder.push(c.to_ascii_lowercase());
+        } else if !folder.ends_with('-') {
+            folder.push('-');
+        }
+    }
+    let folder = folder.trim_matches('-');
+    if folder.is_empty() {
+        String::from("world")
+    } else {
+        folder.chars().take(48).collect()
+    }
+}
+
+/// How long ago `last_played` was, for the world list.
+pub fn played_ago(last_played: u64, now: u64) -> String {
+    let secs = now.saturating_sub(last_played);
+    match secs {
+        0..=59 => String::from("just now"),
+        60..=3599 => plural(secs / 60, "minute"),
+        3600..=86399 => plural(secs / 3600, "hour"),
+        _ => plural(secs / 86400, "day"),
+    }
+}
+
+fn plural(n: u64, unit: &str) -> String {
+    if n == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", n, unit)
+    }
+}
+
+/// Saves pixels read off the screen as the world's thumbnail. `rgba` is bottom row first, how GL reads them.
+pub fn save_thumbnail(dir: &Path, width: u32, height: u32, rgba: Vec<u8>) -> io::Result<()> {
+    let Some(image) = RgbaImage::from_raw(width, height, rgba) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "thumbnail pixels don't match its size"));
+    };
+    let image = imageops::flip_vertical(&image);
+    let thumb_height = (height as u64 * THUMBNAIL_WIDTH as u64 / width.max(1) as u64).max(1) as u32;
+    let thumb = imageops::resize(&image, THUMBNAIL_WIDTH, thumb_height, FilterType::Triangle);
+
+    //Written next to it and renamed over, the world list may be reading the old one
+    let temp = dir.join(format!("{}.tmp", THUMBNAIL_FILE));
+    thumb.save_with_format(&temp, image::ImageFormat::Png).map_err(io::Error::other)?;
+    fs::rename(temp, dir.join(THUMBNAIL_FILE))
+}
@@ -0,0 +1,251 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use vox_format::data::VoxData;
+use vox_format::types::{Model, Size, Voxel};
+
+use crate::blockinfo::Blocks;
+use crate::game::ROWLENGTH;
+use crate::vec::IVec3;
+
+/// Where schematics are kept, shared by every world so builds move between them.
+pub const SCHEMATICS_DIR: &str = "schematics";
+
+pub const SCHEMATIC_EXTENSION: &str = "vlschem";
+
+/// MagicaVoxel's, the format `BUILD_VOXEL_MODELS` are in.
+pub const VOX_EXTENSION: &str = "vox";
+
+/// Boxes bigger than this many blocks aren't copied.
+pub const MAX_VOLUME: i64 = 128 * 128 * 128;
+
+/// .vox coordinates are a byte each, and the build preview reads them signed.
+pub const MAX_VOX_SIZE: i32 = 127;
+
+const MAGIC: &[u8; 8] = b"VLSCHEM\0";
+const VERSION: u32 = 1;
+
+/// The chest's block id.
+const CHEST: u32 = 21;
+
+pub type ChestContents = [(u32, u32); ROWLENGTH as usize * 4];
+
+/// A box of the world copied out, to be saved and pasted back in somewhere else.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Schematic {
+    /// How big the box is, x y z.
+    pub size: IVec3,
+    /// Every block in the box that isn't air, relative to its low corner, with its flag bits.
+    pub blocks: Vec<(IVec3, u32)>,
+    /// What's in the chests among `blocks`, at the same positions.
+    pub chests: Vec<(IVec3, ChestContents)>,
+}
+
+impl Schematic {
+    /// Copies the box with corners `a` and `b`, both inside it. `block_at` and `chest_at` read the world.
+    pub fn capture(
+        a: IVec3,
+        b: IVec3,
+        block_at: impl Fn(IVec3) -> u32,
+        chest_at: impl Fn(IVec3) -> Option<ChestContents>,
+    ) -> io::Result<Schematic> {
+        let low = IVec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+        let high = IVec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+        let size = high.checked_sub(low).and_then(|s| s.checked_add(IVec3::new(1, 1, 1))).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{} to {} is too far across to copy", low, high))
+        })?;
+        let volume = size.x as i64 * size.y as i64 * size.z as i64;
+        if volume > MAX_VOLUME {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} blocks is too many to copy, {} at most", volume, MAX_VOLUME),
+            ));
+        }
+
+        let mut blocks = Vec::new();
+        let mut chests = Vec::new();
+        for y in low.y..=high.y {
+            for z in low.z..=high.z {
+                for x in low.x..=high.x {
+                    let spot = IVec3::new(x, y, z);
+                    let block = block_at(spot);
+                    if block & Blocks::block_id_bits() == 0 {
+                        continue;
+                    }
+                    blocks.push((spot - low, block));
+                    if block & Blocks::block_id_bits() == CHEST {
+                        if let Some(inv) = chest_at(spot) {
+                            chests.push((spot - low, inv));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Schematic { size, blocks, chests })
+    }
+
+    /// Turned a quarter turn `turns` times about the vertical, the way `Blocks::rotate_direction` turns
+    /// blocks, so doors and chests still face the same way relative to the build.
+    pub fn rotated(&self, turns: u32) -> Schematic {
+        let mut schematic = self.clone();
+        for _ in 0..turns % 4 {
+            let size = schematic.size;
+            //(x, z) goes to (-z, x), moved back into the box
+            let turn = |spot: IVec3| IVec3::new(size.z - 1 - spot.z, spot.y, spot.x);
+            schematic = Schematic {
+                size: IVec3::new(size.z, size.y, size.x),
+                blocks: schematic.blocks.iter().map(|(spot, block)| (turn(*spot), Blocks::rotate_direction(*block, 1))).collect(),
+                chests: schematic.chests.iter().map(|(spot, inv)| (turn(*spot), *inv)).collect(),
+            };
+        }
+        schematic
+    }
+
+    /// Flipped along x, a build and its mirror image.
+    pub fn mirrored(&self) -> Schematic {
+        let flip = |spot: &IVec3| IVec3::new(self.size.x - 1 - spot.x, spot.y, spot.z);
+        Schematic {
+            size: self.size,
+            blocks: self.blocks.iter().map(|(spot, block)| (flip(spot), Blocks::mirror_direction(*block))).collect(),
+            chests: self.chests.iter().map(|(spot, inv)| (flip(spot), *inv)).collect(),
+        }
+    }
+
+    /// Whether the box can go with its low corner at `at` without running off the end of the coordinates.
+    pub fn fits_at(&self, at: IVec3) -> bool {
+        at.checked_add(self.size).is_some()
+    }
+
+    /// Every spot in the box, with its low corner at `at`, and what goes there, air included. See `fits_at`.
+    pub fn placed(&self, at: IVec3) -> Vec<(IVec3, u32)> {
+        let mut filled = vec![0; (self.size.x * self.size.y * self.size.z).max(0) as usize];
+        let index = |spot: IVec3| ((spot.y * self.size.z + spot.z) * self.size.x + spot.x) as usize;
+        for (spot, block) in &self.blocks {
+            if let Some(slot) = filled.get_mut(index(*spot)) {
+                *slot = *block;
+            }
+        }
+        let mut placed = Vec::with_capacity(filled.len());
+        for y in 0..self.size.y {
+            for z in 0..self.size.z {
+                for x in 0..self.size.x {
+                    let spot = IVec3::new(x, y, z);
+                    placed.push((at + spot, filled[index(spot)]));
+                }
+            }
+        }
+        placed
+    }
+
+    /// Saves as a .vox if `path` ends in one, otherwise in our own format, which keeps the flag bits and chests.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let bytes = if is_vox(path) {
+            vox_format::to_vec(&self.to_vox()?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        } else {
+            self.to_bytes()
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, bytes)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Schematic> {
+        if is_vox(path) {
+            let vox = vox_format::from_file(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Schematic::from_vox(&vox)
+        } else {
+            Schematic::from_bytes(&fs::read(path)?)
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend(lz4_flex::compress_prepend_size(&bincode::serialize(self).unwrap()));
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Schematic> {
+        let invalid = |detail: String| io::Error::new(io::ErrorKind::InvalidData, detail);
+        if bytes.len() < MAGIC.len() + 4 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(invalid(String::from("not a schematic")));
+        }
+        let version = u32::from_le_bytes(bytes[MAGIC.len()..MAGIC.len() + 4].try_into().unwrap());
+        if version > VERSION {
+            return Err(invalid(format!("schematic is from a newer version of the game (version {})", version)));
+        }
+        let packed = &bytes[MAGIC.len() + 4..];
+        //Sizes say how much to allocate, don't believe one bigger than the biggest box could be
+        let unpacked_size = packed.get(..4).map(|size| u32::from_le_bytes(size.try_into().unwrap()) as i64).unwrap_or(0);
+        if unpacked_size > MAX_VOLUME * 64 {
+            return Err(invalid(format!("schematic says it's {} bytes", unpacked_size)));
+        }
+        let unpacked = lz4_flex::decompress_size_prepended(packed).map_err(|e| invalid(e.to_string()))?;
+        let schematic: Schematic = bincode::deserialize(&unpacked).map_err(|e| invalid(e.to_string()))?;
+        let inside = |spot: &IVec3| {
+            (0..schematic.size.x).contains(&spot.x) && (0..schematic.size.y).contains(&spot.y) && (0..schematic.size.z).contains(&spot.z)
+        };
+        if schematic.size.x as i64 * schematic.size.y as i64 * schematic.size.z as i64 > MAX_VOLUME
+            || !schematic.blocks.iter().all(|(spot, _)| inside(spot))
+            || !schematic.chests.iter().all(|(spot, _)| inside(spot))
+        {
+            return Err(invalid(String::from("schematic has blocks outside its box")));
+        }
+        Ok(schematic)
+    }
+
+    /// As a MagicaVoxel model the way `BUILD_VOXEL_MODELS` are made: a block's id is its color index and
+    /// z is up. The flag bits and chests can't go in one, and the box can't be bigger than `MAX_VOX_SIZE`.
+    pub fn to_vox(&self) -> io::Result<VoxData> {
+        if self.size.x > MAX_VOX_SIZE || self.size.y > MAX_VOX_SIZE || self.size.z > MAX_VOX_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is too big for a .vox, {} a side at most", self.size, MAX_VOX_SIZE),
+            ));
+        }
+        let voxels = self
+            .blocks
+            .iter()
+            .map(|(spot, block)| (spot, block & Blocks::block_id_bits()))
+            .filter(|(_, id)| *id != 0 && *id <= u8::MAX as u32)
+            .map(|(spot, id)| Voxel::new([spot.x as i8, spot.z as i8, spot.y as i8], id as u8))
+            .collect();
+        let model = Model { size: Size::new(self.size.x as u32, self.size.z as u32, self.size.y as u32), voxels };
+        Ok(VoxData { models: vec![model], ..Default::default() })
+    }
+
+    /// Every model in the file in one box, read the way `to_vox` writes them. No bigger than `MAX_VOLUME`,
+    /// like `from_bytes`.
+    pub fn from_vox(vox: &VoxData) -> io::Result<Schematic> {
+        let mut size = IVec3::new(0, 0, 0);
+        let mut blocks = Vec::new();
+        for model in &vox.models {
+            size.x = size.x.max(model.size.x as i32);
+            size.y = size.y.max(model.size.z as i32);
+            size.z = size.z.max(model.size.y as i32);
+            for v in &model.voxels {
+                if v.color_index.0 != 0 {
+                    let spot = IVec3::new(v.point.x as u8 as i32, v.point.z as u8 as i32, v.point.y as u8 as i32);
+                    blocks.push((spot, v.color_index.0 as u32));
+                }
+            }
+        }
+        for (spot, _) in &blocks {
+            size.x = size.x.max(spot.x + 1);
+            size.y = size.y.max(spot.y + 1);
+            size.z = size.z.max(spot.z + 1);
+        }
+        let volume = size.x as i64 * size.y as i64 * size.z as i64;
+        if volume > MAX_VOLUME {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is {} blocks, {} at most", size, volume, MAX_VOLUME)));
+        }
+        Ok(Schematic { size, blocks, chests: Vec::new() })
+    }
+}
+
+fn is_vox(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case(VOX_EXTENSION))
+}
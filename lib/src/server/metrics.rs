@@ -0,0 +1,184 @@
+use std::fmt::Write as _;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::modelentity::SERVER_GENERATED_CHUNKS;
+use crate::server_types::MessageType;
+
+use super::ServerState;
+
+const TYPECOUNT: usize = MessageType::ALL.len();
+
+/// Seconds. Ticks should sit in the first few, sql commits can take a while when sqlite is busy.
+const BUCKETS: [f64; 10] = [0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 5.0];
+
+#[derive(Default)]
+pub struct Histogram {
+    counts: [AtomicU64; BUCKETS.len()],
+    sum_nanos: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram::default()
+    }
+
+    pub fn observe(&self, d: Duration) {
+        let secs = d.as_secs_f64();
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            if secs <= *bound {
+                self.counts[i].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.sum_nanos.fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, self.counts[i].load(Ordering::Relaxed));
+        }
+        let count = self.count();
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+/// Per message type counters, indexed by the `MessageType` discriminant.
+#[derive(Default)]
+pub struct TypeCounters([AtomicU64; TYPECOUNT]);
+
+impl TypeCounters {
+    pub fn new() -> TypeCounters {
+        TypeCounters::default()
+    }
+
+    pub fn add(&self, t: MessageType, n: u64) {
+        self.0[t as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, t: MessageType) -> u64 {
+        self.0[t as usize].load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for t in MessageType::ALL {
+            let _ = writeln!(out, "{}{{type=\"{}\"}} {}", name, t, self.get(t));
+        }
+    }
+}
+
+/// Everything the server counts for the `/metrics` endpoint.
+#[derive(Default)]
+pub struct Metrics {
+    pub messages_in: TypeCounters,
+    pub messages_out: TypeCounters,
+    pub bytes_in: TypeCounters,
+    pub bytes_out: TypeCounters,
+    /// Dropped for being malformed or over the rate limit.
+    pub rejected: TypeCounters,
+    pub sql_commit: Histogram,
+    pub tick: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn record_in(&self, t: MessageType, bytes: usize) {
+        self.messages_in.add(t, 1);
+        self.bytes_in.add(t, bytes as u64);
+    }
+
+    /// Counts one frame of type `t`. Payload bytes that follow a header go through `record_payload_out`.
+    pub fn record_out(&self, t: MessageType, bytes: usize) {
+        self.messages_out.add(t, 1);
+        self.bytes_out.add(t, bytes as u64);
+    }
+
+    pub fn record_payload_out(&self, t: MessageType, bytes: usize) {
+        self.bytes_out.add(t, bytes as u64);
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: usize) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// The whole scrape in Prometheus text exposition format.
+pub fn render(state: &ServerState) -> String {
+    let m = &state.metrics;
+    let mut out = String::new();
+
+    gauge(&mut out, "voxelland_connected_clients", "Players currently connected.", state.client_count());
+    m.messages_in.render(&mut out, "voxelland_messages_in_total", "Messages received from clients.");
+    m.messages_out.render(&mut out, "voxelland_messages_out_total", "Messages sent to clients.");
+    m.bytes_in.render(&mut out, "voxelland_bytes_in_total", "Bytes received from clients.");
+    m.bytes_out.render(&mut out, "voxelland_bytes_out_total", "Bytes sent to clients, payloads included.");
+    m.rejected.render(&mut out, "voxelland_messages_rejected_total", "Messages dropped as malformed or over the rate limit.");
+    gauge(&mut out, "voxelland_queued_sql", "Writes waiting in the sql queue.", state.queued_sql.len());
+    m.sql_commit.render(&mut out, "voxelland_sql_commit_seconds", "Time to commit one queued sql write.");
+    m.tick.render(&mut out, "voxelland_tick_duration_seconds", "Time spent in one main loop iteration, not counting the sleep.");
+    gauge(&mut out, "voxelland_generated_chunks", "Chunks in SERVER_GENERATED_CHUNKS.", SERVER_GENERATED_CHUNKS.len());
+    gauge(&mut out, "voxelland_csys_generated_chunks", "Chunks in the chunk system's generated_chunks.", state.csys.read().generated_chunks.len());
+    gauge(&mut out, "voxelland_mobs", "Live mobs.", state.nsmes.lock().len());
+
+    out
+}
+
+fn respond(mut stream: TcpStream, state: &ServerState) {
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
+
+    let mut buffer = [0u8; 1024];
+    let n = match stream.read(&mut buffer) {
+        Ok(n) => n,
+        Err(_) => return,
+    };
+    let request = String::from_utf8_lossy(&buffer[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let response = if path == "/metrics" || path == "/" {
+        let body = render(state);
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+
+    let _ = stream.write_all(response.as_bytes());
+}
+
+/// Answers scrapes on `listener` until the server stops.
+pub fn serve(listener: TcpListener, state: ServerState) {
+    while state.shouldrun.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => respond(stream, &state),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => {
+                println!("Metrics connection failed: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,1549 @@
+pub mod metrics;
+pub mod plugin;
+pub mod ratelimit;
+pub mod schematics;
+pub mod scripting;
+pub mod sql;
+pub mod udp;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, UdpSocket};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crossbeam::queue::SegQueue;
+use dashmap::DashMap;
+use once_cell::sync::OnceCell;
+use glam::Vec3;
+use parking_lot::{Mutex, RwLock};
+use uuid::Uuid;
+
+use crate::bulkedit;
+use crate::calendar::{Calendar, Sky};
+use crate::chat;
+use crate::chunk::ChunkSystem;
+use crate::compression::{self, Compression};
+use crate::discovery::{ServerStatus, DISCOVERY_PORT, GAME_VERSION};
+use crate::game::{Game, CURRSEED, SONGINDEX, STARTINGITEMS, ROWLENGTH};
+use crate::inventory::{ChestInventory, Inventory};
+use crate::netstream::{NetStream, PayloadReader};
+use crate::server_types::{self, Message, MessageType};
+use crate::vec::{self, IVec3};
+use crate::worlddir::{self, Manifest};
+use crate::worldstorage::StorageResult;
+
+use self::metrics::Metrics;
+use self::plugin::{BlockEdit, ChestMove, MobSpawn, Plugin};
+use self::ratelimit::{QueryLimiter, RateLimiter, RateLimits};
+use self::schematics::SchematicCommands;
+use self::scripting::ScriptHost;
+use self::sql::QueuedSqlType;
+use self::udp::UdpPeer;
+
+pub type Nsme = (u32, Vec3, f32, usize, f32, bool, bool);
+
+pub const DEFAULT_SEED: u32 = 34481915;
+
+/// How often the server drops the edits of chunks nobody is near from memory, in seconds.
+pub const EDIT_UNLOAD_INTERVAL: f32 = 30.0;
+
+/// Chunks within this many chunks of a player keep their edits loaded.
+pub const EDIT_KEEP_RADIUS: i32 = 12;
+
+pub struct Client {
+    pub stream: Arc<Mutex<NetStream>>,
+    pub inv: Inventory,
+    pub errorstrikes: i8,
+    pub saveposcounter: i32,
+    pub ready_for_player_messages: bool,
+    pub sendmobcounter: i32,
+    pub limiter: RateLimiter,
+    /// What the client asked for in its greeting, used for the `Udm` and `ChestReg` payloads.
+    pub compression: Compression,
+    /// Set once the client has asked for a `UdpToken`.
+    pub udp: Option<UdpPeer>,
+    /// May use the server's own commands like `/schem` for the rest of this session, see `ServerConfig::operator_password`.
+    pub operator: bool,
+    /// Sent to the client as a `SessionToken` when it joins. Coming back under the same id while the server still
+    /// has it takes this, anybody can claim somebody else's id.
+    pub session: (u64, u64),
+}
+
+impl Client {
+    pub fn new(stream: Arc<Mutex<NetStream>>, inv: Inventory, limits: &RateLimits, compression: Compression) -> Client {
+        Client {
+            stream,
+            inv,
+            errorstrikes: 0,
+            saveposcounter: 0,
+            ready_for_player_messages: false,
+            sendmobcounter: 0,
+            limiter: RateLimiter::new(limits),
+            compression,
+            udp: None,
+            operator: false,
+            session: udp::new_token(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ServerConfig {
+    /// Address to bind, e.g. "0.0.0.0:4848". Port 0 picks an ephemeral port.
+    pub address: String,
+    /// Directory holding `db`, `chestdb` and the `world/<seed>` folders.
+    pub world_dir: PathBuf,
+    pub initial_seed: u32,
+    /// Where to serve Prometheus metrics over HTTP, e.g. "127.0.0.1:9464". None to not serve them.
+    pub metrics_address: Option<String>,
+    pub limits: RateLimits,
+    /// Open a UDP socket on the same port for player transforms.
+    pub udp: bool,
+    /// Drop a client we haven't heard anything from for this long. Clients send a `PlayerUpdate` at least every second once they're in.
+    pub client_timeout: Duration,
+    /// Shown in server browsers.
+    pub name: String,
+    pub motd: String,
+    /// Answer discovery broadcasts on `DISCOVERY_PORT`. Status queries to our own UDP port are answered regardless.
+    pub lan_discovery: bool,
+    /// Run in this order, see `Plugin`.
+    pub plugins: Vec<Arc<dyn Plugin>>,
+    /// Load the `.rhai` scripts in here as one more plugin after `plugins`, see `ScriptHost`.
+    pub scripts_dir: Option<PathBuf>,
+    /// Turn on the `/schem` commands, saving to and pasting from here. See `SchematicCommands`.
+    pub schematics_dir: Option<PathBuf>,
+    /// Saying `/op <this>` in chat lets that player use the server's own commands like `/schem` until they leave.
+    /// None means nobody can, which is the default. Never a player id, clients can claim any id they like.
+    pub operator_password: Option<String>,
+    /// None to use the world's `calendar.json`, which gets created with the defaults if it isn't there.
+    pub calendar: Option<Calendar>,
+}
+
+impl ServerConfig {
+    pub fn new(address: impl Into<String>, world_dir: impl Into<PathBuf>) -> ServerConfig {
+        ServerConfig {
+            address: address.into(),
+            world_dir: world_dir.into(),
+            initial_seed: DEFAULT_SEED,
+            metrics_address: None,
+            limits: RateLimits::default(),
+            udp: true,
+            client_timeout: Duration::from_secs(30),
+            name: String::from("VoxelLand Server"),
+            motd: String::new(),
+            lan_discovery: true,
+            plugins: Vec::new(),
+            scripts_dir: None,
+            schematics_dir: None,
+            operator_password: None,
+            calendar: None,
+        }
+    }
+}
+
+/// Everything the client threads share. Cheap to clone, every field is an Arc or Copy.
+#[derive(Clone)]
+pub struct ServerState {
+    pub clients: Arc<Mutex<HashMap<Uuid, Client>>>,
+    pub csys: Arc<RwLock<ChunkSystem>>,
+    pub knowncams: Arc<DashMap<Uuid, Vec3>>,
+    pub mobspawnqueued: Arc<AtomicBool>,
+    pub shutupmobmsgs: Arc<AtomicBool>,
+    pub nsmes: Arc<Mutex<Vec<Nsme>>>,
+    pub sky: Arc<Mutex<Sky>>,
+    pub calendar: Arc<Calendar>,
+    pub queued_sql: Arc<SegQueue<QueuedSqlType>>,
+    pub chest_reg: Arc<DashMap<vec::IVec3, ChestInventory>>,
+    pub world_dir: Arc<PathBuf>,
+    pub shouldrun: Arc<AtomicBool>,
+    pub metrics: Arc<Metrics>,
+    pub limits: Arc<RateLimits>,
+    /// Shared by the game's UDP port and the LAN discovery one, both answer status queries.
+    pub query_limiter: Arc<Mutex<QueryLimiter>>,
+    /// Bound by `Server::start` when `ServerConfig::udp` is on.
+    pub udp: Arc<OnceCell<UdpSocket>>,
+    pub udp_tokens: Arc<DashMap<(u64, u64), Uuid>>,
+    pub client_timeout: Duration,
+    pub name: Arc<String>,
+    pub motd: Arc<String>,
+    pub plugins: Arc<Vec<Arc<dyn Plugin>>>,
+    pub operator_password: Arc<Option<String>>,
+    pub next_mob_id: Arc<AtomicU32>,
+    /// Seconds since far chunks were last unloaded.
+    pub unload_timer: Arc<Mutex<f32>>,
+    pub manifest: Arc<Mutex<Manifest>>,
+}
+
+impl ServerState {
+    /// Loads (or creates) the world in `config.world_dir` without touching the network.
+    pub fn load(config: &ServerConfig) -> StorageResult<ServerState> {
+        let world_dir = config.world_dir.clone();
+
+        //Upgrades older worlds, and picks up on whatever planet the world was left on
+        let manifest = worlddir::open(&world_dir, config.initial_seed)?;
+        let seed = manifest.seed;
+        manifest.generator.apply();
+
+        let mut csys = ChunkSystem::new(0, seed, manifest.planet_type as usize, true);
+        csys.db_path = world_dir.join("db");
+
+        unsafe { CURRSEED.store(seed, Ordering::Relaxed) };
+
+        let seeddir = world_dir.join(format!("world/{}", seed)).to_string_lossy().to_string();
+
+        csys.load_world_from_file(seeddir.clone())?;
+
+        unsafe { CURRSEED.store(seed, Ordering::Relaxed) };
+
+        let chest_reg = Arc::new(DashMap::new());
+
+        Game::static_load_chests_from_path(world_dir.join("chestdb"), seed, &chest_reg)?;
+
+        csys.save_current_world_to_file(seeddir)?;
+
+        let calendar = config.calendar.clone().unwrap_or_else(|| Calendar::load_or_create(&world_dir));
+
+        let mut plugins = config.plugins.clone();
+        if let Some(dir) = &config.schematics_dir {
+            plugins.push(Arc::new(SchematicCommands::new(dir)));
+        }
+        if let Some(dir) = &config.scripts_dir {
+            plugins.push(Arc::new(ScriptHost::new(dir)));
+        }
+
+        Ok(ServerState {
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            csys: Arc::new(RwLock::new(csys)),
+            knowncams: Arc::new(DashMap::new()),
+            mobspawnqueued: Arc::new(AtomicBool::new(true)),
+            shutupmobmsgs: Arc::new(AtomicBool::new(false)),
+            nsmes: Arc::new(Mutex::new(Vec::new())),
+            sky: Arc::new(Mutex::new(Sky::new(&calendar))),
+            calendar: Arc::new(calendar),
+            queued_sql: Arc::new(SegQueue::new()),
+            chest_reg,
+            world_dir: Arc::new(world_dir),
+            shouldrun: Arc::new(AtomicBool::new(true)),
+            metrics: Arc::new(Metrics::new()),
+            limits: Arc::new(config.limits.clone()),
+            query_limiter: Arc::new(Mutex::new(QueryLimiter::new(config.limits.status_query))),
+            udp: Arc::new(OnceCell::new()),
+            udp_tokens: Arc::new(DashMap::new()),
+            client_timeout: config.client_timeout,
+            name: Arc::new(config.name.clone()),
+            motd: Arc::new(config.motd.clone()),
+            plugins: Arc::new(plugins),
+            operator_password: Arc::new(config.operator_password.clone()),
+            next_mob_id: Arc::new(AtomicU32::new(0)),
+            unload_timer: Arc::new(Mutex::new(0.0)),
+            manifest: Arc::new(Mutex::new(manifest)),
+        })
+    }
+
+    /// Advances the clock and the weather by the calendar.
+    pub fn tick(&self, delta_time: f32) {
+        let planet_type = self.csys.read().planet_type;
+        self.sky.lock().advance(&self.calendar, planet_type, delta_time, || StdRng::from_entropy().gen());
+
+        let unload = {
+            let mut timer = self.unload_timer.lock();
+            *timer += delta_time;
+            if *timer >= EDIT_UNLOAD_INTERVAL {
+                *timer = 0.0;
+                true
+            } else {
+                false
+            }
+        };
+        if unload {
+            self.unload_far_chunks();
+        }
+
+        plugin::notify(self, |p, api| p.on_tick(api, delta_time));
+    }
+
+    /// Saves and forgets the edits of chunks that aren't near any player. They load back in when something touches them.
+    pub fn unload_far_chunks(&self) {
+        let players: Vec<IVec3> = self
+            .knowncams
+            .iter()
+            .map(|p| IVec3::new(p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32))
+            .collect();
+        let players: Vec<vec::IVec2> = players.iter().map(ChunkSystem::spot_to_chunk_pos).collect();
+
+        self.csys.read().unload_chunk_edits_where(|c| {
+            !players.iter().any(|p| (p.x - c.x).abs() <= EDIT_KEEP_RADIUS && (p.y - c.y).abs() <= EDIT_KEEP_RADIUS)
+        });
+    }
+
+    /// Sends `message` to every player that's in the world.
+    pub fn send_to_all(&self, message: &Message) {
+        self.send_to_all_with(message, &[]);
+    }
+
+    /// `message` followed by its payload, for the types that have one like `BulkBlockSet`.
+    pub fn send_to_all_with(&self, message: &Message, payload: &[u8]) {
+        let mut serial = bincode::serialize(message).unwrap();
+        serial.extend_from_slice(payload);
+        for client in self.clients.lock().values() {
+            if client.ready_for_player_messages && client.stream.lock().write_all(&serial).is_ok() {
+                self.metrics.record_out(message.message_type, serial.len());
+            }
+        }
+    }
+
+    /// Starts tracking a new mob once the plugins are fine with it. Returns its id, None if one of them vetoed it.
+    pub fn spawn_mob(&self, spawn: MobSpawn) -> Option<u32> {
+        let mut spawn = spawn;
+        if !plugin::allowed(self, |p, api| p.on_mob_spawn(api, &mut spawn)) {
+            return None;
+        }
+        Some(self.add_mob(&spawn))
+    }
+
+    fn add_mob(&self, spawn: &MobSpawn) -> u32 {
+        let id = self.next_mob_id.fetch_add(1, Ordering::Relaxed);
+        self.nsmes.lock().push((id, spawn.position, 0.0, spawn.kind, spawn.scale, false, spawn.hostile));
+        id
+    }
+
+    /// Writes out everything still waiting in the sql queue.
+    pub fn flush_sql(&self) {
+        while let Some(sql) = self.queued_sql.pop() {
+            self.commit_sql(&sql);
+        }
+    }
+
+    /// Runs one queued write against the world's databases, timing it for the metrics.
+    pub fn commit_sql(&self, sql: &QueuedSqlType) {
+        let start = Instant::now();
+        if let Err(e) = sql::handlesql(sql, &self.world_dir) {
+            println!("Couldn't save to the world: {}", e);
+        }
+        self.metrics.sql_commit.observe(start.elapsed());
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().len()
+    }
+
+    /// What we answer status queries with. `port` is the TCP port we're listening on.
+    pub fn status(&self, port: u16) -> ServerStatus {
+        ServerStatus {
+            name: (*self.name).clone(),
+            motd: (*self.motd).clone(),
+            players: self.client_count() as u32,
+            version: String::from(GAME_VERSION),
+            port,
+        }
+    }
+}
+
+
+/// What the client thread should do with a message after `handle_message` has seen it.
+#[derive(Debug, PartialEq)]
+pub enum Handled {
+    /// Pass the (possibly amended) message on to the other players.
+    Relay,
+    /// Relay it so the others drop this player, then hang up.
+    Disconnect,
+    /// Only meant for the sender, which has already been answered.
+    Private,
+    /// Malformed or not something a client may send. Nobody else sees it and the sender gets a strike.
+    Rejected(&'static str),
+    /// A plugin stopped it. Nobody else sees it, but the sender did nothing wrong.
+    Vetoed,
+}
+
+/// How many rejected or rate limited messages a client gets away with before being dropped.
+pub const MAX_ERROR_STRIKES: i8 = 30;
+
+fn send_to(state: &ServerState, stream: &Arc<Mutex<NetStream>>, message: &Message) {
+    let serial = bincode::serialize(message).unwrap();
+    let mut mystream = stream.lock();
+    if mystream.write_all(&serial).is_ok() {
+        state.metrics.record_out(message.message_type, serial.len());
+    }
+}
+
+/// Header and text go out under one lock so nothing else gets written in between.
+pub fn send_chat(state: &ServerState, stream: &Arc<Mutex<NetStream>>, from: Option<Uuid>, text: &str) {
+    let bytes = chat::encode(from, text);
+    if stream.lock().write_all(&bytes).is_ok() {
+        state.metrics.record_out(MessageType::Chat, bytes.len());
+    }
+}
+
+/// To everyone connected, even players still loading in.
+pub fn broadcast_chat(state: &ServerState, from: Option<Uuid>, text: &str) {
+    let bytes = chat::encode(from, text);
+    for client in state.clients.lock().values() {
+        if client.stream.lock().write_all(&bytes).is_ok() {
+            state.metrics.record_out(MessageType::Chat, bytes.len());
+        }
+    }
+}
+
+/// Applies one message from `client_id` to the world. Never panics on whatever the client sent.
+pub fn handle_message(client_id: Uuid, message: &mut Message, state: &ServerState) -> Handled {
+    let clients = &state.clients;
+    let csys = &state.csys;
+    let knowncams = &state.knowncams;
+    let mobspawnqueued = &state.mobspawnqueued;
+    let shutupmobmsgs = &state.shutupmobmsgs;
+    let nsmes = &state.nsmes;
+    let sky = &state.sky;
+    let queued_sql = &state.queued_sql;
+    let chest_reg = &state.chest_reg;
+    let world_dir = &state.world_dir;
+
+    let (stream, compression) = match clients.lock().get(&client_id) {
+        Some(c) => (c.stream.clone(), c.compression),
+        None => return Handled::Rejected("message from a client that isn't connected"),
+    };
+
+    match message.message_type {
+        MessageType::ShutUpMobMsgs => {
+            shutupmobmsgs.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        MessageType::RequestUdm => {
+            println!("Recvd req world");
+
+            thread::sleep(Duration::from_millis(50));
+
+            let buffer = {
+                let mut buffer = Vec::new();
+                match File::open(world_dir.join("db")) {
+                    Ok(mut file) => {
+                        println!("Opened the db file");
+                        if file.read_to_end(&mut buffer).is_err() {
+                            buffer.clear();
+                        }
+                    }
+                    Err(_) => {}
+                }
+                println!("Read the file to end");
+                buffer
+            };
+
+            let (buffer, codec) = compression::encode_payload(&buffer, compression);
+
+            let mut udmmsg = Message::new(MessageType::Udm, Vec3::ZERO, 0.0, buffer.len() as u32);
+            udmmsg.info2 = codec as u32;
+
+            {
+                let mut mystream = stream.lock();
+                let _ = mystream.set_nonblocking(false);
+                let header = bincode::serialize(&udmmsg).unwrap();
+                if mystream.write_all(&header).is_ok() {
+                    state.metrics.record_out(MessageType::Udm, header.len());
+                    println!("Wrote the header");
+                    thread::sleep(Duration::from_millis(10));
+                    if mystream.write_all(&buffer).is_ok() {
+                        state.metrics.record_payload_out(MessageType::Udm, buffer.len());
+                        println!("Wrote the file buffer");
+                    }
+                }
+                let _ = mystream.set_nonblocking(true);
+            }
+        }
+        MessageType::ReqChestReg => {
+            println!("Recvd req chest reg");
+
+            let buffer = {
+                let mut buffer = Vec::new();
+                match File::open(world_dir.join("chestdb")) {
+                    Ok(mut file) => {
+                        println!("Opened the db file");
+                        if file.read_to_end(&mut buffer).is_err() {
+                            buffer.clear();
+                        }
+                    }
+                    Err(_) => {}
+                };
+                println!("Read the file to end");
+                buffer
+            };
+
+            let (buffer, codec) = compression::encode_payload(&buffer, compression);
+
+            let mut chestmsg = Message::new(MessageType::ChestReg, Vec3::ZERO, 0.0, buffer.len() as u32);
+            chestmsg.info2 = codec as u32;
+
+            send_to(state, &stream, &chestmsg);
+            println!("Wrote the chest header");
+
+            thread::sleep(Duration::from_millis(20));
+
+            if buffer.len() > 0 {
+                let mut mystream = stream.lock();
+                if mystream.write_all(&buffer).is_ok() {
+                    state.metrics.record_payload_out(MessageType::ChestReg, buffer.len());
+                    println!("Wrote the chest file buffer");
+                }
+            }
+        }
+        MessageType::RequestSeed => {
+            println!("Recvd req seed");
+
+            let currseed = unsafe { CURRSEED.load(Ordering::Relaxed) };
+
+            let mut seedmsg = Message::new(MessageType::Seed, Vec3::ZERO, 0.0, currseed);
+            seedmsg.info2 = state.manifest.lock().generator.to_bits();
+
+            thread::sleep(Duration::from_millis(100));
+
+            send_to(state, &stream, &seedmsg);
+        }
+        MessageType::ChestInvUpdate => {
+            let currchest = message.otherpos;
+            let destslot = message.info as usize;
+
+            match message.info2 {
+                0 => {
+                    if destslot >= ROWLENGTH as usize * 4 {
+                        return Handled::Rejected("chest slot out of range");
+                    }
+
+                    let current = chest_reg.get(&currchest).map(|c| c.inv[destslot]).unwrap_or((0, 0));
+                    if !allow_chest_move(state, &stream, client_id, message, current) {
+                        return Handled::Vetoed;
+                    }
+
+                    let mut chestinv = chest_reg.entry(currchest).or_insert(ChestInventory {
+                        dirty: false,
+                        inv: [(0, 0); ROWLENGTH as usize * 4],
+                    });
+
+                    let slot = &mut chestinv.inv[destslot];
+                    let wasthere = slot.clone();
+
+                    slot.0 = message.rot as u32;
+                    slot.1 = message.infof as u32;
+
+                    if message.bo {
+                        //We decide what to displace to the mouse-zone
+                        message.x = wasthere.0 as f32;
+                        message.y = wasthere.1 as f32;
+                    } else {
+                        //They decide (i.e. theyre adding to a stack and clear their mouse)
+                    }
+
+
+                    let currseed = unsafe { CURRSEED.load(Ordering::Relaxed) };
+
+                    queued_sql.push(QueuedSqlType::ChestInventoryUpdate(currchest, chestinv.inv.clone(), currseed));
+                }
+                1 => {
+                    if destslot >= ROWLENGTH as usize {
+                        return Handled::Rejected("inventory slot out of range");
+                    }
+
+                    let current = match clients.lock().get(&client_id) {
+                        Some(cli) => cli.inv.inv[destslot],
+                        None => (0, 0),
+                    };
+                    if !allow_chest_move(state, &stream, client_id, message, current) {
+                        return Handled::Vetoed;
+                    }
+
+                    let mut clientlock = clients.lock();
+                    if let Some(cli) = clientlock.get_mut(&client_id) {
+                        let slot = &mut cli.inv.inv[destslot];
+                        let wasthere = slot.clone();
+
+                        slot.0 = message.rot as u32;
+                        slot.1 = message.infof as u32;
+                        if message.bo {
+                            message.x = wasthere.0 as f32;
+                            message.y = wasthere.1 as f32;
+                        }
+                        queued_sql.push(QueuedSqlType::InventoryInventoryUpdate(client_id, cli.inv.inv));
+                    }
+                }
+                _ => {}
+            }
+        }
+        MessageType::PlayerUpdate => {
+
+            let mut sendmobs = false;
+
+            //bo: the client is getting our datagrams, and sends its transforms over UDP
+            let mut on_udp = false;
+
+            {
+                let mut clients = clients.lock();
+
+                if let Some(client) = clients.get_mut(&client_id) {
+                    client.ready_for_player_messages = true;
+
+                    if let Some(peer) = client.udp.as_mut() {
+                        peer.confirmed = message.bo;
+                        on_udp = message.bo && peer.addr.is_some();
+                    }
+
+                    client.sendmobcounter += 1;
+
+                    if client.sendmobcounter >= 4 {
+                        sendmobs = true;
+                        client.sendmobcounter = 0;
+                    }
+
+                    if client.saveposcounter > 10 {
+                        client.saveposcounter = 0;
+                        queued_sql.push(QueuedSqlType::PlayerPositionUpdate(client_id,
+                            Vec3::new(message.x, message.y, message.z),
+                            message.infof,
+                            message.info2 as f32
+                        ));
+                    } else {
+                        client.saveposcounter += 1;
+                    }
+                }
+            }
+
+            knowncams.insert(client_id, Vec3::new(message.x, message.y, message.z));
+
+            let now = *sky.lock();
+            let mut timeupdate = Message::new(MessageType::TimeUpdate, Vec3::ZERO, now.weather, unsafe { SONGINDEX } as u32);
+            //println!("Songindex: {}", unsafe { SONGINDEX });
+            timeupdate.infof = now.time;
+            timeupdate.x = state.calendar.day_length;
+            timeupdate.y = if state.calendar.lock_time { 1.0 } else { 0.0 };
+
+            send_to(state, &stream, &timeupdate);
+
+
+            //thread::sleep(Duration::from_millis(10));
+
+            //Every fourth update, over UDP like the player transforms when this client has it
+            if sendmobs && !shutupmobmsgs.load(Ordering::Relaxed) {
+                let mobmsgs: Vec<Message> = nsmes
+                    .lock()
+                    .iter()
+                    .map(|nsme| {
+                        let mut mobmsg = Message::new(MessageType::MobUpdate, nsme.1, nsme.2, nsme.0);
+                        mobmsg.info2 = nsme.3 as u32;
+                        mobmsg.infof = nsme.4;
+                        mobmsg.bo = nsme.5;
+                        mobmsg.hostile = nsme.6;
+                        mobmsg
+                    })
+                    .collect();
+
+                for chunk in mobmsgs.chunks(server_types::MOB_BATCH_SIZE) {
+                    let mut mobmsg = Message::new(MessageType::MobUpdateBatch, Vec3::ZERO, 0.0, 0);
+                    mobmsg.inoculate_with_mobupdates(chunk.len(), chunk);
+                    let serial = bincode::serialize(&mobmsg).unwrap();
+                    if let Some(client) = clients.lock().get_mut(&client_id) {
+                        udp::send_transform(state, client, &mobmsg, &serial);
+                    }
+                }
+            }
+
+            //The others already get this player's transforms over UDP, this one is just for the clock and saving
+            if on_udp {
+                return Handled::Private;
+            }
+        }
+        MessageType::UdpToken => {
+            let port = match state.udp.get().and_then(|s| s.local_addr().ok()) {
+                Some(addr) => addr.port(),
+                None => 0,
+            };
+
+            let mut tokenmsg = Message::new(MessageType::UdpToken, Vec3::ZERO, 0.0, port as u32);
+
+            if port != 0 {
+                let token = udp::new_token();
+                if let Some(client) = clients.lock().get_mut(&client_id) {
+                    if let Some(old) = client.udp.replace(UdpPeer::new(token)) {
+                        state.udp_tokens.remove(&old.token);
+                    }
+                }
+                state.udp_tokens.insert(token, client_id);
+                tokenmsg.goose = token;
+            }
+
+            send_to(state, &stream, &tokenmsg);
+            return Handled::Private;
+        }
+        MessageType::BlockSet => {
+            println!("Recvd block set");
+            if !(message.x.is_finite() && message.y.is_finite() && message.z.is_finite()) {
+                return Handled::Rejected("block set at a non-finite position");
+            }
+            let mut edit = BlockEdit { spot: IVec3::new(message.x as i32, message.y as i32, message.z as i32), block: message.info };
+            if !plugin::allowed(state, |p, api| p.before_block_set(api, client_id, &mut edit)) {
+                return Handled::Vetoed;
+            }
+            let spot = edit.spot;
+            let block = edit.block;
+            message.info = block;
+
+            {
+                let csys = csys.write();
+                csys.set_block(spot, block, true);
+            }
+            let currseed = unsafe { CURRSEED.load(Ordering::Relaxed) };
+            queued_sql.push(QueuedSqlType::UserDataMap(currseed, spot, block));
+
+            plugin::notify(state, |p, api| p.after_block_set(api, client_id, &edit));
+        }
+        MessageType::MultiBlockSet => {
+            println!("Recvd multi block set");
+            if !(message.x.is_finite() && message.y.is_finite() && message.z.is_finite()) {
+                return Handled::Rejected("block set at a non-finite position");
+            }
+
+            let mut edits = [
+                BlockEdit { spot: IVec3::new(message.x as i32, message.y as i32, message.z as i32), block: message.info },
+                BlockEdit { spot: message.otherpos, block: message.info2 },
+            ];
+            for edit in edits.iter_mut() {
+                if !plugin::allowed(state, |p, api| p.before_block_set(api, client_id, edit)) {
+                    return Handled::Vetoed;
+                }
+            }
+            let [BlockEdit { spot, block }, BlockEdit { spot: spot2, block: block2 }] = edits;
+            message.info = block;
+            message.info2 = block2;
+
+            {
+                let csys = csys.write();
+                csys.set_block(spot, block, true);
+                csys.set_block(spot2, block2, true);
+            }
+
+            let currseed = unsafe { CURRSEED.load(Ordering::Relaxed) };
+            queued_sql.push(QueuedSqlType::UserDataMap(currseed, spot, block));
+            queued_sql.push(QueuedSqlType::UserDataMap(currseed, spot2, block2));
+
+            for edit in edits.iter() {
+                plugin::notify(state, |p, api| p.after_block_set(api, client_id, edit));
+            }
+        }
+        MessageType::RequestTakeoff => {
+            println!("Recvd req takeoff");
+            let mut rng = StdRng::from_entropy();
+            let newseed: u32 = rng.gen();
+            let mut csys = csys.write();
+
+            let pt = csys.planet_type.clone();
+            csys.reset(0, newseed, (pt + 1) as usize % 2);
+            if let Err(e) = csys.save_current_world_to_file(world_dir.join(format!("world/{}", newseed)).to_string_lossy().to_string()) {
+                println!("Couldn't save the new planet: {}", e);
+            }
+
+            let mut manifest = state.manifest.lock();
+            manifest.seed = newseed;
+            manifest.planet_type = csys.planet_type;
+            if let Err(e) = manifest.write(world_dir) {
+                println!("Couldn't update the world manifest: {}", e);
+            }
+            drop(manifest);
+            mobspawnqueued.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        MessageType::TellYouMyID => {
+            // println!("Telling someone their id is: {client_id}");
+
+            // let mut idmsg = Message::new(MessageType::YourId, Vec3::ZERO, 0.0, bincode::serialized_size(&client_id.as_u64_pair()).unwrap() as u32);
+            // idmsg.goose = client_id.as_u64_pair();
+
+            // {
+            //     let mut mystream = stream.lock();
+            //     mystream.write_all(&bincode::serialize(&idmsg).unwrap()).unwrap();
+            // }
+        }
+        MessageType::Disconnect => {
+            return Handled::Disconnect;
+        }
+        //A reconnect's proof that we'd already dropped the old session for, nothing left to take over
+        MessageType::SessionToken => {
+            return Handled::Private;
+        }
+        MessageType::ChestOpen => {
+            let chest = message.otherpos;
+            let mut answer = Message::new(MessageType::ChestOpen, Vec3::ZERO, 0.0, 0);
+            answer.otherpos = chest;
+            answer.bo = plugin::allowed(state, |p, api| p.on_chest_open(api, client_id, chest));
+            send_to(state, &stream, &answer);
+            return Handled::Private;
+        }
+        MessageType::Chat => {
+            return Handled::Rejected("chat without its text, see handle_chat");
+        }
+        MessageType::BulkBlockSet => {
+            return Handled::Rejected("bulk block set without its blocks, see handle_bulk_block_set");
+        }
+        MessageType::RequestPt => {
+            let currpt = {
+                let csys = csys.read();
+                csys.planet_type
+            };
+
+            thread::sleep(Duration::from_millis(100));
+
+            let ptmsg = Message::new(MessageType::Pt, Vec3::ZERO, 0.0, currpt as u32);
+            send_to(state, &stream, &ptmsg);
+
+            thread::sleep(Duration::from_millis(100));
+
+            {
+                println!("Telling someone their id is: {client_id}");
+                let mut idmsg = Message::new(MessageType::YourId, Vec3::ZERO, 0.0, bincode::serialized_size(&client_id.as_u64_pair()).unwrap() as u32);
+                idmsg.goose = client_id.as_u64_pair();
+
+                send_to(state, &stream, &idmsg);
+            }
+
+            thread::sleep(Duration::from_millis(100));
+
+            shutupmobmsgs.store(false, std::sync::atomic::Ordering::Relaxed);
+        }
+        MessageType::None => {
+            return Handled::Rejected("undecodable message");
+        }
+        //Only the server sends these, relaying one from a client would have everyone else trust its header
+        MessageType::Udm
+        | MessageType::Seed
+        | MessageType::Pt
+        | MessageType::YourId
+        | MessageType::MobUpdate
+        | MessageType::NewMob
+        | MessageType::WhatsThatMob
+        | MessageType::MobUpdateBatch
+        | MessageType::TimeUpdate
+        | MessageType::ChestReg
+        | MessageType::BlockSetRejected => {
+            return Handled::Rejected("message type only the server sends");
+        }
+    }
+
+    Handled::Relay
+}
+
+/// Runs the chest move hooks on a `ChestInvUpdate` that would put `current` out of its slot. A veto puts the slot
+/// back on the sender's end and gives them back what they tried to put there.
+fn allow_chest_move(state: &ServerState, stream: &Arc<Mutex<NetStream>>, client_id: Uuid, message: &mut Message, current: (u32, u32)) -> bool {
+    let mut change = ChestMove {
+        chest: message.otherpos,
+        slot: message.info as usize,
+        player_inv: message.info2 == 1,
+        item: (message.rot as u32, message.infof as u32),
+    };
+
+    if !plugin::allowed(state, |p, api| p.on_chest_move(api, client_id, &mut change)) {
+        let mut undo = message.clone();
+        undo.rot = current.0 as f32;
+        undo.infof = current.1 as f32;
+        undo.x = message.rot;
+        undo.y = message.infof;
+        undo.z = 1.0;
+        undo.goose = client_id.as_u64_pair();
+        send_to(state, stream, &undo);
+        return false;
+    }
+
+    message.rot = change.item.0 as f32;
+    message.infof = change.item.1 as f32;
+    true
+}
+
+/// A chat line from `client_id`. `handle_client` reads the text that follows the header and hands it over here.
+pub fn handle_chat(client_id: Uuid, text: String, state: &ServerState) -> Handled {
+    //Never relayed or shown to plugins, it has the password in it
+    if text == "/op" || text.starts_with("/op ") {
+        return handle_op(client_id, text["/op".len()..].trim(), state);
+    }
+    let mut text = text;
+    if !plugin::allowed(state, |p, api| p.on_chat(api, client_id, &mut text)) {
+        return Handled::Vetoed;
+    }
+    if !text.trim().is_empty() {
+        broadcast_chat(state, Some(client_id), &text);
+    }
+    Handled::Private
+}
+
+/// `/op <password>`, making `client_id` an operator if it's right. A wrong one counts as a strike so it can't
+/// be guessed at for long.
+fn handle_op(client_id: Uuid, password: &str, state: &ServerState) -> Handled {
+    let mut clients = state.clients.lock();
+    let Some(client) = clients.get_mut(&client_id) else {
+        return Handled::Private;
+    };
+    match state.operator_password.as_deref() {
+        Some(expected) if !password.is_empty() && password == expected => {
+            client.operator = true;
+            println!("{} is an operator now", client_id);
+            send_chat(state, &client.stream, None, "You're an operator now");
+            Handled::Private
+        }
+        _ => {
+            send_chat(state, &client.stream, None, "That's not the operator password");
+            Handled::Rejected("wrong operator password")
+        }
+    }
+}
+
+/// A `BulkBlockSet` from `client_id` with the packed blocks `handle_client` read after its header. Goes into
+/// the world all at once or not at all, and is saved as one write. On the way out `payload` holds the blocks
+/// as the plugins left them, for relaying.
+pub fn handle_bulk_block_set(client_id: Uuid, message: &mut Message, payload: &mut Vec<u8>, state: &ServerState) -> Handled {
+    let anchor = message.otherpos;
+    let Some(blocks) = bulkedit::unpack(anchor, payload) else {
+        return Handled::Rejected("bulk block set that won't unpack");
+    };
+
+    let mut edits: Vec<BlockEdit> = blocks.into_iter().map(|(spot, block)| BlockEdit { spot, block }).collect();
+    for edit in edits.iter_mut() {
+        if !plugin::allowed(state, |p, api| p.before_block_set(api, client_id, edit)) {
+            return Handled::Vetoed;
+        }
+    }
+    let blocks: Vec<(IVec3, u32)> = edits.iter().map(|e| (e.spot, e.block)).collect();
+    //A plugin could have moved one out of reach of the anchor
+    let Some(repacked) = bulkedit::pack(anchor, &blocks) else {
+        return Handled::Vetoed;
+    };
+
+    {
+        let csys = state.csys.write();
+        for (spot, block) in &blocks {
+            csys.set_block(*spot, *block, true);
+        }
+    }
+    let currseed = unsafe { CURRSEED.load(Ordering::Relaxed) };
+    state.queued_sql.push(QueuedSqlType::UserDataMapBulk(currseed, blocks));
+
+    for edit in edits.iter() {
+        plugin::notify(state, |p, api| p.after_block_set(api, client_id, edit));
+    }
+
+    message.info = repacked.len() as u32;
+    *payload = repacked;
+    Handled::Relay
+}
+
+pub fn handle_client(
+    client_id: Uuid,
+    state: &ServerState,
+) {
+    let clients = &state.clients;
+    let knowncams = &state.knowncams;
+
+    let mut buffer = vec![0; Message::get_serialized_size()];
+
+    println!("Inside thread");
+
+    //If the same player connects again their new connection replaces this one, and this thread bows out quietly
+    let mine = match clients.lock().get(&client_id) {
+        Some(c) => c.stream.clone(),
+        None => return,
+    };
+    let superseded = || !clients.lock().get(&client_id).is_some_and(|c| Arc::ptr_eq(&c.stream, &mine));
+
+    let mut last_heard = Instant::now();
+    //A Chat or BulkBlockSet header whose bytes are still coming in, read a bit each time around
+    let mut pending: Option<(Message, PayloadReader)> = None;
+
+    loop {
+        let mut should_break = !state.shouldrun.load(Ordering::Relaxed);
+
+        if superseded() {
+            println!("{} reconnected, dropping their old connection", client_id);
+            break;
+        }
+
+        let mut chattext = None;
+        let mut bulk = None;
+
+        let received = {
+            let mut mystream = mine.lock();
+
+            let received = if pending.is_some() {
+                None
+            } else {
+                match mystream.read(&mut buffer) {
+                    Ok(numbytes) => {
+                        if numbytes > 0 {
+                            last_heard = Instant::now();
+                            let message = match Message::decode(&buffer[..numbytes]) {
+                                Some(m) => m,
+                                None => {
+                                    println!("Erroneous message received!");
+                                    Message::new(MessageType::None, Vec3::ZERO, 0.0, 0)
+                                }
+                            };
+                            state.metrics.record_in(message.message_type, numbytes);
+                            let reader = match message.message_type {
+                                MessageType::Chat => Some(chat::text_reader(message.info)),
+                                MessageType::BulkBlockSet => Some(bulkedit::payload_reader(message.info)),
+                                _ => None,
+                            };
+                            match reader {
+                                Some(Some(reader)) => {
+                                    pending = Some((message, reader));
+                                    None
+                                }
+                                Some(None) => {
+                                    println!("{} sent a {} longer than any could be", client_id, message.message_type);
+                                    Some(Message::new(MessageType::Disconnect, Vec3::ZERO, 0.0, 0))
+                                }
+                                None => Some(message),
+                            }
+                        } else {
+                            //Let the others know this player is gone so they drop its model
+                            Some(Message::new(MessageType::Disconnect, Vec3::ZERO, 0.0, 0))
+                        }
+                    }
+                    Err(e) => {
+                        if e.kind() == std::io::ErrorKind::WouldBlock {
+                            if last_heard.elapsed() > state.client_timeout {
+                                println!("{} timed out", client_id);
+                                Some(Message::new(MessageType::Disconnect, Vec3::ZERO, 0.0, 0))
+                            } else {
+                                None
+                            }
+                        } else {
+                            Some(Message::new(MessageType::Disconnect, Vec3::ZERO, 0.0, 0))
+                        }
+                    }
+                }
+            };
+
+            match pending.take() {
+                Some((header, mut reader)) => match reader.poll(&mut mystream) {
+                    Ok(Some(payload)) => {
+                        last_heard = Instant::now();
+                        if header.message_type == MessageType::Chat {
+                            chattext = Some(String::from_utf8_lossy(&payload).into_owned());
+                        } else {
+                            bulk = Some(payload);
+                        }
+                        Some(header)
+                    }
+                    Ok(None) => {
+                        pending = Some((header, reader));
+                        None
+                    }
+                    Err(e) => {
+                        println!("{} didn't send the rest of a {}: {}", client_id, header.message_type, e);
+                        Some(Message::new(MessageType::Disconnect, Vec3::ZERO, 0.0, 0))
+                    }
+                },
+                None => received,
+            }
+        };
+
+        //A read error from the reconnect shutting our old stream down, not a real disconnect
+        if received.is_some() && superseded() {
+            println!("{} reconnected, dropping their old connection", client_id);
+            break;
+        }
+
+        if let Some(mut message) = received {
+            message.goose = client_id.as_u64_pair();
+
+            let allowed = match clients.lock().get_mut(&client_id) {
+                Some(client) => client.limiter.allow(message.message_type),
+                None => true,
+            };
+
+            let handled = if !allowed {
+                Handled::Rejected("rate limited")
+            } else if let Some(text) = chattext.take() {
+                handle_chat(client_id, text, state)
+            } else if let Some(payload) = bulk.as_mut() {
+                handle_bulk_block_set(client_id, &mut message, payload, state)
+            } else {
+                handle_message(client_id, &mut message, state)
+            };
+
+            //Tell them so they can roll back the edit they already drew
+            let blockset = matches!(message.message_type, MessageType::BlockSet | MessageType::MultiBlockSet | MessageType::BulkBlockSet);
+            let reject = Message::new(MessageType::BlockSetRejected, Vec3::ZERO, 0.0, message.seq);
+
+            let relay = match handled {
+                Handled::Relay => true,
+                Handled::Private => false,
+                Handled::Disconnect => {
+                    should_break = true;
+                    true
+                }
+                Handled::Rejected(why) => {
+                    println!("Rejected {} from {}: {}", message.message_type, client_id, why);
+                    state.metrics.rejected.add(message.message_type, 1);
+                    let mut clients = clients.lock();
+                    if let Some(client) = clients.get_mut(&client_id) {
+                        client.errorstrikes = client.errorstrikes.saturating_add(1);
+                        if client.errorstrikes >= MAX_ERROR_STRIKES {
+                            println!("{} struck out", client_id);
+                            should_break = true;
+                        }
+                        if blockset && message.seq != 0 && !should_break {
+                            send_to(state, &client.stream, &reject);
+                        }
+                    }
+                    false
+                }
+                Handled::Vetoed => {
+                    if blockset && message.seq != 0 {
+                        send_to(state, &mine, &reject);
+                    }
+                    false
+                }
+            };
+
+            if should_break && message.message_type != MessageType::Disconnect {
+                message = Message::new(MessageType::Disconnect, Vec3::ZERO, 0.0, 0);
+                message.goose = client_id.as_u64_pair();
+            }
+
+            if relay || should_break {
+                let mut clients = clients.lock();
+                let mut newmessageserial = bincode::serialize(&message).unwrap();
+                if let Some(payload) = bulk.as_ref().filter(|_| message.message_type == MessageType::BulkBlockSet) {
+                    newmessageserial.extend_from_slice(payload);
+                }
+                for (id, client) in clients.iter_mut() {
+                    if message.message_type == MessageType::PlayerUpdate {
+                        if *id != client_id && client.ready_for_player_messages {
+                            udp::send_transform(state, client, &message, &newmessageserial);
+                        }
+                    } else if client.ready_for_player_messages {
+                        let mut stream = client.stream.lock();
+                        if stream.write_all(&newmessageserial).is_ok() {
+                            state.metrics.record_out(message.message_type, newmessageserial.len());
+                        }
+                    }
+                }
+            }
+        }
+
+
+
+
+        if should_break {
+            println!("Removed {}", client_id);
+            knowncams.remove(&client_id);
+            let mut left = false;
+            {
+                let mut locked_clients = clients.lock();
+                if locked_clients.get(&client_id).is_some_and(|c| Arc::ptr_eq(&c.stream, &mine)) {
+                    if let Some(client) = locked_clients.remove(&client_id) {
+                        if let Some(peer) = &client.udp {
+                            state.udp_tokens.remove(&peer.token);
+                        }
+                        let _ = client.stream.lock().shutdown(Shutdown::Both);
+                        left = true;
+                    }
+                }
+            }
+            if left {
+                plugin::notify(state, |p, api| p.on_leave(api, client_id));
+            }
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Reads the `TellYouMyID` greeting off a freshly accepted stream, registers the client and spawns its thread.
+/// `host` is for the in-process connection of whoever is running the server, who's an operator from the start.
+fn accept_client(stream: NetStream, state: &ServerState, host: bool) -> Option<JoinHandle<()>> {
+    let stream = Arc::new(Mutex::new(stream));
+    let _ = stream.lock().set_nonblocking(true);
+
+    let Some(greeting) = read_greeting(&stream, MessageType::TellYouMyID) else {
+        println!("Sorry, this guy didn't send an ID. He's out!");
+        return None;
+    };
+    let client_id = Uuid::from_u64_pair(greeting.goose.0, greeting.goose.1);
+    println!("Received your client id, its {}", client_id);
+    let compression = Compression::from_u32(greeting.info).unwrap_or(Compression::None);
+
+    //Someone still here under this id. Only the client that session was given to gets to take it over,
+    //if it's really them coming back their SessionToken is right behind the greeting.
+    let online = state.clients.lock().get(&client_id).map(|c| c.session);
+    if let Some(session) = online {
+        let proof = read_greeting(&stream, MessageType::SessionToken);
+        if proof.map(|m| m.goose) != Some(session) {
+            println!("{} is already online and this isn't their session, turning it away", client_id);
+            let _ = stream.lock().shutdown(Shutdown::Both);
+            return None;
+        }
+    }
+
+    let previously_loaded_inv = match sql::load_inventory(&state.world_dir, &client_id) {
+        Ok(inv) => inv.unwrap_or(STARTINGITEMS.clone()),
+        Err(e) => {
+            println!("Couldn't load {}'s inventory, starting them fresh: {}", client_id, e);
+            STARTINGITEMS.clone()
+        }
+    };
+
+    println!("About to lock clients");
+    let mut gotlock = false;
+
+    while !gotlock {
+        match state.clients.try_lock() {
+            Some(mut e) => {
+
+                let mut client = Client::new(
+                    Arc::clone(&stream),
+                    Inventory{
+                        dirty: false, inv: previously_loaded_inv
+                    },
+                    &state.limits,
+                    compression,
+                );
+                client.operator = host;
+
+                //Same id as someone still here means they lost their connection and came back before we noticed,
+                //and they showed us their session above. Their inventory in memory may be newer than what's been written to sql.
+                match e.remove(&client_id) {
+                    Some(old) if old.session == online.unwrap_or(client.session) => {
+                        println!("{} is reconnecting, taking over their session", client_id);
+                        if let Some(peer) = &old.udp {
+                            state.udp_tokens.remove(&peer.token);
+                        }
+                        let _ = old.stream.lock().shutdown(Shutdown::Both);
+                        client.inv = old.inv;
+                        client.session = old.session;
+                        client.operator |= old.operator;
+                    }
+                    //Joined while we were reading the token, first one in keeps it
+                    Some(old) => {
+                        e.insert(client_id, old);
+                        drop(e);
+                        let _ = stream.lock().shutdown(Shutdown::Both);
+                        return None;
+                    }
+                    None => {}
+                }
+
+                e.insert(client_id, client);
+                gotlock = true;
+            }
+            None => {
+            }
+        };
+    }
+
+    println!("Locked clients");
+
+    if !plugin::allowed(state, |p, api| p.on_join(api, client_id)) {
+        println!("A plugin turned {} away", client_id);
+        let mut clients = state.clients.lock();
+        if clients.get(&client_id).is_some_and(|c| Arc::ptr_eq(&c.stream, &stream)) {
+            clients.remove(&client_id);
+        }
+        let _ = stream.lock().shutdown(Shutdown::Both);
+        return None;
+    }
+
+    //Fresh joins get their token, a takeover already has it
+    if online.is_none() {
+        let mut tokenmsg = Message::new(MessageType::SessionToken, Vec3::ZERO, 0.0, 0);
+        tokenmsg.goose = state.clients.lock().get(&client_id).map_or((0, 0), |c| c.session);
+        send_to(state, &stream, &tokenmsg);
+    }
+
+    let state = state.clone();
+    println!("About to spawn thread");
+    let handle = thread::spawn(move || {
+        handle_client(client_id, &state);
+    });
+    println!("Spawned thread");
+    Some(handle)
+}
+
+/// Reads frames off a new connection until one of type `expected` comes, giving up after about a second.
+fn read_greeting(stream: &Arc<Mutex<NetStream>>, expected: MessageType) -> Option<Message> {
+    let mut buffer = vec![0; Message::get_serialized_size()];
+
+    for _ in 0..100 {
+        match stream.lock().read_exact(&mut buffer) {
+            Ok(_bytes) => {
+                match Message::decode(&buffer) {
+                    Some(comm) if comm.message_type == expected => return Some(comm),
+                    Some(comm) => {
+                        println!("Received greeting but it was the wrong messagetype {}", comm.message_type);
+                    }
+                    None => {
+                        println!("Error deserializing id greeting from client");
+                    },
+                }
+            },
+            Err(e) => {
+                println!("Error trying to receive id greeting from client {}", e);
+                thread::sleep(Duration::from_millis(10));
+            },
+        }
+    }
+    None
+}
+
+pub struct Server {
+    pub state: ServerState,
+    pub local_addr: SocketAddr,
+    pub metrics_addr: Option<SocketAddr>,
+    pub udp_addr: Option<SocketAddr>,
+    mainthread: Option<JoinHandle<()>>,
+    metricsthread: Option<JoinHandle<()>>,
+    udpthread: Option<JoinHandle<()>>,
+    discoverythread: Option<JoinHandle<()>>,
+    sqlthread: Option<JoinHandle<()>>,
+    clientthreads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    /// Where `open_to_lan` is taking players.
+    pub lan_addr: Option<SocketAddr>,
+    lanthread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    /// Loads the world, binds the listener and starts the accept, tick and sql threads, plus the udp and metrics ones if configured.
+    pub fn start(config: ServerConfig) -> io::Result<Server> {
+        let listener = TcpListener::bind(&config.address)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        let state = ServerState::load(&config)?;
+
+        let (metrics_addr, metricsthread) = match &config.metrics_address {
+            Some(address) => {
+                let metricslistener = TcpListener::bind(address)?;
+                metricslistener.set_nonblocking(true)?;
+                let addr = metricslistener.local_addr()?;
+                let metricsstate = state.clone();
+                (Some(addr), Some(thread::spawn(move || metrics::serve(metricslistener, metricsstate))))
+            }
+            None => (None, None),
+        };
+
+        let (udp_addr, udpthread) = if config.udp {
+            let (addr, handle) = Server::start_udp(&state, local_addr)?;
+            (Some(addr), Some(handle))
+        } else {
+            (None, None)
+        };
+
+        let discoverythread = if config.lan_discovery {
+            Server::start_discovery(&state, local_addr.port())
+        } else {
+            None
+        };
+
+        let sqlstate = state.clone();
+        let sqlthread = thread::spawn(move || {
+            while sqlstate.shouldrun.load(Ordering::Relaxed) {
+                match sqlstate.queued_sql.pop() {
+                    Some(sql) => {
+                        sqlstate.commit_sql(&sql);
+                        sqlstate.flush_sql();
+                    }
+                    None => {
+                        thread::sleep(Duration::from_millis(250));
+                    }
+                }
+            }
+            sqlstate.flush_sql();
+        });
+
+        let clientthreads: Arc<Mutex<Vec<JoinHandle<()>>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let mainstate = state.clone();
+        let ct = clientthreads.clone();
+        let mainthread = thread::spawn(move || {
+            let mut prev_time = Instant::now();
+
+            while mainstate.shouldrun.load(Ordering::Relaxed) {
+                let tickstart = Instant::now();
+
+                Server::accept_one(&listener, &mainstate, &ct);
+
+                let now = Instant::now();
+                mainstate.tick(now.duration_since(prev_time).as_secs_f32());
+                prev_time = now;
+
+                mainstate.metrics.tick.observe(tickstart.elapsed());
+
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        Ok(Server {
+            state,
+            local_addr,
+            metrics_addr,
+            udp_addr,
+            mainthread: Some(mainthread),
+            metricsthread,
+            udpthread,
+            discoverythread,
+            sqlthread: Some(sqlthread),
+            clientthreads,
+            lan_addr: None,
+            lanthread: None,
+        })
+    }
+
+    fn accept_one(listener: &TcpListener, state: &ServerState, clientthreads: &Arc<Mutex<Vec<JoinHandle<()>>>>) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                println!("New connection: {}", stream.peer_addr().unwrap());
+                if let Some(handle) = accept_client(NetStream::Tcp(stream), state, false) {
+                    clientthreads.lock().push(handle);
+                }
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                // Ignore this specific error
+            }
+            Err(e) => {
+                println!("Connection failed: {}", e);
+            }
+        }
+    }
+
+    /// Same port as TCP at `tcp_addr` if we can get it, so forwarding one port number covers both.
+    fn start_udp(state: &ServerState, tcp_addr: SocketAddr) -> io::Result<(SocketAddr, JoinHandle<()>)> {
+        let socket = UdpSocket::bind(tcp_addr).or_else(|_| UdpSocket::bind((tcp_addr.ip(), 0)))?;
+        socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+        let addr = socket.local_addr()?;
+        let _ = state.udp.set(socket);
+        let udpstate = state.clone();
+        let port = tcp_addr.port();
+        Ok((addr, thread::spawn(move || udp::serve(udpstate, port))))
+    }
+
+    /// Another server on this machine may have the discovery port already, that's fine, we just won't show up on the LAN.
+    fn start_discovery(state: &ServerState, port: u16) -> Option<JoinHandle<()>> {
+        let socket = match UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT)) {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("Not answering LAN discovery, couldn't bind port {}: {}", DISCOVERY_PORT, e);
+                return None;
+            }
+        };
+        socket.set_read_timeout(Some(Duration::from_millis(50))).ok()?;
+        let discoverystate = state.clone();
+        Some(thread::spawn(move || udp::serve_discovery(socket, discoverystate, port)))
+    }
+
+    /// An in-memory connection to this server, for playing on it from the same process without going through the network.
+    /// The other end is handled exactly like a TCP client, except it's an operator: it can only be us.
+    pub fn connect_local(&self) -> NetStream {
+        let (ours, theirs) = NetStream::memory_pair();
+        let _ = theirs.set_nonblocking(true);
+        let state = self.state.clone();
+        let ct = self.clientthreads.clone();
+        //Waits for the greeting, which the caller sends after it gets its end
+        thread::spawn(move || {
+            if let Some(handle) = accept_client(theirs, &state, true) {
+                ct.lock().push(handle);
+            }
+        });
+        ours
+    }
+
+    /// Starts taking players on `address` too, for a server that was only meant for us so far (singleplayer).
+    /// Opens UDP there if we didn't have it yet, and answers LAN discovery.
+    pub fn open_to_lan(&mut self, address: &str) -> io::Result<SocketAddr> {
+        if let Some(addr) = self.lan_addr {
+            return Ok(addr);
+        }
+
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+
+        if self.state.udp.get().is_none() {
+            match Server::start_udp(&self.state, addr) {
+                Ok((udp_addr, handle)) => {
+                    self.udp_addr = Some(udp_addr);
+                    self.udpthread = Some(handle);
+                }
+                Err(e) => println!("No udp for the LAN, transforms stay on tcp: {}", e),
+            }
+        }
+
+        if self.discoverythread.is_none() {
+            self.discoverythread = Server::start_discovery(&self.state, addr.port());
+        }
+
+        let state = self.state.clone();
+        let ct = self.clientthreads.clone();
+        self.lanthread = Some(thread::spawn(move || {
+            while state.shouldrun.load(Ordering::Relaxed) {
+                Server::accept_one(&listener, &state, &ct);
+                thread::sleep(Duration::from_millis(10));
+            }
+        }));
+
+        println!("Opened to LAN on {}", addr);
+        self.lan_addr = Some(addr);
+        Ok(addr)
+    }
+
+    /// Blocks until the server stops.
+    pub fn wait(mut self) {
+        if let Some(handle) = self.mainthread.take() {
+            let _ = handle.join();
+        }
+        self.shutdown();
+    }
+
+    /// Stops accepting, drops every client and writes out any queued sql before returning.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.state.shouldrun.store(false, Ordering::Relaxed);
+
+        if let Some(handle) = self.mainthread.take() {
+            let _ = handle.join();
+        }
+
+        if let Some(handle) = self.lanthread.take() {
+            let _ = handle.join();
+        }
+
+        let handles: Vec<JoinHandle<()>> = self.clientthreads.lock().drain(..).collect();
+        for handle in handles {
+            let _ = handle.join();
+        }
+
+        if let Some(handle) = self.metricsthread.take() {
+            let _ = handle.join();
+        }
+
+        if let Some(handle) = self.udpthread.take() {
+            let _ = handle.join();
+        }
+
+        if let Some(handle) = self.discoverythread.take() {
+            let _ = handle.join();
+        }
+
+        if let Some(handle) = self.sqlthread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,285 @@
+use std::net::Shutdown;
+use std::sync::atomic::Ordering;
+
+use glam::Vec3;
+use uuid::Uuid;
+
+use crate::bulkedit;
+use crate::game::{CURRSEED, ROWLENGTH};
+use crate::inventory::ChestInventory;
+use crate::server_types::{Message, MessageType};
+use crate::vec::IVec3;
+
+use super::sql::QueuedSqlType;
+use super::{broadcast_chat, send_chat, send_to, ServerState};
+
+/// What a hook decides about the action it was asked about.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// Stop it. The player is told where they need to be, e.g. a vetoed block edit is rolled back on their end.
+    Veto,
+}
+
+/// One block a player wants to change. Hooks can change `block` to place something else.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockEdit {
+    pub spot: IVec3,
+    pub block: u32,
+}
+
+/// A player putting `item` into a slot while a chest is open, either in the chest or their own inventory.
+/// Hooks can change `item` to put something else there.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChestMove {
+    pub chest: IVec3,
+    pub slot: usize,
+    /// False for the chest's slots, true for the player's own.
+    pub player_inv: bool,
+    /// (id, count)
+    pub item: (u32, u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MobSpawn {
+    /// Model index, same as the client's.
+    pub kind: usize,
+    pub position: Vec3,
+    pub scale: f32,
+    pub hostile: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayerInfo {
+    pub id: Uuid,
+    /// Where they last said they were, None until their first transform.
+    pub position: Option<Vec3>,
+}
+
+/// Custom server rules. Every hook has a default that allows everything, so a plugin only writes the ones it cares about.
+/// Plugins run in the order they were registered and the first veto wins, later plugins don't see the action at all.
+/// Hooks get called from the client threads at the same time, so any state a plugin keeps needs its own lock.
+#[allow(unused_variables)]
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// The player has sent their greeting. A veto hangs up on them.
+    fn on_join(&self, api: &PluginApi, player: Uuid) -> Verdict {
+        Verdict::Allow
+    }
+
+    fn on_leave(&self, api: &PluginApi, player: Uuid) {}
+
+    /// Before a player's edit goes into the world. For a `MultiBlockSet` or `BulkBlockSet` this runs per block and a veto on any drops them all.
+    fn before_block_set(&self, api: &PluginApi, player: Uuid, edit: &mut BlockEdit) -> Verdict {
+        Verdict::Allow
+    }
+
+    fn after_block_set(&self, api: &PluginApi, player: Uuid, edit: &BlockEdit) {}
+
+    fn on_chest_open(&self, api: &PluginApi, player: Uuid, chest: IVec3) -> Verdict {
+        Verdict::Allow
+    }
+
+    fn on_chest_move(&self, api: &PluginApi, player: Uuid, change: &mut ChestMove) -> Verdict {
+        Verdict::Allow
+    }
+
+    /// A vetoed line isn't shown to anyone, which is also how a plugin swallows its own commands.
+    fn on_chat(&self, api: &PluginApi, player: Uuid, text: &mut String) -> Verdict {
+        Verdict::Allow
+    }
+
+    fn on_mob_spawn(&self, api: &PluginApi, spawn: &mut MobSpawn) -> Verdict {
+        Verdict::Allow
+    }
+
+    /// Every server tick, `delta_time` in seconds.
+    fn on_tick(&self, api: &PluginApi, delta_time: f32) {}
+}
+
+/// What plugins can do to the server. Nothing done through here runs the hooks again, so plugins can't set each other off.
+pub struct PluginApi<'a> {
+    pub(crate) state: &'a ServerState,
+}
+
+impl<'a> PluginApi<'a> {
+    pub fn new(state: &'a ServerState) -> PluginApi<'a> {
+        PluginApi { state }
+    }
+
+    /// A chat line from the server to one player.
+    pub fn send_message(&self, player: Uuid, text: &str) {
+        if let Some(client) = self.state.clients.lock().get(&player) {
+            send_chat(self.state, &client.stream, None, text);
+        }
+    }
+
+    /// A chat line from the server to everyone.
+    pub fn broadcast(&self, text: &str) {
+        broadcast_chat(self.state, None, text);
+    }
+
+    pub fn block_at(&self, spot: IVec3) -> u32 {
+        self.state.csys.read().blockat(spot)
+    }
+
+    /// Changes the world like a player's edit would, saved and sent to everyone.
+    pub fn set_block(&self, spot: IVec3, block: u32) {
+        self.state.csys.write().set_block(spot, block, true);
+        let currseed = unsafe { CURRSEED.load(Ordering::Relaxed) };
+        self.state.queued_sql.push(QueuedSqlType::UserDataMap(currseed, spot, block));
+
+        let message = Message::new(MessageType::BlockSet, Vec3::new(spot.x as f32, spot.y as f32, spot.z as f32), 0.0, block);
+        self.state.send_to_all(&message);
+    }
+
+    /// Changes a lot of the world at once, a `BulkBlockSet`'s worth at a time: each batch is set, saved and
+    /// sent to everyone together, instead of block by block like `set_block`.
+    pub fn set_blocks(&self, blocks: &[(IVec3, u32)]) {
+        let currseed = unsafe { CURRSEED.load(Ordering::Relaxed) };
+        for batch in bulkedit::batches(blocks) {
+            {
+                let csys = self.state.csys.write();
+                for (spot, block) in batch {
+                    csys.set_block(*spot, *block, true);
+                }
+            }
+            self.state.queued_sql.push(QueuedSqlType::UserDataMapBulk(currseed, batch.to_vec()));
+
+            let anchor = batch[0].0;
+            let Some(payload) = bulkedit::pack(anchor, batch) else {
+                continue;
+            };
+            let mut message = bulkedit::header(0);
+            message.otherpos = anchor;
+            message.info = payload.len() as u32;
+            self.state.send_to_all_with(&message, &payload);
+        }
+    }
+
+    /// Whether `player` has said the operator password this session, see `ServerConfig::operator_password`.
+    pub fn is_operator(&self, player: Uuid) -> bool {
+        self.state.clients.lock().get(&player).is_some_and(|c| c.operator)
+    }
+
+    /// Makes `player` an operator until they leave, or takes it away. Nothing happens if they aren't connected.
+    pub fn set_operator(&self, player: Uuid, operator: bool) {
+        if let Some(client) = self.state.clients.lock().get_mut(&player) {
+            client.operator = operator;
+        }
+    }
+
+    /// What's in the chest at `spot` as (id, count) per slot, None if nothing's ever been put in one there.
+    pub fn chest(&self, spot: IVec3) -> Option<[(u32, u32); ROWLENGTH as usize * 4]> {
+        self.state.chest_reg.get(&spot).map(|c| c.inv)
+    }
+
+    /// Fills the chest at `spot`, saved and sent to everyone.
+    pub fn set_chest(&self, spot: IVec3, inv: [(u32, u32); ROWLENGTH as usize * 4]) {
+        self.state.chest_reg.insert(spot, ChestInventory { dirty: false, inv });
+        let currseed = unsafe { CURRSEED.load(Ordering::Relaxed) };
+        self.state.queued_sql.push(QueuedSqlType::ChestInventoryUpdate(spot, inv, currseed));
+
+        //A chest move from nobody, a slot at a time
+        for (slot, item) in inv.iter().enumerate() {
+            let mut message = Message::new(MessageType::ChestInvUpdate, Vec3::ZERO, item.0 as f32, slot as u32);
+            message.infof = item.1 as f32;
+            message.otherpos = spot;
+            self.state.send_to_all(&message);
+        }
+    }
+
+    pub fn players(&self) -> Vec<PlayerInfo> {
+        let ids: Vec<Uuid> = self.state.clients.lock().keys().copied().collect();
+        ids.into_iter().map(|id| self.info(id)).collect()
+    }
+
+    pub fn player(&self, id: Uuid) -> Option<PlayerInfo> {
+        if self.state.clients.lock().contains_key(&id) {
+            Some(self.info(id))
+        } else {
+            None
+        }
+    }
+
+    fn info(&self, id: Uuid) -> PlayerInfo {
+        PlayerInfo { id, position: self.state.knowncams.get(&id).map(|p| *p) }
+    }
+
+    /// The player's hotbar as (id, count) per slot.
+    pub fn inventory(&self, player: Uuid) -> Option<[(u32, u32); ROWLENGTH as usize]> {
+        self.state.clients.lock().get(&player).map(|c| c.inv.inv)
+    }
+
+    /// Puts `item` in one of the player's slots, saved and sent to them. False if they're gone or the slot doesn't exist.
+    pub fn set_inventory_slot(&self, player: Uuid, slot: usize, item: (u32, u32)) -> bool {
+        if slot >= ROWLENGTH as usize {
+            return false;
+        }
+        let stream = {
+            let mut clients = self.state.clients.lock();
+            let Some(client) = clients.get_mut(&player) else {
+                return false;
+            };
+            client.inv.inv[slot] = if item.1 == 0 { (0, 0) } else { item };
+            self.state.queued_sql.push(QueuedSqlType::InventoryInventoryUpdate(player, client.inv.inv));
+            client.stream.clone()
+        };
+
+        //Same as a chest move into their own inventory, z of 0 leaves whatever they're holding alone
+        let mut message = Message::new(MessageType::ChestInvUpdate, Vec3::ZERO, item.0 as f32, slot as u32);
+        message.infof = item.1 as f32;
+        message.info2 = 1;
+        message.goose = player.as_u64_pair();
+        send_to(self.state, &stream, &message);
+        true
+    }
+
+    /// Adds `count` of `id` to the first stack of it, or else the first empty slot. False if there's no room.
+    pub fn give_item(&self, player: Uuid, id: u32, count: u32) -> bool {
+        let Some(inv) = self.inventory(player) else {
+            return false;
+        };
+        let slot = inv.iter().position(|s| s.0 == id && s.1 > 0).or_else(|| inv.iter().position(|s| s.1 == 0));
+        match slot {
+            Some(slot) => {
+                let have = if inv[slot].0 == id { inv[slot].1 } else { 0 };
+                self.set_inventory_slot(player, slot, (id, have + count))
+            }
+            None => false,
+        }
+    }
+
+    /// Hangs up on a player. Their thread notices and they leave like any other disconnect.
+    pub fn kick(&self, player: Uuid) {
+        if let Some(client) = self.state.clients.lock().get(&player) {
+            let _ = client.stream.lock().shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Spawns a mob without asking the mob spawn hooks. Returns its id.
+    pub fn spawn_mob(&self, spawn: MobSpawn) -> u32 {
+        self.state.add_mob(&spawn)
+    }
+}
+
+/// Runs `hook` on every plugin until one vetoes. True if none did.
+pub fn allowed(state: &ServerState, mut hook: impl FnMut(&dyn Plugin, &PluginApi) -> Verdict) -> bool {
+    if state.plugins.is_empty() {
+        return true;
+    }
+    let api = PluginApi::new(state);
+    state.plugins.iter().all(|p| hook(p.as_ref(), &api) == Verdict::Allow)
+}
+
+/// Runs `hook` on every plugin.
+pub fn notify(state: &ServerState, hook: impl Fn(&dyn Plugin, &PluginApi)) {
+    if state.plugins.is_empty() {
+        return;
+    }
+    let api = PluginApi::new(state);
+    for p in state.plugins.iter() {
+        hook(p.as_ref(), &api);
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::server_types::MessageType;
+
+/// Groups of messages that share a budget.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageCategory {
+    Movement,
+    BlockEdit,
+    Inventory,
+    /// Every line goes out to everyone, and commands can be heavy.
+    Chat,
+    /// Seed, planet type and friends. Cheap to answer but the server sleeps on every one.
+    Query,
+    /// `RequestUdm`, rereads and resends the whole db.
+    WorldSync,
+    /// `ReqChestReg`, rereads and resends the whole chestdb.
+    ChestSync,
+    /// `RequestTakeoff`, regenerates the world for everyone.
+    Takeoff,
+    /// Never limited, either the handler rejects these anyway or we always want them (Disconnect).
+    Unlimited,
+}
+
+impl MessageCategory {
+    pub fn of(t: MessageType) -> MessageCategory {
+        match t {
+            MessageType::PlayerUpdate => MessageCategory::Movement,
+            MessageType::BlockSet | MessageType::MultiBlockSet | MessageType::BulkBlockSet => MessageCategory::BlockEdit,
+            MessageType::ChestInvUpdate | MessageType::ChestOpen => MessageCategory::Inventory,
+            MessageType::Chat => MessageCategory::Chat,
+            MessageType::RequestSeed | MessageType::RequestPt | MessageType::TellYouMyID | MessageType::ShutUpMobMsgs | MessageType::UdpToken | MessageType::SessionToken => MessageCategory::Query,
+            MessageType::RequestUdm => MessageCategory::WorldSync,
+            MessageType::ReqChestReg => MessageCategory::ChestSync,
+            MessageType::RequestTakeoff => MessageCategory::Takeoff,
+            _ => MessageCategory::Unlimited,
+        }
+    }
+}
+
+/// Burst size and steady rate for one category.
+#[derive(Clone, Copy, Debug)]
+pub struct BucketLimit {
+    pub capacity: f32,
+    pub per_second: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct RateLimits {
+    pub movement: BucketLimit,
+    pub block_edit: BucketLimit,
+    pub inventory: BucketLimit,
+    pub chat: BucketLimit,
+    pub query: BucketLimit,
+    /// Transforms on the UDP side channel, which aren't held to the 50ms TCP read.
+    pub udp: BucketLimit,
+    /// Server browser status queries, per source address since they come from anyone.
+    pub status_query: BucketLimit,
+    pub world_sync_cooldown: Duration,
+    pub chest_sync_cooldown: Duration,
+    pub takeoff_cooldown: Duration,
+}
+
+impl Default for RateLimits {
+    /// The server reads at most one TCP message per client every 50ms, so the TCP budgets don't need to go above 20/s.
+    fn default() -> Self {
+        RateLimits {
+            movement: BucketLimit { capacity: 10.0, per_second: 8.0 },
+            block_edit: BucketLimit { capacity: 20.0, per_second: 10.0 },
+            inventory: BucketLimit { capacity: 20.0, per_second: 10.0 },
+            chat: BucketLimit { capacity: 5.0, per_second: 1.0 },
+            query: BucketLimit { capacity: 5.0, per_second: 0.5 },
+            udp: BucketLimit { capacity: 40.0, per_second: 30.0 },
+            status_query: BucketLimit { capacity: 4.0, per_second: 1.0 },
+            world_sync_cooldown: Duration::from_secs(5),
+            chest_sync_cooldown: Duration::from_secs(2),
+            takeoff_cooldown: Duration::from_secs(10),
+        }
+    }
+}
+
+pub struct TokenBucket {
+    limit: BucketLimit,
+    tokens: f32,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: BucketLimit) -> TokenBucket {
+        TokenBucket { limit, tokens: limit.capacity, last: Instant::now() }
+    }
+
+    pub fn take_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f32();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.capacity);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn full_at(&self, now: Instant) -> bool {
+        self.tokens + now.saturating_duration_since(self.last).as_secs_f32() * self.limit.per_second >= self.limit.capacity
+    }
+}
+
+pub struct Cooldown {
+    length: Duration,
+    last: Option<Instant>,
+}
+
+impl Cooldown {
+    pub fn new(length: Duration) -> Cooldown {
+        Cooldown { length, last: None }
+    }
+
+    pub fn take_at(&mut self, now: Instant) -> bool {
+        match self.last {
+            Some(last) if now.saturating_duration_since(last) < self.length => false,
+            _ => {
+                self.last = Some(now);
+                true
+            }
+        }
+    }
+}
+
+/// One per client, decides whether each incoming message gets handled or dropped.
+pub struct RateLimiter {
+    movement: TokenBucket,
+    block_edit: TokenBucket,
+    inventory: TokenBucket,
+    chat: TokenBucket,
+    query: TokenBucket,
+    udp: TokenBucket,
+    world_sync: Cooldown,
+    chest_sync: Cooldown,
+    takeoff: Cooldown,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &RateLimits) -> RateLimiter {
+        RateLimiter {
+            movement: TokenBucket::new(limits.movement),
+            block_edit: TokenBucket::new(limits.block_edit),
+            inventory: TokenBucket::new(limits.inventory),
+            chat: TokenBucket::new(limits.chat),
+            query: TokenBucket::new(limits.query),
+            udp: TokenBucket::new(limits.udp),
+            world_sync: Cooldown::new(limits.world_sync_cooldown),
+            chest_sync: Cooldown::new(limits.chest_sync_cooldown),
+            takeoff: Cooldown::new(limits.takeoff_cooldown),
+        }
+    }
+
+    pub fn allow(&mut self, t: MessageType) -> bool {
+        self.allow_at(t, Instant::now())
+    }
+
+    pub fn allow_udp_at(&mut self, now: Instant) -> bool {
+        self.udp.take_at(now)
+    }
+
+    pub fn allow_at(&mut self, t: MessageType, now: Instant) -> bool {
+        match MessageCategory::of(t) {
+            MessageCategory::Movement => self.movement.take_at(now),
+            MessageCategory::BlockEdit => self.block_edit.take_at(now),
+            MessageCategory::Inventory => self.inventory.take_at(now),
+            MessageCategory::Chat => self.chat.take_at(now),
+            MessageCategory::Query => self.query.take_at(now),
+            MessageCategory::WorldSync => self.world_sync.take_at(now),
+            MessageCategory::ChestSync => self.chest_sync.take_at(now),
+            MessageCategory::Takeoff => self.takeoff.take_at(now),
+            MessageCategory::Unlimited => true,
+        }
+    }
+}
+
+/// At most this many addresses are tracked, new ones past that go unanswered until old ones have been quiet a while.
+pub const MAX_QUERY_SOURCES: usize = 4096;
+
+/// Budgets for status queries by where they say they're from. A query is unauthenticated UDP and the status is
+/// bigger than it, so without this anyone could spoof someone's address and have us flood them with answers.
+pub struct QueryLimiter {
+    limit: BucketLimit,
+    sources: HashMap<IpAddr, TokenBucket>,
+}
+
+impl QueryLimiter {
+    pub fn new(limit: BucketLimit) -> QueryLimiter {
+        QueryLimiter { limit, sources: HashMap::new() }
+    }
+
+    pub fn allow_at(&mut self, from: IpAddr, now: Instant) -> bool {
+        if !self.sources.contains_key(&from) && self.sources.len() >= MAX_QUERY_SOURCES {
+            //Anyone whose bucket has filled back up would start over from full anyway
+            self.sources.retain(|_, bucket| !bucket.full_at(now));
+            if self.sources.len() >= MAX_QUERY_SOURCES {
+                return false;
+            }
+        }
+        let limit = self.limit;
+        self.sources.entry(from).or_insert_with(|| TokenBucket::new(limit)).take_at(now)
+    }
+}
@@ -0,0 +1,143 @@
+use std::fs;
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+use crate::saves;
+use crate::schematic::{Schematic, SCHEMATIC_EXTENSION, VOX_EXTENSION};
+use crate::vec::IVec3;
+
+use super::plugin::{Plugin, PluginApi, Verdict};
+
+/// Biggest box `/schem import` pastes, air included. Bigger ones can still be exported, and stamped a piece at a time.
+pub const MAX_PASTE_VOLUME: i64 = 64 * 64 * 64;
+
+/// `/schem` chat commands, copying boxes of the world to files in `dir` and pasting them back in:
+///
+/// - `/schem export <name> <x1> <y1> <z1> <x2> <y2> <z2>` saves `<name>.vlschem`, or a MagicaVoxel model if the name ends in `.vox`
+/// - `/schem import <name> [x y z] [0|90|180|270] [-a]` pastes its low corner at x y z, where the player is if left out,
+///   turned that many degrees. Air in the box clears what's there unless it's `-a`.
+/// - `/schem list`
+///
+/// Only operators may use them (see `ServerConfig::operator_password`), they write files on the server and paste whole
+/// buildings at once.
+pub struct SchematicCommands {
+    pub dir: PathBuf,
+}
+
+impl SchematicCommands {
+    pub fn new(dir: impl Into<PathBuf>) -> SchematicCommands {
+        SchematicCommands { dir: dir.into() }
+    }
+
+    /// Runs one command, what to tell the player either way.
+    pub fn run(&self, api: &PluginApi, player: Uuid, command: &str) -> Result<String, String> {
+        if !api.is_operator(player) {
+            return Err(String::from("Only operators can use /schem"));
+        }
+        let words: Vec<&str> = command.split_whitespace().skip(1).collect();
+        match words.as_slice() {
+            ["export", name, rest @ ..] => {
+                let corners = numbers(rest).filter(|c| c.len() == 6).ok_or("Usage: /schem export <name> <x1> <y1> <z1> <x2> <y2> <z2>")?;
+                let a = IVec3::new(corners[0], corners[1], corners[2]);
+                let b = IVec3::new(corners[3], corners[4], corners[5]);
+                let schematic = Schematic::capture(a, b, |spot| api.block_at(spot), |spot| api.chest(spot)).map_err(|e| e.to_string())?;
+                let path = self.path(name);
+                schematic.save(&path).map_err(|e| format!("Couldn't save {}: {}", file_name(&path), e))?;
+                Ok(format!("Saved {} blocks and {} chests to {}", schematic.blocks.len(), schematic.chests.len(), file_name(&path)))
+            }
+            ["import", name, rest @ ..] => {
+                let keep_air = rest.last() == Some(&"-a");
+                let rest = if keep_air { &rest[..rest.len() - 1] } else { rest };
+                let numbers = numbers(rest).ok_or("Usage: /schem import <name> [x y z] [0|90|180|270] [-a]")?;
+                let (at, degrees) = match numbers.as_slice() {
+                    [x, y, z, rest @ ..] if rest.len() <= 1 => (IVec3::new(*x, *y, *z), rest.first().copied().unwrap_or(0)),
+                    [] | [_] => {
+                        let position = api.player(player).and_then(|p| p.position).ok_or("Where to? You haven't moved yet")?;
+                        let at = IVec3::new(position.x.floor() as i32, position.y.floor() as i32, position.z.floor() as i32);
+                        (at, numbers.first().copied().unwrap_or(0))
+                    }
+                    _ => return Err(String::from("Usage: /schem import <name> [x y z] [0|90|180|270] [-a]")),
+                };
+                if degrees.rem_euclid(90) != 0 {
+                    return Err(String::from("Turns go in 90 degrees"));
+                }
+
+                let path = self.path(name);
+                let schematic = Schematic::load(&path).map_err(|e| format!("Couldn't read {}: {}", file_name(&path), e))?;
+                let schematic = schematic.rotated((degrees.rem_euclid(360) / 90) as u32);
+                if !schematic.fits_at(at) {
+                    return Err(format!("{} won't fit at {}. Usage: /schem import <name> [x y z] [0|90|180|270] [-a]", file_name(&path), at));
+                }
+                let volume = schematic.size.x as i64 * schematic.size.y as i64 * schematic.size.z as i64;
+                if volume > MAX_PASTE_VOLUME {
+                    return Err(format!("{} is {} blocks, {} at most can be pasted at once", file_name(&path), volume, MAX_PASTE_VOLUME));
+                }
+
+                let changes: Vec<(IVec3, u32)> = schematic
+                    .placed(at)
+                    .into_iter()
+                    .filter(|(spot, block)| (*block != 0 || !keep_air) && api.block_at(*spot) != *block)
+                    .collect();
+                api.set_blocks(&changes);
+                let changed = changes.len();
+                for (spot, inv) in &schematic.chests {
+                    api.set_chest(at + *spot, *inv);
+                }
+                Ok(format!("Pasted {} at {}, {} blocks changed", file_name(&path), at, changed))
+            }
+            ["list"] => {
+                let mut names: Vec<String> = fs::read_dir(&self.dir)
+                    .map(|entries| {
+                        entries
+                            .flatten()
+                            .map(|e| e.path())
+                            .filter(|p| p.extension().is_some_and(|e| e == SCHEMATIC_EXTENSION || e == VOX_EXTENSION))
+                            .map(|p| file_name(&p))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                names.sort();
+                if names.is_empty() {
+                    Ok(String::from("No schematics yet"))
+                } else {
+                    Ok(names.join(", "))
+                }
+            }
+            _ => Err(String::from("Usage: /schem export|import|list")),
+        }
+    }
+
+    /// Where `name` is saved. Only ever a file right in `dir`, whatever the name says.
+    pub fn path(&self, name: &str) -> PathBuf {
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, extension)) if extension.eq_ignore_ascii_case(VOX_EXTENSION) => (stem, VOX_EXTENSION),
+            Some((stem, extension)) if extension.eq_ignore_ascii_case(SCHEMATIC_EXTENSION) => (stem, SCHEMATIC_EXTENSION),
+            _ => (name, SCHEMATIC_EXTENSION),
+        };
+        self.dir.join(format!("{}.{}", saves::folder_name(stem), extension))
+    }
+}
+
+impl Plugin for SchematicCommands {
+    fn name(&self) -> &str {
+        "schematics"
+    }
+
+    fn on_chat(&self, api: &PluginApi, player: Uuid, text: &mut String) -> Verdict {
+        if text.split_whitespace().next() != Some("/schem") {
+            return Verdict::Allow;
+        }
+        let reply = self.run(api, player, text).unwrap_or_else(|e| e);
+        api.send_message(player, &reply);
+        Verdict::Veto
+    }
+}
+
+fn numbers(words: &[&str]) -> Option<Vec<i32>> {
+    words.iter().map(|w| w.parse().ok()).collect()
+}
+
+fn file_name(path: &std::path::Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
@@ -0,0 +1,500 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use glam::Vec3;
+use parking_lot::{Mutex, RwLock};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Scope, AST, FLOAT, INT};
+use uuid::Uuid;
+
+use crate::vec::IVec3;
+
+use super::plugin::{BlockEdit, ChestMove, MobSpawn, Plugin, PluginApi, Verdict};
+use super::ServerState;
+
+/// How often `on_tick` looks for new, changed or deleted scripts, in seconds.
+pub const RELOAD_INTERVAL: f32 = 1.0;
+
+/// The sandbox. A script call that goes over any of these is stopped and treated as if the script wasn't there.
+pub const MAX_OPERATIONS: u64 = 200_000;
+pub const MAX_CALL_LEVELS: usize = 32;
+pub const MAX_STRING_SIZE: usize = 4096;
+pub const MAX_ARRAY_SIZE: usize = 1024;
+
+struct Script {
+    /// File name, e.g. "welcome.rhai".
+    name: String,
+    source: String,
+    ast: AST,
+    /// (name, arity) of every function it defines.
+    functions: HashSet<(String, usize)>,
+}
+
+impl Script {
+    fn has(&self, function: &str, arity: usize) -> bool {
+        self.functions.contains(&(function.to_string(), arity))
+    }
+}
+
+struct Timer {
+    script: String,
+    function: String,
+    left: f32,
+    /// Some for `every`, None for `after`.
+    every: Option<f32>,
+}
+
+/// What the bindings can get at. They're registered once on the engine so they can't borrow the hook's `PluginApi`,
+/// the world gets lent to them for the length of each call instead.
+#[derive(Default)]
+struct Shared {
+    state: RwLock<Option<ServerState>>,
+    /// The script being run, for `print` and so timers know who they belong to.
+    current: Mutex<Option<String>>,
+    timers: Mutex<Vec<Timer>>,
+    /// `store`/`recall`, kept across reloads.
+    store: DashMap<String, Dynamic>,
+}
+
+impl Shared {
+    fn with_api<R>(&self, default: R, f: impl FnOnce(&PluginApi) -> R) -> R {
+        match self.state.read().as_ref() {
+            Some(state) => f(&PluginApi::new(state)),
+            None => default,
+        }
+    }
+}
+
+/// Runs the `.rhai` files in a folder as a plugin. Scripts hook in by defining functions with these names:
+///
+/// - `on_join(player)`, `on_leave(player)`
+/// - `on_block_set(player, x, y, z, block)`, return a block id to place that instead
+/// - `after_block_set(player, x, y, z, block)`
+/// - `on_chest_open(player, x, y, z)`
+/// - `on_chest_move(player, x, y, z, slot, player_inv, item, count)`
+/// - `on_chat(player, text)`, return a string to say that instead
+/// - `on_mob_spawn(kind, x, y, z, hostile)`
+/// - `on_tick(delta_time)`
+///
+/// Returning `false` from any of them vetoes like `Verdict::Veto`, anything else allows. Players are their id strings.
+/// A script's top level runs whenever the file is (re)loaded. That's the place to set up timers, but it might be
+/// before there's a world, in which case world functions do nothing.
+pub struct ScriptHost {
+    dir: PathBuf,
+    engine: Engine,
+    shared: Arc<Shared>,
+    /// Sorted by file name, that's the order they run in.
+    scripts: Mutex<Vec<Script>>,
+    since_scan: Mutex<f32>,
+}
+
+impl ScriptHost {
+    /// Loads every script in `dir`. The folder doesn't need to exist yet, scripts get picked up once it does.
+    pub fn new(dir: impl Into<PathBuf>) -> ScriptHost {
+        let shared = Arc::new(Shared::default());
+        let host = ScriptHost {
+            dir: dir.into(),
+            engine: ScriptHost::engine(&shared),
+            shared,
+            scripts: Mutex::new(Vec::new()),
+            since_scan: Mutex::new(0.0),
+        };
+        host.reload();
+        host
+    }
+
+    /// Names of the scripts that are loaded, in the order they run.
+    pub fn loaded(&self) -> Vec<String> {
+        self.scripts.lock().iter().map(|s| s.name.clone()).collect()
+    }
+
+    /// Picks up new, changed and deleted scripts right away instead of waiting for the next scan. A script that doesn't
+    /// compile keeps its last good version running.
+    pub fn reload(&self) {
+        let mut scripts = self.scripts.lock();
+
+        let mut found: Vec<(String, String)> = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|e| e == "rhai"))
+                .filter_map(|p| {
+                    let name = p.file_name()?.to_string_lossy().to_string();
+                    let source = std::fs::read_to_string(&p).ok()?;
+                    Some((name, source))
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        found.sort();
+
+        scripts.retain(|s| {
+            let keep = found.iter().any(|(name, _)| *name == s.name);
+            if !keep {
+                println!("Unloaded script {}", s.name);
+                self.shared.timers.lock().retain(|t| t.script != s.name);
+            }
+            keep
+        });
+
+        for (name, source) in found {
+            let existing = scripts.iter().position(|s| s.name == name);
+            if existing.is_some_and(|i| scripts[i].source == source) {
+                continue;
+            }
+
+            let ast = match self.engine.compile(&source) {
+                Ok(ast) => ast,
+                Err(e) => {
+                    println!("Script {} doesn't compile: {}", name, e);
+                    continue;
+                }
+            };
+            let functions = ast.iter_functions().map(|f| (f.name.to_string(), f.params.len())).collect();
+
+            //Its old timers go, the top level sets them up again
+            self.shared.timers.lock().retain(|t| t.script != name);
+
+            let script = Script { name: name.clone(), source, ast, functions };
+
+            *self.shared.current.lock() = Some(name.clone());
+            if let Err(e) = self.engine.run_ast_with_scope(&mut Scope::new(), &script.ast) {
+                println!("Script {} failed to load: {}", name, e);
+            }
+            *self.shared.current.lock() = None;
+
+            match existing {
+                Some(i) => {
+                    println!("Reloaded script {}", name);
+                    scripts[i] = script;
+                }
+                None => {
+                    println!("Loaded script {}", name);
+                    scripts.push(script);
+                }
+            }
+        }
+
+        scripts.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    fn engine(shared: &Arc<Shared>) -> Engine {
+        let mut engine = Engine::new();
+
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        engine.set_max_string_size(MAX_STRING_SIZE);
+        engine.set_max_array_size(MAX_ARRAY_SIZE);
+        engine.set_max_map_size(MAX_ARRAY_SIZE);
+        engine.set_max_expr_depths(64, 32);
+        //No reading other files through import
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+
+        let s = shared.clone();
+        engine.on_print(move |text| {
+            let current = s.current.lock().clone().unwrap_or_default();
+            println!("[{}] {}", current, text);
+        });
+
+        //World
+        let s = shared.clone();
+        engine.register_fn("block_at", move |x: INT, y: INT, z: INT| -> INT {
+            s.with_api(0, |api| api.block_at(IVec3::new(x as i32, y as i32, z as i32)) as INT)
+        });
+        let s = shared.clone();
+        engine.register_fn("set_block", move |x: INT, y: INT, z: INT, block: INT| {
+            s.with_api((), |api| api.set_block(IVec3::new(x as i32, y as i32, z as i32), block as u32))
+        });
+        let s = shared.clone();
+        engine.register_fn("spawn_mob", move |kind: INT, x: FLOAT, y: FLOAT, z: FLOAT, hostile: bool| -> INT {
+            let spawn = MobSpawn { kind: kind as usize, position: Vec3::new(x as f32, y as f32, z as f32), scale: 1.0, hostile };
+            s.with_api(-1, |api| api.spawn_mob(spawn) as INT)
+        });
+
+        //Players
+        let s = shared.clone();
+        engine.register_fn("players", move || -> Array {
+            s.with_api(Array::new(), |api| api.players().iter().map(|p| Dynamic::from(p.id.to_string())).collect())
+        });
+        let s = shared.clone();
+        engine.register_fn("player_position", move |player: &str| -> Dynamic {
+            s.with_api(Dynamic::UNIT, |api| match parse(player).and_then(|id| api.player(id)).and_then(|p| p.position) {
+                Some(pos) => Dynamic::from_array(vec![(pos.x as FLOAT).into(), (pos.y as FLOAT).into(), (pos.z as FLOAT).into()]),
+                None => Dynamic::UNIT,
+            })
+        });
+        let s = shared.clone();
+        engine.register_fn("kick", move |player: &str| {
+            s.with_api((), |api| {
+                if let Some(id) = parse(player) {
+                    api.kick(id);
+                }
+            })
+        });
+
+        //Inventory
+        let s = shared.clone();
+        engine.register_fn("inventory", move |player: &str| -> Array {
+            s.with_api(Array::new(), |api| match parse(player).and_then(|id| api.inventory(id)) {
+                Some(inv) => inv
+                    .iter()
+                    .map(|(item, count)| Dynamic::from_array(vec![(*item as INT).into(), (*count as INT).into()]))
+                    .collect(),
+                None => Array::new(),
+            })
+        });
+        let s = shared.clone();
+        engine.register_fn("set_slot", move |player: &str, slot: INT, item: INT, count: INT| -> bool {
+            s.with_api(false, |api| match parse(player) {
+                Some(id) if slot >= 0 => api.set_inventory_slot(id, slot as usize, (item.max(0) as u32, count.max(0) as u32)),
+                _ => false,
+            })
+        });
+        let s = shared.clone();
+        engine.register_fn("give_item", move |player: &str, item: INT, count: INT| -> bool {
+            s.with_api(false, |api| match parse(player) {
+                Some(id) if item > 0 && count > 0 => api.give_item(id, item as u32, count as u32),
+                _ => false,
+            })
+        });
+
+        //Chat
+        let s = shared.clone();
+        engine.register_fn("send_message", move |player: &str, text: &str| {
+            s.with_api((), |api| {
+                if let Some(id) = parse(player) {
+                    api.send_message(id, text);
+                }
+            })
+        });
+        let s = shared.clone();
+        engine.register_fn("broadcast", move |text: &str| s.with_api((), |api| api.broadcast(text)));
+
+        //Timers, the function gets called with no arguments
+        for repeat in [false, true] {
+            let name = if repeat { "every" } else { "after" };
+            let s = shared.clone();
+            engine.register_fn(name, move |seconds: FLOAT, function: &str| s.add_timer(seconds as f32, function, repeat));
+            let s = shared.clone();
+            engine.register_fn(name, move |seconds: INT, function: &str| s.add_timer(seconds as f32, function, repeat));
+        }
+
+        //Remembered across calls and reloads, scripts can't keep globals of their own
+        let s = shared.clone();
+        engine.register_fn("store", move |key: &str, value: Dynamic| {
+            s.store.insert(key.to_string(), value);
+        });
+        let s = shared.clone();
+        engine.register_fn("recall", move |key: &str, default: Dynamic| -> Dynamic {
+            s.store.get(key).map(|v| v.clone()).unwrap_or(default)
+        });
+
+        engine
+    }
+
+    /// Lends the world to the bindings while `f` runs the scripts.
+    fn with_scripts<R>(&self, api: &PluginApi, f: impl FnOnce(&[Script]) -> R) -> R {
+        let scripts = self.scripts.lock();
+        if scripts.is_empty() {
+            drop(scripts);
+            return f(&[]);
+        }
+        *self.shared.state.write() = Some(api.state.clone());
+        let result = f(&scripts);
+        *self.shared.state.write() = None;
+        result
+    }
+
+    /// None if the script doesn't have it or it failed, which gets printed.
+    fn call(&self, script: &Script, function: &str, args: Vec<Dynamic>) -> Option<Dynamic> {
+        if !script.has(function, args.len()) {
+            return None;
+        }
+        *self.shared.current.lock() = Some(script.name.clone());
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &script.ast, function, args);
+        *self.shared.current.lock() = None;
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                println!("Script {} failed in {}: {}", script.name, function, e);
+                None
+            }
+        }
+    }
+
+    /// Runs `function` on every script that has it until one returns false.
+    fn allowed(&self, api: &PluginApi, function: &str, args: Vec<Dynamic>) -> Verdict {
+        self.with_scripts(api, |scripts| {
+            for script in scripts {
+                if let Some(false) = self.call(script, function, args.clone()).and_then(|r| r.as_bool().ok()) {
+                    return Verdict::Veto;
+                }
+            }
+            Verdict::Allow
+        })
+    }
+
+    fn run_timers(&self, api: &PluginApi, delta_time: f32) {
+        let due: Vec<Timer> = {
+            let mut timers = self.shared.timers.lock();
+            for timer in timers.iter_mut() {
+                timer.left -= delta_time;
+            }
+            let (due, waiting) = timers.drain(..).partition(|t| t.left <= 0.0);
+            *timers = waiting;
+            due
+        };
+        if due.is_empty() {
+            return;
+        }
+
+        self.with_scripts(api, |scripts| {
+            for mut timer in due {
+                if let Some(script) = scripts.iter().find(|s| s.name == timer.script) {
+                    self.call(script, &timer.function, Vec::new());
+                }
+                if let Some(every) = timer.every {
+                    timer.left += every;
+                    self.shared.timers.lock().push(timer);
+                }
+            }
+        });
+    }
+}
+
+impl Shared {
+    fn add_timer(&self, seconds: f32, function: &str, repeat: bool) {
+        let Some(script) = self.current.lock().clone() else {
+            return;
+        };
+        //Something has to stop an `every(0, ..)` from running every tick forever
+        let seconds = seconds.max(0.05);
+        self.timers.lock().push(Timer { script, function: function.to_string(), left: seconds, every: repeat.then_some(seconds) });
+    }
+}
+
+fn parse(player: &str) -> Option<Uuid> {
+    Uuid::parse_str(player).ok()
+}
+
+fn spot_args(player: Uuid, spot: IVec3) -> Vec<Dynamic> {
+    vec![player.to_string().into(), (spot.x as INT).into(), (spot.y as INT).into(), (spot.z as INT).into()]
+}
+
+impl Plugin for ScriptHost {
+    fn name(&self) -> &str {
+        "scripts"
+    }
+
+    fn on_join(&self, api: &PluginApi, player: Uuid) -> Verdict {
+        self.allowed(api, "on_join", vec![player.to_string().into()])
+    }
+
+    fn on_leave(&self, api: &PluginApi, player: Uuid) {
+        self.with_scripts(api, |scripts| {
+            for script in scripts {
+                self.call(script, "on_leave", vec![player.to_string().into()]);
+            }
+        });
+    }
+
+    fn before_block_set(&self, api: &PluginApi, player: Uuid, edit: &mut BlockEdit) -> Verdict {
+        self.with_scripts(api, |scripts| {
+            for script in scripts {
+                let mut args = spot_args(player, edit.spot);
+                args.push((edit.block as INT).into());
+                match self.call(script, "on_block_set", args) {
+                    Some(r) if r.as_bool() == Ok(false) => return Verdict::Veto,
+                    Some(r) => {
+                        if let Ok(block) = r.as_int() {
+                            edit.block = block.max(0) as u32;
+                        }
+                    }
+                    None => {}
+                }
+            }
+            Verdict::Allow
+        })
+    }
+
+    fn after_block_set(&self, api: &PluginApi, player: Uuid, edit: &BlockEdit) {
+        self.with_scripts(api, |scripts| {
+            for script in scripts {
+                let mut args = spot_args(player, edit.spot);
+                args.push((edit.block as INT).into());
+                self.call(script, "after_block_set", args);
+            }
+        });
+    }
+
+    fn on_chest_open(&self, api: &PluginApi, player: Uuid, chest: IVec3) -> Verdict {
+        self.allowed(api, "on_chest_open", spot_args(player, chest))
+    }
+
+    fn on_chest_move(&self, api: &PluginApi, player: Uuid, change: &mut ChestMove) -> Verdict {
+        let mut args = spot_args(player, change.chest);
+        args.extend([
+            (change.slot as INT).into(),
+            change.player_inv.into(),
+            (change.item.0 as INT).into(),
+            (change.item.1 as INT).into(),
+        ]);
+        self.allowed(api, "on_chest_move", args)
+    }
+
+    fn on_chat(&self, api: &PluginApi, player: Uuid, text: &mut String) -> Verdict {
+        self.with_scripts(api, |scripts| {
+            for script in scripts {
+                match self.call(script, "on_chat", vec![player.to_string().into(), text.clone().into()]) {
+                    Some(r) if r.as_bool() == Ok(false) => return Verdict::Veto,
+                    Some(r) if r.is_string() => *text = r.into_string().unwrap(),
+                    _ => {}
+                }
+            }
+            Verdict::Allow
+        })
+    }
+
+    fn on_mob_spawn(&self, api: &PluginApi, spawn: &mut MobSpawn) -> Verdict {
+        let p = spawn.position;
+        let args = vec![
+            (spawn.kind as INT).into(),
+            (p.x as FLOAT).into(),
+            (p.y as FLOAT).into(),
+            (p.z as FLOAT).into(),
+            spawn.hostile.into(),
+        ];
+        self.allowed(api, "on_mob_spawn", args)
+    }
+
+    fn on_tick(&self, api: &PluginApi, delta_time: f32) {
+        let scan = {
+            let mut since = self.since_scan.lock();
+            *since += delta_time;
+            if *since >= RELOAD_INTERVAL {
+                *since = 0.0;
+                true
+            } else {
+                false
+            }
+        };
+        if scan {
+            *self.shared.state.write() = Some(api.state.clone());
+            self.reload();
+            *self.shared.state.write() = None;
+        }
+
+        self.run_timers(api, delta_time);
+
+        self.with_scripts(api, |scripts| {
+            for script in scripts {
+                self.call(script, "on_tick", vec![(delta_time as FLOAT).into()]);
+            }
+        });
+    }
+}
+
@@ -0,0 +1,168 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use glam::Vec3;
+use rusqlite::{params, Connection, OptionalExtension};
+use uuid::Uuid;
+
+use crate::chunkstore::ChunkStore;
+use crate::game::ROWLENGTH;
+use crate::playerposition::{PlayerPosition, PlayerVec};
+use crate::vec::IVec3;
+use crate::worldstorage::{StorageResult, WorldStorageError};
+
+pub enum QueuedSqlType {
+    UserDataMap(u32, IVec3, u32),
+    /// A `BulkBlockSet`'s blocks, saved together.
+    UserDataMapBulk(u32, Vec<(IVec3, u32)>),
+    ChestInventoryUpdate(IVec3, [(u32, u32); ROWLENGTH as usize * 4], u32),
+    InventoryInventoryUpdate(Uuid, [(u32, u32); ROWLENGTH as usize]),
+    PlayerPositionUpdate(Uuid, Vec3, f32, f32),
+    None
+}
+
+/// Writes one queued change to the world's databases. Waits out another connection holding the
+/// database for a few seconds, anything else comes straight back.
+pub fn handlesql(sql: &QueuedSqlType, world_dir: &Path) -> StorageResult<()> {
+
+    println!("Calling handlesql");
+    let mut retries = 0;
+
+    loop {
+        match write(sql, world_dir) {
+            Err(WorldStorageError::Locked { .. }) if retries < 30 => {
+                println!("Sqlite busy, retrying..");
+                retries += 1;
+                thread::sleep(Duration::from_millis(100));
+            }
+            result => return result,
+        }
+    }
+}
+
+fn write(sql: &QueuedSqlType, world_dir: &Path) -> StorageResult<()> {
+    let chestdb = world_dir.join("chestdb");
+    let sql_error = |e| WorldStorageError::sql(&chestdb, e);
+
+    match sql {
+        QueuedSqlType::UserDataMap(seed, spot, block) => {
+            let db = world_dir.join("db");
+            ChunkStore::new(&db, *seed).set_block(*spot, *block)?;
+        },
+        QueuedSqlType::UserDataMapBulk(seed, blocks) => {
+            let db = world_dir.join("db");
+            ChunkStore::new(&db, *seed).set_blocks(blocks)?;
+        },
+        QueuedSqlType::ChestInventoryUpdate(key, inv, seed) => {
+
+            let table_name = format!("chest_registry_{}", seed);
+
+            let conn = Connection::open(&chestdb).map_err(sql_error)?;
+
+            // Ensure the table exists
+            conn.execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS {} (
+                        x INTEGER,
+                        y INTEGER,
+                        z INTEGER,
+                        dirty BOOLEAN,
+                        inventory BLOB,
+                        PRIMARY KEY (x, y, z)
+                    )",
+                    table_name
+                ),
+                (),
+            )
+            .map_err(sql_error)?;
+
+            let inv_bin = bincode::serialize(&inv).unwrap();
+
+            // Update the specific entry in the database
+            conn.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {} (x, y, z, dirty, inventory) VALUES (?, ?, ?, ?, ?)",
+                    table_name
+                ),
+                params![key.x, key.y, key.z, false, inv_bin],
+            )
+            .map_err(sql_error)?;
+        },
+        QueuedSqlType::InventoryInventoryUpdate(key, inv) => {
+            let inv_bin = bincode::serialize(&inv).unwrap();
+            write_player_row(&chestdb, "invs", "inventory", key, inv_bin)?;
+        },
+
+        QueuedSqlType::PlayerPositionUpdate(key, pos, pitch, yaw) => {
+
+            let playerposition = PlayerPosition{pitch: *pitch, yaw: *yaw, pos: PlayerVec{x: pos.x, y: pos.y, z: pos.z}};
+
+            let inv_bin = bincode::serialize(&playerposition).unwrap();
+            write_player_row(&chestdb, "poses", "playerposition", key, inv_bin)?;
+        },
+        QueuedSqlType::None => {},
+    }
+    Ok(())
+}
+
+/// Inserts or updates a player's row in one of the per-player tables in `chestdb`.
+fn write_player_row(chestdb: &Path, table_name: &str, column: &str, key: &Uuid, blob: Vec<u8>) -> StorageResult<()> {
+    let sql_error = |e| WorldStorageError::sql(chestdb, e);
+
+    let conn = Connection::open(chestdb).map_err(sql_error)?;
+
+    // Ensure the table exists
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                id TEXT PRIMARY KEY,
+                {} BLOB
+            )",
+            table_name, column
+        ),
+        (),
+    )
+    .map_err(sql_error)?;
+
+    // Insert or update the data
+    conn.execute(
+        &format!(
+            "INSERT INTO {0} (id, {1}) VALUES (?1, ?2)
+            ON CONFLICT(id) DO UPDATE SET {1} = excluded.{1}",
+            table_name, column
+        ),
+        (key.to_string(), blob),
+    )
+    .map_err(sql_error)?;
+    Ok(())
+}
+
+/// The inventory saved for player `id`, None if they haven't been here before.
+pub fn load_inventory(world_dir: &Path, id: &Uuid) -> StorageResult<Option<[(u32, u32); ROWLENGTH as usize]>> {
+    let table_name = "invs";
+    let chestdb = world_dir.join("chestdb");
+    let sql_error = |e| WorldStorageError::sql(&chestdb, e);
+
+    let conn = Connection::open(&chestdb).map_err(sql_error)?;
+
+    conn.execute(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            id TEXT PRIMARY KEY,
+            inventory BLOB
+        )",
+        table_name
+    ), ()).map_err(sql_error)?;
+
+    let inventory: Option<Vec<u8>> = conn
+        .query_row(&format!("SELECT inventory FROM {} WHERE id = ?1", table_name), [id.to_string()], |row| row.get(0))
+        .optional()
+        .map_err(sql_error)?;
+
+    match inventory {
+        Some(inventory) => bincode::deserialize::<[(u32, u32); ROWLENGTH as usize]>(&inventory)
+            .map(Some)
+            .map_err(|e| WorldStorageError::corrupt(&chestdb, format!("the inventory of {} won't read back: {}", id, e))),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,197 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::discovery;
+use crate::server_types::{Message, MessageType, UdpPacket};
+
+use super::{Client, ServerState};
+
+/// A client that hasn't sent a datagram for this long is back on TCP.
+pub const UDP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How often we send an empty datagram back so the client knows the channel works both ways.
+pub const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Big enough for a `UdpPacket`, which is a `Message` plus the token and sequence number.
+const DATAGRAM_SIZE: usize = 2048;
+
+/// The UDP side of one client, created when it asks for a `UdpToken`.
+pub struct UdpPeer {
+    pub token: (u64, u64),
+    /// Where its datagrams come from, unknown until the first one arrives.
+    pub addr: Option<SocketAddr>,
+    pub last_seen: Option<Instant>,
+    pub last_ack: Option<Instant>,
+    pub seq_in: u32,
+    pub seq_out: u32,
+    /// The client says, in `bo` of its TCP `PlayerUpdate`, that our datagrams are reaching it.
+    pub confirmed: bool,
+}
+
+impl UdpPeer {
+    pub fn new(token: (u64, u64)) -> UdpPeer {
+        UdpPeer {
+            token,
+            addr: None,
+            last_seen: None,
+            last_ack: None,
+            seq_in: 0,
+            seq_out: 0,
+            confirmed: false,
+        }
+    }
+
+    /// Whether transforms for this client should go over UDP right now.
+    pub fn usable(&self, now: Instant) -> bool {
+        self.confirmed
+            && self.addr.is_some()
+            && self.last_seen.is_some_and(|t| now.saturating_duration_since(t) < UDP_TIMEOUT)
+    }
+
+    /// Records a datagram from the client, `false` if it's stale and should be dropped.
+    pub fn accept(&mut self, seq: u32, from: SocketAddr, now: Instant) -> bool {
+        if self.addr.is_some() && seq <= self.seq_in {
+            return false;
+        }
+        self.seq_in = seq;
+        self.addr = Some(from);
+        self.last_seen = Some(now);
+        true
+    }
+
+    fn send(&mut self, socket: &UdpSocket, message: &Message, state: &ServerState) -> bool {
+        let addr = match self.addr {
+            Some(addr) => addr,
+            None => return false,
+        };
+        self.seq_out = self.seq_out.wrapping_add(1);
+        let packet = UdpPacket { token: (0, 0), seq: self.seq_out, message: message.clone() };
+        let bytes = packet.encode();
+        match socket.send_to(&bytes, addr) {
+            Ok(_) => {
+                state.metrics.record_out(message.message_type, bytes.len());
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Sends a player or mob transform to `client`, over UDP if its channel is up and over TCP otherwise.
+pub fn send_transform(state: &ServerState, client: &mut Client, message: &Message, tcpserial: &[u8]) {
+    let now = Instant::now();
+    if let (Some(socket), Some(peer)) = (state.udp.get(), client.udp.as_mut()) {
+        if peer.usable(now) && peer.send(socket, message, state) {
+            return;
+        }
+    }
+    let mut stream = client.stream.lock();
+    if std::io::Write::write_all(&mut *stream, tcpserial).is_ok() {
+        state.metrics.record_out(message.message_type, tcpserial.len());
+    }
+}
+
+pub fn new_token() -> (u64, u64) {
+    Uuid::new_v4().as_u64_pair()
+}
+
+/// Answers a status query if `from` hasn't sent too many lately, `true` if `datagram` was one.
+fn answer_query(socket: &UdpSocket, state: &ServerState, port: u16, datagram: &[u8], from: SocketAddr) -> bool {
+    match discovery::decode_query(datagram) {
+        Some(nonce) => {
+            if state.query_limiter.lock().allow_at(from.ip(), Instant::now()) {
+                let _ = socket.send_to(&discovery::encode_status(nonce, &state.status(port)), from);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Answers LAN discovery broadcasts on `socket` until the server stops.
+pub fn serve_discovery(socket: UdpSocket, state: ServerState, port: u16) {
+    let mut buffer = [0u8; 64];
+    while state.shouldrun.load(Ordering::Relaxed) {
+        if let Ok((n, from)) = socket.recv_from(&mut buffer) {
+            answer_query(&socket, &state, port, &buffer[..n], from);
+        }
+    }
+}
+
+/// Receives transforms until the server stops. Only `PlayerUpdate`s are taken over UDP, everything else has to come over TCP.
+/// Status queries for the server browser come in here too, `port` is the TCP port we tell them to join on.
+pub fn serve(state: ServerState, port: u16) {
+    let socket = match state.udp.get() {
+        Some(socket) => socket,
+        None => return,
+    };
+
+    let mut buffer = [0u8; DATAGRAM_SIZE];
+
+    while state.shouldrun.load(Ordering::Relaxed) {
+        let (n, from) = match socket.recv_from(&mut buffer) {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+
+        if answer_query(socket, &state, port, &buffer[..n], from) {
+            continue;
+        }
+
+        let packet = match UdpPacket::decode(&buffer[..n]) {
+            Some(p) => p,
+            None => continue,
+        };
+
+        let client_id = match state.udp_tokens.get(&packet.token) {
+            Some(id) => *id,
+            None => continue,
+        };
+
+        let now = Instant::now();
+        let mut clients = state.clients.lock();
+
+        let mut message = packet.message;
+
+        {
+            let client = match clients.get_mut(&client_id) {
+                Some(c) => c,
+                None => continue,
+            };
+            let peer = match client.udp.as_mut() {
+                Some(p) => p,
+                None => continue,
+            };
+
+            if !peer.accept(packet.seq, from, now) {
+                continue;
+            }
+
+            if !peer.last_ack.is_some_and(|t| now.saturating_duration_since(t) < ACK_INTERVAL) {
+                peer.last_ack = Some(now);
+                let ack = Message::new(MessageType::None, glam::Vec3::ZERO, 0.0, 0);
+                peer.send(socket, &ack, &state);
+            }
+
+            if message.message_type != MessageType::PlayerUpdate || !client.limiter.allow_udp_at(now) {
+                state.metrics.rejected.add(message.message_type, 1);
+                continue;
+            }
+        }
+
+        state.metrics.record_in(message.message_type, n);
+
+        message.goose = client_id.as_u64_pair();
+        state.knowncams.insert(client_id, glam::Vec3::new(message.x, message.y, message.z));
+
+        let tcpserial = bincode::serialize(&message).unwrap();
+        for (id, client) in clients.iter_mut() {
+            if *id != client_id && client.ready_for_player_messages {
+                send_transform(&state, client, &message, &tcpserial);
+            }
+        }
+    }
+}
@@ -14,6 +14,12 @@ use crate::vec;
 
 pub const MOB_BATCH_SIZE: usize = 16;
 
+/// Largest `Udm`/`ChestReg` payload a client will allocate for, whatever the header claims.
+pub const MAX_PAYLOAD_SIZE: u32 = 256 * 1024 * 1024;
+
+/// Longest chat line in bytes, anything longer gets cut.
+pub const MAX_CHAT_LEN: u32 = 256;
+
 impl Display for Message {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
@@ -47,6 +53,7 @@ pub enum MessageType {
     RequestPt,
     Pt,
     Udm,
+    /*Server to client. INFO: SEED, INFO2: GENERATOR BITS, see worldgen::NO_CAVES */
     Seed,
     PlayerUpdate,
     BlockSet,
@@ -57,13 +64,62 @@ pub enum MessageType {
     WhatsThatMob,
     ShutUpMobMsgs,
     MobUpdateBatch,
+    /*Server to client. ROT: WEATHERTYPE, INFOF: TIME OF DAY, X: DAY LENGTH, Y: 1 IF THE CLOCK IS LOCKED, INFO: SONGINDEX */
     TimeUpdate,
     TellYouMyID,
     MultiBlockSet,
     ChestReg,
     ReqChestReg,
     ChestInvUpdate,
-    Disconnect
+    Disconnect,
+    /*Client asks with info 0. Server answers with GOOSE: TOKEN, INFO: UDP PORT */
+    UdpToken,
+    /*Server to the sender only. INFO: SEQ of the BlockSet/MultiBlockSet/BulkBlockSet it threw out */
+    BlockSetRejected,
+    /*INFO: LENGTH of the UTF-8 text that follows the header. GOOSE: who said it, nil from the server */
+    Chat,
+    /*Client asks with OTHERPOS: CHEST. Server answers the sender only, BO: whether they may open it */
+    ChestOpen,
+    /*INFO: LENGTH of the packed blocks that follow the header, see bulkedit. OTHERPOS: ANCHOR they're relative to. SEQ like a BlockSet */
+    BulkBlockSet,
+    /*Server to client once it's joined, GOOSE: TOKEN. The client sends it back right after its TellYouMyID when it reconnects, to take over its old session */
+    SessionToken
+}
+
+impl MessageType {
+    /// Every type in declaration order, so `ALL[t as usize] == t`. Add new ones here too, the malformed_messages
+    /// tests fail if one is missing or out of place.
+    pub const ALL: [MessageType; 29] = [
+        MessageType::None,
+        MessageType::RequestUdm,
+        MessageType::RequestSeed,
+        MessageType::RequestPt,
+        MessageType::Pt,
+        MessageType::Udm,
+        MessageType::Seed,
+        MessageType::PlayerUpdate,
+        MessageType::BlockSet,
+        MessageType::RequestTakeoff,
+        MessageType::YourId,
+        MessageType::MobUpdate,
+        MessageType::NewMob,
+        MessageType::WhatsThatMob,
+        MessageType::ShutUpMobMsgs,
+        MessageType::MobUpdateBatch,
+        MessageType::TimeUpdate,
+        MessageType::TellYouMyID,
+        MessageType::MultiBlockSet,
+        MessageType::ChestReg,
+        MessageType::ReqChestReg,
+        MessageType::ChestInvUpdate,
+        MessageType::Disconnect,
+        MessageType::UdpToken,
+        MessageType::BlockSetRejected,
+        MessageType::Chat,
+        MessageType::ChestOpen,
+        MessageType::BulkBlockSet,
+        MessageType::SessionToken,
+    ];
 }
 
 impl Display for MessageType {
@@ -150,6 +206,24 @@ impl Display for MessageType {
             MessageType::Disconnect => {
                 write!(f, "Disconnect")
             }
+            MessageType::UdpToken => {
+                write!(f, "UdpToken")
+            }
+            MessageType::BlockSetRejected => {
+                write!(f, "BlockSetRejected")
+            }
+            MessageType::Chat => {
+                write!(f, "Chat")
+            }
+            MessageType::ChestOpen => {
+                write!(f, "ChestOpen")
+            }
+            MessageType::BulkBlockSet => {
+                write!(f, "BulkBlockSet")
+            }
+            MessageType::SessionToken => {
+                write!(f, "SessionToken")
+            }
         }
     } 
 }
@@ -161,7 +235,7 @@ impl Message {
     pub const fn from_mob_message(message: &MobMessage) -> Self {
         Self {
             message_type: message.message_type, x:message.x, y: message.y, z: message.z, rot: message.rot, info: message.info, info2: message.info2, infof: message.infof, goose: message.goose, otherpos: message.otherpos, bo: message.bo, hostile: message.hostile,
-            count: 0, msgs: [MobMessage::EMPTY; MOB_BATCH_SIZE]
+            seq: 0, count: 0, msgs: [MobMessage::EMPTY; MOB_BATCH_SIZE]
         }
     }
 
@@ -204,12 +278,22 @@ impl Message {
             otherpos: vec::IVec3::new(0,0,0),
             bo: false,
             hostile: false,
+            seq: 0,
 
             count: 0,
             msgs: [MobMessage::EMPTY; MOB_BATCH_SIZE]
         }
     }
 
+    /// Decodes one frame of untrusted bytes, `None` if it isn't a well-formed message.
+    pub fn decode(bytes: &[u8]) -> Option<Message> {
+        let message: Message = bincode::deserialize(bytes).ok()?;
+        if message.count as usize > MOB_BATCH_SIZE {
+            return None;
+        }
+        Some(message)
+    }
+
     pub fn get_serialized_size() -> usize {
         let m = Message::new(MessageType::BlockSet, Vec3::new(0.0,0.0,0.0), 0.0, 0);
         bincode::serialized_size(&m).unwrap() as usize
@@ -266,9 +350,13 @@ pub struct Message {
     pub otherpos: vec::IVec3,
     pub bo: bool,
     pub hostile: bool,
-
+    /*Client's sequence id for a predicted BlockSet/MultiBlockSet/BulkBlockSet, 0 if it didn't predict it */
+    pub seq: u32,
 
     pub count: u8,
+    /*Only a MobUpdateBatch fills these, every other message carries them empty. They're most of the 1059 byte frame,
+    but every read on both ends is that fixed size, so trimming them is a new protocol rather than a tweak. Compressing
+    a batch as a payload wouldn't help either, the header in front of it would be the same full frame. */
     pub msgs: [MobMessage; MOB_BATCH_SIZE]
 }
 
@@ -326,4 +414,25 @@ impl MobUpdateBatch {
     }
 }
 
+/// One datagram on the UDP side channel. Clients sign theirs with the token they got over TCP,
+/// the server's carry a zero token. `seq` only goes up, anything at or below the last one seen is stale.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UdpPacket {
+    pub token: (u64, u64),
+    pub seq: u32,
+    pub message: Message,
+}
+
+impl UdpPacket {
+    pub fn decode(bytes: &[u8]) -> Option<UdpPacket> {
+        let packet: UdpPacket = bincode::deserialize(bytes).ok()?;
+        if packet.message.count as usize > MOB_BATCH_SIZE {
+            return None;
+        }
+        Some(packet)
+    }
 
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+}
@@ -4,6 +4,7 @@ use once_cell::sync::Lazy;
 use uuid::Uuid;
 
 use serde;
+use crate::interpolation::InterpolationSettings;
 use tracing::info;
 
 
@@ -24,7 +25,8 @@ pub struct MiscellaneousSettingsData {
     pub keybinds: HashMap<i32, String>,
     #[serde(with = "vectorize")]
     pub mousebinds: HashMap<String, String>,
-
+    #[serde(default)]
+    pub interpolation: InterpolationSettings,
 }
 
 pub static mut MISCSETTINGS: Lazy<MiscellaneousSettingsData> = Lazy::new(|| MiscellaneousSettingsData {
@@ -39,6 +41,7 @@ pub static mut MISCSETTINGS: Lazy<MiscellaneousSettingsData> = Lazy::new(|| Misc
         (glfw::Key::D.get_scancode().unwrap(), "Right".into()),
 
         (glfw::Key::C.get_scancode().unwrap(), "Craft".into()),
+        (glfw::Key::T.get_scancode().unwrap(), "Chat".into()),
         
         (glfw::Key::Space.get_scancode().unwrap(), "Jump/Swim/Climb Up".into()),
         (glfw::Key::LeftShift.get_scancode().unwrap(), "Sprint".into()),
@@ -46,12 +49,20 @@ pub static mut MISCSETTINGS: Lazy<MiscellaneousSettingsData> = Lazy::new(|| Misc
 
         (glfw::Key::Num0.get_scancode().unwrap(), "Fov Up".into()),
         (glfw::Key::Num9.get_scancode().unwrap(), "Fov Down".into()),
+
+        (glfw::Key::G.get_scancode().unwrap(), "Next Build Tool".into()),
+        (glfw::Key::X.get_scancode().unwrap(), "Copy Selection".into()),
+        (glfw::Key::R.get_scancode().unwrap(), "Rotate Build".into()),
+        (glfw::Key::M.get_scancode().unwrap(), "Mirror Build".into()),
+        (glfw::Key::Z.get_scancode().unwrap(), "Undo".into()),
+        (glfw::Key::Y.get_scancode().unwrap(), "Redo".into()),
     ]),
     mousebinds: HashMap::from([
         ("Button2".into(), "Place/Use".into()),
         ("Button1".into(), "Break/Attack".into()),
 
-    ])
+    ]),
+    interpolation: InterpolationSettings::default(),
 } );
 
 pub fn SAVE_MISC() {
@@ -72,6 +83,21 @@ pub fn LOAD_MISC() {
         if !loaded_settings.keybinds.contains_key(&glfw::Key::B.get_scancode().unwrap()) {
             loaded_settings.keybinds.insert(glfw::Key::B.get_scancode().unwrap(), "Build Mode Toggle".into());
         }
+        if !loaded_settings.keybinds.values().any(|a| a == "Chat") {
+            loaded_settings.keybinds.entry(glfw::Key::T.get_scancode().unwrap()).or_insert("Chat".into());
+        }
+        for (key, action) in [
+            (glfw::Key::G, "Next Build Tool"),
+            (glfw::Key::X, "Copy Selection"),
+            (glfw::Key::R, "Rotate Build"),
+            (glfw::Key::M, "Mirror Build"),
+            (glfw::Key::Z, "Undo"),
+            (glfw::Key::Y, "Redo"),
+        ] {
+            if !loaded_settings.keybinds.values().any(|a| a == action) {
+                loaded_settings.keybinds.entry(key.get_scancode().unwrap()).or_insert(action.into());
+            }
+        }
         unsafe {
             *MISCSETTINGS = loaded_settings;
             SAVE_MISC();
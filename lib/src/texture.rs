@@ -11,11 +11,16 @@ pub struct Texture {
 
 impl Texture {
     pub fn new(texpath: &'static str) -> Result<Texture, String> {
-        let mut id = 0;
         let img = match image::open(texpath) {
             Ok(img) => img,
             Err(e) => return Err(format!("Failed to load texture {}", e)),
         };
+        Texture::from_image(img)
+    }
+
+    /// For images that aren't shipped with the game, like world thumbnails.
+    pub fn from_image(img: image::DynamicImage) -> Result<Texture, String> {
+        let mut id = 0;
         let (width, height) = img.dimensions();
         unsafe {
             gl::CreateTextures(gl::TEXTURE_2D, 1, &mut id);
@@ -63,6 +68,13 @@ impl Texture {
         
     }
 
+    /// Frees it on the GPU. Only for textures that come and go, most live as long as the game.
+    pub fn delete(&self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+
     pub fn update_texture(&mut self, delta_time: f32) {
         let pix = self.data.as_mut();
     
@@ -7,6 +7,8 @@
 use vox_format::data::*;
 use vox_format::types::*;
 
+use crate::vec::IVec3;
+
 pub struct JVoxModel {
     pub model: VoxModels<Model>,
     pub idnumber: i32
@@ -24,4 +26,21 @@ impl JVoxModel {
         }
     }
 
+    /// Its voxels as blocks the way build mode places them: color index is the block id, z is up,
+    /// and it's centered on x and z.
+    pub fn blocks(&self) -> Vec<(IVec3, u32)> {
+        let mut blocks = Vec::new();
+        for i in &self.model.models {
+            let size = i.size;
+            for v in &i.voxels {
+                let rearr_point = IVec3::new(
+                    v.point.x as i32 - (size.x / 2) as i32,
+                    v.point.z as i32,
+                    v.point.y as i32 - (size.y / 2) as i32,
+                );
+                blocks.push((rearr_point, v.color_index.0 as u32));
+            }
+        }
+        blocks
+    }
 }
@@ -1,15 +1,23 @@
 use crate::{
     blockinfo::Blocks,
+    discovery::ServerBrowser,
     game::{
-        Game, CROUCHING, CURRENT_AVAIL_RECIPES, DECIDEDSPORMP, MOUSEX, MOUSEY, SHOWTOOLTIP,
-        SINGLEPLAYER, TOOLTIPNAME,
+        Game, BUILD_PREVIEW_MODE, CROUCHING, CURRENT_AVAIL_RECIPES, DECIDEDSPORMP, MOUSEX, MOUSEY, SHOWTOOLTIP,
+        SINGLEPLAYER, SINGLEPLAYER_WORLD, SINGLEPLAYER_WORLD_DIR, TOOLTIPNAME,
     },
     keybinds::{AboutToRebind, ABOUTTOREBIND, LISTENINGFORREBIND},
+    network::ConnectionState,
+    planetinfo::Planets,
     recipes::{RECIPES_DISABLED, RECIPE_COOLDOWN_TIMER},
+    saves::{self, SaveSlot, Saves, SAVES_DIR},
+    server::DEFAULT_SEED,
     statics::{
         LAST_ENTERED_SERVERADDRESS, LOAD_MISC, LOAD_OR_INITIALIZE_STATICS, MISCSETTINGS, SAVE_LESA,
     },
     texture::Texture,
+    worlddir,
+    worldgen::{self, WorldOptions},
+    worldstorage::WorldStorageError,
 };
 
 use clipboard::ClipboardProvider;
@@ -19,14 +27,17 @@ use glfw::{
 };
 
 use once_cell::sync::Lazy;
+use tracing::info;
 
 use imgui::Key as ImGuiKey;
 use imgui::*;
 use imgui_opengl_renderer::Renderer;
 use parking_lot::{Mutex, RwLock};
 use std::{
+    collections::HashMap,
     f32::consts::E,
     ffi::CStr,
+    path::{Path, PathBuf},
     sync::{atomic::AtomicBool, Arc},
     thread,
     time::{Duration, Instant},
@@ -58,6 +69,21 @@ pub struct WindowAndKeyContext {
     pub serveraddress: Arc<Mutex<Option<String>>>,
 
     pub serveraddrbuffer: String,
+    pub chatbuffer: String,
+    pub serverbrowser: ServerBrowser,
+
+    pub saves: Saves,
+    pub world_list_open: bool,
+    pub world_slots: Vec<SaveSlot>,
+    pub world_thumbnails: HashMap<PathBuf, Texture>,
+    pub worldnamebuffer: String,
+    pub creating_world: bool,
+    pub new_world: WorldOptions,
+    pub renamebuffer: String,
+    pub renaming: Option<usize>,
+    pub confirm_delete: Option<usize>,
+    /// Shown instead of the world list when a world wouldn't open.
+    pub world_error: Option<WorldError>,
 
     pub logo: Texture,
     pub clipboard_context: ClipboardContext,
@@ -68,6 +94,30 @@ pub struct WindowAndKeyContext {
     pub single: SingleClient,
 }
 
+/// A world that failed its check when Play was clicked.
+pub struct WorldError {
+    pub slot: usize,
+    pub error: WorldStorageError,
+    /// The newest backup, if there is one to go back to.
+    pub backup: Option<PathBuf>,
+}
+
+/// What was clicked in the world list, done once the frame's drawn.
+enum WorldAction {
+    Play(usize),
+    /// Puts the newest backup back in place of the world that wouldn't open.
+    RestoreBackup(usize),
+    DismissError,
+    /// Opens the world creation screen.
+    NewWorld,
+    Create,
+    CancelCreate,
+    Rename(usize),
+    Duplicate(usize),
+    Delete(usize),
+    Back,
+}
+
 fn toggle_fullscreen(window_ptr: *mut glfw::ffi::GLFWwindow) {
     unsafe {
         let monitor = glfw::ffi::glfwGetWindowMonitor(window_ptr);
@@ -205,6 +255,19 @@ impl WindowAndKeyContext {
             addressentered: Arc::new(AtomicBool::new(false)),
             serveraddress: Arc::new(Mutex::new(None)),
             serveraddrbuffer: String::with_capacity(128),
+            chatbuffer: String::with_capacity(128),
+            serverbrowser: ServerBrowser::new("favorites"),
+            saves: Saves::new(SAVES_DIR),
+            world_list_open: false,
+            world_slots: Vec::new(),
+            world_thumbnails: HashMap::new(),
+            worldnamebuffer: String::with_capacity(64),
+            creating_world: false,
+            new_world: WorldOptions::default(),
+            renamebuffer: String::with_capacity(64),
+            renaming: None,
+            confirm_delete: None,
+            world_error: None,
             logo: Texture::new(concat!(
                 env!("CARGO_MANIFEST_DIR"),
                 "/../assets/Untitled3.png"
@@ -235,6 +298,110 @@ impl WindowAndKeyContext {
 
     
 
+    /// Shows the singleplayer worlds instead of the first menu's buttons.
+    pub fn open_world_list(&mut self) {
+        if let Err(e) = self.saves.import_legacy(Path::new(SINGLEPLAYER_WORLD_DIR), DEFAULT_SEED) {
+            info!("Couldn't move the old singleplayer world into the world list: {}", e);
+        }
+        self.world_list_open = true;
+        self.refresh_worlds();
+    }
+
+    pub fn refresh_worlds(&mut self) {
+        for (_, texture) in self.world_thumbnails.drain() {
+            texture.delete();
+        }
+        self.world_slots = self.saves.list();
+        for slot in self.world_slots.iter() {
+            if let Some(path) = slot.thumbnail() {
+                match image::open(&path).map_err(|e| e.to_string()).and_then(Texture::from_image) {
+                    Ok(texture) => {
+                        self.world_thumbnails.insert(slot.dir.clone(), texture);
+                    }
+                    Err(e) => info!("Couldn't load the thumbnail {}: {}", path.display(), e),
+                }
+            }
+        }
+        self.renaming = None;
+        self.confirm_delete = None;
+    }
+
+    fn do_world_action(&mut self, action: WorldAction) {
+        let slot = |i: usize| self.world_slots.get(i).cloned();
+        let result = match action {
+            WorldAction::Play(i) => {
+                if let Some(slot) = slot(i) {
+                    //Once the game's started there's no going back to this menu, so a damaged world is caught here
+                    let checked = worlddir::check(&slot.dir).and_then(|_| match slot.manifest.format_version {
+                        version if version > worlddir::FORMAT_VERSION => Err(WorldStorageError::NewerFormat { path: slot.dir.clone(), version }),
+                        _ => Ok(()),
+                    });
+                    if let Err(error) = checked {
+                        info!("Couldn't open the world in {}: {}", slot.dir.display(), error);
+                        let backup = worlddir::backups(&slot.dir).into_iter().next();
+                        self.world_error = Some(WorldError { slot: i, error, backup });
+                        return;
+                    }
+                    *SINGLEPLAYER_WORLD.lock() = Some(slot.dir);
+                    unsafe {
+                        SINGLEPLAYER = true;
+                        DECIDEDSPORMP = true;
+                    }
+                }
+                return;
+            }
+            WorldAction::RestoreBackup(i) => {
+                let backup = self.world_error.take().and_then(|e| e.backup);
+                match (slot(i), backup) {
+                    (Some(slot), Some(backup)) => worlddir::restore(&slot.dir, &backup).map(|_| ()).map_err(Into::into),
+                    _ => Ok(()),
+                }
+            }
+            WorldAction::DismissError => {
+                self.world_error = None;
+                return;
+            }
+            WorldAction::NewWorld => {
+                self.worldnamebuffer.clear();
+                self.new_world = WorldOptions::default();
+                self.creating_world = true;
+                return;
+            }
+            WorldAction::Create => {
+                let name = match self.worldnamebuffer.trim() {
+                    "" => format!("World {}", self.world_slots.len() + 1),
+                    name => name.to_string(),
+                };
+                self.creating_world = false;
+                self.saves.create(&name, &self.new_world).map(|_| ())
+            }
+            WorldAction::CancelCreate => {
+                self.creating_world = false;
+                return;
+            }
+            WorldAction::Rename(i) => match slot(i) {
+                Some(slot) if !self.renamebuffer.trim().is_empty() => self.saves.rename(&slot, &self.renamebuffer).map(|_| ()),
+                _ => Ok(()),
+            },
+            WorldAction::Duplicate(i) => match slot(i) {
+                Some(slot) => self.saves.duplicate(&slot, &format!("{} copy", slot.name())).map(|_| ()),
+                None => Ok(()),
+            },
+            WorldAction::Delete(i) => match slot(i) {
+                Some(slot) => self.saves.delete(&slot),
+                None => Ok(()),
+            },
+            WorldAction::Back => {
+                self.world_list_open = false;
+                return;
+            }
+        };
+        if let Err(e) = result {
+            info!("World list: {}", e);
+        }
+        self.refresh_worlds();
+    }
+
     pub fn run(&mut self) {
         #[cfg(feature = "glfw")]
         self.glfw.poll_events();
@@ -281,6 +448,207 @@ impl WindowAndKeyContext {
                         (height as f32 / 2.0 - (window_size.1 / 2.0)) + 75.0,
                     ];
 
+                    let mut world_action: Option<WorldAction> = None;
+                    let mut open_world_list = false;
+
+                    if let Some(world_error) = &self.world_error {
+                        let now = worlddir::now();
+                        let name = self.world_slots.get(world_error.slot).map(|s| s.name()).unwrap_or("the world");
+
+                        ui.window("Transparent Window")
+                            .size([window_size.0, window_size.1], Condition::Always)
+                            .position(window_pos, Condition::Always)
+                            .flags(window_flags)
+                            .build(|| {
+                                let button_width = 500.0;
+                                let button_height = 20.0;
+                                let pos_x = (ui.window_size()[0] - button_width) / 2.0;
+
+                                ui.set_cursor_pos([pos_x, 60.0]);
+                                ui.text(format!("Couldn't open {}", name));
+                                ui.set_cursor_pos([pos_x, 90.0]);
+                                {
+                                    let _wrap = ui.push_text_wrap_pos_with_pos(pos_x + button_width);
+                                    ui.text_disabled(world_error.error.to_string());
+                                }
+
+                                let mut top = 170.0;
+                                if world_error.error.is_corrupt() {
+                                    ui.set_cursor_pos([pos_x, top]);
+                                    match &world_error.backup {
+                                        Some(backup) => {
+                                            let made = saves::played_ago(worlddir::backup_time(backup), now);
+                                            if ui.button_with_size(format!("Restore the backup from {}", made), [button_width, button_height]) {
+                                                world_action = Some(WorldAction::RestoreBackup(world_error.slot));
+                                            }
+                                            ui.set_cursor_pos([pos_x, top + 25.0]);
+                                            ui.text_disabled("What's there now is kept in the world's backups folder.");
+                                        }
+                                        None => ui.text("There are no backups of this world to restore."),
+                                    }
+                                    top += 60.0;
+                                }
+
+                                ui.set_cursor_pos([pos_x, top]);
+                                if ui.button_with_size("Back to the world list", [button_width, button_height]) {
+                                    world_action = Some(WorldAction::DismissError);
+                                }
+                            });
+                    } else if self.creating_world {
+                        ui.window("Transparent Window")
+                            .size([window_size.0, window_size.1], Condition::Always)
+                            .position(window_pos, Condition::Always)
+                            .flags(window_flags)
+                            .build(|| {
+                                let button_width = 500.0;
+                                let button_height = 20.0;
+                                let pos_x = (ui.window_size()[0] - button_width) / 2.0;
+                                let options = &mut self.new_world;
+
+                                ui.set_cursor_pos([pos_x, 60.0]);
+                                ui.text("Create a new world");
+
+                                ui.set_cursor_pos([pos_x, 95.0]);
+                                ui.text("Name");
+                                ui.set_cursor_pos([pos_x, 115.0]);
+                                ui.set_next_item_width(button_width);
+                                ui.input_text("##worldname", &mut self.worldnamebuffer)
+                                    .hint("World name")
+                                    .build();
+
+                                ui.set_cursor_pos([pos_x, 150.0]);
+                                ui.text("Seed");
+                                ui.set_cursor_pos([pos_x, 170.0]);
+                                ui.set_next_item_width(button_width);
+                                ui.input_text("##worldseed", &mut options.seed_text)
+                                    .hint("Anything, or leave it blank for a random one")
+                                    .build();
+                                ui.set_cursor_pos([pos_x, 195.0]);
+                                match worldgen::seed_from_text(&options.seed_text) {
+                                    Some(seed) => ui.text_disabled(format!("Seed number {}", seed)),
+                                    None => ui.text_disabled("Random seed"),
+                                }
+
+                                ui.set_cursor_pos([pos_x, 230.0]);
+                                ui.text("Planet");
+                                for planet in 0..Planets::COUNT {
+                                    ui.set_cursor_pos([pos_x + planet as f32 * 150.0, 250.0]);
+                                    ui.radio_button(Planets::get_name(planet), &mut options.planet_type, planet as u8);
+                                }
+
+                                ui.set_cursor_pos([pos_x, 290.0]);
+                                ui.text("Generate");
+                                ui.set_cursor_pos([pos_x, 310.0]);
+                                ui.checkbox("Caves", &mut options.generator.caves);
+                                ui.set_cursor_pos([pos_x + 150.0, 310.0]);
+                                ui.checkbox("Trees and rocks", &mut options.generator.features);
+                                ui.set_cursor_pos([pos_x + 330.0, 310.0]);
+                                ui.checkbox("Ores", &mut options.generator.ores);
+
+                                ui.set_cursor_pos([pos_x, 370.0]);
+                                if ui.button_with_size("Create World", [button_width / 2.0 - 5.0, button_height]) {
+                                    world_action = Some(WorldAction::Create);
+                                }
+                                ui.set_cursor_pos([pos_x + button_width / 2.0 + 5.0, 370.0]);
+                                if ui.button_with_size("Cancel", [button_width / 2.0 - 5.0, button_height]) {
+                                    world_action = Some(WorldAction::CancelCreate);
+                                }
+                            });
+                    } else if self.world_list_open {
+                        let now = worlddir::now();
+                        let slots = &self.world_slots;
+                        let thumbnails = &self.world_thumbnails;
+
+                        ui.window("Transparent Window")
+                            .size([window_size.0, window_size.1], Condition::Always)
+                            .position(window_pos, Condition::Always)
+                            .flags(window_flags)
+                            .build(|| {
+                                let button_width = 600.0;
+                                let button_height = 20.0;
+                                let pos_x = (ui.window_size()[0] - button_width) / 2.0;
+
+                                ui.set_cursor_pos([pos_x, 20.0]);
+                                ui.text("Worlds");
+
+                                ui.set_cursor_pos([pos_x, 45.0]);
+                                if ui.button_with_size("Create a new world", [button_width, button_height]) {
+                                    world_action = Some(WorldAction::NewWorld);
+                                }
+
+                                ui.set_cursor_pos([pos_x, 80.0]);
+                                ui.child_window("##worlds").size([button_width, 520.0]).build(|| {
+                                    if slots.is_empty() {
+                                        ui.text_disabled("No worlds yet, create one above.");
+                                    }
+
+                                    for (i, slot) in slots.iter().enumerate() {
+                                        let _id = ui.push_id_usize(i);
+                                        let top = i as f32 * 80.0;
+
+                                        ui.set_cursor_pos([0.0, top]);
+                                        match thumbnails.get(&slot.dir) {
+                                            Some(texture) => {
+                                                let texture_id = imgui::TextureId::from(texture.id as usize);
+                                                imgui::Image::new(texture_id, [112.0, 63.0]).build(&ui);
+                                            }
+                                            None => ui.text_disabled("No picture yet"),
+                                        }
+
+                                        ui.set_cursor_pos([125.0, top]);
+                                        if self.renaming == Some(i) {
+                                            ui.set_next_item_width(250.0);
+                                            ui.input_text("##rename", &mut self.renamebuffer).build();
+                                            ui.same_line();
+                                            if ui.button("Done") {
+                                                world_action = Some(WorldAction::Rename(i));
+                                            }
+                                        } else {
+                                            ui.text(slot.name());
+                                        }
+
+                                        ui.set_cursor_pos([125.0, top + 20.0]);
+                                        ui.text_disabled(format!(
+                                            "Played {}  {}  Seed {}",
+                                            saves::played_ago(slot.manifest.last_played, now),
+                                            Planets::get_name(slot.manifest.planet_type as u32),
+                                            slot.manifest.seed
+                                        ));
+
+                                        ui.set_cursor_pos([125.0, top + 42.0]);
+                                        if ui.button_with_size("Play", [80.0, button_height]) {
+                                            world_action = Some(WorldAction::Play(i));
+                                        }
+                                        ui.same_line();
+                                        if ui.button_with_size("Rename", [80.0, button_height]) {
+                                            self.renaming = Some(i);
+                                            self.renamebuffer = slot.name().to_string();
+                                        }
+                                        ui.same_line();
+                                        if ui.button_with_size("Duplicate", [80.0, button_height]) {
+                                            world_action = Some(WorldAction::Duplicate(i));
+                                        }
+                                        ui.same_line();
+                                        if self.confirm_delete == Some(i) {
+                                            if ui.button_with_size("Really delete", [100.0, button_height]) {
+                                                world_action = Some(WorldAction::Delete(i));
+                                            }
+                                            ui.same_line();
+                                            if ui.button_with_size("Keep", [60.0, button_height]) {
+                                                self.confirm_delete = None;
+                                            }
+                                        } else if ui.button_with_size("Delete", [80.0, button_height]) {
+                                            self.confirm_delete = Some(i);
+                                        }
+                                    }
+                                });
+
+                                ui.set_cursor_pos([pos_x, 615.0]);
+                                if ui.button_with_size("Back", [button_width, button_height]) {
+                                    world_action = Some(WorldAction::Back);
+                                }
+                            });
+                    } else {
                     ui.window("Transparent Window")
                         .size([window_size.0, window_size.1], Condition::Always)
                         .position(window_pos, Condition::Always)
@@ -322,10 +690,7 @@ impl WindowAndKeyContext {
                             ui.set_cursor_pos([pos_x, pos_y - 25.0]);
 
                             if ui.button_with_size("Singleplayer", [button_width, button_height]) {
-                                unsafe {
-                                    SINGLEPLAYER = true;
-                                    DECIDEDSPORMP = true;
-                                }
+                                open_world_list = true;
                             }
 
                             ui.set_cursor_pos([pos_x, pos_y]);
@@ -337,10 +702,18 @@ impl WindowAndKeyContext {
                                 }
                             }
                         });
+                    }
 
                     // Render the ImGui frame
                     self.guirenderer.render(&mut self.imgui);
 
+                    if open_world_list {
+                        self.open_world_list();
+                    }
+                    if let Some(action) = world_action {
+                        self.do_world_action(action);
+                    }
+
                     let io = self.imgui.io_mut();
                     for (_, event) in glfw::flush_messages(&self.events) {
                         match event {
@@ -432,6 +805,8 @@ impl WindowAndKeyContext {
 
                             let gcraftopen = g.crafting_open;
 
+                            let gchatopen = g.chat_open;
+
                             #[cfg(feature = "glfw")]
                             let gchestopen = g.hud.chest_open;
 
@@ -507,6 +882,89 @@ impl WindowAndKeyContext {
                                     self.guirenderer.render(&mut self.imgui);
                                 }
 
+                                if g.vars.in_multiplayer && g.netconn.connection_state() == ConnectionState::Reconnecting {
+                                    let attempts = g.netconn.reconnect_attempts.load(std::sync::atomic::Ordering::Relaxed);
+
+                                    let (width, _height) = self.window.read().get_framebuffer_size();
+                                    let ui = self.imgui.frame();
+
+                                    ui.window("Reconnecting")
+                                        .size([260.0, 50.0], Condition::Always)
+                                        .position([width as f32 / 2.0 - 130.0, 20.0], Condition::Always)
+                                        .flags(WindowFlags::NO_DECORATION | WindowFlags::NO_MOVE | WindowFlags::NO_INPUTS)
+                                        .build(|| {
+                                            if attempts == 0 {
+                                                ui.text("Lost the server, reconnecting...");
+                                            } else {
+                                                ui.text(format!("Reconnecting... (attempt {})", attempts + 1));
+                                            }
+                                        });
+
+                                    self.guirenderer.render(&mut self.imgui);
+                                }
+
+                                if unsafe { BUILD_PREVIEW_MODE } && !gmenuopen {
+                                    let lines = g.build_tools.overlay_lines(&g.inventory.read().inv);
+                                    let (width, _height) = self.window.read().get_framebuffer_size();
+                                    let ui = self.imgui.frame();
+
+                                    ui.window("Build")
+                                        .size([300.0, 30.0 + 18.0 * lines.len() as f32], Condition::Always)
+                                        .position([width as f32 - 310.0, 10.0], Condition::Always)
+                                        .flags(WindowFlags::NO_DECORATION | WindowFlags::NO_MOVE | WindowFlags::NO_INPUTS)
+                                        .build(|| {
+                                            for (line, short) in &lines {
+                                                if *short {
+                                                    ui.text_colored([1.0, 0.3, 0.3, 1.0], line);
+                                                } else {
+                                                    ui.text(line);
+                                                }
+                                            }
+                                        });
+
+                                    self.guirenderer.render(&mut self.imgui);
+                                }
+
+                                //Recent lines fade out, all of them show while typing
+                                let chatlines: Vec<String> = {
+                                    let log = g.netconn.chat.lock();
+                                    if g.chat_open {
+                                        log.lines.iter().map(|l| l.label()).collect()
+                                    } else {
+                                        log.recent().map(|l| l.label()).collect()
+                                    }
+                                };
+
+                                if g.chat_open || !chatlines.is_empty() {
+                                    let chatopen = g.chat_open;
+                                    let (_width, height) = self.window.read().get_framebuffer_size();
+                                    let ui = self.imgui.frame();
+
+                                    let flags = if chatopen {
+                                        WindowFlags::NO_DECORATION | WindowFlags::NO_MOVE
+                                    } else {
+                                        WindowFlags::NO_DECORATION | WindowFlags::NO_MOVE | WindowFlags::NO_INPUTS | WindowFlags::NO_BACKGROUND
+                                    };
+
+                                    let chatbuffer = &mut self.chatbuffer;
+                                    ui.window("Chat")
+                                        .size([500.0, 240.0], Condition::Always)
+                                        .position([10.0, height as f32 - 320.0], Condition::Always)
+                                        .flags(flags)
+                                        .build(|| {
+                                            for line in &chatlines {
+                                                ui.text_wrapped(line);
+                                            }
+                                            if chatopen {
+                                                ui.set_scroll_here_y_with_ratio(1.0);
+                                                ui.set_keyboard_focus_here();
+                                                ui.input_text("##chatinput", chatbuffer).build();
+                                            }
+                                        });
+
+                                    self.guirenderer.render(&mut self.imgui);
+                                }
+
                                 if gmenuopen {
                                     let gamecurrentbuttons = g.currentbuttons.clone();
 
@@ -933,6 +1391,33 @@ impl WindowAndKeyContext {
                                                 io.mouse_pos = [xpos as f32, ypos as f32];
                                             }
                                         }
+                                        glfw::WindowEvent::Char(char) if gchatopen => {
+                                            io.add_input_character(char);
+                                        }
+                                        glfw::WindowEvent::Key(
+                                            key,
+                                            _scancode,
+                                            action,
+                                            _modifiers,
+                                        ) if gchatopen => {
+                                            let pressed = action == glfw::Action::Press
+                                                || action == glfw::Action::Repeat;
+                                            if (key as i32) >= 0 && (key as usize) < io.keys_down.len() {
+                                                io.keys_down[key as usize] = pressed;
+                                            }
+                                            if pressed && key == Key::Enter {
+                                                let text = std::mem::take(&mut self.chatbuffer);
+                                                if !text.trim().is_empty() {
+                                                    g.netconn.send_chat(text.trim());
+                                                }
+                                                #[cfg(feature = "glfw")]
+                                                g.close_chat();
+                                            } else if pressed && key == Key::Escape {
+                                                self.chatbuffer.clear();
+                                                #[cfg(feature = "glfw")]
+                                                g.close_chat();
+                                            }
+                                        }
                                         glfw::WindowEvent::Key(
                                             key,
                                             scancode,
@@ -1104,6 +1589,10 @@ impl WindowAndKeyContext {
                             height as f32 / 2.0 - (window_size.1 / 2.0),
                         ];
 
+                        self.serverbrowser.tick();
+                        let entries = self.serverbrowser.entries();
+                        let mut join: Option<String> = None;
+
                         ui.window("Transparent Window")
                             .size([window_size.0, window_size.1], Condition::Always)
                             .position(window_pos, Condition::Always)
@@ -1114,10 +1603,9 @@ impl WindowAndKeyContext {
                                 let window_size = ui.window_size();
 
                                 let available_width = window_size[0];
-                                let available_height = window_size[1];
 
                                 let pos_x = (available_width - button_width) / 2.0;
-                                let mut pos_y = (available_height - (button_height) - 10.0) / 2.0;
+                                let mut pos_y = 60.0;
 
                                 ui.set_cursor_pos([pos_x, pos_y]);
 
@@ -1141,23 +1629,102 @@ impl WindowAndKeyContext {
 
                                 ui.set_cursor_pos([pos_x, pos_y + 50.0]);
 
-                                if ui.button_with_size("Connect", [button_width, button_height]) {
-                                    unsafe {
-                                        SINGLEPLAYER = false;
-                                        DECIDEDSPORMP = true;
+                                if ui.button_with_size("Connect", [button_width / 2.0 - 5.0, button_height]) {
+                                    join = Some(self.serveraddrbuffer.clone());
+                                }
+
+                                ui.set_cursor_pos([pos_x + button_width / 2.0 + 5.0, pos_y + 50.0]);
+
+                                if ui.button_with_size("Save to favorites", [button_width / 2.0 - 5.0, button_height]) {
+                                    let address = self.serveraddrbuffer.trim().to_string();
+                                    self.serverbrowser.add_favorite("", address);
+                                }
+
+                                pos_y += 100.0;
+
+                                ui.set_cursor_pos([pos_x, pos_y]);
+                                ui.text(if self.serverbrowser.is_refreshing() { "Servers (refreshing...)" } else { "Servers" });
+
+                                ui.set_cursor_pos([pos_x + button_width - 80.0, pos_y - 3.0]);
+                                if ui.button_with_size("Refresh", [80.0, button_height]) {
+                                    self.serverbrowser.refresh();
+                                }
+
+                                pos_y += 25.0;
+
+                                if entries.is_empty() {
+                                    ui.set_cursor_pos([pos_x, pos_y]);
+                                    ui.text_disabled("No favorites yet and nothing found on the LAN.");
+                                }
+
+                                let mut unfavorite: Option<String> = None;
+                                let mut favorite: Option<(String, String)> = None;
+
+                                for (i, entry) in entries.iter().enumerate() {
+                                    let _id = ui.push_id_usize(i);
+
+                                    ui.set_cursor_pos([pos_x, pos_y]);
+                                    let star = if entry.favorite.is_some() { "* " } else { "" };
+                                    ui.text(format!("{}{}", star, entry.label()));
+
+                                    ui.set_cursor_pos([pos_x + 250.0, pos_y]);
+                                    match (&entry.status, entry.ping) {
+                                        (Some(status), Some(ping)) => {
+                                            ui.text(format!("{} online  {}ms  v{}", status.players, ping.as_millis(), status.version));
+                                        }
+                                        _ => {
+                                            ui.text_disabled("not responding");
+                                        }
                                     }
-                                    unsafe {
-                                        *LAST_ENTERED_SERVERADDRESS = self.serveraddrbuffer.clone();
+
+                                    ui.set_cursor_pos([pos_x, pos_y + 18.0]);
+                                    match &entry.status {
+                                        Some(status) if !status.motd.is_empty() => ui.text_disabled(format!("{}  {}", entry.address, status.motd)),
+                                        _ => ui.text_disabled(&entry.address),
+                                    }
+
+                                    ui.set_cursor_pos([pos_x + button_width - 170.0, pos_y + 2.0]);
+                                    if ui.button_with_size("Join", [80.0, button_height]) {
+                                        join = Some(entry.address.clone());
                                     }
-                                    SAVE_LESA();
-                                    *(self.serveraddress.lock()) =
-                                        Some(self.serveraddrbuffer.clone());
-                                    self.addressentered
-                                        .store(true, std::sync::atomic::Ordering::Relaxed);
+
+                                    ui.set_cursor_pos([pos_x + button_width - 80.0, pos_y + 2.0]);
+                                    if entry.favorite.is_some() {
+                                        if ui.button_with_size("Remove", [80.0, button_height]) {
+                                            unfavorite = Some(entry.address.clone());
+                                        }
+                                    } else if ui.button_with_size("Save", [80.0, button_height]) {
+                                        let name = entry.status.as_ref().map(|s| s.name.clone()).unwrap_or_default();
+                                        favorite = Some((name, entry.address.clone()));
+                                    }
+
+                                    pos_y += 45.0;
+                                }
+
+                                if let Some(address) = unfavorite {
+                                    self.serverbrowser.remove_favorite(&address);
+                                }
+                                if let Some((name, address)) = favorite {
+                                    self.serverbrowser.add_favorite(name, address);
                                 }
-                                pos_y += button_height + 10.0; // Add some spacing between buttons
                             });
 
+                        if let Some(address) = join {
+                            self.serveraddrbuffer = address;
+                            unsafe {
+                                SINGLEPLAYER = false;
+                                DECIDEDSPORMP = true;
+                            }
+                            unsafe {
+                                *LAST_ENTERED_SERVERADDRESS = self.serveraddrbuffer.clone();
+                            }
+                            SAVE_LESA();
+                            *(self.serveraddress.lock()) =
+                                Some(self.serveraddrbuffer.clone());
+                            self.addressentered
+                                .store(true, std::sync::atomic::Ordering::Relaxed);
+                        }
+
                         // Render the ImGui frame
                         self.guirenderer.render(&mut self.imgui);
 
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use voxelland::blockinfo::Blocks;
+use voxelland::buildtools::{self, BuildTool, BuildTools, Selection, BATCH_SIZE, MAX_TOOL_VOLUME};
+use voxelland::edithistory::HistoryEntry;
+use voxelland::specialblocks::door::DOORTOP_BITS;
+use voxelland::vec::IVec3;
+
+/// A little world: stone below y = 0, air above, and whatever's been put in `blocks`.
+fn world(blocks: &HashMap<IVec3, u32>) -> impl Fn(IVec3) -> u32 + '_ {
+    move |spot| blocks.get(&spot).copied().unwrap_or(if spot.y < 0 { 5 } else { 0 })
+}
+
+fn facing(id: u32, direction: u32) -> u32 {
+    let mut block = id;
+    Blocks::set_direction_bits(&mut block, direction);
+    block
+}
+
+fn selected(tools: &mut BuildTools, a: IVec3, b: IVec3) {
+    tools.mark(a);
+    tools.mark(b);
+}
+
+#[test]
+fn selections_take_two_corners() {
+    let mut selection = Selection::default();
+    selection.mark(IVec3::new(5, 1, -2));
+    assert_eq!(selection.bounds(), None);
+    selection.mark(IVec3::new(2, 3, 0));
+    assert_eq!(selection.bounds(), Some((IVec3::new(2, 1, -2), IVec3::new(5, 3, 0))));
+    assert_eq!(selection.size(), Some(IVec3::new(4, 3, 3)));
+    assert_eq!(selection.volume(), 36);
+
+    //A third click starts over
+    selection.mark(IVec3::new(9, 9, 9));
+    assert_eq!((selection.first, selection.second), (Some(IVec3::new(9, 9, 9)), None));
+}
+
+#[test]
+fn brushes_are_hollow() {
+    let (low, high) = (IVec3::new(0, 0, 0), IVec3::new(4, 4, 4));
+    assert_eq!(buildtools::cuboid(low, high).len(), 125);
+    assert_eq!(buildtools::hollow_box(low, high).len(), 125 - 27);
+
+    let sphere = buildtools::hollow_sphere(low, high);
+    assert!(sphere.contains(&IVec3::new(2, 0, 2)) && sphere.contains(&IVec3::new(2, 4, 2)));
+    assert!(!sphere.contains(&IVec3::new(2, 2, 2)));
+    assert!(!sphere.contains(&IVec3::new(0, 0, 0)));
+    //The same from every side
+    for spot in &sphere {
+        assert!(sphere.contains(&IVec3::new(4 - spot.x, spot.y, spot.z)));
+        assert!(sphere.contains(&IVec3::new(spot.z, spot.x, spot.y)));
+    }
+
+    //Open at both ends, the same ring all the way up
+    let cylinder = buildtools::hollow_cylinder(low, IVec3::new(4, 2, 4));
+    assert!(!cylinder.contains(&IVec3::new(2, 0, 2)));
+    assert!(cylinder.contains(&IVec3::new(0, 0, 2)) && cylinder.contains(&IVec3::new(2, 2, 4)));
+    assert!(!cylinder.contains(&IVec3::new(0, 0, 0)));
+    let ring = cylinder.iter().filter(|s| s.y == 0).count();
+    assert_eq!(cylinder.len(), ring * 3);
+}
+
+#[test]
+fn fill_and_replace_only_change_what_differs() {
+    let mut blocks = HashMap::new();
+    blocks.insert(IVec3::new(1, 0, 1), 3);
+    let block_at = world(&blocks);
+    let mut tools = BuildTools::default();
+    selected(&mut tools, IVec3::new(0, -1, 0), IVec3::new(2, 0, 2));
+
+    tools.tool = BuildTool::Fill;
+    let (origin, edits) = tools.edits(IVec3::new(9, 9, 9), IVec3::new(0, 0, 0), 5, &[], &block_at).unwrap();
+    assert_eq!(origin, IVec3::new(0, -1, 0));
+    //The bottom layer is already stone
+    assert_eq!(edits.len(), 9);
+    assert!(edits.iter().all(|(spot, block)| spot.y == 1 && *block == 5));
+
+    //Grass to sand, looking at the grass
+    tools.tool = BuildTool::Replace;
+    let (origin, edits) = tools.edits(IVec3::new(1, 0, 1), IVec3::new(0, 0, 0), 1, &[], &block_at).unwrap();
+    assert_eq!(edits.iter().map(|(s, b)| (origin + *s, *b)).collect::<Vec<_>>(), vec![(IVec3::new(1, 0, 1), 1)]);
+
+    //Empty handed clears
+    tools.tool = BuildTool::Fill;
+    let (_, edits) = tools.edits(IVec3::new(1, 0, 1), IVec3::new(0, 0, 0), 0, &[], &block_at).unwrap();
+    assert_eq!(edits.len(), 10);
+
+    assert!(tools.edits(IVec3::new(0, 0, 0), IVec3::new(0, 0, 0), 32, &[], &block_at).is_err());
+    selected(&mut tools, IVec3::new(0, 0, 0), IVec3::new(64, 64, 64));
+    assert!(tools.selection.volume() > MAX_TOOL_VOLUME);
+    assert!(tools.edits(IVec3::new(0, 0, 0), IVec3::new(0, 0, 0), 5, &[], &block_at).is_err());
+}
+
+#[test]
+fn pastes_turn_and_flip() {
+    let mut blocks = HashMap::new();
+    blocks.insert(IVec3::new(10, 0, 10), 6);
+    blocks.insert(IVec3::new(11, 0, 10), facing(21, 1));
+    let mut tools = BuildTools::default();
+    tools.tool = BuildTool::Paste;
+    assert!(tools.edits(IVec3::new(0, 0, 0), IVec3::new(0, 0, 0), 0, &[], &world(&blocks)).is_err());
+
+    selected(&mut tools, IVec3::new(10, 0, 10), IVec3::new(11, 0, 10));
+    assert_eq!(tools.copy(world(&blocks)).unwrap(), 2);
+
+    //On top of what's looked at, nudged by the arrow keys
+    let empty = HashMap::new();
+    let (origin, edits) = tools.edits(IVec3::new(0, -1, 0), IVec3::new(3, 0, 0), 0, &[], &world(&empty)).unwrap();
+    assert_eq!(origin, IVec3::new(3, 0, 0));
+    assert_eq!(edits, vec![(IVec3::new(0, 0, 0), 6), (IVec3::new(1, 0, 0), facing(21, 1))]);
+
+    tools.mirror();
+    let (_, edits) = tools.edits(IVec3::new(0, -1, 0), IVec3::new(0, 0, 0), 0, &[], &world(&empty)).unwrap();
+    assert_eq!(edits, vec![(IVec3::new(1, 0, 0), 6), (IVec3::new(0, 0, 0), facing(21, 3))]);
+
+    tools.mirror();
+    tools.rotate();
+    let (_, edits) = tools.edits(IVec3::new(0, -1, 0), IVec3::new(0, 0, 0), 0, &[], &world(&empty)).unwrap();
+    assert_eq!(edits, vec![(IVec3::new(0, 0, 0), 6), (IVec3::new(0, 0, 1), facing(21, 2))]);
+}
+
+#[test]
+fn building_costs_what_it_places() {
+    let door = facing(19, 2);
+    let edits = vec![
+        (IVec3::new(0, 0, 0), 5),
+        (IVec3::new(1, 0, 0), 5),
+        (IVec3::new(2, 0, 0), door),
+        (IVec3::new(2, 1, 0), door | DOORTOP_BITS),
+        (IVec3::new(3, 0, 0), 0),
+    ];
+    let cost = buildtools::material_cost(&edits);
+    assert_eq!(cost, vec![(5, 2), (19, 1)]);
+
+    let mut inventory = [(5, 1), (19, 4), (0, 0), (5, 1), (0, 0), (0, 0), (0, 0), (0, 0)];
+    assert!(buildtools::shortfall(&cost, &inventory).is_empty());
+    assert_eq!(buildtools::pay(&mut inventory, &cost), Some(vec![0, 1, 3]));
+    assert_eq!(inventory[..4], [(0, 0), (19, 3), (0, 0), (0, 0)]);
+
+    //Not enough left, nothing's taken
+    assert_eq!(buildtools::shortfall(&cost, &inventory), vec![(5, 2, 0)]);
+    assert_eq!(buildtools::pay(&mut inventory, &cost), None);
+    assert_eq!(inventory[1], (19, 3));
+
+    let mut tools = BuildTools::default();
+    tools.set_preview(IVec3::new(0, 0, 0), edits);
+    let lines = tools.overlay_lines(&inventory);
+    assert!(lines.contains(&(String::from("  2 Cobblestone (have 0)"), true)), "{:?}", lines);
+    assert!(lines.contains(&(String::from("  1 Door (have 3)"), false)), "{:?}", lines);
+}
+
+#[test]
+fn edits_go_out_a_few_messages_at_a_time() {
+    let mut tools = BuildTools::default();
+    //More than one message holds, and one too far from the rest to share one
+    let mut edits: Vec<(IVec3, u32)> = (0..BATCH_SIZE as i32 + 10).map(|i| (IVec3::new(i % 100, i / 100, 0), 5)).collect();
+    edits.push((IVec3::new(40000, 0, 0), 5));
+    tools.queue(edits.iter().map(|(spot, block)| (*spot, 0, *block)).collect(), Some(HistoryEntry::Recorded(3)));
+
+    //A quarter second is one message's worth
+    let mut sent = tools.next_batches(0.25);
+    assert_eq!(sent.iter().map(|b| b.edits.len()).collect::<Vec<_>>(), vec![BATCH_SIZE]);
+    //Each with what it cost, to give back if the server turns it down
+    assert_eq!(sent[0].cost, vec![(5, BATCH_SIZE as u32)]);
+    assert_eq!(sent[0].history, Some(HistoryEntry::Recorded(3)));
+    assert!(sent[0].refund.is_empty());
+    //Coming back after a long while doesn't send everything at once
+    assert!(tools.next_batches(0.0).is_empty());
+    let rest = tools.next_batches(10.0);
+    assert_eq!(rest.iter().map(|b| b.edits.len()).collect::<Vec<_>>(), vec![10, 1]);
+    assert_eq!(rest[1].cost, vec![(5, 1)]);
+    sent.extend(rest);
+
+    assert_eq!(tools.pending(), 0);
+    assert_eq!(sent.into_iter().flat_map(|b| b.edits).collect::<Vec<_>>(), edits);
+    assert!(tools.next_batches(1.0).is_empty());
+}
+
+#[test]
+fn undo_batches_carry_their_share_of_the_trade() {
+    let mut tools = BuildTools::default();
+    //Undoing a stone wall built over dirt
+    let blocks: Vec<(IVec3, u32, u32)> = (0..BATCH_SIZE as i32 + 2).map(|x| (IVec3::new(x, 0, 0), 5, 3)).collect();
+    tools.queue(blocks, Some(HistoryEntry::Undid(7)));
+
+    let batches = tools.next_batches(10.0);
+    assert_eq!(batches.len(), 2);
+    assert_eq!((batches[0].cost.clone(), batches[0].refund.clone()), (vec![(3, BATCH_SIZE as u32)], vec![(5, BATCH_SIZE as u32)]));
+    assert_eq!((batches[1].cost.clone(), batches[1].refund.clone()), (vec![(3, 2)], vec![(5, 2)]));
+    assert!(batches.iter().all(|b| b.history == Some(HistoryEntry::Undid(7))));
+}
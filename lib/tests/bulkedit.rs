@@ -0,0 +1,84 @@
+use voxelland::bulkedit::{self, MAX_BULK_BLOCKS};
+use voxelland::server_types::{Message, MessageType};
+use voxelland::vec::{IVec2, IVec3};
+
+#[test]
+fn blocks_pack_relative_to_the_anchor() {
+    let anchor = IVec3::new(-1000, 60, 5000);
+    let blocks = vec![(anchor, 5), (IVec3::new(-1010, 40, 5001), 19 | 0b1 << 30), (IVec3::new(31767, 60, 5000), 1)];
+    let payload = bulkedit::pack(anchor, &blocks).unwrap();
+    assert_eq!(bulkedit::unpack(anchor, &payload), Some(blocks.clone()));
+    //Same offsets, somewhere else
+    let moved = bulkedit::unpack(IVec3::new(0, 0, 0), &payload).unwrap();
+    assert_eq!(moved[1].0, IVec3::new(-10, -20, 1));
+
+    //A big flat fill comes out well under half of sending whole coordinates
+    let fill: Vec<(IVec3, u32)> = (0..MAX_BULK_BLOCKS as i32).map(|i| (IVec3::new(i % 64, 0, i / 64), 5)).collect();
+    let packed = bulkedit::pack(IVec3::new(0, 0, 0), &fill).unwrap();
+    assert!(packed.len() < MAX_BULK_BLOCKS * (3 * 4 + 4) / 2, "{}", packed.len());
+
+    assert!(bulkedit::fits(anchor, IVec3::new(-1000 - 32768, 60, 5000)));
+    assert!(!bulkedit::fits(anchor, IVec3::new(-1000 + 32768, 60, 5000)));
+    assert_eq!(bulkedit::pack(anchor, &[(IVec3::new(40000, 0, 0), 1)]), None);
+    assert_eq!(bulkedit::pack(anchor, &[]), None);
+    let too_many: Vec<(IVec3, u32)> = (0..MAX_BULK_BLOCKS as i32 + 1).map(|i| (IVec3::new(i, 0, 0), 1)).collect();
+    assert_eq!(bulkedit::pack(IVec3::new(0, 0, 0), &too_many), None);
+}
+
+#[test]
+fn garbage_payloads_dont_unpack() {
+    let anchor = IVec3::new(0, 0, 0);
+    let payload = bulkedit::pack(anchor, &[(IVec3::new(1, 2, 3), 4), (IVec3::new(4, 5, 6), 7)]).unwrap();
+    assert_eq!(bulkedit::unpack(anchor, &payload[..payload.len() - 1]), None);
+    assert_eq!(bulkedit::unpack(anchor, &payload[..3]), None);
+    assert_eq!(bulkedit::unpack(anchor, &[]), None);
+
+    //Claiming to be huge
+    let mut huge = payload.clone();
+    huge[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(bulkedit::unpack(anchor, &huge), None);
+    //Or not a whole number of blocks
+    let odd = lz4_flex::compress_prepend_size(&[0u8; 15]);
+    assert_eq!(bulkedit::unpack(anchor, &odd), None);
+    assert_eq!(bulkedit::unpack(anchor, &[20, 0, 0, 0, 1, 2, 3, 4, 5, 6]), None);
+
+    //Anchored so close to the edge of the world the blocks would be past it
+    assert_eq!(bulkedit::unpack(IVec3::new(i32::MAX, 0, 0), &payload), None);
+    assert_eq!(bulkedit::pack(IVec3::new(i32::MIN, 0, 0), &[(IVec3::new(i32::MAX, 0, 0), 1)]), None);
+    assert!(!bulkedit::fits(IVec3::new(0, i32::MIN, 0), IVec3::new(0, i32::MAX, 0)));
+}
+
+#[test]
+fn the_header_says_how_much_follows() {
+    let anchor = IVec3::new(3, 70, -2);
+    let blocks = vec![(anchor, 5), (IVec3::new(4, 70, -2), 5)];
+    let bytes = bulkedit::encode(&bulkedit::header(9), anchor, &blocks).unwrap();
+    let size = Message::get_serialized_size();
+    let header: Message = bincode::deserialize(&bytes[..size]).unwrap();
+    assert_eq!(header.message_type, MessageType::BulkBlockSet);
+    assert_eq!((header.seq, header.otherpos), (9, anchor));
+    assert_eq!(header.info as usize, bytes.len() - size);
+    assert_eq!(bulkedit::unpack(header.otherpos, &bytes[size..]), Some(blocks));
+}
+
+#[test]
+fn a_bulk_edit_rebuilds_each_chunk_once() {
+    //All inside one chunk, away from its edges
+    let inside: Vec<(IVec3, u32)> = (2..10).map(|x| (IVec3::new(x, 50, 4), 5)).collect();
+    assert_eq!(bulkedit::implicated_chunks(&inside).into_iter().collect::<Vec<_>>(), vec![IVec2 { x: 0, y: 0 }]);
+
+    //Along the edge the neighbour's faces change too
+    let edge = bulkedit::implicated_chunks(&[(IVec3::new(0, 50, 4), 5), (IVec3::new(1, 50, 4), 5)]);
+    assert_eq!(edge.len(), 2);
+    assert!(edge.contains(&IVec2 { x: -1, y: 0 }));
+}
+
+#[test]
+fn big_edits_split_into_messages() {
+    let mut blocks: Vec<(IVec3, u32)> = (0..MAX_BULK_BLOCKS as i32 + 5).map(|i| (IVec3::new(i, 0, 0), 1)).collect();
+    blocks.push((IVec3::new(-40000, 0, 0), 1));
+    let batches = bulkedit::batches(&blocks);
+    assert_eq!(batches.iter().map(|b| b.len()).collect::<Vec<_>>(), vec![MAX_BULK_BLOCKS, 5, 1]);
+    assert!(batches.iter().all(|b| bulkedit::pack(b[0].0, b).is_some()));
+    assert!(bulkedit::batches(&[]).is_empty());
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::TempDir;
+use voxelland::calendar::{self, Calendar, Sky, WeatherOdds, CLEAR, RAIN, SNOW};
+
+#[test]
+fn odds_split_the_roll_by_weight() {
+    let odds = WeatherOdds { clear: 2.0, snow: 1.0, rain: 1.0 };
+    assert_eq!(odds.pick(0.0), CLEAR);
+    assert_eq!(odds.pick(0.49), CLEAR);
+    assert_eq!(odds.pick(0.6), SNOW);
+    assert_eq!(odds.pick(0.99), RAIN);
+
+    let never_rains = WeatherOdds { clear: 1.0, snow: 1.0, rain: 0.0 };
+    assert_eq!(never_rains.pick(1.0), SNOW);
+
+    let nothing = WeatherOdds { clear: 0.0, snow: 0.0, rain: 0.0 };
+    assert_eq!(nothing.pick(0.7), CLEAR);
+}
+
+#[test]
+fn planets_can_have_their_own_weather() {
+    let mut planet_weather = HashMap::new();
+    planet_weather.insert(1, WeatherOdds { clear: 0.0, snow: 1.0, rain: 0.0 });
+    let calendar = Calendar { weather_interval: 10.0, planet_weather, ..Default::default() };
+
+    let mut sky = Sky::new(&calendar);
+    sky.advance(&calendar, 1, 10.0, || 0.1);
+    assert_eq!(sky.weather, SNOW);
+
+    let mut sky = Sky::new(&calendar);
+    sky.advance(&calendar, 0, 10.0, || 0.1);
+    assert_eq!(sky.weather, CLEAR);
+
+    //Not due yet, the roll isn't even asked for
+    sky.advance(&calendar, 0, 5.0, || panic!("rolled early"));
+}
+
+#[test]
+fn the_clock_wraps_and_locks() {
+    let calendar = Calendar { day_length: 100.0, start_time: 90.0, ..Default::default() };
+    let mut sky = Sky::new(&calendar);
+    sky.advance(&calendar, 0, 20.0, || 0.0);
+    assert_eq!(sky.time, 10.0);
+
+    let locked = Calendar { lock_time: true, lock_weather: Some(RAIN), ..calendar };
+    let mut sky = Sky::new(&locked);
+    assert_eq!(sky.weather, RAIN);
+    sky.advance(&locked, 0, 500.0, || 0.0);
+    assert_eq!((sky.time, sky.weather), (90.0, RAIN));
+}
+
+#[test]
+fn clients_ease_toward_the_server_clock() {
+    //The short way around midnight
+    assert_eq!(calendar::drift(890.0, 10.0, 900.0), 20.0);
+    assert_eq!(calendar::drift(10.0, 890.0, 900.0), -20.0);
+    assert_eq!(calendar::drift(100.0, 130.0, 900.0), 30.0);
+
+    //A little off closes over a few frames instead of at once
+    let step = calendar::catch_up(30.0, 900.0, 0.1);
+    assert!(step > 0.0 && step < 30.0);
+
+    let mut drift = 30.0;
+    for _ in 0..100 {
+        drift -= calendar::catch_up(drift, 900.0, 0.05);
+    }
+    assert!(drift.abs() < 0.1);
+
+    //Way off, like just after joining, snaps
+    assert_eq!(calendar::catch_up(400.0, 900.0, 0.016), 400.0);
+}
+
+#[test]
+fn calendar_file_is_created_and_read_back() {
+    let dir = TempDir::new();
+
+    let calendar = Calendar::load_or_create(&dir);
+    assert_eq!(calendar, Calendar::default());
+    assert!(dir.join("calendar.json").exists());
+
+    //Admins only need to write what they change
+    std::fs::write(dir.join("calendar.json"), r#"{ "day_length": 1200.0, "lock_weather": 1.0 }"#).unwrap();
+    let calendar = Calendar::load_or_create(&dir);
+    assert_eq!(calendar.day_length, 1200.0);
+    assert_eq!(calendar.lock_weather, Some(SNOW));
+    assert_eq!(calendar.weather_interval, Calendar::default().weather_interval);
+}
@@ -0,0 +1,204 @@
+mod common;
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use common::{TempDir, SERIAL};
+use rusqlite::{params, Connection};
+use uuid::Uuid;
+use voxelland::chunk::ChunkSystem;
+use voxelland::chunkstore::ChunkStore;
+use voxelland::game::CURRSEED;
+use voxelland::server::{ServerConfig, ServerState};
+use voxelland::vec::{IVec2, IVec3};
+
+fn load(dir: &PathBuf, seed: u32) -> ChunkSystem {
+    unsafe { CURRSEED.store(seed, Ordering::Relaxed) };
+    let mut csys = ChunkSystem::new(0, seed, 0, true);
+    csys.db_path = dir.join("db");
+    csys.load_world_from_file(dir.join(format!("world/{}", seed)).to_string_lossy().to_string()).unwrap();
+    csys
+}
+
+#[test]
+fn a_block_at_a_time_worlds_migrate_to_chunks() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    /* How worlds used to be saved */
+    {
+        let conn = Connection::open(dir.join("db")).unwrap();
+        conn.execute("CREATE TABLE userdatamap_99 (x INTEGER, y INTEGER, z INTEGER, value INTEGER, PRIMARY KEY (x, y, z))", ()).unwrap();
+        for (x, z, value) in [(1, 1, 5), (2, 1, 6), (40, -3, 7)] {
+            conn.execute("INSERT INTO userdatamap_99 (x, y, z, value) VALUES (?, ?, ?, ?)", params![x, 60, z, value]).unwrap();
+        }
+    }
+
+    let csys = load(&dir, 99);
+
+    //Nothing comes in until it's asked for
+    assert!(csys.userdatamap.is_empty());
+    assert_eq!(csys.blockat(IVec3::new(2, 60, 1)), 6);
+    assert_eq!(csys.userdatamap.len(), 2);
+    assert_eq!(csys.blockat(IVec3::new(40, 60, -3)), 7);
+
+    let store = csys.edit_store.clone().unwrap();
+    let mut chunks = store.chunks().unwrap();
+    chunks.sort_by_key(|c| (c.x, c.y));
+    assert_eq!(chunks, vec![IVec2::new(0, 0), IVec2::new(2, -1)]);
+
+    //The old table is gone, so loading again doesn't move anything twice
+    assert_eq!(store.migrate().unwrap(), 0);
+
+}
+
+#[test]
+fn chunks_unload_and_keep_their_edits() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let csys = load(&dir, 100);
+    let spot = IVec3::new(20, 70, 20);
+    let cpos = ChunkSystem::spot_to_chunk_pos(&spot);
+
+    csys.set_block(spot, 9, true);
+    assert_eq!(csys.edits_loaded.get(&cpos).map(|d| *d), Some(true));
+
+    csys.unload_chunk_edits(cpos).unwrap();
+    assert!(csys.userdatamap.is_empty());
+    assert!(!csys.edits_loaded.contains_key(&cpos));
+    assert_eq!(csys.edit_store.as_ref().unwrap().load_chunk(cpos).unwrap(), vec![(spot, 9)]);
+
+    assert_eq!(csys.blockat(spot), 9);
+
+    //Single edits saved by the server go on top of what's there
+    let store = ChunkStore::new(dir.join("db"), 100);
+    store.set_block(spot + IVec3::new(1, 0, 0), 3).unwrap();
+    store.set_block(spot, 4).unwrap();
+    let mut saved = store.load_chunk(cpos).unwrap();
+    saved.sort_by_key(|(s, _)| s.x);
+    assert_eq!(saved, vec![(spot, 4), (spot + IVec3::new(1, 0, 0), 3)]);
+
+}
+
+#[test]
+fn unloading_while_someone_builds_loses_nothing() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let csys = load(&dir, 101);
+    let spots: Vec<IVec3> = (0..2000).map(|i| IVec3::new(i % 15, 60 + i / 225, i / 15 % 15)).collect();
+    let cpos = ChunkSystem::spot_to_chunk_pos(&spots[0]);
+    let done = AtomicBool::new(false);
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for spot in &spots {
+                csys.set_block(*spot, 7, true);
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                csys.unload_chunk_edits(cpos).unwrap();
+            }
+        });
+    });
+
+    csys.unload_chunk_edits(cpos).unwrap();
+    assert_eq!(csys.edit_store.as_ref().unwrap().load_chunk(cpos).unwrap().len(), spots.len());
+
+}
+
+#[test]
+fn a_chunk_that_will_not_read_is_never_saved_over() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let csys = load(&dir, 103);
+    let spot = IVec3::new(3, 60, 3);
+    let cpos = ChunkSystem::spot_to_chunk_pos(&spot);
+
+    let store = csys.edit_store.clone().unwrap();
+    store.save_chunk(cpos, &[(spot, 5)]).unwrap();
+    Connection::open(dir.join("db")).unwrap()
+        .execute("UPDATE chunkedits_103 SET edits = ?1", [b"nonsense".to_vec()]).unwrap();
+    assert!(store.load_chunk(cpos).unwrap_err().is_corrupt());
+
+    /* Edits still work in memory, but the chunk never counts as loaded */
+    csys.set_block(spot + IVec3::new(1, 0, 0), 9, true);
+    assert_eq!(csys.blockat(spot + IVec3::new(1, 0, 0)), 9);
+    assert!(!csys.edits_loaded.contains_key(&cpos));
+    csys.unload_chunk_edits(cpos).unwrap();
+    csys.save_current_world_to_dir(&dir).unwrap();
+    assert!(store.set_block(spot, 2).unwrap_err().is_corrupt());
+
+    let blob: Vec<u8> = Connection::open(dir.join("db")).unwrap()
+        .query_row("SELECT edits FROM chunkedits_103", [], |row| row.get(0)).unwrap();
+    assert_eq!(blob, b"nonsense");
+}
+
+#[test]
+fn a_failed_save_keeps_the_edits_loaded() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let csys = load(&dir, 104);
+    let spot = IVec3::new(3, 60, 3);
+    let cpos = ChunkSystem::spot_to_chunk_pos(&spot);
+    csys.set_block(spot, 6, true);
+
+    /* A view can be read from but not written to */
+    let conn = Connection::open(dir.join("db")).unwrap();
+    conn.execute_batch("DROP TABLE chunkedits_104; CREATE VIEW chunkedits_104 AS SELECT 0 AS cx, 0 AS cz, NULL AS edits WHERE 0;").unwrap();
+    assert!(csys.unload_chunk_edits(cpos).is_err());
+    assert_eq!(csys.edits_loaded.get(&cpos).map(|d| *d), Some(true));
+    assert_eq!(csys.blockat(spot), 6);
+
+    conn.execute_batch("DROP VIEW chunkedits_104; CREATE TABLE chunkedits_104 (cx INTEGER, cz INTEGER, edits BLOB, PRIMARY KEY (cx, cz));").unwrap();
+    csys.unload_chunk_edits(cpos).unwrap();
+    assert!(csys.userdatamap.is_empty());
+    assert_eq!(csys.edit_store.as_ref().unwrap().load_chunk(cpos).unwrap(), vec![(spot, 6)]);
+}
+
+#[test]
+fn saving_somewhere_new_brings_the_unloaded_chunks() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let csys = load(&dir, 101);
+
+    let far = IVec3::new(-200, 50, 300);
+    let near = IVec3::new(1, 50, 1);
+    csys.set_block(far, 2, true);
+    csys.unload_chunk_edits(ChunkSystem::spot_to_chunk_pos(&far)).unwrap();
+    csys.set_block(near, 3, true);
+
+    let copy = TempDir::new();
+    csys.save_current_world_to_dir(&copy).unwrap();
+
+    let loaded = load(&copy, 101);
+    assert_eq!(loaded.blockat(far), 2);
+    assert_eq!(loaded.blockat(near), 3);
+
+}
+
+#[test]
+fn the_server_forgets_chunks_nobody_is_near() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let mut config = ServerConfig::new("127.0.0.1:0", dir.clone());
+    config.initial_seed = 102;
+    let state = ServerState::load(&config).unwrap();
+
+    let here = IVec3::new(5, 60, 5);
+    let there = IVec3::new(5000, 60, 5);
+    state.csys.read().set_block(here, 8, true);
+    state.csys.read().set_block(there, 8, true);
+
+    let player = Uuid::new_v4();
+    state.knowncams.insert(player, glam::Vec3::new(5.0, 60.0, 5.0));
+    state.unload_far_chunks();
+
+    let csys = state.csys.read();
+    assert!(csys.userdatamap.contains_key(&here));
+    assert!(!csys.userdatamap.contains_key(&there));
+    assert_eq!(csys.blockat(there), 8);
+
+    drop(csys);
+}
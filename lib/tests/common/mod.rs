@@ -0,0 +1,188 @@
+//Helpers the integration tests share, each test file `mod common;`s this and uses what it needs
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use glam::Vec3;
+use uuid::Uuid;
+use voxelland::chat;
+use voxelland::compression::Compression;
+use voxelland::netstream::NetStream;
+use voxelland::server::{Server, ServerConfig};
+use voxelland::server_types::{Message, MessageType};
+
+/* The server keeps the current seed in a global, and the generator settings are one too, so only one test
+that loads a world may run at a time. */
+pub static SERIAL: Mutex<()> = Mutex::new(());
+
+/// A fresh directory under the system temp dir, deleted with everything in it when this is dropped, even if
+/// the test panics.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let dir = std::env::temp_dir().join(format!("voxelland-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A server on a free port for the world in `dir`, with `setup` changing whatever the test needs first.
+pub fn start_server(dir: &Path, setup: impl FnOnce(&mut ServerConfig)) -> Server {
+    let mut config = ServerConfig::new("127.0.0.1:0", dir.to_path_buf());
+    setup(&mut config);
+    Server::start(config).unwrap()
+}
+
+/// Polls `pred` for up to 5 seconds, true as soon as it is.
+pub fn wait_until(mut pred: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if pred() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+/// A player talking to a server the way the game does, one fixed size frame at a time.
+pub struct TestClient {
+    pub stream: NetStream,
+    pub id: Uuid,
+}
+
+impl TestClient {
+    pub fn connect(addr: SocketAddr) -> TestClient {
+        TestClient::connect_asking(addr, Compression::None)
+    }
+
+    pub fn connect_asking(addr: SocketAddr, compression: Compression) -> TestClient {
+        TestClient::connect_as(addr, Uuid::new_v4(), compression)
+    }
+
+    pub fn connect_as(addr: SocketAddr, id: Uuid, compression: Compression) -> TestClient {
+        TestClient::greet(NetStream::Tcp(TcpStream::connect(addr).unwrap()), id, compression)
+    }
+
+    /// Comes back as `id`, showing `session` the way a reconnecting client does.
+    pub fn resume(addr: SocketAddr, id: Uuid, session: (u64, u64)) -> TestClient {
+        let mut client = TestClient::connect_as(addr, id, Compression::None);
+        let mut proof = Message::new(MessageType::SessionToken, Vec3::ZERO, 0.0, 0);
+        proof.goose = session;
+        client.send(&proof);
+        client
+    }
+
+    /// Over the server's in-memory stream, the way singleplayer talks to its own server.
+    pub fn connect_local(server: &Server) -> TestClient {
+        let mut client = TestClient::greet(server.connect_local(), Uuid::new_v4(), Compression::None);
+        client.ready();
+        client
+    }
+
+    fn greet(stream: NetStream, id: Uuid, compression: Compression) -> TestClient {
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut client = TestClient { stream, id };
+
+        let mut greeting = Message::new(MessageType::TellYouMyID, Vec3::ZERO, 0.0, compression as u32);
+        greeting.goose = client.id.as_u64_pair();
+        client.send(&greeting);
+        client
+    }
+
+    /// Sends a first PlayerUpdate so the server starts relaying messages to us.
+    pub fn ready(&mut self) {
+        self.send(&Message::new(MessageType::PlayerUpdate, Vec3::new(0.0, 100.0, 0.0), 0.0, 0));
+        self.expect(MessageType::TimeUpdate);
+    }
+
+    pub fn connect_ready(addr: SocketAddr) -> TestClient {
+        let mut client = TestClient::connect(addr);
+        client.ready();
+        client
+    }
+
+    pub fn send(&mut self, message: &Message) {
+        self.stream.write_all(&bincode::serialize(message).unwrap()).unwrap();
+    }
+
+    pub fn say(&mut self, text: &str) {
+        self.stream.write_all(&chat::encode(None, text)).unwrap();
+    }
+
+    pub fn recv(&mut self) -> Option<Message> {
+        let mut buffer = vec![0u8; Message::get_serialized_size()];
+        match self.stream.read_exact(&mut buffer) {
+            Ok(_) => Some(bincode::deserialize(&buffer).unwrap()),
+            Err(_) => None,
+        }
+    }
+
+    /// Reads past anything still buffered, true if the server hung up on us rather than just going quiet.
+    pub fn hung_up(&mut self) -> bool {
+        let mut buffer = vec![0u8; Message::get_serialized_size()];
+        loop {
+            match self.stream.read_exact(&mut buffer) {
+                Ok(_) => {}
+                Err(e) => {
+                    return matches!(e.kind(), std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset);
+                }
+            }
+        }
+    }
+
+    pub fn recv_payload(&mut self, len: usize) -> Vec<u8> {
+        let mut buffer = vec![0u8; len];
+        self.stream.read_exact(&mut buffer).unwrap();
+        buffer
+    }
+
+    /// Skips everything until a message of type `t` shows up.
+    pub fn expect(&mut self, t: MessageType) -> Message {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            match self.recv() {
+                Some(m) if m.message_type == t => return m,
+                //Chat text follows its header, skip that too
+                Some(m) if m.message_type == MessageType::Chat => {
+                    self.recv_payload(m.info as usize);
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        panic!("Never received a {}", t);
+    }
+
+    pub fn expect_chat(&mut self) -> String {
+        let header = self.expect(MessageType::Chat);
+        String::from_utf8(self.recv_payload(header.info as usize)).unwrap()
+    }
+}
@@ -0,0 +1,44 @@
+use proptest::prelude::*;
+use voxelland::compression::{decode_payload, encode_payload, Compression, COMPRESSION_THRESHOLD};
+
+proptest! {
+    #[test]
+    fn payloads_round_trip(data in prop::collection::vec(any::<u8>(), 0..COMPRESSION_THRESHOLD * 4), lz4 in any::<bool>()) {
+        let mode = if lz4 { Compression::Lz4 } else { Compression::None };
+        let (wire, codec) = encode_payload(&data, mode);
+        prop_assert_eq!(decode_payload(&wire, codec as u32), Some(data));
+    }
+
+    #[test]
+    fn garbage_never_panics(data in prop::collection::vec(any::<u8>(), 0..512), codec in 0u32..4) {
+        let _ = decode_payload(&data, codec);
+    }
+}
+
+#[test]
+fn small_payloads_stay_raw() {
+    let data = vec![7u8; COMPRESSION_THRESHOLD - 1];
+    let (wire, codec) = encode_payload(&data, Compression::Lz4);
+    assert_eq!(codec, Compression::None);
+    assert_eq!(wire, data);
+}
+
+#[test]
+fn sqlite_like_payloads_shrink() {
+    /* Mostly empty pages, like a fresh db */
+    let mut data = vec![0u8; 64 * 1024];
+    for (i, b) in data.iter_mut().enumerate().step_by(97) {
+        *b = i as u8;
+    }
+    let (wire, codec) = encode_payload(&data, Compression::Lz4);
+    assert_eq!(codec, Compression::Lz4);
+    assert!(wire.len() < data.len() / 4);
+}
+
+#[test]
+fn lying_size_prefix_is_rejected() {
+    let (mut wire, _) = encode_payload(&vec![1u8; COMPRESSION_THRESHOLD], Compression::Lz4);
+    wire[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(decode_payload(&wire, Compression::Lz4 as u32), None);
+    assert_eq!(decode_payload(&wire, 9), None);
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::time::Duration;
+
+use common::{TempDir, SERIAL};
+use voxelland::discovery::{
+    decode_query, decode_status, discover_on, encode_query, encode_status, load_favorites, query_status, save_favorites,
+    Favorite, ServerBrowser, ServerStatus, DISCOVERY_PORT, GAME_VERSION,
+};
+use voxelland::server::ratelimit::BucketLimit;
+use voxelland::server::Server;
+
+fn start_server(dir: &Path, lan: bool) -> Server {
+    common::start_server(dir, |config| {
+        config.name = String::from("Test Server");
+        config.motd = String::from("Hello there");
+        config.lan_discovery = lan;
+    })
+}
+
+fn status() -> ServerStatus {
+    ServerStatus {
+        name: String::from("A"),
+        motd: String::from("B"),
+        players: 3,
+        version: String::from(GAME_VERSION),
+        port: 4848,
+    }
+}
+
+#[test]
+fn query_and_status_round_trip() {
+    assert_eq!(decode_query(&encode_query(77)), Some(77));
+    assert_eq!(decode_status(&encode_status(77, &status())), Some((77, status())));
+}
+
+#[test]
+fn garbage_is_not_a_query_or_status() {
+    assert_eq!(decode_query(b"hello there!"), None);
+    assert_eq!(decode_query(&encode_query(1)[..11]), None);
+    assert_eq!(decode_status(&encode_query(1)), None);
+
+    /* Claims a name far longer than the datagram */
+    let mut bytes = encode_status(1, &status());
+    bytes[12..20].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert_eq!(decode_status(&bytes), None);
+}
+
+#[test]
+fn servers_answer_status_queries_on_their_udp_port() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let server = start_server(&dir, false);
+
+    let (status, ping) = query_status(&server.udp_addr.unwrap().to_string(), Duration::from_secs(2)).unwrap();
+    assert_eq!(status.name, "Test Server");
+    assert_eq!(status.motd, "Hello there");
+    assert_eq!(status.players, 0);
+    assert_eq!(status.version, GAME_VERSION);
+    assert_eq!(status.port, server.local_addr.port());
+    assert!(ping < Duration::from_secs(2));
+
+    server.shutdown();
+}
+
+#[test]
+fn status_answers_are_limited_per_address() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let server = common::start_server(&dir, |config| {
+        config.limits.status_query = BucketLimit { capacity: 3.0, per_second: 0.01 };
+    });
+
+    /* The answer is bigger than the query, so a flood of them (maybe spoofed to come from someone else) only
+    gets the first few answered */
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    for nonce in 0..10 {
+        socket.send_to(&encode_query(nonce), server.udp_addr.unwrap()).unwrap();
+    }
+    let mut buffer = [0u8; 2048];
+    let mut answers = 0;
+    while let Ok((n, _)) = socket.recv_from(&mut buffer) {
+        assert!(decode_status(&buffer[..n]).is_some());
+        answers += 1;
+    }
+    assert_eq!(answers, 3);
+
+    server.shutdown();
+}
+
+#[test]
+fn servers_answer_lan_discovery() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let server = start_server(&dir, true);
+
+    let found = discover_on(SocketAddr::from(([127, 0, 0, 1], DISCOVERY_PORT)), Duration::from_millis(500)).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].address, server.local_addr);
+    assert_eq!(found[0].status.name, "Test Server");
+
+    server.shutdown();
+}
+
+#[test]
+fn favorites_persist() {
+    let dir = TempDir::new();
+    let path = dir.join("favorites");
+
+    assert!(load_favorites(&path).is_empty());
+
+    let mut browser = ServerBrowser::new(path.to_string_lossy());
+    browser.add_favorite("Home", "192.168.1.2:4848");
+    browser.add_favorite("", "example.com:4848");
+    browser.add_favorite("Again", "192.168.1.2:4848");
+    browser.remove_favorite("example.com:4848");
+
+    let favorites = load_favorites(&path);
+    assert_eq!(favorites, vec![Favorite { name: String::from("Home"), address: String::from("192.168.1.2:4848") }]);
+
+    /* Nothing has answered, it's still listed */
+    let entries = ServerBrowser::new(path.to_string_lossy()).entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].label(), "Home");
+    assert!(entries[0].status.is_none());
+
+    save_favorites(&path, &[]);
+    assert!(load_favorites(&path).is_empty());
+}
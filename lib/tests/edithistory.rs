@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use voxelland::blockinfo::Blocks;
+use voxelland::buildtools;
+use voxelland::edithistory::{EditAction, EditHistory, HistoryEntry, MAX_UNDO};
+use voxelland::specialblocks::door::{DOOROPEN_BITS, DOORTOP_BITS};
+use voxelland::vec::IVec3;
+
+fn door(direction: u32) -> u32 {
+    let mut block = 19;
+    Blocks::set_direction_bits(&mut block, direction);
+    block
+}
+
+#[test]
+fn a_click_is_one_action() {
+    let mut history = EditHistory::new();
+    let (bottom, top) = (IVec3::new(0, 1, 0), IVec3::new(0, 2, 0));
+    history.begin();
+    history.note(&[(bottom, 0, door(1)), (top, 0, door(1) | DOORTOP_BITS)]);
+    //The neighbour turning into a double door
+    history.note(&[(IVec3::new(1, 1, 0), door(1), door(3))]);
+    history.note(&[(bottom, door(1), door(2))]);
+    history.end();
+    assert_eq!(history.undo_len(), 1);
+
+    let action = history.take_undo().unwrap();
+    assert_eq!(action.blocks.len(), 3);
+    //First before, last after
+    assert_eq!(action.blocks[0], (bottom, 0, door(2)));
+
+    //Edits nobody's recording aren't ours to undo
+    history.note(&[(bottom, 0, 5)]);
+    assert_eq!(history.undo_len(), 0);
+
+    //Opening a door isn't building
+    history.begin();
+    history.note(&[(bottom, door(1), door(1) | DOOROPEN_BITS), (top, door(1) | DOORTOP_BITS, door(1) | DOORTOP_BITS | DOOROPEN_BITS)]);
+    history.end();
+    assert_eq!(history.undo_len(), 0);
+}
+
+#[test]
+fn undoing_then_building_forgets_the_redo() {
+    let mut history = EditHistory::new();
+    history.record(vec![(IVec3::new(0, 0, 0), 0, 5)]);
+    history.record(vec![(IVec3::new(1, 0, 0), 0, 5)]);
+
+    let undone = history.take_undo().unwrap();
+    history.undone(undone.clone());
+    assert_eq!((history.undo_len(), history.redo_len()), (1, 1));
+    let redone = history.take_redo().unwrap();
+    assert_eq!(redone, undone);
+    history.redone(redone);
+    assert_eq!((history.undo_len(), history.redo_len()), (2, 0));
+
+    let undone = history.take_undo().unwrap();
+    history.undone(undone);
+    history.record(vec![(IVec3::new(2, 0, 0), 0, 5)]);
+    assert_eq!((history.undo_len(), history.redo_len()), (2, 0));
+
+    for x in 0..MAX_UNDO as i32 * 2 {
+        history.record(vec![(IVec3::new(x, 5, 0), 0, 5)]);
+    }
+    assert_eq!(history.undo_len(), MAX_UNDO);
+    assert_eq!(history.take_undo().unwrap().blocks[0].0, IVec3::new(MAX_UNDO as i32 * 2 - 1, 5, 0));
+}
+
+#[test]
+fn rejected_edits_come_out_of_the_history() {
+    let mut history = EditHistory::new();
+    let first = history.record(vec![(IVec3::new(0, 0, 0), 0, 5)]);
+    let stamp = history.record(vec![(IVec3::new(1, 0, 0), 0, 5), (IVec3::new(2, 0, 0), 0, 5)]);
+    assert_ne!(first, stamp);
+
+    //Half the stamp didn't go through
+    history.rejected(stamp, &[IVec3::new(1, 0, 0)]);
+    assert_eq!(history.undo_len(), 2);
+    assert_eq!(history.take_undo().unwrap().blocks, vec![(IVec3::new(2, 0, 0), 0, 5)]);
+
+    //None of it did
+    history.rejected(first, &[IVec3::new(0, 0, 0)]);
+    assert_eq!(history.undo_len(), 0);
+    //Clicks being noted are found too
+    history.begin();
+    assert!(matches!(history.open_entry(), Some(HistoryEntry::Recorded(_))));
+    history.end();
+    assert_eq!(history.open_entry(), None);
+}
+
+#[test]
+fn a_rejected_undo_goes_back_on_the_stack() {
+    let mut history = EditHistory::new();
+    history.record(vec![(IVec3::new(0, 0, 0), 0, 5)]);
+    history.record(vec![(IVec3::new(1, 0, 0), 0, 5)]);
+
+    let action = history.take_undo().unwrap();
+    let id = action.id;
+    history.undone(action);
+    history.rejected(HistoryEntry::Undid(id), &[IVec3::new(1, 0, 0)]);
+    assert_eq!((history.undo_len(), history.redo_len()), (2, 0));
+    //The rest of its batches being turned down too changes nothing more
+    history.rejected(HistoryEntry::Undid(id), &[IVec3::new(1, 0, 0)]);
+    assert_eq!((history.undo_len(), history.redo_len()), (2, 0));
+    assert_eq!(history.take_undo().unwrap().id, id);
+
+    let action = history.take_undo().unwrap();
+    let id = action.id;
+    history.undone(action);
+    let action = history.take_redo().unwrap();
+    history.redone(action);
+    history.rejected(HistoryEntry::Redid(id), &[IVec3::new(0, 0, 0)]);
+    assert_eq!((history.undo_len(), history.redo_len()), (0, 1));
+}
+
+#[test]
+fn undo_leaves_other_peoples_changes() {
+    let action = EditAction {
+        id: 1,
+        blocks: vec![(IVec3::new(0, 0, 0), 3, 0), (IVec3::new(1, 0, 0), 0, 5), (IVec3::new(2, 0, 0), 1, 6)],
+    };
+    let back = action.reversed();
+    assert_eq!(back.blocks[0], (IVec3::new(2, 0, 0), 6, 1));
+    assert_eq!(back.reversed(), action);
+
+    //Someone's put glass where our stone was
+    let mut world = HashMap::new();
+    world.insert(IVec3::new(0, 0, 0), 0);
+    world.insert(IVec3::new(1, 0, 0), 7);
+    world.insert(IVec3::new(2, 0, 0), 6);
+    let step = back.still_applicable(|spot| world[&spot]);
+    assert_eq!(step.edits(), vec![(IVec3::new(2, 0, 0), 1), (IVec3::new(0, 0, 0), 3)]);
+}
+
+#[test]
+fn undo_pays_and_refunds_like_building() {
+    let action = EditAction {
+        id: 1,
+        blocks: vec![
+            (IVec3::new(0, 0, 0), 0, door(1)),
+            (IVec3::new(0, 1, 0), 0, door(1) | DOORTOP_BITS),
+            (IVec3::new(1, 0, 0), 3, 5),
+            (IVec3::new(2, 0, 0), 3, 5),
+            (IVec3::new(3, 0, 0), door(0), door(0) | DOOROPEN_BITS),
+        ],
+    };
+    assert_eq!(action.exchange(), (vec![(5, 2), (19, 1)], vec![(3, 2)]));
+    assert_eq!(action.reversed().exchange(), (vec![(3, 2)], vec![(5, 2), (19, 1)]));
+
+    let mut inventory = [(5, 1), (0, 0), (19, 2), (0, 0)];
+    assert_eq!(buildtools::give(&mut inventory, &[(5, 2), (3, 4), (7, 1)]), Some(vec![0, 1, 3]));
+    assert_eq!(inventory, [(5, 3), (3, 4), (19, 2), (7, 1)]);
+    //No room, nothing's given
+    assert_eq!(buildtools::give(&mut inventory, &[(19, 1), (8, 1)]), None);
+    assert_eq!(inventory, [(5, 3), (3, 4), (19, 2), (7, 1)]);
+}
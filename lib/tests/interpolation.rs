@@ -0,0 +1,95 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+use voxelland::interpolation::{lerp_angle, InterpolationSettings, Snapshot, SnapshotBuffer};
+
+fn snap(time: f64, x: f32) -> Snapshot {
+    Snapshot { time, position: Vec3::new(x, 0.0, 0.0), rot: Vec3::ZERO }
+}
+
+fn buffer(snaps: &[Snapshot]) -> SnapshotBuffer {
+    let mut b = SnapshotBuffer::new();
+    for s in snaps {
+        b.push(*s, 8);
+    }
+    b
+}
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < 1e-4
+}
+
+#[test]
+fn empty_buffer_has_nothing_to_draw() {
+    assert!(SnapshotBuffer::new().sample(1.0, 0.25).is_none());
+}
+
+#[test]
+fn lerps_between_snapshots() {
+    let b = buffer(&[snap(0.0, 0.0), snap(0.25, 10.0), snap(0.5, 20.0)]);
+
+    assert!(close(b.sample(0.125, 0.25).unwrap().position.x, 5.0));
+    assert!(close(b.sample(0.25, 0.25).unwrap().position.x, 10.0));
+    assert!(close(b.sample(0.4375, 0.25).unwrap().position.x, 17.5));
+}
+
+#[test]
+fn holds_the_first_snapshot_before_the_buffer_starts() {
+    let b = buffer(&[snap(1.0, 3.0), snap(1.25, 6.0)]);
+    assert!(close(b.sample(0.5, 0.25).unwrap().position.x, 3.0));
+}
+
+#[test]
+fn extrapolates_briefly_then_holds() {
+    let b = buffer(&[snap(0.0, 0.0), snap(0.25, 10.0)]);
+
+    /* 40 blocks/s, so 0.1s late is 4 blocks on */
+    assert!(close(b.sample(0.35, 0.25).unwrap().position.x, 14.0));
+    /* Capped at 0.25s past the last one */
+    assert!(close(b.sample(0.5, 0.25).unwrap().position.x, 20.0));
+    assert!(close(b.sample(5.0, 0.25).unwrap().position.x, 20.0));
+    /* No extrapolation at all */
+    assert!(close(b.sample(0.35, 0.0).unwrap().position.x, 10.0));
+}
+
+#[test]
+fn single_snapshot_is_held() {
+    let b = buffer(&[snap(1.0, 7.0)]);
+    assert!(close(b.sample(2.0, 0.25).unwrap().position.x, 7.0));
+}
+
+#[test]
+fn buffer_keeps_only_the_newest_and_drops_out_of_order() {
+    let mut b = SnapshotBuffer::new();
+    for i in 0..10 {
+        b.push(snap(i as f64, i as f32), 4);
+    }
+    assert_eq!(b.len(), 4);
+    assert!(close(b.sample(0.0, 0.0).unwrap().position.x, 6.0));
+
+    b.push(snap(3.0, 100.0), 4);
+    assert_eq!(b.len(), 4);
+    assert!(close(b.latest().unwrap().position.x, 9.0));
+}
+
+#[test]
+fn angles_take_the_short_way_around() {
+    assert!(close(lerp_angle(0.0, 1.0, 0.5), 0.5));
+
+    let mid = lerp_angle(PI - 0.1, -PI + 0.1, 0.5);
+    assert!(close(mid.abs(), PI));
+
+    let b = buffer(&[
+        Snapshot { time: 0.0, position: Vec3::ZERO, rot: Vec3::new(0.0, PI - 0.1, 0.0) },
+        Snapshot { time: 1.0, position: Vec3::ZERO, rot: Vec3::new(0.0, -PI + 0.1, 0.0) },
+    ]);
+    let quarter = b.sample(0.25, 0.0).unwrap().rot.y;
+    assert!(close(quarter, PI - 0.05));
+}
+
+#[test]
+fn default_delay_covers_the_player_update_interval() {
+    let settings = InterpolationSettings::default();
+    assert!(settings.delay > 0.25);
+    assert!(settings.buffer_size >= 2);
+}
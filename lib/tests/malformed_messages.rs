@@ -0,0 +1,314 @@
+mod common;
+
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use common::{TempDir, SERIAL};
+use glam::Vec3;
+use parking_lot::Mutex;
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestRunner};
+use uuid::Uuid;
+use voxelland::compression::Compression;
+use voxelland::game::STARTINGITEMS;
+use voxelland::inventory::Inventory;
+use voxelland::bulkedit;
+use voxelland::server::{handle_bulk_block_set, handle_chat, handle_message, Client, Handled, ServerConfig, ServerState};
+use voxelland::server_types::{Message, MessageType, MobMessage, MOB_BATCH_SIZE};
+use voxelland::vec::IVec3;
+
+/// A world in a temp dir with no listener, plus one registered client whose other end is drained in the background.
+struct Harness {
+    state: ServerState,
+    client_id: Uuid,
+    dir: TempDir,
+}
+
+impl Harness {
+    fn new() -> Harness {
+        let dir = TempDir::new();
+
+        let mut config = ServerConfig::new("127.0.0.1:0", dir.clone());
+        config.initial_seed = 5;
+        let state = ServerState::load(&config).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (serverside, _) = listener.accept().unwrap();
+        serverside.set_nonblocking(true).unwrap();
+
+        thread::spawn(move || {
+            let mut sink = [0u8; 4096];
+            while let Ok(n) = peer.read(&mut sink) {
+                if n == 0 {
+                    break;
+                }
+            }
+        });
+
+        let client_id = Uuid::new_v4();
+        let mut client = Client::new(Arc::new(Mutex::new(serverside.into())), Inventory { dirty: false, inv: STARTINGITEMS }, &state.limits, Compression::None);
+        client.ready_for_player_messages = true;
+        state.clients.lock().insert(client_id, client);
+
+        Harness { state, client_id, dir }
+    }
+
+    fn handle(&self, mut message: Message) -> Handled {
+        message.goose = self.client_id.as_u64_pair();
+        handle_message(self.client_id, &mut message, &self.state)
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.state.clients.lock().clear();
+    }
+}
+
+fn arb_f32() -> impl Strategy<Value = f32> {
+    prop_oneof![
+        any::<f32>(),
+        -1000.0f32..1000.0,
+        Just(f32::NAN),
+        Just(f32::INFINITY),
+        Just(-1.0),
+    ]
+}
+
+fn arb_message() -> impl Strategy<Value = Message> {
+    (
+        prop::sample::select(MessageType::ALL.to_vec()),
+        (arb_f32(), arb_f32(), arb_f32(), arb_f32()),
+        (any::<u32>(), prop_oneof![0u32..3, any::<u32>()], prop_oneof![0u32..80, any::<u32>()]),
+        (any::<i32>(), any::<i32>(), any::<i32>()),
+        (any::<bool>(), any::<bool>(), any::<u8>()),
+    )
+        .prop_map(|(t, (x, y, z, rot), (infof, info2, info), (ox, oy, oz), (bo, hostile, count))| {
+            let mut m = Message::new(t, Vec3::new(x, y, z), rot, info);
+            m.info2 = info2;
+            m.infof = f32::from_bits(infof);
+            m.otherpos = IVec3::new(ox, oy, oz);
+            m.bo = bo;
+            m.hostile = hostile;
+            m.count = count;
+            m
+        })
+}
+
+#[test]
+fn decoding_arbitrary_bytes_never_panics() {
+    let size = Message::get_serialized_size();
+    let mut runner = TestRunner::new(Config { cases: 2048, failure_persistence: None, ..Config::default() });
+    runner
+        .run(&prop::collection::vec(any::<u8>(), 0..size * 2), |bytes| {
+            if let Some(m) = Message::decode(&bytes) {
+                prop_assert!(m.count as usize <= MOB_BATCH_SIZE);
+            }
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn decoding_mutated_frames_never_panics() {
+    let frame = bincode::serialize(&Message::new(MessageType::ChestInvUpdate, Vec3::ONE, 2.0, 3)).unwrap();
+    let mut runner = TestRunner::new(Config { cases: 2048, failure_persistence: None, ..Config::default() });
+    runner
+        .run(&(prop::collection::vec((0..frame.len(), any::<u8>()), 1..8), 0..=frame.len()), |(flips, cut)| {
+            let mut bytes = frame.clone();
+            for (i, b) in flips {
+                bytes[i] = b;
+            }
+            let _ = Message::decode(&bytes[..cut]);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn every_message_type_is_in_all_in_order() {
+    //The metrics index by `t as usize`, and arb_message only picks from here
+    for (i, t) in MessageType::ALL.into_iter().enumerate() {
+        assert_eq!(t as usize, i, "{} is out of place in MessageType::ALL", t);
+    }
+    //bincode writes the variant index, so the one past the end only decodes if a variant was left out
+    let past = bincode::serialize(&(MessageType::ALL.len() as u32)).unwrap();
+    assert!(bincode::deserialize::<MessageType>(&past).is_err(), "MessageType::ALL is missing a variant");
+}
+
+#[test]
+fn well_formed_messages_round_trip() {
+    let mut runner = TestRunner::new(Config { cases: 512, failure_persistence: None, ..Config::default() });
+    runner
+        .run(&arb_message(), |m| {
+            let bytes = bincode::serialize(&m).unwrap();
+            prop_assert_eq!(bytes.len(), Message::get_serialized_size());
+            match Message::decode(&bytes) {
+                Some(d) => {
+                    prop_assert!(m.count as usize <= MOB_BATCH_SIZE);
+                    prop_assert_eq!(d.message_type, m.message_type);
+                    prop_assert_eq!(d.info, m.info);
+                    prop_assert_eq!(d.otherpos, m.otherpos);
+                }
+                None => prop_assert!(m.count as usize > MOB_BATCH_SIZE),
+            }
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn handler_survives_arbitrary_messages() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let harness = Harness::new();
+
+    let mut runner = TestRunner::new(Config { cases: 256, failure_persistence: None, ..Config::default() });
+    runner
+        .run(&arb_message(), |m| {
+            let t = m.message_type;
+            let handled = harness.handle(m);
+            match t {
+                MessageType::Disconnect => prop_assert_eq!(handled, Handled::Disconnect),
+                MessageType::Udm | MessageType::ChestReg | MessageType::WhatsThatMob | MessageType::MobUpdateBatch => {
+                    prop_assert!(matches!(handled, Handled::Rejected(_)))
+                }
+                _ => {}
+            }
+            prop_assert!(harness.state.clients.lock().contains_key(&harness.client_id));
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn bulk_block_sets_survive_arbitrary_payloads() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let harness = Harness::new();
+
+    let anchor = prop_oneof![(-100i32..100, -10i32..200, -100i32..100), (any::<i32>(), any::<i32>(), any::<i32>())].prop_map(|(x, y, z)| IVec3::new(x, y, z));
+    //Real packed blocks around the anchor some of the time, so it gets past unpacking
+    let blocks = prop::collection::vec(((any::<i16>(), any::<i16>(), any::<i16>()), prop_oneof![0u32..80, any::<u32>()]), 1..64);
+    let payload = prop_oneof![
+        prop::collection::vec(any::<u8>(), 0..256).prop_map(Err),
+        blocks.prop_map(Ok),
+    ];
+
+    let mut runner = TestRunner::new(Config { cases: 256, failure_persistence: None, ..Config::default() });
+    runner
+        .run(&(anchor, payload), |(anchor, payload)| {
+            let mut payload = match payload {
+                Err(bytes) => bytes,
+                Ok(offsets) => {
+                    let blocks: Vec<(IVec3, u32)> = offsets
+                        .into_iter()
+                        .filter_map(|((x, y, z), block)| Some((anchor.checked_add(IVec3::new(x as i32, y as i32, z as i32))?, block)))
+                        .collect();
+                    bulkedit::pack(anchor, &blocks).unwrap_or_default()
+                }
+            };
+            let mut header = bulkedit::header(1);
+            header.otherpos = anchor;
+            header.info = payload.len() as u32;
+            header.goose = harness.client_id.as_u64_pair();
+            let _ = handle_bulk_block_set(harness.client_id, &mut header, &mut payload, &harness.state);
+            prop_assert!(harness.state.clients.lock().contains_key(&harness.client_id));
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn chat_survives_arbitrary_text() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let harness = Harness::new();
+
+    //Commands half the time, they're where the parsing is
+    let text = prop_oneof![any::<String>(), "/[a-z]{0,8}( [-0-9a-z./]{0,12}){0,5}", Just(String::from("/schem import ../../etc/passwd 0 0 0"))];
+    let mut runner = TestRunner::new(Config { cases: 256, failure_persistence: None, ..Config::default() });
+    runner
+        .run(&text, |text| {
+            let handled = handle_chat(harness.client_id, text, &harness.state);
+            prop_assert!(!matches!(handled, Handled::Disconnect));
+            prop_assert!(harness.state.clients.lock().contains_key(&harness.client_id));
+            Ok(())
+        })
+        .unwrap();
+}
+
+/* Regressions for panics the handler fuzzing turned up. */
+
+#[test]
+fn chest_slot_out_of_range_is_rejected() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let harness = Harness::new();
+
+    let mut m = Message::new(MessageType::ChestInvUpdate, Vec3::ZERO, 5.0, 4_000_000_000);
+    m.info2 = 0;
+    assert!(matches!(harness.handle(m), Handled::Rejected(_)));
+    assert!(harness.state.chest_reg.is_empty());
+
+    let mut m = Message::new(MessageType::ChestInvUpdate, Vec3::ZERO, 5.0, 40);
+    m.info2 = 0;
+    assert!(matches!(harness.handle(m), Handled::Rejected(_)));
+}
+
+#[test]
+fn inventory_slot_out_of_range_is_rejected() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let harness = Harness::new();
+
+    let mut m = Message::new(MessageType::ChestInvUpdate, Vec3::ZERO, 5.0, 9);
+    m.info2 = 1;
+    assert!(matches!(harness.handle(m), Handled::Rejected(_)));
+    assert_eq!(harness.state.clients.lock()[&harness.client_id].inv.inv, STARTINGITEMS);
+}
+
+#[test]
+fn request_udm_without_a_db_file() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let harness = Harness::new();
+    let _ = std::fs::remove_file(harness.dir.join("db"));
+
+    assert_eq!(harness.handle(Message::new(MessageType::RequestUdm, Vec3::ZERO, 0.0, 0)), Handled::Relay);
+}
+
+#[test]
+fn replies_to_a_hung_up_client() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let harness = Harness::new();
+    let _ = harness.state.clients.lock()[&harness.client_id].stream.lock().shutdown(std::net::Shutdown::Both);
+
+    assert_eq!(harness.handle(Message::new(MessageType::RequestSeed, Vec3::ZERO, 0.0, 0)), Handled::Relay);
+    assert_eq!(harness.handle(Message::new(MessageType::RequestUdm, Vec3::ZERO, 0.0, 0)), Handled::Relay);
+}
+
+#[test]
+fn messages_from_unknown_clients_are_rejected() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let harness = Harness::new();
+
+    let mut m = Message::new(MessageType::PlayerUpdate, Vec3::ZERO, 0.0, 0);
+    assert!(matches!(handle_message(Uuid::new_v4(), &mut m, &harness.state), Handled::Rejected(_)));
+}
+
+#[test]
+fn non_finite_block_set_is_rejected() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let harness = Harness::new();
+
+    let m = Message::new(MessageType::BlockSet, Vec3::new(f32::NAN, 10.0, 0.0), 0.0, 3);
+    assert!(matches!(harness.handle(m), Handled::Rejected(_)));
+    assert!(harness.state.csys.read().userdatamap.is_empty());
+}
+
+#[test]
+fn oversized_mob_batch_does_not_decode() {
+    let mut m = Message::new(MessageType::MobUpdateBatch, Vec3::ZERO, 0.0, 0);
+    m.count = MOB_BATCH_SIZE as u8 + 1;
+    m.msgs[0] = MobMessage::new();
+    assert!(Message::decode(&bincode::serialize(&m).unwrap()).is_none());
+}
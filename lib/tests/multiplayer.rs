@@ -0,0 +1,743 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{wait_until, TempDir, TestClient, SERIAL};
+use glam::Vec3;
+use voxelland::bulkedit;
+use voxelland::calendar::Calendar;
+use voxelland::chat;
+use voxelland::chunk::ChunkSystem;
+use voxelland::compression::{decode_payload, Compression, COMPRESSION_THRESHOLD};
+use voxelland::network::{reconnect_backoff, RECONNECT_BACKOFF_MAX, RECONNECT_BACKOFF_START};
+use voxelland::server::plugin::{MobSpawn, PluginApi};
+use voxelland::server::ratelimit::BucketLimit;
+use voxelland::game::CURRSEED;
+use voxelland::server::{Server, ServerConfig};
+use voxelland::server_types::{Message, MessageType, UdpPacket};
+use voxelland::vec::IVec3;
+use voxelland::worldgen::GeneratorSettings;
+use voxelland::worlddir::Manifest;
+
+fn start_server(dir: &Path, seed: u32) -> Server {
+    common::start_server(dir, |config| config.initial_seed = seed)
+}
+
+#[test]
+fn block_edits_propagate_and_persist_across_restart() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let spot = IVec3::new(5, 40, -7);
+
+    let server = start_server(&dir, 1234);
+    let mut a = TestClient::connect_ready(server.local_addr);
+    let mut b = TestClient::connect_ready(server.local_addr);
+
+    let mut blockset = Message::new(MessageType::BlockSet, Vec3::new(spot.x as f32, spot.y as f32, spot.z as f32), 0.0, 12);
+    blockset.infof = 1.0;
+    a.send(&blockset);
+
+    let relayed = b.expect(MessageType::BlockSet);
+    assert_eq!((relayed.x, relayed.y, relayed.z), (5.0, 40.0, -7.0));
+    assert_eq!(relayed.info, 12);
+    assert_eq!(relayed.goose, a.id.as_u64_pair());
+
+    assert_eq!(server.state.csys.read().blockat(spot), 12);
+
+    drop(a);
+    drop(b);
+    server.shutdown();
+
+    let server = start_server(&dir, 1234);
+    assert_eq!(server.state.csys.read().blockat(spot), 12);
+
+    let mut c = TestClient::connect_ready(server.local_addr);
+    c.send(&Message::new(MessageType::RequestUdm, Vec3::ZERO, 0.0, 0));
+    let header = c.expect(MessageType::Udm);
+    assert!(header.info > 0);
+    let payload = c.recv_payload(header.info as usize);
+    assert_eq!(payload, std::fs::read(dir.join("db")).unwrap());
+
+    server.shutdown();
+}
+
+#[test]
+fn chest_inventory_moves_reconcile() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let chest = IVec3::new(1, 50, 1);
+
+    let server = start_server(&dir, 99);
+    let mut a = TestClient::connect_ready(server.local_addr);
+    let mut b = TestClient::connect_ready(server.local_addr);
+
+    /* A drops 7 of item 5 into chest slot 3, picking up whatever was there. */
+    let mut put = Message::new(MessageType::ChestInvUpdate, Vec3::ZERO, 5.0, 3);
+    put.otherpos = chest;
+    put.info2 = 0;
+    put.infof = 7.0;
+    put.bo = true;
+    a.send(&put);
+
+    let echo = a.expect(MessageType::ChestInvUpdate);
+    assert_eq!((echo.x, echo.y), (0.0, 0.0));
+    let seen = b.expect(MessageType::ChestInvUpdate);
+    assert_eq!((seen.rot, seen.infof, seen.info), (5.0, 7.0, 3));
+    assert_eq!(seen.goose, a.id.as_u64_pair());
+
+    /* B empties the slot, the server hands the displaced stack back to B's mouse. */
+    let mut take = Message::new(MessageType::ChestInvUpdate, Vec3::ZERO, 0.0, 3);
+    take.otherpos = chest;
+    take.info2 = 0;
+    take.infof = 0.0;
+    take.bo = true;
+    b.send(&take);
+
+    let echo = b.expect(MessageType::ChestInvUpdate);
+    assert_eq!((echo.x, echo.y), (5.0, 7.0));
+    let seen = a.expect(MessageType::ChestInvUpdate);
+    assert_eq!((seen.rot, seen.infof), (0.0, 0.0));
+
+    assert_eq!(server.state.chest_reg.get(&chest).unwrap().inv[3], (0, 0));
+
+    server.shutdown();
+
+    let server = start_server(&dir, 99);
+    assert_eq!(server.state.chest_reg.get(&chest).unwrap().inv[3], (0, 0));
+    server.shutdown();
+}
+
+#[test]
+fn disconnects_remove_the_player() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let server = start_server(&dir, 7);
+    let mut a = TestClient::connect_ready(server.local_addr);
+    let b = TestClient::connect_ready(server.local_addr);
+    let mut c = TestClient::connect_ready(server.local_addr);
+    assert!(wait_until(|| server.state.client_count() == 3));
+
+    a.send(&Message::new(MessageType::Disconnect, Vec3::ZERO, 0.0, 0));
+    let gone = c.expect(MessageType::Disconnect);
+    assert_eq!(gone.goose, a.id.as_u64_pair());
+    assert!(wait_until(|| server.state.client_count() == 2));
+
+    /* Dropping the socket without saying goodbye has to work too. */
+    let bid = b.id;
+    drop(b);
+    let gone = c.expect(MessageType::Disconnect);
+    assert_eq!(gone.goose, bid.as_u64_pair());
+    assert!(wait_until(|| server.state.client_count() == 1));
+
+    server.shutdown();
+}
+
+#[test]
+fn reconnecting_takes_over_the_old_session() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let server = start_server(&dir, 8);
+    let mut a = TestClient::connect_ready(server.local_addr);
+    let mut b = TestClient::connect_ready(server.local_addr);
+    assert!(wait_until(|| server.state.client_count() == 2));
+
+    /* Not written to sql yet, the new session still has to get it */
+    server.state.clients.lock().get_mut(&a.id).unwrap().inv.inv[0] = (9, 42);
+
+    /* a's connection died without the server noticing, and a comes back under the same id with the token it got */
+    let session = server.state.clients.lock().get(&a.id).unwrap().session;
+    let mut again = TestClient::resume(server.local_addr, a.id, session);
+    again.send(&Message::new(MessageType::PlayerUpdate, Vec3::new(0.0, 100.0, 0.0), 0.0, 0));
+    again.expect(MessageType::TimeUpdate);
+
+    assert!(a.hung_up());
+    assert_eq!(server.state.client_count(), 2);
+    assert_eq!(server.state.clients.lock().get(&a.id).unwrap().inv.inv[0], (9, 42));
+
+    /* Nobody was told a left, and a's edits still reach them */
+    again.send(&Message::new(MessageType::BlockSet, Vec3::new(1.0, 60.0, 1.0), 0.0, 3));
+    loop {
+        let m = b.recv().expect("never got the block set");
+        assert_ne!(m.message_type, MessageType::Disconnect);
+        if m.message_type == MessageType::BlockSet {
+            assert_eq!(m.goose, a.id.as_u64_pair());
+            break;
+        }
+    }
+
+    server.shutdown();
+}
+
+#[test]
+fn someone_elses_id_does_not_take_over_their_session() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let server = start_server(&dir, 8);
+    let mut a = TestClient::connect(server.local_addr);
+    let session = a.expect(MessageType::SessionToken).goose;
+    a.ready();
+    assert_eq!(server.state.clients.lock().get(&a.id).unwrap().session, session);
+    server.state.clients.lock().get_mut(&a.id).unwrap().inv.inv[0] = (9, 42);
+
+    /* a's id goes out with everything they do, but their token only went to them */
+    let mut impostor = TestClient::connect_as(server.local_addr, a.id, Compression::None);
+    assert!(impostor.hung_up());
+    let mut guesser = TestClient::resume(server.local_addr, a.id, (1, 2));
+    assert!(guesser.hung_up());
+
+    assert_eq!(server.state.client_count(), 1);
+    assert_eq!(server.state.clients.lock().get(&a.id).unwrap().inv.inv[0], (9, 42));
+    a.send(&Message::new(MessageType::RequestSeed, Vec3::ZERO, 0.0, 0));
+    assert_eq!(a.expect(MessageType::Seed).info, 8);
+
+    server.shutdown();
+}
+
+#[test]
+fn silent_clients_time_out() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let mut config = ServerConfig::new("127.0.0.1:0", dir.clone());
+    config.client_timeout = Duration::from_millis(500);
+    let server = Server::start(config).unwrap();
+
+    let mut a = TestClient::connect_ready(server.local_addr);
+    assert!(wait_until(|| server.state.client_count() == 1));
+    assert!(wait_until(|| server.state.client_count() == 0));
+    assert!(a.hung_up());
+
+    server.shutdown();
+}
+
+#[test]
+fn reconnect_backoff_doubles_up_to_the_cap() {
+    assert_eq!(reconnect_backoff(0), RECONNECT_BACKOFF_START);
+    assert_eq!(reconnect_backoff(1), RECONNECT_BACKOFF_START * 2);
+    assert_eq!(reconnect_backoff(3), RECONNECT_BACKOFF_START * 8);
+    assert_eq!(reconnect_backoff(20), RECONNECT_BACKOFF_MAX);
+    assert_eq!(reconnect_backoff(u32::MAX), RECONNECT_BACKOFF_MAX);
+}
+
+#[test]
+fn a_world_saved_from_the_client_can_be_served() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let spot = IVec3::new(3, 70, -4);
+
+    /* What saving the client's world writes out */
+    unsafe { CURRSEED.store(5150, Ordering::Relaxed) };
+    let mut csys = ChunkSystem::new(0, 5150, 1, true);
+    csys.planet_type = 1;
+    csys.userdatamap.insert(spot, 17);
+    csys.save_current_world_to_dir(&dir).unwrap();
+
+    let server = start_server(&dir, 5150);
+    assert_eq!(server.state.csys.read().blockat(spot), 17);
+    assert_eq!(server.state.csys.read().planet_type, 1);
+
+    let mut a = TestClient::connect_ready(server.local_addr);
+    a.send(&Message::new(MessageType::RequestPt, Vec3::ZERO, 0.0, 0));
+    assert_eq!(a.expect(MessageType::Pt).info, 1);
+
+    server.shutdown();
+}
+
+#[test]
+fn local_players_play_alongside_tcp_ones() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let server = start_server(&dir, 1234);
+    let mut host = TestClient::connect_local(&server);
+    let mut guest = TestClient::connect_ready(server.local_addr);
+    assert!(wait_until(|| server.state.clients.lock().len() == 2));
+    /* Running the server is what makes you an operator, not anybody's id */
+    let api = PluginApi::new(&server.state);
+    assert!(api.is_operator(host.id));
+    assert!(!api.is_operator(guest.id));
+
+    let mut blockset = Message::new(MessageType::BlockSet, Vec3::new(1.0, 50.0, 1.0), 0.0, 12);
+    blockset.infof = 1.0;
+    host.send(&blockset);
+    assert_eq!(guest.expect(MessageType::BlockSet).info, 12);
+
+    let mut blockset = Message::new(MessageType::BlockSet, Vec3::new(2.0, 50.0, 2.0), 0.0, 5);
+    blockset.infof = 1.0;
+    guest.send(&blockset);
+    /* Our own edit comes back to us too */
+    let mut seen = host.expect(MessageType::BlockSet);
+    if seen.info == 12 {
+        seen = host.expect(MessageType::BlockSet);
+    }
+    assert_eq!((seen.x, seen.info), (2.0, 5));
+
+    host.send(&Message::new(MessageType::Disconnect, Vec3::ZERO, 0.0, 0));
+    assert!(wait_until(|| server.state.clients.lock().len() == 1));
+
+    server.shutdown();
+}
+
+#[test]
+fn opening_to_lan_takes_tcp_players() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let mut config = ServerConfig::new("127.0.0.1:0", dir.clone());
+    config.udp = false;
+    config.lan_discovery = false;
+    let mut server = Server::start(config).unwrap();
+    let mut host = TestClient::connect_local(&server);
+    assert!(server.udp_addr.is_none());
+
+    let addr = server.open_to_lan("127.0.0.1:0").unwrap();
+    assert_ne!(addr, server.local_addr);
+    assert_eq!(server.open_to_lan("127.0.0.1:0").unwrap(), addr);
+    assert!(server.udp_addr.is_some());
+
+    let mut guest = TestClient::connect_ready(addr);
+    guest.send(&Message::new(MessageType::PlayerUpdate, Vec3::new(3.0, 100.0, 3.0), 0.0, 0));
+    let seen = host.expect(MessageType::PlayerUpdate);
+    assert_eq!(seen.goose, guest.id.as_u64_pair());
+
+    server.shutdown();
+}
+
+#[test]
+fn request_seed_and_planet_type() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let server = start_server(&dir, 424242);
+    let mut a = TestClient::connect_ready(server.local_addr);
+
+    a.send(&Message::new(MessageType::RequestSeed, Vec3::ZERO, 0.0, 0));
+    assert_eq!(a.expect(MessageType::Seed).info, 424242);
+
+    a.send(&Message::new(MessageType::RequestPt, Vec3::ZERO, 0.0, 0));
+    let pt = a.expect(MessageType::Pt);
+    assert_eq!(pt.info, server.state.csys.read().planet_type as u32);
+    let yourid = a.expect(MessageType::YourId);
+    assert_eq!(yourid.goose, a.id.as_u64_pair());
+
+    server.shutdown();
+}
+
+#[test]
+fn the_seed_comes_with_the_generator_settings() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let mut manifest = Manifest::new(9001, 1);
+    manifest.generator = GeneratorSettings { caves: false, features: true, ores: false };
+    manifest.write(&dir).unwrap();
+
+    let server = start_server(&dir, 424242);
+    let mut a = TestClient::connect_ready(server.local_addr);
+
+    a.send(&Message::new(MessageType::RequestSeed, Vec3::ZERO, 0.0, 0));
+    let seed = a.expect(MessageType::Seed);
+    assert_eq!(seed.info, 9001);
+    assert_eq!(GeneratorSettings::from_bits(seed.info2), manifest.generator);
+
+    a.send(&Message::new(MessageType::RequestPt, Vec3::ZERO, 0.0, 0));
+    assert_eq!(a.expect(MessageType::Pt).info, 1);
+
+    server.shutdown();
+    GeneratorSettings::default().apply();
+}
+
+fn scrape(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    response
+}
+
+#[test]
+fn metrics_endpoint_reports_traffic() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let mut config = ServerConfig::new("127.0.0.1:0", dir.clone());
+    config.metrics_address = Some(String::from("127.0.0.1:0"));
+    let server = Server::start(config).unwrap();
+    let metrics = server.metrics_addr.unwrap();
+
+    let mut a = TestClient::connect_ready(server.local_addr);
+    let mut b = TestClient::connect_ready(server.local_addr);
+    a.send(&Message::new(MessageType::BlockSet, Vec3::new(1.0, 60.0, 1.0), 0.0, 4));
+    b.expect(MessageType::BlockSet);
+    assert!(wait_until(|| server.state.metrics.sql_commit.count() > 0));
+
+    let body = scrape(metrics);
+    assert!(body.contains("\nvoxelland_connected_clients 2\n"));
+    assert!(body.contains("\nvoxelland_messages_in_total{type=\"BlockSet\"} 1\n"));
+    /* The echo back to a and the relay to b */
+    assert!(body.contains("\nvoxelland_messages_out_total{type=\"BlockSet\"} 2\n"));
+    assert!(body.contains(&format!("\nvoxelland_bytes_in_total{{type=\"BlockSet\"}} {}\n", Message::get_serialized_size())));
+    assert!(body.contains("# TYPE voxelland_sql_commit_seconds histogram"));
+    assert!(!body.contains("\nvoxelland_sql_commit_seconds_count 0\n"));
+    assert!(!body.contains("\nvoxelland_tick_duration_seconds_count 0\n"));
+    assert!(body.contains("\nvoxelland_mobs 0\n"));
+
+    server.shutdown();
+}
+
+#[test]
+fn flooding_block_sets_gets_you_kicked() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let mut config = ServerConfig::new("127.0.0.1:0", dir.clone());
+    config.limits.block_edit = BucketLimit { capacity: 5.0, per_second: 1.0 };
+    let server = Server::start(config).unwrap();
+
+    let mut flooder = TestClient::connect_ready(server.local_addr);
+    let mut bystander = TestClient::connect_ready(server.local_addr);
+    assert!(wait_until(|| server.state.client_count() == 2));
+
+    for i in 0..100 {
+        flooder.send(&Message::new(MessageType::BlockSet, Vec3::new(i as f32, 80.0, 0.0), 0.0, 3));
+    }
+
+    let gone = bystander.expect(MessageType::Disconnect);
+    assert_eq!(gone.goose, flooder.id.as_u64_pair());
+    assert!(wait_until(|| server.state.client_count() == 1));
+
+    /* Only the burst and whatever trickled in went through */
+    let placed = server.state.csys.read().userdatamap.len();
+    assert!(placed >= 5 && placed < 20, "placed {}", placed);
+
+    server.shutdown();
+}
+
+#[test]
+fn flooding_chat_gets_you_kicked() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let mut config = ServerConfig::new("127.0.0.1:0", dir.clone());
+    config.limits.chat = BucketLimit { capacity: 5.0, per_second: 1.0 };
+    let server = Server::start(config).unwrap();
+
+    let mut flooder = TestClient::connect_ready(server.local_addr);
+    let mut bystander = TestClient::connect_ready(server.local_addr);
+    assert!(wait_until(|| server.state.client_count() == 2));
+
+    for i in 0..100 {
+        flooder.stream.write_all(&chat::encode(None, &format!("spam {}", i))).unwrap();
+    }
+
+    /* Only the burst and whatever trickled in reach anyone else */
+    let mut heard = 0;
+    loop {
+        let m = bystander.recv().expect("never saw the flooder leave");
+        match m.message_type {
+            MessageType::Chat => {
+                bystander.recv_payload(m.info as usize);
+                heard += 1;
+            }
+            MessageType::Disconnect if m.goose == flooder.id.as_u64_pair() => break,
+            _ => {}
+        }
+    }
+    assert!((5..20).contains(&heard), "heard {}", heard);
+    assert!(wait_until(|| server.state.client_count() == 1));
+
+    server.shutdown();
+}
+
+#[test]
+fn rejected_block_edits_are_reported_to_the_sender() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let mut config = ServerConfig::new("127.0.0.1:0", dir.clone());
+    config.limits.block_edit = BucketLimit { capacity: 2.0, per_second: 0.01 };
+    let server = Server::start(config).unwrap();
+
+    let mut a = TestClient::connect_ready(server.local_addr);
+
+    for seq in 1..=3 {
+        let mut m = Message::new(MessageType::BlockSet, Vec3::new(seq as f32, 80.0, 0.0), 0.0, 3);
+        m.seq = seq;
+        a.send(&m);
+    }
+
+    /* The two that fit the bucket come back as echoes the sender can match up */
+    for seq in 1..=2 {
+        let echo = a.expect(MessageType::BlockSet);
+        assert_eq!(echo.seq, seq);
+        assert_eq!(echo.goose, a.id.as_u64_pair());
+    }
+
+    let rejected = a.expect(MessageType::BlockSetRejected);
+    assert_eq!(rejected.info, 3);
+    assert_eq!(server.state.csys.read().userdatamap.len(), 2);
+
+    server.shutdown();
+}
+
+#[test]
+fn slow_payloads_do_not_hold_up_everyone_else() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let server = start_server(&dir, 8);
+    let mut a = TestClient::connect_ready(server.local_addr);
+    let mut b = TestClient::connect_ready(server.local_addr);
+    assert!(wait_until(|| server.state.client_count() == 2));
+
+    /* a sends the header and half the text, then goes quiet */
+    let line = chat::encode(None, "hello there");
+    let split = line.len() - 5;
+    a.stream.write_all(&line[..split]).unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let started = Instant::now();
+    let mut blockset = Message::new(MessageType::BlockSet, Vec3::new(1.0, 60.0, 1.0), 0.0, 3);
+    blockset.infof = 1.0;
+    b.send(&blockset);
+    assert_eq!(a.expect(MessageType::BlockSet).info, 3);
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    a.stream.write_all(&line[split..]).unwrap();
+    assert_eq!(b.expect_chat(), "hello there");
+
+    /* Same for the blocks after a bulk edit */
+    let anchor = IVec3::new(4, 60, 4);
+    let blocks: Vec<(IVec3, u32)> = (0..50).map(|i| (anchor + IVec3::new(i, 0, 0), 5)).collect();
+    let bytes = bulkedit::encode(&bulkedit::header(6), anchor, &blocks).unwrap();
+    let split = bytes.len() - 3;
+    a.stream.write_all(&bytes[..split]).unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let started = Instant::now();
+    let mut blockset = Message::new(MessageType::BlockSet, Vec3::new(2.0, 60.0, 2.0), 0.0, 7);
+    blockset.infof = 1.0;
+    b.send(&blockset);
+    assert_eq!(a.expect(MessageType::BlockSet).info, 7);
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    a.stream.write_all(&bytes[split..]).unwrap();
+    let relayed = b.expect(MessageType::BulkBlockSet);
+    assert_eq!(bulkedit::unpack(relayed.otherpos, &b.recv_payload(relayed.info as usize)), Some(blocks));
+
+    server.shutdown();
+}
+
+#[test]
+fn bulk_edits_apply_together_and_persist() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let anchor = IVec3::new(-20, 70, 30);
+    let blocks: Vec<(IVec3, u32)> = (0..600).map(|i| (anchor + IVec3::new(i % 40, i / 400, i / 40 % 10), 5)).collect();
+
+    let server = start_server(&dir, 1234);
+    let mut a = TestClient::connect_ready(server.local_addr);
+    let mut b = TestClient::connect_ready(server.local_addr);
+    a.stream.write_all(&bulkedit::encode(&bulkedit::header(4), anchor, &blocks).unwrap()).unwrap();
+
+    let relayed = b.expect(MessageType::BulkBlockSet);
+    assert_eq!(relayed.goose, a.id.as_u64_pair());
+    let payload = b.recv_payload(relayed.info as usize);
+    assert_eq!(bulkedit::unpack(relayed.otherpos, &payload), Some(blocks.clone()));
+    //The sender's echo, to confirm what it predicted
+    let echo = a.expect(MessageType::BulkBlockSet);
+    assert_eq!(echo.seq, 4);
+    a.recv_payload(echo.info as usize);
+    assert!(blocks.iter().all(|(spot, block)| server.state.csys.read().blockat(*spot) == *block));
+
+    //Nonsense is turned away without touching anything
+    let mut garbage = bulkedit::header(5);
+    garbage.info = 16;
+    a.send(&garbage);
+    a.stream.write_all(&[7u8; 16]).unwrap();
+    assert_eq!(a.expect(MessageType::BlockSetRejected).info, 5);
+    assert_eq!(server.state.csys.read().userdatamap.len(), blocks.len());
+
+    drop(a);
+    drop(b);
+    server.shutdown();
+
+    let server = start_server(&dir, 1234);
+    assert!(blocks.iter().all(|(spot, block)| server.state.csys.read().blockat(*spot) == *block));
+    server.shutdown();
+}
+
+#[test]
+fn world_sync_requests_are_cooled_down() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let server = start_server(&dir, 55);
+    let mut a = TestClient::connect_ready(server.local_addr);
+
+    a.send(&Message::new(MessageType::RequestUdm, Vec3::ZERO, 0.0, 0));
+    let header = a.expect(MessageType::Udm);
+    a.recv_payload(header.info as usize);
+
+    a.send(&Message::new(MessageType::RequestUdm, Vec3::ZERO, 0.0, 0));
+    a.send(&Message::new(MessageType::RequestSeed, Vec3::ZERO, 0.0, 0));
+    loop {
+        let m = a.recv().expect("Never received a Seed");
+        assert_ne!(m.message_type, MessageType::Udm);
+        if m.message_type == MessageType::Seed {
+            break;
+        }
+    }
+    assert_eq!(server.state.clients.lock()[&a.id].errorstrikes, 1);
+
+    server.shutdown();
+}
+
+#[test]
+fn compressed_world_and_chest_sync() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let server = start_server(&dir, 31);
+    let mut a = TestClient::connect_asking(server.local_addr, Compression::Lz4);
+    a.send(&Message::new(MessageType::PlayerUpdate, Vec3::new(0.0, 100.0, 0.0), 0.0, 0));
+    a.expect(MessageType::TimeUpdate);
+
+    for i in 0..10 {
+        a.send(&Message::new(MessageType::BlockSet, Vec3::new(i as f32, 70.0, 3.0), 0.0, 2));
+        a.expect(MessageType::BlockSet);
+    }
+    assert!(wait_until(|| server.state.queued_sql.is_empty()));
+    thread::sleep(Duration::from_millis(300));
+
+    a.send(&Message::new(MessageType::RequestUdm, Vec3::ZERO, 0.0, 0));
+    let header = a.expect(MessageType::Udm);
+    let wire = a.recv_payload(header.info as usize);
+    let db = std::fs::read(dir.join("db")).unwrap();
+    assert!(db.len() >= COMPRESSION_THRESHOLD);
+    assert_eq!(header.info2, Compression::Lz4 as u32);
+    assert!(wire.len() < db.len());
+    assert_eq!(decode_payload(&wire, header.info2).unwrap(), db);
+
+    a.send(&Message::new(MessageType::ReqChestReg, Vec3::ZERO, 0.0, 0));
+    let header = a.expect(MessageType::ChestReg);
+    let wire = a.recv_payload(header.info as usize);
+    assert_eq!(decode_payload(&wire, header.info2).unwrap(), std::fs::read(dir.join("chestdb")).unwrap());
+
+    /* Someone who didn't ask still gets it raw */
+    let mut b = TestClient::connect_ready(server.local_addr);
+    b.send(&Message::new(MessageType::RequestUdm, Vec3::ZERO, 0.0, 0));
+    let header = b.expect(MessageType::Udm);
+    assert_eq!(header.info2, Compression::None as u32);
+    assert_eq!(b.recv_payload(header.info as usize), db);
+
+    server.shutdown();
+}
+
+/// Redeems a `UdpToken` for `client` and returns a socket talking to the server's UDP port.
+fn open_udp(server: &Server, client: &mut TestClient) -> (UdpSocket, (u64, u64)) {
+    client.send(&Message::new(MessageType::UdpToken, Vec3::ZERO, 0.0, 0));
+    let reply = client.expect(MessageType::UdpToken);
+    assert_eq!(reply.info as u16, server.udp_addr.unwrap().port());
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.connect(server.udp_addr.unwrap()).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    (socket, reply.goose)
+}
+
+fn udp_transform(token: (u64, u64), seq: u32, x: f32) -> Vec<u8> {
+    UdpPacket { token, seq, message: Message::new(MessageType::PlayerUpdate, Vec3::new(x, 90.0, 0.0), 0.0, 0) }.encode()
+}
+
+fn recv_udp(socket: &UdpSocket, t: MessageType) -> UdpPacket {
+    let mut buffer = [0u8; 2048];
+    loop {
+        let n = socket.recv(&mut buffer).expect("Nothing came over udp");
+        let packet = UdpPacket::decode(&buffer[..n]).unwrap();
+        if packet.message.message_type == t {
+            return packet;
+        }
+    }
+}
+
+#[test]
+fn transforms_over_udp_with_tcp_fallback() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+
+    let server = start_server(&dir, 17);
+    let mob = server.state.spawn_mob(MobSpawn { kind: 0, position: Vec3::new(5.0, 80.0, 5.0), scale: 1.0, hostile: false }).unwrap();
+    let mut a = TestClient::connect_ready(server.local_addr);
+    let mut b = TestClient::connect_ready(server.local_addr);
+
+    let (audp, atoken) = open_udp(&server, &mut a);
+    audp.send(&udp_transform(atoken, 1, 11.0)).unwrap();
+    recv_udp(&audp, MessageType::None);
+
+    /* b hasn't opened udp, so a's transform reaches it over tcp */
+    let seen = b.expect(MessageType::PlayerUpdate);
+    assert_eq!((seen.x, seen.goose), (11.0, a.id.as_u64_pair()));
+
+    /* And so do the mobs, every fourth update */
+    for _ in 0..4 {
+        b.send(&Message::new(MessageType::PlayerUpdate, Vec3::ZERO, 0.0, 0));
+    }
+    let mobs = b.expect(MessageType::MobUpdateBatch);
+    assert_eq!((mobs.count, mobs.msgs[0].info, mobs.msgs[0].x), (1, mob, 5.0));
+
+    /* Once b says our datagrams get through, it gets them over udp instead */
+    let (budp, btoken) = open_udp(&server, &mut b);
+    budp.send(&udp_transform(btoken, 1, 0.0)).unwrap();
+    recv_udp(&budp, MessageType::None);
+    let mut confirm = Message::new(MessageType::PlayerUpdate, Vec3::ZERO, 0.0, 0);
+    confirm.bo = true;
+    b.send(&confirm);
+    b.expect(MessageType::TimeUpdate);
+
+    audp.send(&udp_transform(atoken, 2, 22.0)).unwrap();
+    let seen = recv_udp(&budp, MessageType::PlayerUpdate);
+    assert_eq!((seen.message.x, seen.message.goose), (22.0, a.id.as_u64_pair()));
+
+    for _ in 0..4 {
+        b.send(&confirm);
+    }
+    let mobs = recv_udp(&budp, MessageType::MobUpdateBatch);
+    assert_eq!((mobs.message.count, mobs.message.msgs[0].info), (1, mob));
+
+    /* Stale and forged datagrams go nowhere */
+    audp.send(&udp_transform(atoken, 2, 33.0)).unwrap();
+    audp.send(&udp_transform((1, 2), 9, 44.0)).unwrap();
+    audp.send(&udp_transform(atoken, 3, 55.0)).unwrap();
+    let seen = recv_udp(&budp, MessageType::PlayerUpdate);
+    assert_eq!(seen.message.x, 55.0);
+
+    server.shutdown();
+}
+
+#[test]
+fn time_updates_follow_the_server_calendar() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let mut config = ServerConfig::new("127.0.0.1:0", dir.clone());
+    config.udp = false;
+    config.calendar = Some(Calendar { day_length: 600.0, start_time: 123.0, lock_time: true, lock_weather: Some(2.0), ..Default::default() });
+    let mut server = Server::start(config).unwrap();
+
+    let mut client = TestClient::connect(server.local_addr);
+    client.send(&Message::new(MessageType::PlayerUpdate, Vec3::new(0.0, 100.0, 0.0), 0.0, 0));
+    let update = client.expect(MessageType::TimeUpdate);
+    assert_eq!((update.infof, update.rot, update.x, update.y), (123.0, 2.0, 600.0, 1.0));
+
+    server.shutdown();
+}
@@ -0,0 +1,96 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use voxelland::netstream::{NetStream, PayloadReader};
+
+#[test]
+fn memory_pairs_carry_bytes_both_ways() {
+    let (mut a, mut b) = NetStream::memory_pair();
+    a.write_all(b"hello").unwrap();
+    b.write_all(b"hi").unwrap();
+
+    let mut buf = [0u8; 5];
+    b.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+
+    let mut buf = [0u8; 2];
+    assert_eq!(a.peek(&mut buf).unwrap(), 2);
+    a.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hi");
+    assert!(a.peer_addr().is_err());
+}
+
+#[test]
+fn nonblocking_and_timed_out_reads_would_block() {
+    let (a, mut b) = NetStream::memory_pair();
+    let mut buf = [0u8; 4];
+
+    b.set_nonblocking(true).unwrap();
+    assert_eq!(b.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+    assert_eq!(b.peek(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+
+    b.set_nonblocking(false).unwrap();
+    b.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+    let start = Instant::now();
+    assert_eq!(b.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    drop(a);
+}
+
+#[test]
+fn blocking_reads_wake_up_when_bytes_arrive() {
+    let (mut a, mut b) = NetStream::memory_pair();
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        a.write_all(&[1, 2, 3]).unwrap();
+        a
+    });
+
+    let mut buf = [0u8; 3];
+    b.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [1, 2, 3]);
+    writer.join().unwrap();
+}
+
+#[test]
+fn closing_either_end_hangs_up() {
+    let (mut a, mut b) = NetStream::memory_pair();
+    a.write_all(b"bye").unwrap();
+    drop(a);
+
+    /* What was already sent can still be read, then it's EOF */
+    let mut buf = [0u8; 8];
+    assert_eq!(b.read(&mut buf).unwrap(), 3);
+    assert_eq!(b.read(&mut buf).unwrap(), 0);
+    assert_eq!(b.write(b"x").unwrap_err().kind(), ErrorKind::BrokenPipe);
+
+    let (mut c, d) = NetStream::memory_pair();
+    d.shutdown(Shutdown::Both).unwrap();
+    assert_eq!(c.write(b"x").unwrap_err().kind(), ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn payloads_are_read_as_they_arrive() {
+    let (mut a, mut b) = NetStream::memory_pair();
+    b.set_nonblocking(true).unwrap();
+    let mut reader = PayloadReader::new(6);
+
+    assert!(reader.poll(&mut b).unwrap().is_none());
+    a.write_all(b"hal").unwrap();
+    assert!(reader.poll(&mut b).unwrap().is_none());
+    /* Only its own bytes, the next message stays on the stream */
+    a.write_all(b"f it!next").unwrap();
+    assert_eq!(reader.poll(&mut b).unwrap().unwrap(), b"half i");
+
+    let mut rest = [0u8; 6];
+    b.read_exact(&mut rest).unwrap();
+    assert_eq!(&rest, b"t!next");
+
+    /* Hanging up halfway through isn't just a slow sender */
+    let mut reader = PayloadReader::new(4);
+    a.write_all(b"ab").unwrap();
+    drop(a);
+    assert!(reader.poll(&mut b).is_err());
+}
@@ -0,0 +1,203 @@
+mod common;
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use common::{wait_until, TempDir, TestClient, SERIAL};
+use glam::Vec3;
+use uuid::Uuid;
+use voxelland::server::plugin::{BlockEdit, MobSpawn, Plugin, PluginApi, Verdict};
+use voxelland::server::Server;
+use voxelland::server_types::{Message, MessageType};
+use voxelland::vec::IVec3;
+
+fn start_server(dir: &Path, plugins: Vec<Arc<dyn Plugin>>) -> Server {
+    common::start_server(dir, |config| {
+        config.initial_seed = 1234;
+        config.plugins = plugins;
+    })
+}
+
+/// No building within `radius` blocks of the origin, and says so to whoever tries.
+struct SpawnProtection {
+    radius: i32,
+}
+
+impl Plugin for SpawnProtection {
+    fn name(&self) -> &str {
+        "spawn protection"
+    }
+
+    fn before_block_set(&self, api: &PluginApi, player: Uuid, edit: &mut BlockEdit) -> Verdict {
+        if edit.spot.x.abs() <= self.radius && edit.spot.z.abs() <= self.radius {
+            api.send_message(player, "Spawn is protected");
+            Verdict::Veto
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+struct Welcome;
+
+impl Plugin for Welcome {
+    fn name(&self) -> &str {
+        "welcome"
+    }
+
+    fn on_join(&self, api: &PluginApi, player: Uuid) -> Verdict {
+        api.send_message(player, "Welcome!");
+        Verdict::Allow
+    }
+
+    fn on_chat(&self, api: &PluginApi, player: Uuid, text: &mut String) -> Verdict {
+        if text == "/ping" {
+            api.send_message(player, "pong");
+            return Verdict::Veto;
+        }
+        *text = text.replace("darn", "****");
+        Verdict::Allow
+    }
+}
+
+#[test]
+fn vetoed_block_edits_are_rejected_and_leave_the_world_alone() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let mut server = start_server(&dir, vec![Arc::new(SpawnProtection { radius: 8 })]);
+
+    let mut client = TestClient::connect_ready(server.local_addr);
+    let before = server.state.csys.read().blockat(IVec3::new(2, 60, 2));
+
+    let mut edit = Message::new(MessageType::BlockSet, Vec3::new(2.0, 60.0, 2.0), 0.0, 5);
+    edit.seq = 7;
+    client.send(&edit);
+
+    assert_eq!(client.expect_chat(), "Spawn is protected");
+    let rejected = client.expect(MessageType::BlockSetRejected);
+    assert_eq!(rejected.info, 7);
+    assert_eq!(server.state.csys.read().blockat(IVec3::new(2, 60, 2)), before);
+
+    //Outside the protected area goes through
+    client.send(&Message::new(MessageType::BlockSet, Vec3::new(20.0, 60.0, 20.0), 0.0, 5));
+    assert!(wait_until(|| server.state.csys.read().blockat(IVec3::new(20, 60, 20)) == 5));
+
+    server.shutdown();
+}
+
+struct Replace;
+
+impl Plugin for Replace {
+    fn name(&self) -> &str {
+        "replace"
+    }
+
+    fn before_block_set(&self, _api: &PluginApi, _player: Uuid, edit: &mut BlockEdit) -> Verdict {
+        if edit.block == 5 {
+            edit.block = 6;
+        }
+        Verdict::Allow
+    }
+}
+
+#[test]
+fn plugins_can_change_what_gets_placed() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let mut server = start_server(&dir, vec![Arc::new(Replace)]);
+
+    let mut placer = TestClient::connect_ready(server.local_addr);
+    let mut watcher = TestClient::connect_ready(server.local_addr);
+
+    placer.send(&Message::new(MessageType::BlockSet, Vec3::new(3.0, 60.0, 3.0), 0.0, 5));
+    let relayed = watcher.expect(MessageType::BlockSet);
+    assert_eq!(relayed.info, 6);
+    assert_eq!(server.state.csys.read().blockat(IVec3::new(3, 60, 3)), 6);
+
+    server.shutdown();
+}
+
+#[test]
+fn join_and_chat_hooks_talk_to_players() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let mut server = start_server(&dir, vec![Arc::new(Welcome)]);
+
+    //The greeting comes in before anything else the server has to say
+    let mut alice = TestClient::connect(server.local_addr);
+    assert_eq!(alice.expect_chat(), "Welcome!");
+    alice.ready();
+    let mut bob = TestClient::connect(server.local_addr);
+    assert_eq!(bob.expect_chat(), "Welcome!");
+    bob.ready();
+
+    //Commands only answer the one who asked
+    alice.say("/ping");
+    assert_eq!(alice.expect_chat(), "pong");
+
+    bob.say("oh darn");
+    assert_eq!(alice.expect_chat(), "oh ****");
+    assert_eq!(bob.expect_chat(), "oh ****");
+
+    server.shutdown();
+}
+
+struct Gatekeeper {
+    ticks: AtomicU32,
+}
+
+impl Plugin for Gatekeeper {
+    fn name(&self) -> &str {
+        "gatekeeper"
+    }
+
+    fn on_chest_open(&self, _api: &PluginApi, _player: Uuid, chest: IVec3) -> Verdict {
+        if chest.x < 0 {
+            Verdict::Veto
+        } else {
+            Verdict::Allow
+        }
+    }
+
+    fn on_mob_spawn(&self, _api: &PluginApi, spawn: &mut MobSpawn) -> Verdict {
+        if spawn.hostile {
+            Verdict::Veto
+        } else {
+            spawn.scale = 2.0;
+            Verdict::Allow
+        }
+    }
+
+    fn on_tick(&self, _api: &PluginApi, _delta_time: f32) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn chest_mob_and_tick_hooks_run() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new();
+    let gatekeeper = Arc::new(Gatekeeper { ticks: AtomicU32::new(0) });
+    let mut server = start_server(&dir, vec![gatekeeper.clone()]);
+
+    let mut client = TestClient::connect_ready(server.local_addr);
+
+    let mut open = Message::new(MessageType::ChestOpen, Vec3::ZERO, 0.0, 0);
+    open.otherpos = IVec3::new(-4, 60, 0);
+    client.send(&open);
+    assert!(!client.expect(MessageType::ChestOpen).bo);
+
+    open.otherpos = IVec3::new(4, 60, 0);
+    client.send(&open);
+    assert!(client.expect(MessageType::ChestOpen).bo);
+
+    let hostile = MobSpawn { kind: 0, position: Vec3::new(0.0, 80.0, 0.0), scale: 1.0, hostile: true };
+    assert_eq!(server.state.spawn_mob(hostile), None);
+    let friendly = MobSpawn { hostile: false, ..hostile };
+    assert!(server.state.spawn_mob(friendly).is_some());
+
+    assert!(wait_until(|| gatekeeper.ticks.load(Ordering::Relaxed) > 0));
+
+    server.shutdown();
+}
@@ -0,0 +1,91 @@
+use voxelland::prediction::{PendingEdit, PendingEdits};
+use voxelland::vec::IVec3;
+
+fn spot(x: i32) -> IVec3 {
+    IVec3::new(x, 70, 0)
+}
+
+fn place(edits: &mut PendingEdits, at: IVec3, old: u32, new: u32) -> u32 {
+    let seq = edits.next_seq();
+    edits.push(PendingEdit { seq, blocks: vec![(at, old, new)], cost: vec![(new, 1)], refund: Vec::new(), yields: None, history: None });
+    seq
+}
+
+#[test]
+fn sequence_ids_skip_zero() {
+    let mut edits = PendingEdits::new();
+    let first = edits.next_seq();
+    assert_ne!(first, 0);
+    assert_ne!(edits.next_seq(), first);
+}
+
+#[test]
+fn confirmed_edit_needs_no_redraw() {
+    let mut edits = PendingEdits::new();
+    let seq = place(&mut edits, spot(0), 0, 5);
+
+    let (edit, corrections) = edits.confirm(seq, &[(spot(0), 5)]).unwrap();
+    assert_eq!(edit.cost, vec![(5, 1)]);
+    assert!(corrections.is_empty());
+    assert!(edits.is_empty());
+    assert!(edits.confirm(seq, &[(spot(0), 5)]).is_none());
+}
+
+#[test]
+fn server_correction_is_redrawn() {
+    let mut edits = PendingEdits::new();
+    let seq = place(&mut edits, spot(0), 0, 5);
+
+    let (_, corrections) = edits.confirm(seq, &[(spot(0), 6)]).unwrap();
+    assert_eq!(corrections, vec![(spot(0), 6)]);
+}
+
+#[test]
+fn rejected_edit_restores_the_old_block() {
+    let mut edits = PendingEdits::new();
+    let seq = place(&mut edits, spot(0), 3, 0);
+
+    let (edit, restore) = edits.reject(seq).unwrap();
+    assert_eq!(restore, vec![(spot(0), 3)]);
+    assert_eq!(edit.blocks, vec![(spot(0), 3, 0)]);
+    assert!(!edits.is_pending(spot(0)));
+}
+
+#[test]
+fn rejecting_an_edit_under_a_later_one_leaves_the_later_one_drawn() {
+    let mut edits = PendingEdits::new();
+    let first = place(&mut edits, spot(0), 0, 5);
+    let second = place(&mut edits, spot(0), 5, 6);
+
+    let (_, restore) = edits.reject(first).unwrap();
+    assert!(restore.is_empty());
+
+    /* The later one now restores to what was there before both */
+    let (_, restore) = edits.reject(second).unwrap();
+    assert_eq!(restore, vec![(spot(0), 0)]);
+}
+
+#[test]
+fn foreign_edits_on_pending_spots_are_absorbed() {
+    let mut edits = PendingEdits::new();
+    let seq = place(&mut edits, spot(0), 0, 5);
+
+    assert!(edits.absorb_foreign(spot(0), 9));
+    assert!(!edits.absorb_foreign(spot(1), 9));
+
+    let (_, restore) = edits.reject(seq).unwrap();
+    assert_eq!(restore, vec![(spot(0), 9)]);
+}
+
+#[test]
+fn correction_under_a_later_edit_is_absorbed() {
+    let mut edits = PendingEdits::new();
+    let first = place(&mut edits, spot(0), 0, 5);
+    let second = place(&mut edits, spot(0), 5, 6);
+
+    let (_, corrections) = edits.confirm(first, &[(spot(0), 7)]).unwrap();
+    assert!(corrections.is_empty());
+
+    let (_, restore) = edits.reject(second).unwrap();
+    assert_eq!(restore, vec![(spot(0), 7)]);
+}
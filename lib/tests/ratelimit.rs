@@ -0,0 +1,85 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use voxelland::server::ratelimit::{BucketLimit, QueryLimiter, RateLimiter, RateLimits, TokenBucket, MAX_QUERY_SOURCES};
+use voxelland::server_types::MessageType;
+
+#[test]
+fn bucket_allows_a_burst_then_refills() {
+    let mut bucket = TokenBucket::new(BucketLimit { capacity: 3.0, per_second: 2.0 });
+    let start = Instant::now();
+
+    assert!(bucket.take_at(start));
+    assert!(bucket.take_at(start));
+    assert!(bucket.take_at(start));
+    assert!(!bucket.take_at(start));
+
+    assert!(!bucket.take_at(start + Duration::from_millis(400)));
+    assert!(bucket.take_at(start + Duration::from_millis(600)));
+
+    /* A long pause never banks more than the capacity */
+    let later = start + Duration::from_secs(60);
+    for _ in 0..3 {
+        assert!(bucket.take_at(later));
+    }
+    assert!(!bucket.take_at(later));
+}
+
+#[test]
+fn world_sync_has_a_cooldown() {
+    let limits = RateLimits { world_sync_cooldown: Duration::from_secs(5), ..RateLimits::default() };
+    let mut limiter = RateLimiter::new(&limits);
+    let start = Instant::now();
+
+    assert!(limiter.allow_at(MessageType::RequestUdm, start));
+    assert!(!limiter.allow_at(MessageType::RequestUdm, start + Duration::from_secs(4)));
+    assert!(limiter.allow_at(MessageType::RequestUdm, start + Duration::from_secs(6)));
+
+    /* Categories don't share budgets, and leaving is never limited */
+    assert!(limiter.allow_at(MessageType::ReqChestReg, start + Duration::from_secs(6)));
+    for _ in 0..100 {
+        assert!(limiter.allow_at(MessageType::Disconnect, start));
+    }
+}
+
+#[test]
+fn chat_and_chests_have_budgets() {
+    let limits = RateLimits { chat: BucketLimit { capacity: 2.0, per_second: 0.1 }, inventory: BucketLimit { capacity: 1.0, per_second: 0.1 }, ..RateLimits::default() };
+    let mut limiter = RateLimiter::new(&limits);
+    let start = Instant::now();
+
+    assert!(limiter.allow_at(MessageType::Chat, start));
+    assert!(limiter.allow_at(MessageType::Chat, start));
+    assert!(!limiter.allow_at(MessageType::Chat, start));
+
+    /* Opening a chest comes out of the same budget as moving things around in one */
+    assert!(limiter.allow_at(MessageType::ChestOpen, start));
+    assert!(!limiter.allow_at(MessageType::ChestInvUpdate, start));
+}
+
+#[test]
+fn status_queries_are_limited_per_address() {
+    let mut limiter = QueryLimiter::new(BucketLimit { capacity: 2.0, per_second: 1.0 });
+    let victim: IpAddr = "10.0.0.1".parse().unwrap();
+    let other: IpAddr = "10.0.0.2".parse().unwrap();
+    let start = Instant::now();
+
+    assert!(limiter.allow_at(victim, start));
+    assert!(limiter.allow_at(victim, start));
+    assert!(!limiter.allow_at(victim, start));
+
+    /* Someone spoofing the victim doesn't use up anyone else's answers */
+    assert!(limiter.allow_at(other, start));
+    assert!(limiter.allow_at(victim, start + Duration::from_secs(1)));
+}
+
+#[test]
+fn spoofing_many_addresses_cant_grow_the_query_limiter_forever() {
+    let mut limiter = QueryLimiter::new(BucketLimit { capacity: 1.0, per_second: 0.01 });
+    let start = Instant::now();
+    let answered = (0..10_000u32).filter(|i| limiter.allow_at(IpAddr::from(i.to_be_bytes()), start)).count();
+    assert_eq!(answered, MAX_QUERY_SOURCES);
+
+    /* Once they've all been quiet long enough there's room again */
+    assert!(limiter.allow_at("10.9.9.9".parse().unwrap(), start + Duration::from_secs(200)));
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::TempDir;
+use voxelland::saves::{self, Saves, THUMBNAIL_FILE, THUMBNAIL_WIDTH};
+use voxelland::worldgen::WorldOptions;
+use voxelland::worlddir::{self, Manifest, BACKUP_DIR, CLIENT_DIR};
+
+fn seed(seed: u32) -> WorldOptions {
+    WorldOptions { seed_text: seed.to_string(), ..Default::default() }
+}
+
+#[test]
+fn worlds_are_created_listed_renamed_copied_and_deleted() {
+    let temp = TempDir::new();
+    let saves = Saves::new(temp.0.join("saves"));
+    assert!(saves.list().is_empty());
+
+    let first = saves.create("My World", &seed(11)).unwrap();
+    assert_eq!(first.dir, saves.root.join("my-world"));
+    assert_eq!((first.name(), first.manifest.seed), ("My World", 11));
+
+    //Same name, its own folder
+    let second = saves.create("My World", &seed(12)).unwrap();
+    assert_eq!(second.dir, saves.root.join("my-world-2"));
+
+    //Last played first
+    let mut manifest = first.manifest.clone();
+    manifest.last_played += 100;
+    manifest.write(&first.dir).unwrap();
+    let listed = saves.list();
+    assert_eq!(listed.iter().map(|s| s.dir.clone()).collect::<Vec<_>>(), vec![first.dir.clone(), second.dir.clone()]);
+
+    let renamed = saves.rename(&listed[1], "Skyblock").unwrap();
+    assert_eq!(renamed.dir, second.dir);
+    assert_eq!(Manifest::read(&second.dir).unwrap().unwrap().name, "Skyblock");
+
+    //Copies leave the backups and the game's own copy behind
+    std::fs::create_dir_all(first.dir.join(BACKUP_DIR).join("old")).unwrap();
+    std::fs::create_dir_all(first.dir.join(CLIENT_DIR).join("mp")).unwrap();
+    std::fs::write(first.dir.join("chestdb"), b"chests").unwrap();
+    let copy = saves.duplicate(&listed[0], "My World copy").unwrap();
+    assert_eq!(copy.manifest.seed, 11);
+    assert_eq!(copy.name(), "My World copy");
+    assert_eq!(std::fs::read(copy.dir.join("chestdb")).unwrap(), b"chests");
+    assert!(!copy.dir.join(BACKUP_DIR).exists());
+    assert!(!copy.dir.join(CLIENT_DIR).exists());
+    assert_eq!(saves.list().len(), 3);
+
+    saves.delete(&copy).unwrap();
+    assert!(!copy.dir.exists());
+    assert_eq!(saves.list().len(), 2);
+
+    //Nothing outside the saves folder goes
+    let mut outside = first.clone();
+    outside.dir = temp.0.clone();
+    assert!(saves.delete(&outside).is_err());
+    assert!(temp.0.exists());
+}
+
+#[test]
+fn the_old_singleplayer_world_moves_into_the_list() {
+    let temp = TempDir::new();
+    let saves = Saves::new(temp.0.join("saves"));
+    let legacy = temp.0.join("singleplayer");
+    std::fs::create_dir_all(legacy.join("world/5")).unwrap();
+    std::fs::write(legacy.join("world/5/pt"), "1\n").unwrap();
+    std::fs::write(legacy.join("world/5/seed"), "5\n").unwrap();
+
+    let slot = saves.import_legacy(&legacy, 5).unwrap().unwrap();
+    assert!(!legacy.exists());
+    assert_eq!(slot.name(), "World");
+    assert_eq!((slot.manifest.seed, slot.manifest.planet_type), (5, 1));
+    assert_eq!(saves.list(), vec![slot]);
+
+    //Only once
+    assert_eq!(saves.import_legacy(&legacy, 5).unwrap(), None);
+}
+
+#[test]
+fn names_make_safe_folders() {
+    assert_eq!(saves::folder_name("My World"), "my-world");
+    assert_eq!(saves::folder_name("  ../../etc/passwd "), "etc-passwd");
+    assert_eq!(saves::folder_name("???"), "world");
+    assert_eq!(saves::folder_name(""), "world");
+}
+
+#[test]
+fn played_times_read_naturally() {
+    let now = 1_000_000;
+    assert_eq!(saves::played_ago(now - 5, now), "just now");
+    assert_eq!(saves::played_ago(now - 60, now), "1 minute ago");
+    assert_eq!(saves::played_ago(now - 7200, now), "2 hours ago");
+    assert_eq!(saves::played_ago(now - 86400 * 3, now), "3 days ago");
+    //Clocks that went backwards
+    assert_eq!(saves::played_ago(now + 50, now), "just now");
+}
+
+#[test]
+fn thumbnails_are_flipped_and_shrunk() {
+    let temp = TempDir::new();
+    let dir = temp.0.clone();
+    worlddir::open(&dir, 1).unwrap();
+
+    //GL reads bottom row first, make the bottom half red
+    let (width, height) = (512, 288);
+    let mut pixels = Vec::new();
+    for y in 0..height {
+        for _ in 0..width {
+            pixels.extend_from_slice(if y < height / 2 { &[255, 0, 0, 255] } else { &[0, 0, 255, 255] });
+        }
+    }
+    saves::save_thumbnail(&dir, width, height, pixels).unwrap();
+
+    let thumb = image::open(dir.join(THUMBNAIL_FILE)).unwrap().to_rgba8();
+    assert_eq!(thumb.dimensions(), (THUMBNAIL_WIDTH, 144));
+    assert_eq!(thumb.get_pixel(10, 5).0, [0, 0, 255, 255]);
+    assert_eq!(thumb.get_pixel(10, 140).0, [255, 0, 0, 255]);
+
+    assert!(saves::save_thumbnail(&dir, 10, 10, vec![0; 3]).is_err());
+}
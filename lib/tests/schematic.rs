@@ -0,0 +1,198 @@
+mod common;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use common::{TempDir, SERIAL};
+use parking_lot::Mutex;
+use uuid::Uuid;
+use vox_format::data::VoxData;
+use vox_format::types::{Model, Size};
+use voxelland::blockinfo::Blocks;
+use voxelland::compression::Compression;
+use voxelland::inventory::Inventory;
+use voxelland::netstream::NetStream;
+use voxelland::schematic::{Schematic, MAX_VOX_SIZE};
+use voxelland::server::plugin::{Plugin, PluginApi, Verdict};
+use voxelland::server::schematics::SchematicCommands;
+use voxelland::server::{handle_chat, Client, Handled, ServerConfig, ServerState};
+use voxelland::specialblocks::door::{DOORTOP_BITS, OPPOSITEDOOR_BITS};
+use voxelland::vec::IVec3;
+
+/// A player over an in-memory stream, with our end of it to keep it open.
+fn join(state: &ServerState) -> (Uuid, NetStream) {
+    let id = Uuid::new_v4();
+    let (ours, theirs) = NetStream::memory_pair();
+    let inv = Inventory { dirty: false, inv: [(0, 0); 8] };
+    state.clients.lock().insert(id, Client::new(Arc::new(Mutex::new(theirs)), inv, &state.limits, Compression::None));
+    (id, ours)
+}
+
+fn facing(id: u32, direction: u32) -> u32 {
+    let mut block = id;
+    Blocks::set_direction_bits(&mut block, direction);
+    block
+}
+
+/// A door facing +x on top of a plank, a chest of 3 apples next to it, in a 3x2x2 box. In the order `capture` finds them.
+fn little_house() -> Schematic {
+    let mut chest = [(0, 0); 32];
+    chest[4] = (40, 3);
+    Schematic {
+        size: IVec3::new(3, 2, 2),
+        blocks: vec![
+            (IVec3::new(0, 0, 0), 10),
+            (IVec3::new(2, 0, 1), facing(21, 3)),
+            (IVec3::new(0, 1, 0), facing(19, 1) | DOORTOP_BITS | OPPOSITEDOOR_BITS),
+        ],
+        chests: vec![(IVec3::new(2, 0, 1), chest)],
+    }
+}
+
+#[test]
+fn blocks_turn_with_the_build() {
+    assert_eq!(Blocks::rotate_direction(facing(19, 3), 1), facing(19, 0));
+    assert_eq!(Blocks::rotate_direction(facing(21, 0) | DOORTOP_BITS, 2), facing(21, 2) | DOORTOP_BITS);
+    //Nothing to turn on a plain block
+    assert_eq!(Blocks::rotate_direction(10 | (1 << 16), 1), 10 | (1 << 16));
+
+    let house = little_house();
+    let turned = house.rotated(1);
+    assert_eq!(turned.size, IVec3::new(2, 2, 3));
+    //(x, z) to (-z, x), back inside the box
+    assert_eq!(turned.blocks[0], (IVec3::new(1, 0, 0), 10));
+    assert_eq!(turned.blocks[1], (IVec3::new(0, 0, 2), facing(21, 0)));
+    assert_eq!(turned.blocks[2], (IVec3::new(1, 1, 0), facing(19, 2) | DOORTOP_BITS | OPPOSITEDOOR_BITS));
+    assert_eq!(turned.chests[0].0, IVec3::new(0, 0, 2));
+
+    assert_eq!(house.rotated(4), house);
+    assert_eq!(house.rotated(1).rotated(3), house);
+    assert_eq!(house.rotated(2).rotated(2), house);
+}
+
+#[test]
+fn pasting_fills_the_whole_box() {
+    let placed = little_house().placed(IVec3::new(100, 50, -7));
+    assert_eq!(placed.len(), 12);
+    assert!(placed.contains(&(IVec3::new(100, 50, -7), 10)));
+    assert!(placed.contains(&(IVec3::new(102, 50, -6), facing(21, 3))));
+    assert!(placed.contains(&(IVec3::new(101, 51, -6), 0)));
+}
+
+#[test]
+fn schematics_save_and_load() {
+    let temp = TempDir::new();
+    let house = little_house();
+
+    let path = temp.0.join("house.vlschem");
+    house.save(&path).unwrap();
+    assert_eq!(Schematic::load(&path).unwrap(), house);
+
+    //Not ours, cut short, or claiming blocks outside its box
+    assert!(Schematic::from_bytes(b"PK\x03\x04 a zip file").is_err());
+    let bytes = house.to_bytes();
+    assert!(Schematic::from_bytes(&bytes[..bytes.len() - 5]).is_err());
+    let mut stray = house.clone();
+    stray.blocks.push((IVec3::new(3, 0, 0), 1));
+    assert!(Schematic::from_bytes(&stray.to_bytes()).is_err());
+}
+
+#[test]
+fn vox_files_are_builds_without_flags() {
+    let temp = TempDir::new();
+    let house = little_house();
+
+    let path = temp.0.join("house.vox");
+    house.save(&path).unwrap();
+    let back = Schematic::load(&path).unwrap();
+    assert_eq!(back.size, house.size);
+    assert_eq!(back.blocks, vec![(IVec3::new(0, 0, 0), 10), (IVec3::new(2, 0, 1), 21), (IVec3::new(0, 1, 0), 19)]);
+    assert!(back.chests.is_empty());
+
+    //Read like the build preview reads them, z is up
+    let vox = vox_format::from_file(&path).unwrap();
+    assert_eq!((vox.models[0].size.x, vox.models[0].size.y, vox.models[0].size.z), (3, 2, 2));
+    //And the ones that come with the game read the same
+    let build = Schematic::load(&PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../assets/voxelmodels/build1.vox")).unwrap();
+    assert!(!build.blocks.is_empty());
+
+    let mut big = house.clone();
+    big.size.x = MAX_VOX_SIZE + 1;
+    assert!(big.to_vox().is_err());
+
+    //A .vox can say it's any size, it's held to the same limit as our own
+    let huge = VoxData { models: vec![Model { size: Size::new(256, 256, 256), voxels: Vec::new() }], ..Default::default() };
+    assert!(Schematic::from_vox(&huge).is_err());
+}
+
+#[test]
+fn schem_commands_copy_and_paste_the_world() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let temp = TempDir::new();
+    let mut config = ServerConfig::new("127.0.0.1:0", temp.0.join("world"));
+    config.initial_seed = 77;
+    config.schematics_dir = Some(temp.0.join("schematics"));
+    config.operator_password = Some(String::from("hunter2"));
+    let state = ServerState::load(&config).unwrap();
+    let api = PluginApi::new(&state);
+    let (player, _stream) = join(&state);
+    assert!(matches!(handle_chat(player, String::from("/op hunter3"), &state), Handled::Rejected(_)));
+    assert!(!api.is_operator(player));
+    assert!(matches!(handle_chat(player, String::from("/op hunter2"), &state), Handled::Private));
+    assert!(api.is_operator(player));
+    let plugin = SchematicCommands::new(temp.0.join("schematics"));
+
+    let origin = IVec3::new(0, 150, 0);
+    let house = little_house();
+    for (spot, block) in house.placed(origin) {
+        api.set_block(spot, block);
+    }
+    api.set_chest(origin + house.chests[0].0, house.chests[0].1);
+
+    let mut text = String::from("/schem export House 2 151 1 0 150 0");
+    assert_eq!(plugin.on_chat(&api, player, &mut text), Verdict::Veto);
+    assert!(temp.0.join("schematics/house.vlschem").exists());
+    assert_eq!(Schematic::load(&temp.0.join("schematics/house.vlschem")).unwrap(), house);
+    //Other chat goes through
+    assert_eq!(plugin.on_chat(&api, player, &mut String::from("/schematics are neat")), Verdict::Allow);
+
+    //Something in the way where the turned house has air
+    let at = IVec3::new(20, 150, 20);
+    api.set_block(at + IVec3::new(0, 0, 1), 5);
+    plugin.run(&api, player, "/schem import house 20 150 20 90").unwrap();
+    for (spot, block) in house.rotated(1).placed(at) {
+        assert_eq!(api.block_at(spot), block, "at {}", spot);
+    }
+    assert_eq!(api.chest(at + IVec3::new(0, 0, 2)).unwrap()[4], (40, 3));
+
+    //Keeping the air out of it leaves what's there
+    let at = IVec3::new(40, 150, 40);
+    api.set_block(at + IVec3::new(1, 1, 1), 5);
+    plugin.run(&api, player, "/schem import house 40 150 40 -a").unwrap();
+    assert_eq!(api.block_at(at + IVec3::new(1, 1, 1)), 5);
+    assert_eq!(api.block_at(at), 10);
+
+    assert!(plugin.run(&api, player, "/schem import house 1 2 3 45").is_err());
+    assert!(plugin.run(&api, player, "/schem import nothing 1 2 3").is_err());
+    assert!(plugin.run(&api, player, "/schem export big 0 0 0 1000 100 1000").is_err());
+    //Off the end of the coordinates is a mistake, not a crash
+    assert!(plugin.run(&api, player, "/schem export far -2147483648 0 0 2147483647 0 0").is_err());
+    assert!(plugin.run(&api, player, "/schem import house 2147483647 0 0").unwrap_err().contains("Usage"));
+    //Names stay in the folder
+    assert_eq!(plugin.path("../../etc/passwd"), temp.0.join("schematics/etc-passwd.vlschem"));
+    assert_eq!(plugin.path("Tree.VOX"), temp.0.join("schematics/tree.vox"));
+    assert_eq!(plugin.run(&api, player, "/schem list").unwrap(), "house.vlschem");
+
+    //Only for operators, and being one goes with the session rather than the id
+    let (guest, _guest_stream) = join(&state);
+    assert!(plugin.run(&api, guest, "/schem list").is_err());
+    assert_eq!(plugin.on_chat(&api, guest, &mut String::from("/schem import house 60 150 60")), Verdict::Veto);
+    assert_eq!(api.block_at(IVec3::new(60, 150, 60)), 0);
+    assert!(plugin.run(&api, Uuid::new_v4(), "/schem list").is_err());
+
+    //Nor too big to paste in one go
+    let mut tower = little_house();
+    tower.size = IVec3::new(100, 100, 100);
+    tower.save(&temp.0.join("schematics/tower.vlschem")).unwrap();
+    assert!(plugin.run(&api, player, "/schem import tower 0 0 0").unwrap_err().contains("at most"));
+}
@@ -0,0 +1,259 @@
+mod common;
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{TempDir, SERIAL};
+use glam::Vec3;
+use parking_lot::Mutex;
+use uuid::Uuid;
+use voxelland::compression::Compression;
+use voxelland::inventory::Inventory;
+use voxelland::netstream::NetStream;
+use voxelland::server::plugin::{BlockEdit, MobSpawn, Plugin, PluginApi, Verdict};
+use voxelland::server::scripting::ScriptHost;
+use voxelland::server::{Client, ServerConfig, ServerState};
+use voxelland::server_types::{Message, MessageType};
+use voxelland::vec::IVec3;
+
+fn examples() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../scripts/examples")
+}
+
+/// A world with no network, and a scripts folder next to it.
+struct Headless {
+    _dir: TempDir,
+    scripts: PathBuf,
+    state: ServerState,
+}
+
+impl Headless {
+    fn new(scripts: &[&str]) -> Headless {
+        let dir = TempDir::new();
+        let scriptdir = dir.join("scripts");
+        std::fs::create_dir_all(&scriptdir).unwrap();
+        for name in scripts {
+            std::fs::copy(examples().join(name), scriptdir.join(name)).unwrap();
+        }
+
+        let mut config = ServerConfig::new("127.0.0.1:0", dir.join("world"));
+        config.initial_seed = 77;
+        let state = ServerState::load(&config).unwrap();
+
+        Headless { _dir: dir, scripts: scriptdir, state }
+    }
+
+    /// A player over an in-memory stream, returns our end of it.
+    fn join(&self) -> (Uuid, NetStream) {
+        let id = Uuid::new_v4();
+        let (ours, theirs) = NetStream::memory_pair();
+        ours.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let inv = Inventory { dirty: false, inv: [(0, 0); 8] };
+        let client = Client::new(Arc::new(Mutex::new(theirs)), inv, &self.state.limits, Compression::None);
+        self.state.clients.lock().insert(id, client);
+        (id, ours)
+    }
+
+    fn api(&self) -> PluginApi {
+        PluginApi::new(&self.state)
+    }
+}
+
+fn read_message(stream: &mut NetStream) -> Message {
+    let mut buffer = vec![0u8; Message::get_serialized_size()];
+    stream.read_exact(&mut buffer).unwrap();
+    bincode::deserialize(&buffer).unwrap()
+}
+
+fn read_chat(stream: &mut NetStream) -> String {
+    let header = read_message(stream);
+    assert_eq!(header.message_type, MessageType::Chat);
+    let mut text = vec![0u8; header.info as usize];
+    stream.read_exact(&mut text).unwrap();
+    String::from_utf8(text).unwrap()
+}
+
+#[test]
+fn spawn_protection_example_guards_spawn() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let world = Headless::new(&["spawn_protection.rhai"]);
+    let host = ScriptHost::new(&world.scripts);
+    assert_eq!(host.loaded(), vec!["spawn_protection.rhai"]);
+
+    let (player, mut stream) = world.join();
+
+    let mut near = BlockEdit { spot: IVec3::new(3, 60, -10), block: 5 };
+    assert_eq!(host.before_block_set(&world.api(), player, &mut near), Verdict::Veto);
+    assert!(read_chat(&mut stream).starts_with("Spawn is protected"));
+
+    let mut far = BlockEdit { spot: IVec3::new(40, 60, 0), block: 5 };
+    assert_eq!(host.before_block_set(&world.api(), player, &mut far), Verdict::Allow);
+    assert_eq!(far.block, 5);
+
+    assert_eq!(host.on_chest_open(&world.api(), player, IVec3::new(0, 60, 0)), Verdict::Veto);
+    assert_eq!(host.on_chest_open(&world.api(), player, IVec3::new(0, 60, 100)), Verdict::Allow);
+}
+
+#[test]
+fn welcome_example_remembers_visits() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let world = Headless::new(&["welcome.rhai"]);
+    let host = ScriptHost::new(&world.scripts);
+
+    let (player, mut stream) = world.join();
+    assert_eq!(host.on_join(&world.api(), player), Verdict::Allow);
+    assert_eq!(read_chat(&mut stream), "Welcome! Press T to chat.");
+
+    assert_eq!(host.on_join(&world.api(), player), Verdict::Allow);
+    assert_eq!(read_chat(&mut stream), "Welcome back! Visit number 2.");
+
+    //Someone else coming in gets announced to the first
+    let (other, _otherstream) = world.join();
+    host.on_join(&world.api(), other);
+    assert_eq!(read_chat(&mut stream), "A player joined, there are 2 of you now.");
+}
+
+#[test]
+fn bindings_reach_blocks_inventory_and_chat() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let world = Headless::new(&[]);
+    std::fs::write(
+        world.scripts.join("rules.rhai"),
+        r#"
+        fn on_block_set(player, x, y, z, block) {
+            if block == 5 { 6 } else { block }
+        }
+        fn after_block_set(player, x, y, z, block) {
+            set_block(x, y + 1, z, 9);
+            give_item(player, block, 2);
+        }
+        fn on_chat(player, text) {
+            if text == "/where" {
+                send_message(player, "you are at " + player_position(player));
+                return false;
+            }
+            text.to_upper()
+        }
+        fn on_mob_spawn(kind, x, y, z, hostile) {
+            !hostile
+        }
+        "#,
+    )
+    .unwrap();
+    let host = ScriptHost::new(&world.scripts);
+    let (player, mut stream) = world.join();
+
+    let mut edit = BlockEdit { spot: IVec3::new(10, 50, 10), block: 5 };
+    assert_eq!(host.before_block_set(&world.api(), player, &mut edit), Verdict::Allow);
+    assert_eq!(edit.block, 6);
+
+    host.after_block_set(&world.api(), player, &edit);
+    assert_eq!(world.state.csys.read().blockat(IVec3::new(10, 51, 10)), 9);
+    assert_eq!(world.api().inventory(player).unwrap()[0], (6, 2));
+    //They're told about the new stack
+    let update = read_message(&mut stream);
+    assert_eq!(update.message_type, MessageType::ChestInvUpdate);
+    assert_eq!((update.rot, update.infof, update.info2), (6.0, 2.0, 1));
+
+    let mut text = String::from("hello");
+    assert_eq!(host.on_chat(&world.api(), player, &mut text), Verdict::Allow);
+    assert_eq!(text, "HELLO");
+
+    world.state.knowncams.insert(player, Vec3::new(1.0, 2.0, 3.0));
+    let mut command = String::from("/where");
+    assert_eq!(host.on_chat(&world.api(), player, &mut command), Verdict::Veto);
+    assert_eq!(read_chat(&mut stream), "you are at [1.0, 2.0, 3.0]");
+
+    let mut spawn = MobSpawn { kind: 0, position: Vec3::ZERO, scale: 1.0, hostile: true };
+    assert_eq!(host.on_mob_spawn(&world.api(), &mut spawn), Verdict::Veto);
+}
+
+#[test]
+fn runaway_scripts_are_cut_off() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let world = Headless::new(&[]);
+    std::fs::write(
+        world.scripts.join("spin.rhai"),
+        r#"
+        fn on_block_set(player, x, y, z, block) {
+            loop {}
+            false
+        }
+        fn on_chat(player, text) {
+            let s = "";
+            loop { s += "aaaaaaaa"; }
+        }
+        "#,
+    )
+    .unwrap();
+    let host = ScriptHost::new(&world.scripts);
+
+    //Stopped and treated as having no opinion
+    let mut edit = BlockEdit { spot: IVec3::new(0, 0, 0), block: 1 };
+    assert_eq!(host.before_block_set(&world.api(), Uuid::new_v4(), &mut edit), Verdict::Allow);
+    let mut text = String::from("hi");
+    assert_eq!(host.on_chat(&world.api(), Uuid::new_v4(), &mut text), Verdict::Allow);
+    assert_eq!(text, "hi");
+}
+
+#[test]
+fn scripts_hot_reload_and_keep_the_last_good_version() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let world = Headless::new(&[]);
+    let host = ScriptHost::new(&world.scripts);
+    assert!(host.loaded().is_empty());
+
+    let path = world.scripts.join("gate.rhai");
+    let mut edit = BlockEdit { spot: IVec3::new(0, 0, 0), block: 1 };
+    let player = Uuid::new_v4();
+
+    std::fs::write(&path, "fn on_block_set(p, x, y, z, b) { false }").unwrap();
+    //Picked up by the periodic scan
+    host.on_tick(&world.api(), 1.5);
+    assert_eq!(host.before_block_set(&world.api(), player, &mut edit), Verdict::Veto);
+
+    std::fs::write(&path, "fn on_block_set(p, x, y, z, b) { true }").unwrap();
+    host.reload();
+    assert_eq!(host.before_block_set(&world.api(), player, &mut edit), Verdict::Allow);
+
+    std::fs::write(&path, "fn on_block_set(p, x, y, z, b) { false ").unwrap();
+    host.reload();
+    assert_eq!(host.before_block_set(&world.api(), player, &mut edit), Verdict::Allow);
+
+    std::fs::remove_file(&path).unwrap();
+    host.reload();
+    assert!(host.loaded().is_empty());
+}
+
+#[test]
+fn timers_fire_on_ticks() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let world = Headless::new(&[]);
+    std::fs::write(
+        world.scripts.join("timers.rhai"),
+        r#"
+        after(2, "once");
+        every(1.0, "again");
+
+        fn once() {
+            set_block(0, 90, 0, 4);
+        }
+        fn again() {
+            store("count", recall("count", 0) + 1);
+            set_block(1, 90, 0, recall("count", 0));
+        }
+        "#,
+    )
+    .unwrap();
+    let host = ScriptHost::new(&world.scripts);
+    let block = |x| world.state.csys.read().blockat(IVec3::new(x, 90, 0));
+
+    host.on_tick(&world.api(), 1.0);
+    assert_eq!((block(0), block(1)), (0, 1));
+    host.on_tick(&world.api(), 1.0);
+    assert_eq!((block(0), block(1)), (4, 2));
+    host.on_tick(&world.api(), 1.0);
+    assert_eq!((block(0), block(1)), (4, 3));
+}
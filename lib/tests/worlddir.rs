@@ -0,0 +1,54 @@
+mod common;
+
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use common::{TempDir, SERIAL};
+use uuid::Uuid;
+use voxelland::game::CURRSEED;
+use voxelland::server::{sql, ServerConfig, ServerState};
+use voxelland::vec::IVec3;
+use voxelland::worlddir;
+
+/* Upgrading the files themselves is tested in the world crate, these check the server still finds
+everything in them afterwards. */
+
+/// Every layout a world has been saved in before, see `world/tests/fixtures/worlds`.
+const LAYOUTS: [&str; 2] = ["v1_per_block", "v2_per_chunk"];
+
+/// A copy of a saved world from the world crate's fixtures to upgrade.
+fn fixture(name: &str) -> TempDir {
+    let dir = TempDir::new();
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("../world/tests/fixtures/worlds").join(name);
+    worlddir::copy_all(&source, &dir).unwrap();
+    dir
+}
+
+#[test]
+fn upgraded_worlds_keep_their_blocks_chests_and_players() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    for name in LAYOUTS {
+        let world = fixture(name);
+        //The seed it's started with is only for new worlds, this one was already on 777
+        let mut config = ServerConfig::new("127.0.0.1:0", world.to_path_buf());
+        config.initial_seed = 5;
+        let state = ServerState::load(&config).unwrap();
+
+        assert_eq!(unsafe { CURRSEED.load(Ordering::Relaxed) }, 777, "{}", name);
+        assert_eq!(state.manifest.lock().seed, 777);
+        {
+            let csys = state.csys.read();
+            assert_eq!(csys.planet_type, 1, "{}", name);
+            assert_eq!(csys.blockat(IVec3::new(5, 40, 5)), 17, "{}", name);
+            assert_eq!(csys.blockat(IVec3::new(6, 40, 5)), 17, "{}", name);
+            assert_eq!(csys.blockat(IVec3::new(100, 41, -3)), 9, "{}", name);
+            assert_eq!(csys.blockat(IVec3::new(-20, 42, -20)), 4, "{}", name);
+        }
+
+        let chest = state.chest_reg.get(&IVec3::new(5, 41, 5)).unwrap();
+        assert_eq!(&chest.inv[..2], &[(3, 10), (21, 1)]);
+
+        let player = Uuid::parse_str("00000000-0000-0000-0000-000000000001").unwrap();
+        assert_eq!(sql::load_inventory(&world, &player).unwrap().unwrap()[0], (2, 5));
+    }
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use common::{TempDir, SERIAL};
+use noise::Perlin;
+use voxelland::chunk::ChunkSystem;
+use voxelland::saves::Saves;
+use voxelland::server::{ServerConfig, ServerState};
+use voxelland::vec::IVec3;
+use voxelland::worldgen::{self, GeneratorSettings, WorldOptions, NO_CAVES, NO_FEATURES, NO_ORES};
+use voxelland::worlddir::Manifest;
+
+/// The first spot, going down a column at a time, that `pred` is true of.
+fn find(mut pred: impl FnMut(IVec3) -> bool) -> IVec3 {
+    for x in 0..200 {
+        for z in 0..20 {
+            for y in (1..100).rev() {
+                let spot = IVec3::new(x, y, z);
+                if pred(spot) {
+                    return spot;
+                }
+            }
+        }
+    }
+    panic!("nowhere in range");
+}
+
+#[test]
+fn seeds_come_from_any_text() {
+    assert_eq!(worldgen::seed_from_text("  123 "), Some(123));
+    assert_eq!(worldgen::seed_from_text(""), None);
+    assert_eq!(worldgen::seed_from_text("   "), None);
+
+    //The same text is the same seed, everywhere and always
+    assert_eq!(worldgen::seed_from_text("hello"), Some(0x4f9f2cab));
+    assert_eq!(worldgen::seed_from_text("hello"), worldgen::seed_from_text(" hello "));
+    assert_ne!(worldgen::seed_from_text("hello"), worldgen::seed_from_text("Hello"));
+    //Too big for a number, so it's text
+    assert!(worldgen::seed_from_text("99999999999").is_some());
+}
+
+#[test]
+fn settings_pack_into_bits() {
+    //Zero is everything on, what older servers send
+    assert_eq!(GeneratorSettings::from_bits(0), GeneratorSettings::default());
+    assert_eq!(GeneratorSettings::default().to_bits(), 0);
+
+    let settings = GeneratorSettings { caves: false, features: true, ores: false };
+    assert_eq!(settings.to_bits(), NO_CAVES | NO_ORES);
+    assert_eq!(GeneratorSettings::from_bits(settings.to_bits()), settings);
+    assert!(!GeneratorSettings::from_bits(NO_FEATURES).features);
+}
+
+#[test]
+fn turning_things_off_changes_the_terrain() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let perlin = Perlin::new(5);
+    GeneratorSettings::default().apply();
+
+    //Somewhere the ground is hollowed out, and somewhere there's ore
+    let cave = find(|spot| {
+        ChunkSystem::_natural_blockat(&perlin, spot) == 0 && ChunkSystem::_cave_noise(&perlin, spot) > 0.5 && ChunkSystem::_noise_func(&perlin, spot) > 10.0
+    });
+    let ore = find(|spot| ChunkSystem::_natural_blockat(&perlin, spot) == 35);
+
+    GeneratorSettings { caves: false, ..Default::default() }.apply();
+    assert_ne!(ChunkSystem::_natural_blockat(&perlin, cave), 0);
+    assert_eq!(ChunkSystem::_natural_blockat(&perlin, ore), 35);
+
+    GeneratorSettings { ores: false, ..Default::default() }.apply();
+    assert_ne!(ChunkSystem::_natural_blockat(&perlin, ore), 35);
+    assert_eq!(ChunkSystem::_natural_blockat(&perlin, cave), 0);
+
+    GeneratorSettings::default().apply();
+}
+
+#[test]
+fn worlds_keep_what_they_were_made_with() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let root = TempDir::new();
+    let saves = Saves::new(root.as_path());
+
+    let options = WorldOptions {
+        seed_text: String::from("glass canyon"),
+        planet_type: 1,
+        generator: GeneratorSettings { caves: false, features: false, ores: true },
+    };
+    let slot = saves.create("Canyon", &options).unwrap();
+    let manifest = Manifest::read(&slot.dir).unwrap().unwrap();
+    assert_eq!(Some(manifest.seed), worldgen::seed_from_text("glass canyon"));
+    assert_eq!(manifest.seed_text, "glass canyon");
+    assert_eq!((manifest.planet_type, manifest.generator), (1, options.generator));
+
+    //The same options again are the same world
+    let again = saves.create("Canyon", &options).unwrap();
+    assert_eq!(again.manifest.seed, manifest.seed);
+
+    //And the server makes it that way
+    let state = ServerState::load(&ServerConfig::new("127.0.0.1:0", slot.dir.clone())).unwrap();
+    assert_eq!(state.csys.read().planet_type, 1);
+    assert_eq!(GeneratorSettings::current(), options.generator);
+
+    GeneratorSettings::default().apply();
+}
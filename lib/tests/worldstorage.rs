@@ -0,0 +1,126 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use rusqlite::{ffi, params, Connection};
+use common::{TempDir, SERIAL};
+use voxelland::chunkstore::ChunkStore;
+use voxelland::game::Game;
+use voxelland::server::{ServerConfig, ServerState};
+use voxelland::vec::IVec3;
+use voxelland::worlddir::{self, BACKUP_DIR, FORMAT_VERSION, KEEP_BACKUPS, MANIFEST_FILE};
+use voxelland::worldstorage::{self, WorldStorageError};
+
+/// A world on seed 3 with a block edit and a chest saved.
+fn played_world(dir: &Path) {
+    worlddir::open(dir, 3).unwrap();
+    ChunkStore::new(dir.join("db"), 3).set_block(IVec3::new(1, 40, 1), 17).unwrap();
+    let conn = Connection::open(dir.join("chestdb")).unwrap();
+    conn.execute("CREATE TABLE chest_registry_3 (x INTEGER, y INTEGER, z INTEGER, dirty BOOLEAN, inventory BLOB, PRIMARY KEY (x, y, z))", ())
+        .unwrap();
+    let inv = bincode::serialize(&[(5u32, 2u32); 32]).unwrap();
+    conn.execute("INSERT INTO chest_registry_3 VALUES (1, 41, 1, 0, ?1)", params![inv]).unwrap();
+}
+
+#[test]
+fn damaged_worlds_are_caught_and_restored_from_a_backup() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let temp = TempDir::new();
+    let dir = temp.0.clone();
+    played_world(&dir);
+    let backup = worlddir::backup(&dir, FORMAT_VERSION).unwrap();
+
+    std::fs::write(dir.join("chestdb"), "not a database, just some text that happens to be where one was".repeat(4)).unwrap();
+
+    let error = worlddir::check(&dir).unwrap_err();
+    assert!(error.is_corrupt(), "{}", error);
+    assert_eq!(error.path(), dir.join("chestdb"));
+    //Nothing goes ahead with it, and nothing panics
+    assert!(worlddir::open(&dir, 3).unwrap_err().is_corrupt());
+    assert!(ServerState::load(&ServerConfig::new("127.0.0.1:0", dir.clone())).is_err());
+
+    assert_eq!(worlddir::backups(&dir), vec![backup.clone()]);
+    let aside = worlddir::restore(&dir, &backup).unwrap();
+    worlddir::check(&dir).unwrap();
+    //What was there is kept, but isn't something to restore
+    assert!(std::fs::read(aside.join("chestdb")).unwrap().starts_with(b"not a database"));
+    assert_eq!(worlddir::backups(&dir), vec![backup]);
+
+    let state = ServerState::load(&ServerConfig::new("127.0.0.1:0", dir.clone())).unwrap();
+    assert_eq!(state.csys.read().blockat(IVec3::new(1, 40, 1)), 17);
+    assert_eq!(state.chest_reg.get(&IVec3::new(1, 41, 1)).unwrap().inv[0], (5, 2));
+}
+
+#[test]
+fn what_doesnt_read_back_is_corrupt() {
+    let temp = TempDir::new();
+    let dir = temp.0.clone();
+    played_world(&dir);
+
+    //A chest cut short
+    let conn = Connection::open(dir.join("chestdb")).unwrap();
+    conn.execute("INSERT INTO chest_registry_3 VALUES (9, 9, 9, 0, ?1)", params![vec![1u8, 2, 3]]).unwrap();
+    let error = Game::static_load_chests_from_path(dir.join("chestdb"), 3, &Arc::new(DashMap::new())).unwrap_err();
+    assert!(error.is_corrupt(), "{}", error);
+    assert!(error.to_string().contains("9 9 9"), "{}", error);
+
+    //A manifest cut short
+    std::fs::write(dir.join(MANIFEST_FILE), "{\"format_version\": 3,").unwrap();
+    let error = worlddir::check(&dir).unwrap_err();
+    assert!(error.is_corrupt());
+    assert_eq!(error.path(), dir.join(MANIFEST_FILE));
+}
+
+#[test]
+fn played_worlds_are_backed_up_once_a_day_keeping_the_last_few() {
+    let temp = TempDir::new();
+    let dir = temp.0.clone();
+    played_world(&dir);
+
+    worlddir::open(&dir, 3).unwrap();
+    assert_eq!(worlddir::backups(&dir).len(), 1);
+    worlddir::open(&dir, 3).unwrap();
+    assert_eq!(worlddir::backups(&dir).len(), 1);
+
+    //Older ones from days before, and one a restore moved aside
+    for day in 1..=KEEP_BACKUPS as u64 {
+        std::fs::create_dir_all(dir.join(BACKUP_DIR).join(format!("{}-format3", day * 86400))).unwrap();
+    }
+    std::fs::create_dir_all(dir.join(BACKUP_DIR).join("50-damaged")).unwrap();
+    worlddir::open(&dir, 3).unwrap();
+
+    let backups = worlddir::backups(&dir);
+    assert_eq!(backups.len(), KEEP_BACKUPS);
+    assert!(!dir.join(BACKUP_DIR).join("86400-format3").exists());
+    assert!(dir.join(BACKUP_DIR).join(format!("{}-format3", 2 * 86400)).exists());
+    assert!(dir.join(BACKUP_DIR).join("50-damaged").exists());
+}
+
+#[test]
+fn sqlite_errors_say_what_can_be_done() {
+    let failure = |code| rusqlite::Error::SqliteFailure(ffi::Error::new(code), None);
+    assert!(matches!(WorldStorageError::sql("db", failure(ffi::SQLITE_BUSY)), WorldStorageError::Locked { .. }));
+    assert!(matches!(WorldStorageError::sql("db", failure(ffi::SQLITE_LOCKED)), WorldStorageError::Locked { .. }));
+    assert!(WorldStorageError::sql("db", failure(ffi::SQLITE_CORRUPT)).is_corrupt());
+    assert!(WorldStorageError::sql("db", failure(ffi::SQLITE_NOTADB)).is_corrupt());
+    assert!(matches!(WorldStorageError::sql("db", failure(ffi::SQLITE_FULL)), WorldStorageError::Sql { .. }));
+
+    //Still says which world it was when it's only an io error
+    let error: std::io::Error = WorldStorageError::NewerFormat { path: PathBuf::from("saves/w"), version: 9 }.into();
+    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+    assert!(error.to_string().contains("saves/w"));
+}
+
+#[test]
+fn files_get_their_folders_or_say_why_not() {
+    let temp = TempDir::new();
+    worldstorage::write_file(temp.join("mp/seed2"), b"42").unwrap();
+    assert_eq!(std::fs::read(temp.join("mp/seed2")).unwrap(), b"42");
+
+    /* A file where the folder should be */
+    let error = worldstorage::write_file(temp.join("mp/seed2/pt"), b"1").unwrap_err();
+    assert!(matches!(error, WorldStorageError::Io { .. }), "{}", error);
+    assert_eq!(error.path(), temp.join("mp/seed2"));
+}
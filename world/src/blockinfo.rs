@@ -1,4 +1,6 @@
-use crate::{chunk::LightColor, cube::CubeSide};
+use crate::cube::CubeSide;
+
+pub type LightColor = glam::U16Vec3;
 
 pub const BLOCK_DIRECTION_BITS: u32 = 0b0000_0000_0000_0011_0000_0000_0000_0000;
 pub struct Blocks {}
@@ -189,6 +191,7 @@ impl Blocks {
             }
         }
     }
+    #[allow(non_upper_case_globals)]
     pub fn get_light_color(id: u32) -> LightColor {
         static white: LightColor = LightColor{x: 15, y: 15, z:15};
         static blue: LightColor = LightColor{x: 0, y:0, z:15};
@@ -237,58 +240,58 @@ impl Blocks {
         }
     }
     pub fn get_break_time(id: u32) -> f32 {
-        return BREAKTIMES[id as usize];
+        BREAKTIMES[id as usize]
     }
     pub fn get_texs_length() -> usize {
-        return TEXS.len();
+        TEXS.len()
     }
     pub fn get_tex_coords(id: u32, side: CubeSide) -> &'static (u8, u8) {
         static SIDES: [usize; 6] = [0, 0, 1, 2, 0, 0];
         
-        return &TEXS[id as usize][SIDES[side as usize]];
+        &TEXS[id as usize][SIDES[side as usize]]
     }
 
     pub fn is_overwritable(id: u32) -> bool {
         static OV: [u32; 2] = [
             0, 2
         ];
-        return OV.contains(&id);
+        OV.contains(&id)
     }
     pub fn is_transparent(id: u32) -> bool {
         static TRANSPARENTS: [u32; 3] = [
             2, 8, 49
         ];
-        return TRANSPARENTS.contains(&id);
+        TRANSPARENTS.contains(&id)
     }
     pub fn is_climbable(id: u32) -> bool {
         static CLIMBABLES: [u32; 2] = [
             20, 22
         ];
-        return CLIMBABLES.contains(&id);
+        CLIMBABLES.contains(&id)
     }
     pub fn is_semi_transparent(id: u32) -> bool {
         static SEMI_TRANSPARENTS: [u32; 9] = [
             7, 11, 19, 20, 21, 22, 23, 31, 44
         ];
-        return SEMI_TRANSPARENTS.contains(&id);
+        SEMI_TRANSPARENTS.contains(&id)
     }
     pub fn is_non_placeable(id: u32) -> bool {
         static NP: [u32; 7] = [
             32, 33, 17, 36, 37, 38, 39
         ];
-        return NP.contains(&id);
+        NP.contains(&id)
     }
     pub fn is_light(id: u32) -> bool {
         static LIGHTS: [u32; 9] = [
             18, 24, 25, 26, 27, 28, 29, 30, 49
         ];
-        return LIGHTS.contains(&id);
+        LIGHTS.contains(&id)
     }
     pub fn is_food(id: u32) -> bool {
         static FOOD: [u32; 2] = [
             32, 33
         ];
-        return FOOD.contains(&id);
+        FOOD.contains(&id)
     }
 
     pub fn block_id_bits() -> u32 {
@@ -296,7 +299,7 @@ impl Blocks {
     }
 
     pub fn get_direction_bits(input: u32) -> u32 {
-        return (input & BLOCK_DIRECTION_BITS) >> 16;
+        (input & BLOCK_DIRECTION_BITS) >> 16
     }
 
     pub fn set_direction_bits(input: &mut u32, direction: u32) {
@@ -304,11 +307,40 @@ impl Blocks {
         *input |= bits;
     }
 
+    /// Blocks whose direction bits say which way they face: doors, ladders, chests, crafting benches, conveyors and torches.
+    pub fn is_directional(id: u32) -> bool {
+        matches!(id, 19 | 20 | 21 | 31 | 45 | 49)
+    }
+
+    /// `input` turned a quarter turn `turns` times the way (x, z) turns to (-z, x). Only directional blocks change.
+    pub fn rotate_direction(input: u32, turns: u32) -> u32 {
+        if !Self::is_directional(input & Self::block_id_bits()) {
+            return input;
+        }
+        let direction = (Self::get_direction_bits(input) + turns) % 4;
+        (input & !BLOCK_DIRECTION_BITS) | (direction << 16)
+    }
+
+    /// `input` flipped the way x flips to -x, so one facing +x faces -x. Only directional blocks change.
+    pub fn mirror_direction(input: u32) -> u32 {
+        if !Self::is_directional(input & Self::block_id_bits()) {
+            return input;
+        }
+        let direction = match Self::get_direction_bits(input) {
+            1 => 3,
+            3 => 1,
+            d => d,
+        };
+        (input & !BLOCK_DIRECTION_BITS) | (direction << 16)
+    }
+
 
 
     pub fn block_flag_bits() -> u32 {
         0b1111_1111_1111_1111_0000_0000_0000_0000
     }
+    //Every food is the same for now
+    #[allow(clippy::match_single_binding)]
     pub fn get_food_stats(id: u32) -> (i32, i32) {
         match id {
             _ => {
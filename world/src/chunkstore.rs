@@ -0,0 +1,210 @@
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::terrain;
+use crate::vec::{IVec2, IVec3};
+use crate::worldstorage::{StorageResult, WorldStorageError};
+
+/// Player edits kept one row per chunk in the world's `db`, so a chunk's edits come and go together
+/// instead of the whole world being read in at startup.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkStore {
+    pub db_path: PathBuf,
+    pub seed: u32,
+}
+
+impl ChunkStore {
+    pub fn new(db_path: impl Into<PathBuf>, seed: u32) -> ChunkStore {
+        ChunkStore { db_path: db_path.into(), seed }
+    }
+
+    fn table(&self) -> String {
+        format!("chunkedits_{}", self.seed)
+    }
+
+    /// The per-row table worlds were saved in before.
+    fn old_table(&self) -> String {
+        format!("userdatamap_{}", self.seed)
+    }
+
+    fn sql_error(&self, e: rusqlite::Error) -> WorldStorageError {
+        WorldStorageError::sql(&self.db_path, e)
+    }
+
+    fn open(&self) -> StorageResult<Connection> {
+        let conn = Connection::open(&self.db_path).map_err(|e| self.sql_error(e))?;
+        conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    cx INTEGER,
+                    cz INTEGER,
+                    edits BLOB,
+                    PRIMARY KEY (cx, cz)
+                )",
+                self.table()
+            ),
+            (),
+        ).map_err(|e| self.sql_error(e))?;
+        Ok(conn)
+    }
+
+    /// What's saved for the chunk. A chunk whose edits won't read back is an error rather than no edits,
+    /// so nobody saves over them.
+    pub fn load_chunk(&self, cpos: IVec2) -> StorageResult<Vec<(IVec3, u32)>> {
+        let conn = self.open()?;
+        self.read_chunk(&conn, cpos)
+    }
+
+    /// Replaces everything saved for the chunk with `edits`.
+    pub fn save_chunk(&self, cpos: IVec2, edits: &[(IVec3, u32)]) -> StorageResult<usize> {
+        let conn = self.open()?;
+        self.write_chunk(&conn, cpos, edits)
+    }
+
+    /// Saves a single edit on top of whatever the chunk already has.
+    pub fn set_block(&self, spot: IVec3, block: u32) -> StorageResult<usize> {
+        let mut conn = self.open()?;
+        let tx = conn.transaction().map_err(|e| self.sql_error(e))?;
+        let cpos = terrain::spot_to_chunk_pos(&spot);
+
+        let mut edits = self.read_chunk(&tx, cpos)?;
+        match edits.iter_mut().find(|(s, _)| *s == spot) {
+            Some(edit) => edit.1 = block,
+            None => edits.push((spot, block)),
+        }
+        let written = self.write_chunk(&tx, cpos, &edits)?;
+        tx.commit().map_err(|e| self.sql_error(e))?;
+        Ok(written)
+    }
+
+    /// Saves a batch of edits in one transaction, so either all of them are kept or none are.
+    pub fn set_blocks(&self, blocks: &[(IVec3, u32)]) -> StorageResult<usize> {
+        let mut conn = self.open()?;
+        let tx = conn.transaction().map_err(|e| self.sql_error(e))?;
+
+        let mut chunks: Vec<(IVec2, Vec<(IVec3, u32)>)> = Vec::new();
+        for (spot, block) in blocks {
+            let cpos = terrain::spot_to_chunk_pos(spot);
+            let index = match chunks.iter().position(|(c, _)| *c == cpos) {
+                Some(index) => index,
+                None => {
+                    chunks.push((cpos, self.read_chunk(&tx, cpos)?));
+                    chunks.len() - 1
+                }
+            };
+            let edits = &mut chunks[index].1;
+            match edits.iter_mut().find(|(s, _)| s == spot) {
+                Some(edit) => edit.1 = *block,
+                None => edits.push((*spot, *block)),
+            }
+        }
+        let mut written = 0;
+        for (cpos, edits) in &chunks {
+            written += self.write_chunk(&tx, *cpos, edits)?;
+        }
+        tx.commit().map_err(|e| self.sql_error(e))?;
+        Ok(written)
+    }
+
+    /// Every chunk that has edits saved.
+    pub fn chunks(&self) -> StorageResult<Vec<IVec2>> {
+        let conn = self.open()?;
+        let mut stmt = conn.prepare(&format!("SELECT cx, cz FROM {}", self.table())).map_err(|e| self.sql_error(e))?;
+        let rows = stmt.query_map([], |row| Ok(IVec2::new(row.get(0)?, row.get(1)?))).map_err(|e| self.sql_error(e))?;
+        rows.collect::<rusqlite::Result<Vec<IVec2>>>().map_err(|e| self.sql_error(e))
+    }
+
+    /// Moves a world saved one row per block over to one row per chunk, then drops the old table.
+    /// Returns how many edits moved, zero if there was nothing to do.
+    pub fn migrate(&self) -> StorageResult<usize> {
+        let mut conn = self.open()?;
+        let old = self.old_table();
+        let sql_error = |e| self.sql_error(e);
+
+        let exists: Option<String> = conn
+            .query_row("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?1", [&old], |row| row.get(0))
+            .optional()
+            .map_err(sql_error)?;
+        if exists.is_none() {
+            return Ok(0);
+        }
+
+        let tx = conn.transaction().map_err(sql_error)?;
+        let mut bychunk: std::collections::HashMap<IVec2, Vec<(IVec3, u32)>> = std::collections::HashMap::new();
+        {
+            let mut stmt = tx.prepare(&format!("SELECT x, y, z, value FROM {}", old)).map_err(sql_error)?;
+            let rows = stmt
+                .query_map([], |row| Ok((IVec3::new(row.get(0)?, row.get(1)?, row.get(2)?), row.get::<_, u32>(3)?)))
+                .map_err(sql_error)?;
+            for row in rows {
+                let (spot, block) = row.map_err(sql_error)?;
+                bychunk.entry(terrain::spot_to_chunk_pos(&spot)).or_default().push((spot, block));
+            }
+        }
+
+        let mut moved = 0;
+        for (cpos, mut edits) in bychunk {
+            //Anything already in the new table is newer
+            let existing = self.read_chunk(&tx, cpos)?;
+            edits.retain(|(s, _)| !existing.iter().any(|(e, _)| e == s));
+            moved += edits.len();
+            edits.extend(existing);
+            self.write_chunk(&tx, cpos, &edits)?;
+        }
+
+        tx.execute(&format!("DROP TABLE {}", old), ()).map_err(sql_error)?;
+        tx.commit().map_err(sql_error)?;
+        Ok(moved)
+    }
+
+    /// Copies every saved chunk into another db, e.g. when a world gets saved somewhere new.
+    pub fn copy_to(&self, db_path: &Path) -> StorageResult<()> {
+        if !self.db_path.exists() {
+            return Ok(());
+        }
+        let target = ChunkStore::new(db_path, self.seed);
+        let conn = target.open()?;
+        conn.execute("ATTACH DATABASE ?1 AS source", [self.db_path.to_string_lossy()]).map_err(|e| self.sql_error(e))?;
+        let copied = conn.execute(
+            &format!("CREATE TABLE IF NOT EXISTS source.{table} (cx INTEGER, cz INTEGER, edits BLOB, PRIMARY KEY (cx, cz))", table = self.table()),
+            (),
+        ).and_then(|_| conn.execute(&format!("INSERT OR REPLACE INTO main.{table} SELECT * FROM source.{table}", table = self.table()), ()));
+        conn.execute("DETACH DATABASE source", ()).map_err(|e| self.sql_error(e))?;
+        copied.map(|_| ()).map_err(|e| target.sql_error(e))
+    }
+
+    fn read_chunk(&self, conn: &Connection, cpos: IVec2) -> StorageResult<Vec<(IVec3, u32)>> {
+        let blob: Option<Vec<u8>> = conn
+            .query_row(&format!("SELECT edits FROM {} WHERE cx = ?1 AND cz = ?2", self.table()), params![cpos.x, cpos.y], |row| row.get(0))
+            .optional()
+            .map_err(|e| self.sql_error(e))?;
+        match blob {
+            Some(blob) => decode(&blob)
+                .map_err(|e| WorldStorageError::corrupt(&self.db_path, format!("the edits in chunk {} {} won't read back: {}", cpos.x, cpos.y, e))),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn write_chunk(&self, conn: &Connection, cpos: IVec2, edits: &[(IVec3, u32)]) -> StorageResult<usize> {
+        let written = if edits.is_empty() {
+            conn.execute(&format!("DELETE FROM {} WHERE cx = ?1 AND cz = ?2", self.table()), params![cpos.x, cpos.y])
+        } else {
+            conn.execute(
+                &format!("INSERT OR REPLACE INTO {} (cx, cz, edits) VALUES (?1, ?2, ?3)", self.table()),
+                params![cpos.x, cpos.y, encode(edits)],
+            )
+        };
+        written.map_err(|e| self.sql_error(e))
+    }
+}
+
+pub fn encode(edits: &[(IVec3, u32)]) -> Vec<u8> {
+    lz4_flex::compress_prepend_size(&bincode::serialize(edits).unwrap())
+}
+
+/// Reads back what `encode` made, saying what's wrong with anything it couldn't have made.
+pub fn decode(blob: &[u8]) -> Result<Vec<(IVec3, u32)>, String> {
+    let raw = lz4_flex::decompress_size_prepended(blob).map_err(|e| e.to_string())?;
+    bincode::deserialize(&raw).map_err(|e| e.to_string())
+}
@@ -24,7 +24,7 @@ impl Cube {
             vec::IVec3 { x: 0, y: 0, z: -1 },
             vec::IVec3 { x: 0, y: 0, z: 1 },
         ];
-        return NEIGHBORS.as_slice();
+        NEIGHBORS.as_slice()
     }
     pub fn get_side(side: CubeSide) -> &'static [u8] {
         #[rustfmt::skip]
@@ -79,7 +79,7 @@ impl Cube {
             ],
         ];
 
-        return SIDES[side as usize].as_slice();
+        SIDES[side as usize].as_slice()
     }
     pub fn get_amb_occul_spots(side: CubeSide, corner: u8) -> &'static [vec::IVec3; 3]{
         #[rustfmt::skip]
@@ -283,6 +283,6 @@ impl Cube {
         ],
     ],
 ];
-        return &SPOTS[side as usize][corner as usize];
+        &SPOTS[side as usize][corner as usize]
     }
 }
@@ -0,0 +1,9 @@
+pub mod vec;
+pub mod cube;
+pub mod blockinfo;
+pub mod terrain;
+pub mod worldstorage;
+pub mod worldgen;
+pub mod chunkstore;
+pub mod worlddir;
+pub mod worldmap;
@@ -0,0 +1,270 @@
+//The natural terrain, what a seed generates before anyone builds on it. Pure functions of the noise so
+//anything that reads a world off disk can work out the ground without a whole ChunkSystem.
+
+use glam::Vec3;
+use noise::{NoiseFn, Perlin};
+
+use crate::vec::{IVec2, IVec3};
+use crate::worldgen;
+
+#[allow(non_upper_case_globals)]
+pub static ChW: i32 = 15;
+#[allow(non_upper_case_globals)]
+pub static ChH: i32 = 255;
+
+pub fn spot_to_chunk_pos(spot: &IVec3) -> IVec2 {
+    IVec2 {
+        x: (spot.x as f32 / ChW as f32).floor() as i32,
+        y: (spot.z as f32 / ChW as f32).floor() as i32,
+    }
+}
+
+pub fn mix(a: f64, b: f64, t: f64) -> f64 {
+    a * (1.0 - t) + b * t
+}
+
+pub fn biome_noise(perlin: &Perlin, spot: IVec2) -> f64 {
+    const XZDIVISOR1: f64 = 100.35 * 4.0;
+
+    let y = 20;
+
+    f64::max(
+        0.0,
+        perlin.get([
+            spot.x as f64 / XZDIVISOR1,
+            y as f64,
+            spot.y as f64 / XZDIVISOR1,
+        ]),
+    )
+}
+
+pub fn ore_noise(perlin: &Perlin, spot: IVec3) -> f64 {
+    const XYZDIVISOR: f64 = 15.53;
+
+    let noise1 = f64::max(
+        0.0,
+        perlin.get([
+            spot.x as f64 / XYZDIVISOR,
+            spot.y as f64 / XYZDIVISOR,
+            spot.z as f64 / XYZDIVISOR,
+        ]),
+    );
+
+    noise1 * ((60.0 - spot.y as f64).max(0.0) / 7.0)
+}
+
+pub fn feature_noise(perlin: &Perlin, spot: IVec2) -> f64 {
+    const XZDIVISOR1: f64 = 45.35 * 4.0;
+
+    let y = 20;
+
+    f64::max(
+        0.0,
+        perlin.get([
+            (spot.x as f64 + 200.0) / XZDIVISOR1,
+            y as f64,
+            spot.y as f64 / XZDIVISOR1,
+        ]),
+    )
+}
+
+pub fn cave_noise(perlin: &Perlin, spot: IVec3) -> f64 {
+    const XZDIVISOR1: f64 = 25.35;
+
+    f64::max(
+        0.0,
+        perlin.get([
+            (spot.x as f64) / XZDIVISOR1,
+            (spot.y as f64) / XZDIVISOR1,
+            spot.z as f64 / XZDIVISOR1,
+        ]),
+    )
+}
+
+pub fn noise_func(perlin: &Perlin, spot: IVec3) -> f64 {
+
+    let per = perlin;
+
+    let spot = (Vec3::new(spot.x as f32, spot.y as f32, spot.z as f32) / 3.0) + Vec3::new(0.0, 10.0, 0.0);
+    let xzdivisor2 = 1000.35 * 4.0;
+
+    let mut y = spot.y - 20.0;
+
+    let noise1 = f64::max(
+        0.0,
+        20.0 + per.get([
+            spot.x as f64 / xzdivisor2,
+            y as f64 / xzdivisor2,
+            spot.z as f64 / xzdivisor2,
+        ]) * 5.0
+            - f64::max(
+                y as f64 / 1.7
+                    + per
+                        .get([spot.x as f64 / 65.0, spot.z as f64 / 65.0])
+                        * 10.0,
+                0.0,
+            ),
+    ) * 2.0;
+
+    y += 100.0;
+
+    let noise2 = f64::max(
+        0.0,
+        50.0 + per.get([
+            spot.x as f64 / 100.35,
+            y as f64 / 50.35,
+            spot.z as f64 / 100.35,
+        ]) * 10.0
+            + per.get([
+                spot.x as f64 / 300.35,
+                y as f64 / 100.35,
+                spot.z as f64 / 300.35,
+            ]) * 10.0
+            - f64::max(y as f64 / 3.0, 0.0),
+    );
+
+    let mut p = per
+        .get([spot.x as f64 / 500.0, spot.z as f64 / 500.0])
+        * 2.0;
+
+    p = f64::max(p, 0.0);
+    p = f64::min(p, 1.0);
+
+    // Mixing noise1 and noise2 based on p, assuming `mix` is a function that blends the two values
+    // Rust doesn't have a direct `mix` function, but you can create one or use a linear interpolation
+    let noisemix = mix(noise1, noise2, p);
+
+    let texture = per.get([
+        spot.x as f64 / 12.35,
+        y as f64 / 12.35,
+        spot.z as f64 / 12.35,
+    ]) * 1.0;
+
+    let noise3 = f64::max(
+        0.0,
+        50.0 + per.get([
+            spot.x as f64 / 25.35,
+            y as f64 / 25.35,
+            spot.z as f64 / 25.35,
+        ]) * 10.0
+            + per.get([
+                spot.x as f64 / 60.35,
+                y as f64 / 50.35,
+                spot.z as f64 / 60.35,
+            ]) * 10.0
+            - f64::max(y as f64 / 3.0, 0.0),
+    );
+
+    let mut p2 = 0.5 + per.get([
+        (spot.x as f64 + 4500.0) / 150.0,
+        (spot.y as f64 + 5000.0) / 150.0,
+        (spot.z as f64 - 5000.0) / 150.0,
+    ]) * 1.0;
+
+    let p3 = (per.get([
+        (spot.x as f64 - 1500.0) / 3500.0,
+        (spot.z as f64 + 1000.0) / 3500.0,
+    ]) * 10.0).min(9.0);
+
+
+
+    p2 = f64::max(p2, 0.0);
+    p2 = f64::min(p2, 1.0);
+
+    mix(noisemix + texture, noise3, p2.clamp(0.0, 1.0)).min(20.0) + p3
+}
+
+//The other terrain kinds are commented out in the match for now
+#[allow(clippy::match_single_binding)]
+pub fn natural_blockat(perlin: &Perlin, spot: IVec3) -> u32 {
+
+
+    let per = perlin;
+    if spot.y == 0 {
+        return 15;
+    }
+
+    
+    let ret = match 0 {
+        // 1 => {
+        //     if self.noise_func2(spot) > 10.0 {
+        //         if self.noise_func2(spot + IVec3 { x: 0, y: 1, z: 0 }) < 10.0 {
+        //             14
+        //         } else {
+        //             1
+        //         }
+
+        //     } else {
+        //         0
+        //     }
+        // }
+        _ => {
+            static WL: f32 = 30.0;
+
+            let biomenum = biome_noise(per, IVec2 {
+                x: spot.x,
+                y: spot.z,
+            });
+            let biomenum2 = biome_noise(per, IVec2 {
+                x: spot.x * 20 + 5000,
+                y: spot.z * 20 + 5000,
+            });
+
+            let mut underdirt = 5;
+            let mut surface = 3;
+            let mut undersurface = 4;
+            let mut liquid = 2;
+            let mut beach = 1;
+
+            if biomenum > 0.0 {
+                underdirt = 1;
+                surface = 1;
+                undersurface = 1;
+                liquid = 2;
+                beach = 1;
+            } else {
+                if biomenum2 > 0.0 {
+                    surface = 34;
+                }
+            }
+
+            if noise_func(per, spot) > 10.0 {
+                if noise_func(per, spot + IVec3 { x: 0, y: 10, z: 0 }) > 10.0 {
+                    if worldgen::ores_on() && ore_noise(per, spot) > 1.0 {
+                        35
+                    } else {
+                        underdirt
+                    }
+                } else {
+
+                    let beachnoise = per.get([spot.y as f64/7.5, spot.z as f64/7.5, spot.x as f64/7.5]);
+                    if spot.y > (WL + beachnoise as f32) as i32
+                    || noise_func(per, spot + IVec3 { x: 0, y: 5, z: 0 }) > 10.0
+                    {
+                        if noise_func(per, spot + IVec3 { x: 0, y: 1, z: 0 }) < 10.0 {
+                            surface
+                        } else {
+                            undersurface
+                        }
+                        
+                    } else {
+                        beach
+                    }
+                }
+
+
+                
+            } else {
+                if spot.y < WL as i32 {
+                    liquid
+                } else {
+                    0
+                }
+            }
+        }
+    };
+    if ret != 2 && worldgen::caves_on() && cave_noise(per, spot) > 0.5 {
+        return 0;
+    }
+    ret
+}
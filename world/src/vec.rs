@@ -98,6 +98,15 @@ impl IVec3 {
             z
         }
     }
+
+    /// `self + rhs`, None if an axis would overflow. For coordinates that came from outside.
+    pub fn checked_add(self, rhs: IVec3) -> Option<IVec3> {
+        Some(IVec3::new(self.x.checked_add(rhs.x)?, self.y.checked_add(rhs.y)?, self.z.checked_add(rhs.z)?))
+    }
+
+    pub fn checked_sub(self, rhs: IVec3) -> Option<IVec3> {
+        Some(IVec3::new(self.x.checked_sub(rhs.x)?, self.y.checked_sub(rhs.y)?, self.z.checked_sub(rhs.z)?))
+    }
 }
 
 impl IVec2 {
@@ -0,0 +1,326 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::chunkstore::ChunkStore;
+use crate::worldgen::GeneratorSettings;
+use crate::worldstorage::{StorageResult, WorldStorageError};
+
+/// What `open` upgrades every world to.
+///
+/// 1. Edits one sqlite row per block in `db`, `world/<seed>/seed` and `pt` files, no manifest.
+/// 2. Edits one row per chunk.
+/// 3. `world.json` manifest.
+pub const FORMAT_VERSION: u32 = 3;
+
+pub const MANIFEST_FILE: &str = "world.json";
+
+/// Copies of a world go in here, one folder each: from before every upgrade, and once a day of playing.
+pub const BACKUP_DIR: &str = "backups";
+
+/// The game's own copy of what the server sends when it's playing on its own world. Not part of the world, so never backed up.
+pub const CLIENT_DIR: &str = "client";
+
+/// How old the newest backup can get before opening the world makes another.
+pub const BACKUP_INTERVAL: u64 = 24 * 60 * 60;
+
+/// Older backups than the newest this many are deleted.
+pub const KEEP_BACKUPS: usize = 5;
+
+/// Stamped on every world this version saves. This crate is versioned along with the game, so it's the same
+/// as `discovery::GAME_VERSION`.
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What a world is moved aside as when a backup is restored over it. Never restored from or pruned.
+const DAMAGED: &str = "damaged";
+
+/// Describes the world in a world folder. The world's data stays where it always was around it:
+/// `db` for block edits, `chestdb` for chests, inventories and positions, `world/<seed>` per planet visited.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// What the world list calls it.
+    #[serde(default)]
+    pub name: String,
+    /// The planet the world is on right now.
+    pub seed: u32,
+    pub planet_type: u8,
+    /// What was typed in for the seed when the world was made, if anything.
+    #[serde(default)]
+    pub seed_text: String,
+    #[serde(default)]
+    pub generator: GeneratorSettings,
+    /// Seconds since the unix epoch.
+    pub created: u64,
+    pub last_played: u64,
+    /// The game that last opened it.
+    pub game_version: String,
+}
+
+impl Manifest {
+    pub fn new(seed: u32, planet_type: u8) -> Manifest {
+        Manifest {
+            format_version: FORMAT_VERSION,
+            name: String::new(),
+            seed,
+            planet_type,
+            seed_text: String::new(),
+            generator: GeneratorSettings::default(),
+            created: now(),
+            last_played: now(),
+            game_version: GAME_VERSION.to_string(),
+        }
+    }
+
+    pub fn read(dir: &Path) -> StorageResult<Option<Manifest>> {
+        let path = dir.join(MANIFEST_FILE);
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map(Some).map_err(|e| WorldStorageError::corrupt(path, e.to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(WorldStorageError::io(path, e)),
+        }
+    }
+
+    /// Written next to itself and renamed over, so a crash halfway leaves the old one.
+    pub fn write(&self, dir: &Path) -> StorageResult<()> {
+        let temp = dir.join(format!("{}.tmp", MANIFEST_FILE));
+        let written = File::create(&temp).and_then(|mut file| {
+            file.write_all(serde_json::to_string_pretty(self).unwrap().as_bytes())?;
+            file.sync_all()
+        });
+        written.map_err(|e| WorldStorageError::io(&temp, e))?;
+        fs::rename(&temp, dir.join(MANIFEST_FILE)).map_err(|e| WorldStorageError::io(dir.join(MANIFEST_FILE), e))
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+struct Migration {
+    /// The version it upgrades from, to the next one.
+    from: u32,
+    name: &'static str,
+    run: fn(dir: &Path, default_seed: u32) -> StorageResult<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration { from: 1, name: "block edits per chunk", run: edits_per_chunk },
+    Migration { from: 2, name: "world manifest", run: write_manifest },
+];
+
+/// Opens the world in `dir`, upgrading it first if it's from an older version (after backing it up).
+/// An empty or missing folder becomes a new world on `default_seed`. Marks the world as played.
+///
+/// A damaged world isn't touched, the error says so (`WorldStorageError::is_corrupt`) so whoever's
+/// opening it can offer to `restore` one of its `backups` instead.
+pub fn open(dir: &Path, default_seed: u32) -> StorageResult<Manifest> {
+    fs::create_dir_all(dir).map_err(|e| WorldStorageError::io(dir, e))?;
+    check(dir)?;
+
+    let version = detect_version(dir)?;
+    if version > FORMAT_VERSION {
+        return Err(WorldStorageError::NewerFormat { path: dir.to_path_buf(), version });
+    }
+
+    if version < FORMAT_VERSION {
+        let backup = backup(dir, version)?;
+        println!("Upgrading the world in {} from format {}, backed up to {}", dir.display(), version, backup.display());
+        for migration in MIGRATIONS.iter().filter(|m| m.from >= version) {
+            println!("Upgrading to format {}: {}", migration.from + 1, migration.name);
+            (migration.run)(dir, default_seed)?;
+        }
+    } else if has_data(dir) && backups(dir).first().is_none_or(|b| now().saturating_sub(backup_time(b)) >= BACKUP_INTERVAL) {
+        let backup = backup(dir, version)?;
+        println!("Backed up the world in {} to {}", dir.display(), backup.display());
+    }
+    for old in backups(dir).into_iter().skip(KEEP_BACKUPS) {
+        if let Err(e) = fs::remove_dir_all(&old) {
+            println!("Couldn't delete the old backup {}: {}", old.display(), e);
+        }
+    }
+
+    let mut manifest = match Manifest::read(dir)? {
+        Some(manifest) => manifest,
+        //Nothing here yet
+        None => Manifest::new(default_seed, 0),
+    };
+    manifest.last_played = now();
+    manifest.game_version = GAME_VERSION.to_string();
+    manifest.write(dir)?;
+    Ok(manifest)
+}
+
+/// Which format the world in `dir` is in. A folder with nothing in it counts as the current one.
+pub fn detect_version(dir: &Path) -> StorageResult<u32> {
+    if let Some(manifest) = Manifest::read(dir)? {
+        return Ok(manifest.format_version);
+    }
+    if !edit_tables(dir, "userdatamap_")?.is_empty() {
+        return Ok(1);
+    }
+    if dir.join("db").exists() || dir.join("chestdb").exists() || dir.join("world").exists() {
+        return Ok(2);
+    }
+    Ok(FORMAT_VERSION)
+}
+
+/// Whether the manifest parses and sqlite finds nothing wrong with the databases. Quick enough to do
+/// every time a world is opened, and catches a world half written when the game or the disk died.
+pub fn check(dir: &Path) -> StorageResult<()> {
+    Manifest::read(dir)?;
+    for name in ["db", "chestdb"] {
+        let path = dir.join(name);
+        if !path.exists() {
+            continue;
+        }
+        let conn = Connection::open(&path).map_err(|e| WorldStorageError::sql(&path, e))?;
+        let result: String = conn
+            .query_row("PRAGMA quick_check", [], |row| row.get(0))
+            .map_err(|e| WorldStorageError::sql(&path, e))?;
+        if result != "ok" {
+            return Err(WorldStorageError::corrupt(path, result));
+        }
+    }
+    Ok(())
+}
+
+/// Copies everything but older backups into `backups/<time>-format<version>`.
+pub fn backup(dir: &Path, version: u32) -> StorageResult<PathBuf> {
+    let target = free_backup_dir(dir, &format!("format{}", version));
+    fs::create_dir_all(&target).map_err(|e| WorldStorageError::io(&target, e))?;
+
+    for entry in world_entries(dir)? {
+        copy_all(&entry, &target.join(entry.file_name().unwrap())).map_err(|e| WorldStorageError::io(&entry, e))?;
+    }
+    Ok(target)
+}
+
+/// The backups that can be restored, the newest first.
+pub fn backups(dir: &Path) -> Vec<PathBuf> {
+    let mut backups: Vec<PathBuf> = match fs::read_dir(dir.join(BACKUP_DIR)) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_dir() && !p.to_string_lossy().ends_with(DAMAGED))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    backups.sort_by(|a, b| (backup_time(b), b).cmp(&(backup_time(a), a)));
+    backups
+}
+
+/// When a backup was made, from its folder name.
+pub fn backup_time(backup: &Path) -> u64 {
+    let name = backup.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    name.split('-').next().and_then(|t| t.parse().ok()).unwrap_or(0)
+}
+
+/// Puts the world back how it was in `backup`. What's there now isn't deleted, it's moved into
+/// `backups/<time>-damaged` in case there's anything in it worth getting back. Returns where that went.
+pub fn restore(dir: &Path, backup: &Path) -> StorageResult<PathBuf> {
+    let aside = free_backup_dir(dir, DAMAGED);
+    fs::create_dir_all(&aside).map_err(|e| WorldStorageError::io(&aside, e))?;
+    for entry in world_entries(dir)? {
+        fs::rename(&entry, aside.join(entry.file_name().unwrap())).map_err(|e| WorldStorageError::io(&entry, e))?;
+    }
+    for entry in world_entries(backup)? {
+        copy_all(&entry, &dir.join(entry.file_name().unwrap())).map_err(|e| WorldStorageError::io(&entry, e))?;
+    }
+    println!("Restored the world in {} from {}, moved what was there to {}", dir.display(), backup.display(), aside.display());
+    Ok(aside)
+}
+
+pub fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Everything in the world folder but its backups and the game's copy.
+fn world_entries(dir: &Path) -> StorageResult<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir).map_err(|e| WorldStorageError::io(dir, e))?;
+    Ok(entries.flatten().filter(|e| e.file_name() != BACKUP_DIR && e.file_name() != CLIENT_DIR).map(|e| e.path()).collect())
+}
+
+/// `backups/<time>-<label>`, with a number on the end if there's already one from this second.
+fn free_backup_dir(dir: &Path, label: &str) -> PathBuf {
+    let mut target = dir.join(BACKUP_DIR).join(format!("{}-{}", now(), label));
+    let mut n = 1;
+    while target.exists() {
+        target = dir.join(BACKUP_DIR).join(format!("{}-{}-{}", now(), n, label));
+        n += 1;
+    }
+    target
+}
+
+/// Whether there's been anything saved in the world yet.
+fn has_data(dir: &Path) -> bool {
+    dir.join("db").exists() || dir.join("chestdb").exists()
+}
+
+/// Seeds that have a table starting with `prefix` in the world's `db`.
+fn edit_tables(dir: &Path, prefix: &str) -> StorageResult<Vec<u32>> {
+    let db = dir.join("db");
+    if !db.exists() {
+        return Ok(Vec::new());
+    }
+    let sql_error = |e| WorldStorageError::sql(&db, e);
+    let conn = Connection::open(&db).map_err(sql_error)?;
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table'").map_err(sql_error)?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(0)).map_err(sql_error)?;
+    Ok(names.flatten().filter_map(|n| n.strip_prefix(prefix).and_then(|s| s.parse().ok())).collect())
+}
+
+/// 1 to 2: every planet's per-block table becomes a per-chunk one.
+fn edits_per_chunk(dir: &Path, _default_seed: u32) -> StorageResult<()> {
+    for seed in edit_tables(dir, "userdatamap_")? {
+        let moved = ChunkStore::new(dir.join("db"), seed).migrate()?;
+        println!("Moved {} edits on planet {}", moved, seed);
+    }
+    Ok(())
+}
+
+/// 2 to 3: the manifest, made from the last planet that was saved.
+fn write_manifest(dir: &Path, default_seed: u32) -> StorageResult<()> {
+    let mut planets: Vec<(SystemTime, u32)> = Vec::new();
+    if let Ok(entries) = fs::read_dir(dir.join("world")) {
+        for entry in entries.flatten() {
+            let Some(seed) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+                continue;
+            };
+            let modified = fs::metadata(entry.path().join("pt")).and_then(|m| m.modified()).unwrap_or(UNIX_EPOCH);
+            planets.push((modified, seed));
+        }
+    }
+    //The default seed wins a tie, it's what the world was last started with
+    planets.sort_by_key(|(modified, seed)| (*modified, *seed == default_seed));
+    let seed = planets.last().map(|(_, seed)| *seed).unwrap_or(default_seed);
+
+    let planet_type = fs::read_to_string(dir.join(format!("world/{}/pt", seed)))
+        .ok()
+        .and_then(|pt| pt.trim().parse().ok())
+        .unwrap_or(0);
+
+    let created = fs::metadata(dir.join("db"))
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_else(now);
+
+    let mut manifest = Manifest::new(seed, planet_type);
+    manifest.created = created;
+    manifest.write(dir)
+}
@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+/// Bits of `GENERATOR_OFF`, each one turns a part of the generator off. Off rather than on so that
+/// zero, what servers from before these existed send, is the full generator.
+pub const NO_CAVES: u32 = 1;
+pub const NO_FEATURES: u32 = 2;
+pub const NO_ORES: u32 = 4;
+
+/// What the current world turned off. The terrain functions are called from everywhere without a world
+/// at hand, so like `CURRSEED` it's a global.
+pub static GENERATOR_OFF: AtomicU32 = AtomicU32::new(0);
+
+/// The parts of the generator a world can do without, kept in its manifest.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct GeneratorSettings {
+    pub caves: bool,
+    /// Trees, rocks and the other voxel models stamped on the surface.
+    pub features: bool,
+    pub ores: bool,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        GeneratorSettings { caves: true, features: true, ores: true }
+    }
+}
+
+impl GeneratorSettings {
+    pub fn current() -> GeneratorSettings {
+        GeneratorSettings::from_bits(GENERATOR_OFF.load(Ordering::Relaxed))
+    }
+
+    /// Makes these the settings the terrain is generated with from here on.
+    pub fn apply(&self) {
+        GENERATOR_OFF.store(self.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn to_bits(&self) -> u32 {
+        let mut bits = 0;
+        if !self.caves {
+            bits |= NO_CAVES;
+        }
+        if !self.features {
+            bits |= NO_FEATURES;
+        }
+        if !self.ores {
+            bits |= NO_ORES;
+        }
+        bits
+    }
+
+    pub fn from_bits(bits: u32) -> GeneratorSettings {
+        GeneratorSettings {
+            caves: bits & NO_CAVES == 0,
+            features: bits & NO_FEATURES == 0,
+            ores: bits & NO_ORES == 0,
+        }
+    }
+}
+
+pub fn caves_on() -> bool {
+    GENERATOR_OFF.load(Ordering::Relaxed) & NO_CAVES == 0
+}
+
+pub fn features_on() -> bool {
+    GENERATOR_OFF.load(Ordering::Relaxed) & NO_FEATURES == 0
+}
+
+pub fn ores_on() -> bool {
+    GENERATOR_OFF.load(Ordering::Relaxed) & NO_ORES == 0
+}
+
+/// What the world creation screen asks for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorldOptions {
+    /// Anything at all, blank for a random seed.
+    pub seed_text: String,
+    pub planet_type: u8,
+    pub generator: GeneratorSettings,
+}
+
+/// The seed typed into the world creation screen. A number is used as is so seeds shared from the
+/// world list type back in to the same world, anything else is hashed. None if it's blank.
+pub fn seed_from_text(text: &str) -> Option<u32> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    if let Ok(seed) = text.parse::<u32>() {
+        return Some(seed);
+    }
+    //FNV-1a, the same on every platform and version, unlike std's hasher
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in text.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    Some(hash)
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::thread;
+
+use image::{Rgb, RgbImage, RgbaImage};
+use noise::Perlin;
+
+use crate::blockinfo::Blocks;
+use crate::chunkstore::ChunkStore;
+use crate::cube::CubeSide;
+use crate::terrain::{self, ChH};
+use crate::vec::{IVec2, IVec3};
+use crate::worlddir::Manifest;
+use crate::worldstorage::{StorageResult, WorldStorageError};
+
+/// Tiles are this many pixels on a side.
+pub const TILE_SIZE: u32 = 256;
+
+/// At zoom `z` a pixel is `2^z` blocks across, so a tile at this zoom covers 16384 blocks.
+pub const MAX_ZOOM: u32 = 6;
+
+/// What water is drawn as over whatever's at the bottom of it.
+const WATER: Rgb<u8> = Rgb([38, 92, 190]);
+
+/// How far apart the tiles are on the texture atlas, padding and all, and how big the textures in them are.
+const ATLAS_CELL: u32 = 18;
+const ATLAS_TEXTURE: u32 = 16;
+
+/// Block colors for the map, each block's top texture averaged down to one color.
+#[derive(Clone, Debug)]
+pub struct Palette {
+    colors: Vec<Rgb<u8>>,
+}
+
+impl Palette {
+    pub fn from_atlas(atlas: &RgbaImage) -> Palette {
+        let colors = (0..Blocks::get_texs_length() as u32)
+            .map(|id| {
+                let (tx, ty) = *Blocks::get_tex_coords(id, CubeSide::TOP);
+                //The atlas is read bottom up like the game does, see TextureFace
+                let left = tx as u32 * ATLAS_CELL + 1;
+                let top = atlas.height().saturating_sub(ty as u32 * ATLAS_CELL + 1 + ATLAS_TEXTURE);
+                let mut sum = [0u64; 3];
+                let mut count = 0;
+                for y in top..(top + ATLAS_TEXTURE).min(atlas.height()) {
+                    for x in left..(left + ATLAS_TEXTURE).min(atlas.width()) {
+                        let pixel = atlas.get_pixel(x, y);
+                        if pixel[3] > 0 {
+                            for c in 0..3 {
+                                sum[c] += pixel[c] as u64;
+                            }
+                            count += 1;
+                        }
+                    }
+                }
+                match count {
+                    0 => Rgb([255, 0, 255]),
+                    _ => Rgb(sum.map(|c| (c / count) as u8)),
+                }
+            })
+            .collect();
+        Palette { colors }
+    }
+
+    pub fn open(atlas: &Path) -> image::ImageResult<Palette> {
+        Ok(Palette::from_atlas(&image::open(atlas)?.to_rgba8()))
+    }
+
+    /// Magenta for anything the atlas doesn't have, so it stands out.
+    pub fn color(&self, block: u32) -> Rgb<u8> {
+        self.colors.get((block & Blocks::block_id_bits()) as usize).copied().unwrap_or(Rgb([255, 0, 255]))
+    }
+}
+
+/// The top of one column of the world, as seen from above.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Column {
+    pub height: i32,
+    /// The first thing under the water if there's any, so the map shows the sea floor through it.
+    pub block: u32,
+    /// How much water is over `block`.
+    pub depth: i32,
+}
+
+/// A world as it is on disk: the terrain its seed generates with the player edits from its `db` on top.
+/// Trees and the other generated features aren't drawn, only the ground they stand on.
+pub struct WorldMap {
+    perlin: Perlin,
+    pub edits: HashMap<IVec3, u32>,
+}
+
+impl WorldMap {
+    pub fn new(seed: u32) -> WorldMap {
+        WorldMap { perlin: Perlin::new(seed), edits: HashMap::new() }
+    }
+
+    /// Reads the world in `dir` without changing anything in it. It has to have a manifest, a world from
+    /// before those needs opening in the game once first.
+    pub fn open(dir: &Path) -> StorageResult<WorldMap> {
+        let Some(manifest) = Manifest::read(dir)? else {
+            return Err(WorldStorageError::corrupt(dir, "no world.json, open the world in the game once to upgrade it"));
+        };
+        manifest.generator.apply();
+
+        let mut map = WorldMap::new(manifest.seed);
+        let db = dir.join("db");
+        if db.exists() {
+            let store = ChunkStore::new(db, manifest.seed);
+            for cpos in store.chunks()? {
+                map.edits.extend(store.load_chunk(cpos)?);
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn blockat(&self, spot: IVec3) -> u32 {
+        match self.edits.get(&spot) {
+            Some(block) => *block,
+            None => terrain::natural_blockat(&self.perlin, spot),
+        }
+    }
+
+    /// The highest block in the column at (x, z). None if it's air all the way down.
+    pub fn column(&self, x: i32, z: i32) -> Option<Column> {
+        let mut depth = 0;
+        for y in (0..ChH).rev() {
+            let block = self.blockat(IVec3::new(x, y, z));
+            match block & Blocks::block_id_bits() {
+                0 => {}
+                2 => depth += 1,
+                _ => return Some(Column { height: y + depth, block, depth }),
+            }
+        }
+        None
+    }
+
+    /// The (x, z) corners of every block anyone's edited, None if nobody has.
+    pub fn edited_bounds(&self) -> Option<(IVec2, IVec2)> {
+        let mut spots = self.edits.keys();
+        let first = spots.next()?;
+        let (mut min, mut max) = (IVec2::new(first.x, first.z), IVec2::new(first.x, first.z));
+        for spot in spots {
+            min = IVec2::new(min.x.min(spot.x), min.y.min(spot.z));
+            max = IVec2::new(max.x.max(spot.x), max.y.max(spot.z));
+        }
+        Some((min, max))
+    }
+
+    /// The tile at `tile` for `zoom`, north (-z) up. Each pixel is the top of the column at its corner,
+    /// lighter where the ground rises from the pixel north of it and darker where it falls.
+    pub fn render_tile(&self, palette: &Palette, zoom: u32, tile: IVec2) -> RgbImage {
+        let span = tile_span(zoom);
+        self.render(palette, IVec2::new(tile.x * span, tile.y * span), 1 << zoom, TILE_SIZE)
+    }
+
+    /// `size` pixels square from the block at `origin`, `scale` blocks to a pixel.
+    pub fn render(&self, palette: &Palette, origin: IVec2, scale: i32, size: u32) -> RgbImage {
+        let columns = self.columns(origin, scale, size);
+        let row = size as usize;
+        let mut image = RgbImage::new(size, size);
+        for (px, pz, pixel) in image.enumerate_pixels_mut() {
+            let (px, pz) = (px as usize, pz as usize);
+            //Row 0 of `columns` is the one just north of the image
+            let here = columns[(pz + 1) * row + px];
+            let north = columns[pz * row + px];
+            *pixel = shade(palette, here, north);
+        }
+        image
+    }
+
+    /// Every column the image needs, a row north of it included, split over all the cores.
+    fn columns(&self, origin: IVec2, scale: i32, size: u32) -> Vec<Option<Column>> {
+        let row = size as usize;
+        let mut columns = vec![None; row * (row + 1)];
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let rows_each = (row + 1).div_ceil(threads).max(1);
+        thread::scope(|scope| {
+            for (band, chunk) in columns.chunks_mut(rows_each * row).enumerate() {
+                scope.spawn(move || {
+                    for (i, column) in chunk.iter_mut().enumerate() {
+                        let pz = (band * rows_each + i / row) as i32 - 1;
+                        let px = (i % row) as i32;
+                        *column = self.column(origin.x + px * scale, origin.y + pz * scale);
+                    }
+                });
+            }
+        });
+        columns
+    }
+}
+
+fn shade(palette: &Palette, here: Option<Column>, north: Option<Column>) -> Rgb<u8> {
+    let Some(here) = here else {
+        return Rgb([0, 0, 0]);
+    };
+    let mut color = palette.color(here.block).0.map(|c| c as f32);
+    let slope = north.map_or(0, |n| here.height - here.depth - (n.height - n.depth)).clamp(-4, 4);
+    let light = 1.0 + slope as f32 * 0.06 + (here.height as f32 - 40.0) / 400.0;
+    for c in color.iter_mut() {
+        *c *= light;
+    }
+    if here.depth > 0 {
+        let murk = (0.45 + here.depth as f32 * 0.06).min(0.9);
+        for (c, w) in color.iter_mut().zip(WATER.0) {
+            *c = *c * (1.0 - murk) + w as f32 * murk;
+        }
+    }
+    Rgb(color.map(|c| c.clamp(0.0, 255.0) as u8))
+}
+
+/// How many blocks across a tile at `zoom` is.
+pub fn tile_span(zoom: u32) -> i32 {
+    TILE_SIZE as i32 * (1 << zoom)
+}
+
+/// The tiles at `zoom` it takes to cover the blocks from `min` to `max`, corners included.
+pub fn tiles_covering(min: IVec2, max: IVec2, zoom: u32) -> Vec<IVec2> {
+    let span = tile_span(zoom);
+    let mut tiles = Vec::new();
+    for tz in min.y.div_euclid(span)..=max.y.div_euclid(span) {
+        for tx in min.x.div_euclid(span)..=max.x.div_euclid(span) {
+            tiles.push(IVec2::new(tx, tz));
+        }
+    }
+    tiles
+}
@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rusqlite::ErrorCode;
+
+/// Why a world couldn't be read or written. Every variant says which file it was about.
+#[derive(Debug)]
+pub enum WorldStorageError {
+    /// Reading or writing a file failed.
+    Io { path: PathBuf, source: io::Error },
+    /// sqlite failed for some reason other than the ones below.
+    Sql { path: PathBuf, source: rusqlite::Error },
+    /// Something else has the database open and isn't letting go, usually another copy of the game.
+    Locked { path: PathBuf },
+    /// Not what we wrote: a damaged database, a manifest that doesn't parse, a seed file that isn't a number.
+    Corrupt { path: PathBuf, detail: String },
+    /// Saved by a newer version of the game than this one.
+    NewerFormat { path: PathBuf, version: u32 },
+}
+
+pub type StorageResult<T> = Result<T, WorldStorageError>;
+
+impl WorldStorageError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        WorldStorageError::Io { path: path.into(), source }
+    }
+
+    /// Sorts sqlite's errors into the ones a player can do something about.
+    pub fn sql(path: impl Into<PathBuf>, source: rusqlite::Error) -> Self {
+        let path = path.into();
+        match source.sqlite_error_code() {
+            Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => WorldStorageError::Corrupt { path, detail: source.to_string() },
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => WorldStorageError::Locked { path },
+            _ => WorldStorageError::Sql { path, source },
+        }
+    }
+
+    pub fn corrupt(path: impl Into<PathBuf>, detail: impl Into<String>) -> Self {
+        WorldStorageError::Corrupt { path: path.into(), detail: detail.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            WorldStorageError::Io { path, .. }
+            | WorldStorageError::Sql { path, .. }
+            | WorldStorageError::Locked { path }
+            | WorldStorageError::Corrupt { path, .. }
+            | WorldStorageError::NewerFormat { path, .. } => path,
+        }
+    }
+
+    /// Whether restoring a backup is what would fix it.
+    pub fn is_corrupt(&self) -> bool {
+        matches!(self, WorldStorageError::Corrupt { .. })
+    }
+}
+
+impl Display for WorldStorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorldStorageError::Io { path, source } => write!(f, "couldn't read or write {}: {}", path.display(), source),
+            WorldStorageError::Sql { path, source } => write!(f, "database error in {}: {}", path.display(), source),
+            WorldStorageError::Locked { path } => write!(f, "{} is in use by something else", path.display()),
+            WorldStorageError::Corrupt { path, detail } => write!(f, "{} is damaged: {}", path.display(), detail),
+            WorldStorageError::NewerFormat { path, version } => {
+                write!(f, "{} is from a newer version of the game (format {})", path.display(), version)
+            }
+        }
+    }
+}
+
+impl Error for WorldStorageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WorldStorageError::Io { source, .. } => Some(source),
+            WorldStorageError::Sql { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Writes `bytes` to `path`, making the folders it's in if they aren't there.
+pub fn write_file(path: impl AsRef<Path>, bytes: &[u8]) -> StorageResult<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|e| WorldStorageError::io(parent, e))?;
+    }
+    std::fs::write(path, bytes).map_err(|e| WorldStorageError::io(path, e))
+}
+
+/// For the places that only deal in io errors, like starting a server.
+impl From<WorldStorageError> for io::Error {
+    fn from(e: WorldStorageError) -> io::Error {
+        let kind = match &e {
+            WorldStorageError::Io { source, .. } => source.kind(),
+            WorldStorageError::Locked { .. } => io::ErrorKind::PermissionDenied,
+            WorldStorageError::Corrupt { .. } => io::ErrorKind::InvalidData,
+            WorldStorageError::NewerFormat { .. } => io::ErrorKind::Unsupported,
+            WorldStorageError::Sql { .. } => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
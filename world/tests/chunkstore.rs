@@ -0,0 +1,55 @@
+mod common;
+
+use common::TempDir;
+use rusqlite::{params, Connection};
+use voxelland_world::chunkstore::{self, ChunkStore};
+use voxelland_world::vec::{IVec2, IVec3};
+
+#[test]
+fn edits_round_trip_through_a_blob() {
+    let edits = vec![(IVec3::new(1, 2, 3), 4), (IVec3::new(-16, 80, 30), 1 << 20)];
+    assert_eq!(chunkstore::decode(&chunkstore::encode(&edits)), Ok(edits));
+    assert!(chunkstore::decode(b"nonsense").is_err());
+}
+
+#[test]
+fn old_rows_move_into_their_chunks_once() {
+    let dir = TempDir::new();
+    {
+        let conn = Connection::open(dir.join("db")).unwrap();
+        conn.execute("CREATE TABLE userdatamap_99 (x INTEGER, y INTEGER, z INTEGER, value INTEGER, PRIMARY KEY (x, y, z))", ()).unwrap();
+        for (x, z, value) in [(1, 1, 5), (2, 1, 6), (40, -3, 7)] {
+            conn.execute("INSERT INTO userdatamap_99 (x, y, z, value) VALUES (?, ?, ?, ?)", params![x, 60, z, value]).unwrap();
+        }
+    }
+
+    let store = ChunkStore::new(dir.join("db"), 99);
+    //Already in the new table wins over the old row
+    store.set_block(IVec3::new(1, 60, 1), 9).unwrap();
+    assert_eq!(store.migrate().unwrap(), 2);
+    assert_eq!(store.migrate().unwrap(), 0);
+
+    let mut chunks = store.chunks().unwrap();
+    chunks.sort_by_key(|c| (c.x, c.y));
+    assert_eq!(chunks, vec![IVec2::new(0, 0), IVec2::new(2, -1)]);
+
+    let mut first = store.load_chunk(IVec2::new(0, 0)).unwrap();
+    first.sort_by_key(|(s, _)| s.x);
+    assert_eq!(first, vec![(IVec3::new(1, 60, 1), 9), (IVec3::new(2, 60, 1), 6)]);
+}
+
+#[test]
+fn a_chunk_that_will_not_read_says_so() {
+    let dir = TempDir::new();
+    let store = ChunkStore::new(dir.join("db"), 5);
+    store.set_block(IVec3::new(3, 50, 3), 1).unwrap();
+    {
+        let conn = Connection::open(dir.join("db")).unwrap();
+        conn.execute("UPDATE chunkedits_5 SET edits = ?1", [b"nonsense".to_vec()]).unwrap();
+    }
+
+    let error = store.load_chunk(IVec2::new(0, 0)).unwrap_err();
+    assert!(error.is_corrupt());
+    //Nothing saved there is just empty
+    assert!(store.load_chunk(IVec2::new(9, 9)).unwrap().is_empty());
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::TempDir;
+use rusqlite::Connection;
+use voxelland_world::worlddir::{self, Manifest, BACKUP_DIR, FORMAT_VERSION, MANIFEST_FILE};
+use voxelland_world::worldstorage::WorldStorageError;
+
+/// Every layout a world has been saved in before, oldest first. See `worlddir::FORMAT_VERSION`.
+const LAYOUTS: [(&str, u32); 2] = [("v1_per_block", 1), ("v2_per_chunk", 2)];
+
+/// A copy of a saved world from `tests/fixtures/worlds` to upgrade.
+struct Fixture {
+    dir: TempDir,
+}
+
+impl Fixture {
+    fn new(name: &str) -> Fixture {
+        let dir = TempDir::new();
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/worlds").join(name);
+        worlddir::copy_all(&source, &dir).unwrap();
+        Fixture { dir }
+    }
+
+    fn empty() -> Fixture {
+        Fixture { dir: TempDir::new() }
+    }
+
+    fn backups(&self) -> Vec<PathBuf> {
+        match std::fs::read_dir(self.dir.join(BACKUP_DIR)) {
+            Ok(entries) => entries.flatten().map(|e| e.path()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+fn tables(db: &Path) -> Vec<String> {
+    let conn = Connection::open(db).unwrap();
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name").unwrap();
+    let names = stmt.query_map([], |row| row.get(0)).unwrap();
+    names.flatten().collect()
+}
+
+#[test]
+fn every_old_layout_upgrades_to_the_current_one() {
+    for (name, version) in LAYOUTS {
+        let world = Fixture::new(name);
+        assert_eq!(worlddir::detect_version(&world.dir).unwrap(), version, "{}", name);
+
+        let manifest = worlddir::open(&world.dir, 1).unwrap();
+        assert_eq!(manifest.format_version, FORMAT_VERSION);
+        assert_eq!((manifest.seed, manifest.planet_type), (777, 1), "{}", name);
+        assert_eq!(worlddir::detect_version(&world.dir).unwrap(), FORMAT_VERSION);
+        assert_eq!(tables(&world.dir.join("db")), vec!["chunkedits_777"], "{}", name);
+
+        //The backup is the world as it was
+        let backups = world.backups();
+        assert_eq!(backups.len(), 1, "{}", name);
+        assert!(backups[0].to_string_lossy().ends_with(&format!("-format{}", version)));
+        assert_eq!(worlddir::detect_version(&backups[0]).unwrap(), version);
+        assert!(!backups[0].join(MANIFEST_FILE).exists());
+        assert!(backups[0].join("chestdb").exists());
+        assert!(backups[0].join("world/777/pt").exists());
+
+        //Once is enough
+        let again = worlddir::open(&world.dir, 1).unwrap();
+        assert_eq!(again.created, manifest.created);
+        assert_eq!(world.backups().len(), 1);
+    }
+}
+
+#[test]
+fn new_worlds_start_on_the_current_format() {
+    let world = Fixture::empty();
+    let manifest = worlddir::open(&world.dir, 42).unwrap();
+    assert_eq!(manifest.format_version, FORMAT_VERSION);
+    assert_eq!((manifest.seed, manifest.planet_type), (42, 0));
+    assert_eq!(Manifest::read(&world.dir).unwrap(), Some(manifest.clone()));
+
+    //Nothing to back up, and it stays that way
+    assert!(world.backups().is_empty());
+    worlddir::open(&world.dir, 42).unwrap();
+    assert!(world.backups().is_empty());
+}
+
+#[test]
+fn worlds_from_newer_versions_are_left_alone() {
+    let world = Fixture::empty();
+    let mut manifest = worlddir::open(&world.dir, 42).unwrap();
+    manifest.format_version = FORMAT_VERSION + 1;
+    manifest.write(&world.dir).unwrap();
+
+    let error = worlddir::open(&world.dir, 42).unwrap_err();
+    assert!(matches!(error, WorldStorageError::NewerFormat { version, .. } if version == FORMAT_VERSION + 1));
+    assert_eq!(Manifest::read(&world.dir).unwrap(), Some(manifest));
+}
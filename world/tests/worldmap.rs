@@ -0,0 +1,87 @@
+mod common;
+
+use common::TempDir;
+use image::{Rgb, Rgba, RgbaImage};
+use voxelland_world::blockinfo::Blocks;
+use voxelland_world::chunkstore::ChunkStore;
+use voxelland_world::cube::CubeSide;
+use voxelland_world::vec::{IVec2, IVec3};
+use voxelland_world::worlddir;
+use voxelland_world::worldmap::{self, Palette, WorldMap, TILE_SIZE};
+
+/// An atlas where every cell is a different flat color, with some see-through padding around it.
+fn atlas() -> RgbaImage {
+    RgbaImage::from_fn(544, 544, |x, y| {
+        if x % 18 == 0 || y % 18 == 0 {
+            return Rgba([255, 255, 255, 0]);
+        }
+        Rgba([(x / 18) as u8 * 8, ((543 - y) / 18) as u8 * 8, 100, 255])
+    })
+}
+
+#[test]
+fn colors_come_from_each_blocks_top() {
+    let palette = Palette::from_atlas(&atlas());
+    for id in [1, 3, 5, 19, 34] {
+        let (tx, ty) = *Blocks::get_tex_coords(id, CubeSide::TOP);
+        assert_eq!(palette.color(id), Rgb([tx * 8, ty * 8, 100]), "block {}", id);
+    }
+    //Flags don't change what it looks like from above
+    assert_eq!(palette.color(3 | 0b1 << 20), palette.color(3));
+    assert_eq!(palette.color(60000), Rgb([255, 0, 255]));
+}
+
+#[test]
+fn edits_go_over_the_terrain() {
+    let mut map = WorldMap::new(1234);
+    let ground = map.column(10, 10).unwrap();
+    assert!(ground.height > 0);
+
+    map.edits.insert(IVec3::new(10, ground.height + 5, 10), 6);
+    assert_eq!(map.column(10, 10).unwrap().height, ground.height + 5);
+    assert_eq!(map.column(10, 10).unwrap().block, 6);
+
+    //Digging the top out shows what's under it
+    map.edits.clear();
+    map.edits.insert(IVec3::new(10, ground.height, 10), 0);
+    assert!(map.column(10, 10).unwrap().height < ground.height);
+
+    assert_eq!(map.edited_bounds(), Some((IVec2::new(10, 10), IVec2::new(10, 10))));
+}
+
+#[test]
+fn tiles_cover_the_region() {
+    let tiles = worldmap::tiles_covering(IVec2::new(-1, 0), IVec2::new(300, 10), 0);
+    assert_eq!(tiles, vec![IVec2::new(-1, 0), IVec2::new(0, 0), IVec2::new(1, 0)]);
+    //A tile at zoom 2 is four times as far across
+    assert_eq!(worldmap::tiles_covering(IVec2::new(-1, -1), IVec2::new(1023, 1023), 2).len(), 4);
+    assert_eq!(worldmap::tile_span(3), TILE_SIZE as i32 * 8);
+}
+
+#[test]
+fn maps_a_world_off_disk() {
+    let dir = TempDir::new();
+    let manifest = worlddir::open(&dir, 777).unwrap();
+    let store = ChunkStore::new(dir.join("db"), manifest.seed);
+    let mut tower = Vec::new();
+    for y in 100..120 {
+        tower.push((IVec3::new(3, y, 4), 11));
+    }
+    store.set_blocks(&tower).unwrap();
+
+    let map = WorldMap::open(&dir).unwrap();
+    assert_eq!(map.edits.len(), 20);
+    assert_eq!(map.column(3, 4).unwrap().height, 119);
+
+    let palette = Palette::from_atlas(&atlas());
+    let image = map.render(&palette, IVec2::new(0, 0), 1, 8);
+    assert_eq!(image.dimensions(), (8, 8));
+    //The top of the tower, lit up from climbing so far above what's north of it
+    let top = *image.get_pixel(3, 4);
+    let plain = palette.color(11);
+    assert!(top.0.iter().zip(plain.0).all(|(a, b)| *a >= b));
+    assert_ne!(top, *image.get_pixel(3, 5));
+
+    //A folder that isn't a world isn't mistaken for an empty one
+    assert!(WorldMap::open(&TempDir::new()).is_err());
+}